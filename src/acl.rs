@@ -0,0 +1,294 @@
+// acl.rs
+#![allow(dead_code)]
+
+// POSIX.1e access control lists, stored on inodes as the
+// system.posix_acl_access and system.posix_acl_default xattrs in
+// Linux's binary layout, so getfacl/setfacl work unchanged.
+
+use crate::errno::EINVAL;
+
+pub const ACL_ACCESS_XATTR: &str = "system.posix_acl_access";
+pub const ACL_DEFAULT_XATTR: &str = "system.posix_acl_default";
+
+// Version in the xattr header
+pub const ACL_EA_VERSION: u32 = 0x0002;
+
+// Entry tags
+pub const ACL_USER_OBJ: u16 = 0x01;
+pub const ACL_USER: u16 = 0x02;
+pub const ACL_GROUP_OBJ: u16 = 0x04;
+pub const ACL_GROUP: u16 = 0x08;
+pub const ACL_MASK: u16 = 0x10;
+pub const ACL_OTHER: u16 = 0x20;
+
+// Id of the entries that don't name a user or group
+pub const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+// Permission bits of an entry, the same as a mode triplet
+pub const ACL_READ: u16 = 0x04;
+pub const ACL_WRITE: u16 = 0x02;
+pub const ACL_EXECUTE: u16 = 0x01;
+
+const HEADER_SIZE: usize = 4;
+const ENTRY_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclEntry {
+    pub tag: u16,
+    pub perm: u16,
+    pub id: u32,
+}
+
+// The identity a permission check is made for
+pub struct Credentials<'a> {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: &'a [u32],
+}
+
+impl Credentials<'_> {
+    fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acl {
+    entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    // The three-entry ACL equivalent to a permission mode
+    pub fn from_mode(mode: u16) -> Acl {
+        Acl {
+            entries: vec![
+                AclEntry { tag: ACL_USER_OBJ, perm: (mode >> 6) & 7, id: ACL_UNDEFINED_ID },
+                AclEntry { tag: ACL_GROUP_OBJ, perm: (mode >> 3) & 7, id: ACL_UNDEFINED_ID },
+                AclEntry { tag: ACL_OTHER, perm: mode & 7, id: ACL_UNDEFINED_ID },
+            ],
+        }
+    }
+
+    // Parses and validates the xattr form. Anything malformed, out of
+    // order or missing a required entry is EINVAL, as on Linux.
+    pub fn from_xattr(bytes: &[u8]) -> Result<Acl, i32> {
+        if bytes.len() < HEADER_SIZE || (bytes.len() - HEADER_SIZE) % ENTRY_SIZE != 0 {
+            return Err(EINVAL);
+        }
+        let version = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if version != ACL_EA_VERSION {
+            return Err(EINVAL);
+        }
+        let entries = bytes[HEADER_SIZE..]
+            .chunks_exact(ENTRY_SIZE)
+            .map(|e| AclEntry {
+                tag: u16::from_le_bytes([e[0], e[1]]),
+                perm: u16::from_le_bytes([e[2], e[3]]),
+                id: u32::from_le_bytes([e[4], e[5], e[6], e[7]]),
+            })
+            .collect();
+        let acl = Acl { entries };
+        acl.validate()?;
+        Ok(acl)
+    }
+
+    pub fn to_xattr(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + self.entries.len() * ENTRY_SIZE);
+        out.extend_from_slice(&ACL_EA_VERSION.to_le_bytes());
+        for entry in &self.entries {
+            out.extend_from_slice(&entry.tag.to_le_bytes());
+            out.extend_from_slice(&entry.perm.to_le_bytes());
+            out.extend_from_slice(&entry.id.to_le_bytes());
+        }
+        out
+    }
+
+    // Linux's posix_acl_valid: entries sorted by tag, then by id for
+    // named entries; exactly one USER_OBJ, GROUP_OBJ and OTHER; a MASK
+    // whenever there are named entries.
+    fn validate(&self) -> Result<(), i32> {
+        let mut previous: Option<AclEntry> = None;
+        let (mut user_obj, mut group_obj, mut other, mut mask, mut named) = (0, 0, 0, 0, 0);
+        for entry in &self.entries {
+            if entry.perm & !7 != 0 {
+                return Err(EINVAL);
+            }
+            if let Some(prev) = previous {
+                let ordered = prev.tag < entry.tag
+                    || (prev.tag == entry.tag && matches!(entry.tag, ACL_USER | ACL_GROUP) && prev.id < entry.id);
+                if !ordered {
+                    return Err(EINVAL);
+                }
+            }
+            match entry.tag {
+                ACL_USER_OBJ => user_obj += 1,
+                ACL_GROUP_OBJ => group_obj += 1,
+                ACL_OTHER => other += 1,
+                ACL_MASK => mask += 1,
+                ACL_USER | ACL_GROUP if entry.id != ACL_UNDEFINED_ID => named += 1,
+                _ => return Err(EINVAL),
+            }
+            previous = Some(*entry);
+        }
+        if user_obj != 1 || group_obj != 1 || other != 1 || (named > 0 && mask != 1) {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+
+    fn entry(&self, tag: u16) -> Option<&AclEntry> {
+        self.entries.iter().find(|e| e.tag == tag)
+    }
+
+    fn entry_mut(&mut self, tag: u16) -> Option<&mut AclEntry> {
+        self.entries.iter_mut().find(|e| e.tag == tag)
+    }
+
+    // Whether the ACL says no more than the permission bits do, in
+    // which case it is not stored
+    pub fn is_equivalent_mode(&self) -> bool {
+        self.entries.len() == 3
+    }
+
+    // The permission bits the ACL implies. The group bits show the
+    // mask entry when there is one.
+    pub fn mode(&self) -> u16 {
+        let perm = |tag| self.entry(tag).map(|e| e.perm).unwrap_or(0);
+        let group = self.entry(ACL_MASK).map(|e| e.perm).unwrap_or_else(|| perm(ACL_GROUP_OBJ));
+        (perm(ACL_USER_OBJ) << 6) | (group << 3) | perm(ACL_OTHER)
+    }
+
+    // chmod on a file with an ACL: the owner and other entries take the
+    // new bits, and the group bits become the mask (or the owning
+    // group's entry if there is no mask)
+    pub fn chmod(&mut self, mode: u16) {
+        if let Some(e) = self.entry_mut(ACL_USER_OBJ) {
+            e.perm = (mode >> 6) & 7;
+        }
+        let group_tag = if self.entry(ACL_MASK).is_some() { ACL_MASK } else { ACL_GROUP_OBJ };
+        if let Some(e) = self.entry_mut(group_tag) {
+            e.perm = (mode >> 3) & 7;
+        }
+        if let Some(e) = self.entry_mut(ACL_OTHER) {
+            e.perm = mode & 7;
+        }
+    }
+
+    // The access ACL a new inode gets from its directory's default ACL,
+    // given the mode it was created with (the umask is not applied)
+    pub fn create_masq(&self, mode: u16) -> Acl {
+        let mut acl = self.clone();
+        let group_tag = if acl.entry(ACL_MASK).is_some() { ACL_MASK } else { ACL_GROUP_OBJ };
+        for entry in acl.entries.iter_mut() {
+            match entry.tag {
+                ACL_USER_OBJ => entry.perm &= (mode >> 6) & 7,
+                ACL_OTHER => entry.perm &= mode & 7,
+                tag if tag == group_tag => entry.perm &= (mode >> 3) & 7,
+                _ => {}
+            }
+        }
+        acl
+    }
+
+    // POSIX.1e access check of `want` (ACL_READ etc.) by `cred` against
+    // an inode owned by `owner`:`group`
+    pub fn permits(&self, cred: &Credentials, owner: u32, group: u32, want: u16) -> bool {
+        let mask = self.entry(ACL_MASK).map(|e| e.perm).unwrap_or(7);
+        if cred.uid == owner {
+            return self.entry(ACL_USER_OBJ).map(|e| e.perm & want == want).unwrap_or(false);
+        }
+        if let Some(e) = self.entries.iter().find(|e| e.tag == ACL_USER && e.id == cred.uid) {
+            return e.perm & mask & want == want;
+        }
+        let mut group_matched = false;
+        for entry in &self.entries {
+            let matches = match entry.tag {
+                ACL_GROUP_OBJ => cred.in_group(group),
+                ACL_GROUP => cred.in_group(entry.id),
+                _ => false,
+            };
+            if matches {
+                group_matched = true;
+                if entry.perm & mask & want == want {
+                    return true;
+                }
+            }
+        }
+        if group_matched {
+            return false;
+        }
+        self.entry(ACL_OTHER).map(|e| e.perm & want == want).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(entries: &[(u16, u16, u32)]) -> Acl {
+        Acl { entries: entries.iter().map(|&(tag, perm, id)| AclEntry { tag, perm, id }).collect() }
+    }
+
+    const U: u32 = ACL_UNDEFINED_ID;
+
+    #[test]
+    fn mode_round_trips() {
+        let a = Acl::from_mode(0o750);
+        assert!(a.is_equivalent_mode());
+        assert_eq!(a.mode(), 0o750);
+        assert_eq!(Acl::from_xattr(&a.to_xattr()), Ok(a));
+    }
+
+    #[test]
+    fn from_xattr_rejects_malformed_acls() {
+        let good = acl(&[(ACL_USER_OBJ, 7, U), (ACL_USER, 4, 1000), (ACL_GROUP_OBJ, 5, U), (ACL_MASK, 5, U), (ACL_OTHER, 0, U)]);
+        assert_eq!(Acl::from_xattr(&good.to_xattr()), Ok(good.clone()));
+        let bytes = good.to_xattr();
+        assert_eq!(Acl::from_xattr(&bytes[..bytes.len() - 1]), Err(EINVAL));
+        let mut wrong_version = bytes.clone();
+        wrong_version[0] = 1;
+        assert_eq!(Acl::from_xattr(&wrong_version), Err(EINVAL));
+        // a named entry needs a mask
+        let no_mask = acl(&[(ACL_USER_OBJ, 7, U), (ACL_USER, 4, 1000), (ACL_GROUP_OBJ, 5, U), (ACL_OTHER, 0, U)]);
+        assert_eq!(Acl::from_xattr(&no_mask.to_xattr()), Err(EINVAL));
+        let unordered = acl(&[(ACL_GROUP_OBJ, 5, U), (ACL_USER_OBJ, 7, U), (ACL_OTHER, 0, U)]);
+        assert_eq!(Acl::from_xattr(&unordered.to_xattr()), Err(EINVAL));
+        let bad_perm = acl(&[(ACL_USER_OBJ, 8, U), (ACL_GROUP_OBJ, 5, U), (ACL_OTHER, 0, U)]);
+        assert_eq!(Acl::from_xattr(&bad_perm.to_xattr()), Err(EINVAL));
+        let duplicate_user = acl(&[(ACL_USER_OBJ, 7, U), (ACL_USER, 4, 5), (ACL_USER, 4, 5), (ACL_GROUP_OBJ, 5, U), (ACL_MASK, 5, U), (ACL_OTHER, 0, U)]);
+        assert_eq!(Acl::from_xattr(&duplicate_user.to_xattr()), Err(EINVAL));
+    }
+
+    #[test]
+    fn chmod_and_create_masq_go_through_the_mask() {
+        let mut a = acl(&[(ACL_USER_OBJ, 7, U), (ACL_USER, 6, 1000), (ACL_GROUP_OBJ, 5, U), (ACL_MASK, 7, U), (ACL_OTHER, 4, U)]);
+        assert_eq!(a.mode(), 0o774);
+        a.chmod(0o640);
+        assert_eq!(a.mode(), 0o640);
+        assert_eq!(a.entry(ACL_GROUP_OBJ).unwrap().perm, 5);
+        let masked = acl(&[(ACL_USER_OBJ, 7, U), (ACL_GROUP_OBJ, 7, U), (ACL_OTHER, 7, U)]).create_masq(0o751);
+        assert_eq!(masked.mode(), 0o751);
+    }
+
+    #[test]
+    fn permits_follows_posix_1e() {
+        let a = acl(&[(ACL_USER_OBJ, 6, U), (ACL_USER, 7, 1000), (ACL_GROUP_OBJ, 4, U), (ACL_GROUP, 2, 50), (ACL_MASK, 6, U), (ACL_OTHER, 1, U)]);
+        let owner = Credentials { uid: 10, gid: 0, groups: &[] };
+        assert!(a.permits(&owner, 10, 20, ACL_READ | ACL_WRITE));
+        assert!(!a.permits(&owner, 10, 20, ACL_EXECUTE));
+        // the named user is cut down by the mask
+        let named = Credentials { uid: 1000, gid: 0, groups: &[] };
+        assert!(a.permits(&named, 10, 20, ACL_READ | ACL_WRITE));
+        assert!(!a.permits(&named, 10, 20, ACL_EXECUTE));
+        // any matching group entry that grants the access will do
+        let member = Credentials { uid: 1, gid: 20, groups: &[50] };
+        assert!(a.permits(&member, 10, 20, ACL_READ));
+        assert!(a.permits(&member, 10, 20, ACL_WRITE));
+        assert!(!a.permits(&member, 10, 20, ACL_READ | ACL_WRITE));
+        // a matching group that denies does not fall through to other
+        assert!(!a.permits(&member, 10, 20, ACL_EXECUTE));
+        let stranger = Credentials { uid: 2, gid: 2, groups: &[] };
+        assert!(a.permits(&stranger, 10, 20, ACL_EXECUTE));
+        assert!(!a.permits(&stranger, 10, 20, ACL_READ));
+    }
+}
@@ -0,0 +1,350 @@
+// changes.rs
+#![allow(dead_code)]
+
+// A feed of filesystem mutations for the embedder, for mirroring the
+// tree into a UI or syncing it elsewhere. Unlike inotify it covers a
+// whole subtree with full paths, and it can hold changes back to hand
+// them over in batches.
+
+use core::fmt;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::errno::EINVAL;
+use crate::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeOp {
+    // A file, directory, FIFO, device node or symlink was made
+    Create,
+    // Bytes `[offset, offset + len)` changed; fallocate modes that move
+    // data report everything from `offset` on, with `len` u64::MAX
+    Write { offset: u64, len: u64 },
+    Truncate { size: u64 },
+    // Mode, ownership or extended attributes
+    Attrib,
+    // A new hard link at `path`
+    Link,
+    // `path` was removed (unlink or rmdir)
+    Unlink,
+    Rename { from: PathBuf },
+}
+
+impl ChangeOp {
+    // Numbering used across the FFI
+    pub fn code(&self) -> u32 {
+        match self {
+            ChangeOp::Create => 1,
+            ChangeOp::Write { .. } => 2,
+            ChangeOp::Truncate { .. } => 3,
+            ChangeOp::Attrib => 4,
+            ChangeOp::Link => 5,
+            ChangeOp::Unlink => 6,
+            ChangeOp::Rename { .. } => 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub op: ChangeOp,
+    // For a rename, the new path
+    pub path: PathBuf,
+    pub inode: u64,
+}
+
+impl Change {
+    // A rename counts for a subtree it leaves as well as one it enters
+    fn within(&self, root: &PathBuf) -> bool {
+        match &self.op {
+            ChangeOp::Rename { from } => self.path.starts_with(root) || from.starts_with(root),
+            _ => self.path.starts_with(root),
+        }
+    }
+
+    // Folds `next` into a pending write it directly continues, so a
+    // file written in small pieces shows up as one range
+    fn absorb(&mut self, next: &Change) -> bool {
+        if self.inode != next.inode || self.path != next.path {
+            return false;
+        }
+        match (&mut self.op, &next.op) {
+            (ChangeOp::Write { offset, len }, ChangeOp::Write { offset: next_offset, len: next_len })
+                if offset.checked_add(*len) == Some(*next_offset) =>
+            {
+                *len = len.saturating_add(*next_len);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Receives changes, one batch at a time. Called with the process
+// lock held; see "Re-entrancy" on `get_or_init_proc`.
+pub trait ChangeObserver {
+    fn changes(&mut self, batch: &[Change]);
+}
+
+impl fmt::Debug for dyn ChangeObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ChangeObserver")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Batching {
+    // Every change is delivered as it happens
+    Immediate,
+    // Changes are held until this many are pending or the feed is
+    // flushed
+    Batch(usize),
+}
+
+struct Subscription {
+    id: u32,
+    root: PathBuf,
+    batching: Batching,
+    observer: Box<dyn ChangeObserver>,
+    pending: Vec<Change>,
+}
+
+impl Subscription {
+    fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            self.observer.changes(&self.pending);
+            self.pending.clear();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ChangeFeed {
+    subscriptions: Vec<Subscription>,
+    next_id: u32,
+}
+
+impl ChangeFeed {
+    pub fn new() -> Self {
+        Self { subscriptions: Vec::new(), next_id: 1 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    // Changes at or below `root` go to `observer`; returns the id to
+    // unsubscribe with
+    pub fn subscribe(&mut self, root: PathBuf, batching: Batching, observer: Box<dyn ChangeObserver>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.subscriptions.push(Subscription { id, root, batching, observer, pending: Vec::new() });
+        id
+    }
+
+    // Anything still held back is delivered first
+    pub fn unsubscribe(&mut self, id: u32) -> Result<(), i32> {
+        let index = self.subscriptions.iter().position(|s| s.id == id).ok_or(EINVAL)?;
+        let mut subscription = self.subscriptions.remove(index);
+        subscription.flush();
+        Ok(())
+    }
+
+    pub fn publish(&mut self, change: Change) {
+        for subscription in self.subscriptions.iter_mut() {
+            if !change.within(&subscription.root) {
+                continue;
+            }
+            match subscription.batching {
+                Batching::Immediate => subscription.observer.changes(core::slice::from_ref(&change)),
+                Batching::Batch(max) => {
+                    let merged = subscription.pending.last_mut().map(|last| last.absorb(&change)).unwrap_or(false);
+                    if !merged {
+                        subscription.pending.push(change.clone());
+                    }
+                    if subscription.pending.len() >= max {
+                        subscription.flush();
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        for subscription in self.subscriptions.iter_mut() {
+            subscription.flush();
+        }
+    }
+}
+
+impl fmt::Debug for ChangeFeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChangeFeed").field("subscriptions", &self.subscriptions.len()).finish()
+    }
+}
+
+// Collects changes in memory for the embedder to take. Clones share
+// the same log, so keep one around after subscribing the other.
+#[derive(Clone, Default)]
+pub struct ChangeLog {
+    inner: Rc<RefCell<Vec<Change>>>,
+}
+
+impl ChangeLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Everything recorded so far, leaving the log empty
+    pub fn take(&self) -> Vec<Change> {
+        core::mem::take(&mut *self.inner.borrow_mut())
+    }
+}
+
+impl ChangeObserver for ChangeLog {
+    fn changes(&mut self, batch: &[Change]) {
+        self.inner.borrow_mut().extend_from_slice(batch);
+    }
+}
+
+// One change as the host import sees it. Paths are not NUL terminated;
+// `old_path` is only set for a rename, and `offset`/`len` are the
+// written range or (for a truncate) the new size and 0.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeRecord {
+    pub op: u32,
+    pub inode: u64,
+    pub offset: u64,
+    pub len: u64,
+    pub path: *const u8,
+    pub path_len: usize,
+    pub old_path: *const u8,
+    pub old_path_len: usize,
+}
+
+#[cfg(feature = "host-changes")]
+#[link(wasm_import_module = "env")]
+extern "C" {
+    /// Host function that receives a batch of `count` records for the
+    /// subscription registered with `tag`. The records and the paths
+    /// they point to are only valid during the call.
+    fn box_host_fs_changes(tag: u32, records: *const ChangeRecord, count: usize);
+}
+
+// Hands every batch to the `box_host_fs_changes` import
+#[cfg(feature = "host-changes")]
+pub struct HostObserver {
+    tag: u32,
+}
+
+#[cfg(feature = "host-changes")]
+impl HostObserver {
+    pub fn new(tag: u32) -> Self {
+        Self { tag }
+    }
+}
+
+#[cfg(feature = "host-changes")]
+impl ChangeObserver for HostObserver {
+    fn changes(&mut self, batch: &[Change]) {
+        // Keep the strings alive until the call returns
+        let paths: Vec<(String, Option<String>)> = batch.iter()
+            .map(|c| {
+                let from = match &c.op {
+                    ChangeOp::Rename { from } => Some(from.to_string_lossy()),
+                    _ => None,
+                };
+                (c.path.to_string_lossy(), from)
+            })
+            .collect();
+        let records: Vec<ChangeRecord> = batch.iter().zip(paths.iter())
+            .map(|(c, (path, from))| {
+                let (offset, len) = match c.op {
+                    ChangeOp::Write { offset, len } => (offset, len),
+                    ChangeOp::Truncate { size } => (size, 0),
+                    _ => (0, 0),
+                };
+                let (old_path, old_path_len) = match from {
+                    Some(from) => (from.as_ptr(), from.len()),
+                    None => (core::ptr::null(), 0),
+                };
+                ChangeRecord {
+                    op: c.op.code(),
+                    inode: c.inode,
+                    offset,
+                    len,
+                    path: path.as_ptr(),
+                    path_len: path.len(),
+                    old_path,
+                    old_path_len,
+                }
+            })
+            .collect();
+        unsafe { box_host_fs_changes(self.tag, records.as_ptr(), records.len()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(op: ChangeOp, path: &str, inode: u64) -> Change {
+        Change { op, path: PathBuf::from(path), inode }
+    }
+
+    fn write(offset: u64, len: u64) -> ChangeOp {
+        ChangeOp::Write { offset, len }
+    }
+
+    #[test]
+    fn subscriptions_see_their_subtree() {
+        let mut feed = ChangeFeed::new();
+        let (all, sub) = (ChangeLog::new(), ChangeLog::new());
+        feed.subscribe(PathBuf::from("/"), Batching::Immediate, Box::new(all.clone()));
+        feed.subscribe(PathBuf::from("/a"), Batching::Immediate, Box::new(sub.clone()));
+        feed.publish(change(ChangeOp::Create, "/a/f", 2));
+        feed.publish(change(ChangeOp::Create, "/ab", 3));
+        // a rename out of the subtree still concerns it
+        feed.publish(change(ChangeOp::Rename { from: PathBuf::from("/a/f") }, "/b", 2));
+        assert_eq!(all.take().len(), 3);
+        assert_eq!(
+            sub.take(),
+            [change(ChangeOp::Create, "/a/f", 2), change(ChangeOp::Rename { from: PathBuf::from("/a/f") }, "/b", 2)]
+        );
+    }
+
+    #[test]
+    fn batches_merge_adjacent_writes() {
+        let mut feed = ChangeFeed::new();
+        let log = ChangeLog::new();
+        let id = feed.subscribe(PathBuf::from("/"), Batching::Batch(3), Box::new(log.clone()));
+        feed.publish(change(write(0, 3), "/f", 2));
+        feed.publish(change(write(3, 3), "/f", 2));
+        feed.publish(change(write(10, 1), "/f", 2));
+        assert!(log.take().is_empty());
+        // the third pending change fills the batch
+        feed.publish(change(write(11, 1), "/g", 4));
+        assert_eq!(
+            log.take(),
+            [change(write(0, 6), "/f", 2), change(write(10, 1), "/f", 2), change(write(11, 1), "/g", 4)]
+        );
+        feed.publish(change(ChangeOp::Attrib, "/f", 2));
+        feed.flush();
+        assert_eq!(log.take(), [change(ChangeOp::Attrib, "/f", 2)]);
+        // unsubscribing hands over what is still pending
+        feed.publish(change(ChangeOp::Unlink, "/f", 2));
+        assert_eq!(feed.unsubscribe(id), Ok(()));
+        assert_eq!(log.take(), [change(ChangeOp::Unlink, "/f", 2)]);
+        assert_eq!(feed.unsubscribe(id), Err(EINVAL));
+        assert!(feed.is_empty());
+    }
+
+    #[test]
+    fn writes_to_the_end_do_not_overflow() {
+        let mut first = change(write(u64::MAX - 1, 1), "/f", 2);
+        assert!(first.absorb(&change(write(u64::MAX, u64::MAX), "/f", 2)));
+        assert_eq!(first.op, write(u64::MAX - 1, u64::MAX));
+        assert!(!first.absorb(&change(write(0, 1), "/f", 2)));
+    }
+}
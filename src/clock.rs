@@ -0,0 +1,103 @@
+// clock.rs
+#![allow(dead_code)]
+
+// The time source for timeouts and timers. With the `host-clock`
+// feature it is the embedder's clock, and waiting sleeps in the host.
+// Without it the clock is virtual: it starts at zero and only moves
+// when something waits, which jumps it straight to the deadline. Runs
+// are then reproducible and never actually block.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub const CLOCK_REALTIME: i32 = 0;
+pub const CLOCK_MONOTONIC: i32 = 1;
+pub const CLOCK_BOOTTIME: i32 = 7;
+
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+pub const NSEC_PER_MSEC: u64 = 1_000_000;
+pub const NSEC_PER_USEC: u64 = 1_000;
+
+// Longest single host sleep while waiting, so a wait notices input
+// the host delivered in the meantime
+pub const WAIT_SLICE_NS: u64 = 10 * NSEC_PER_MSEC;
+
+#[cfg(feature = "host-clock")]
+#[link(wasm_import_module = "env")]
+extern "C" {
+    /// Host function returning the time of `clock` (CLOCK_REALTIME or
+    /// CLOCK_MONOTONIC) in nanoseconds.
+    fn box_host_clock_ns(clock: i32) -> u64;
+    /// Host function that suspends the guest for `ns` nanoseconds.
+    fn box_host_sleep_ns(ns: u64);
+}
+
+static VIRTUAL_NS: AtomicU64 = AtomicU64::new(0);
+
+// CLOCK_BOOTTIME counts the same as CLOCK_MONOTONIC here
+pub fn is_valid(clock: i32) -> bool {
+    matches!(clock, CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME)
+}
+
+pub fn is_virtual() -> bool {
+    !cfg!(feature = "host-clock")
+}
+
+#[cfg(feature = "host-clock")]
+pub fn now(clock: i32) -> u64 {
+    let clock = if clock == CLOCK_REALTIME { CLOCK_REALTIME } else { CLOCK_MONOTONIC };
+    unsafe { box_host_clock_ns(clock) }
+}
+
+#[cfg(not(feature = "host-clock"))]
+pub fn now(_clock: i32) -> u64 {
+    VIRTUAL_NS.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "host-clock")]
+pub fn sleep(ns: u64) {
+    unsafe { box_host_sleep_ns(ns) }
+}
+
+#[cfg(not(feature = "host-clock"))]
+pub fn sleep(ns: u64) {
+    VIRTUAL_NS.fetch_add(ns, Ordering::Relaxed);
+}
+
+// Timeouts as the calls take them. Each returns None for "forever".
+pub fn from_millis(ms: i32) -> Option<u64> {
+    if ms < 0 { None } else { Some(ms as u64 * NSEC_PER_MSEC) }
+}
+
+// A negative or out-of-range field is EINVAL
+pub fn from_timespec(ts: &Timespec) -> Result<u64, i32> {
+    if ts.tv_sec < 0 || ts.tv_nsec < 0 || ts.tv_nsec as u64 >= NSEC_PER_SEC {
+        return Err(crate::errno::EINVAL);
+    }
+    Ok((ts.tv_sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(ts.tv_nsec as u64))
+}
+
+pub fn from_timeval(tv: &Timeval) -> Result<u64, i32> {
+    if tv.tv_sec < 0 || tv.tv_usec < 0 || tv.tv_usec as u64 >= NSEC_PER_SEC / NSEC_PER_USEC {
+        return Err(crate::errno::EINVAL);
+    }
+    Ok((tv.tv_sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(tv.tv_usec as u64 * NSEC_PER_USEC))
+}
+
+pub fn to_timespec(ns: u64) -> Timespec {
+    Timespec { tv_sec: (ns / NSEC_PER_SEC) as i64, tv_nsec: (ns % NSEC_PER_SEC) as isize }
+}
+
+// struct timespec with a 64-bit time_t, as in musl and wasi-libc
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: isize,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
@@ -2,6 +2,14 @@
 
 use core::fmt;
 
+// The ordered map and the deque are std's own; every module takes them
+// from here so that swapping in other implementations is a change to
+// this file alone. Unlike HashMap below they grow as needed, which is
+// what page tables, xattrs and event queues want. (Rc and RefCell are
+// imported from std directly: they aren't containers, and single-
+// threaded sharing is all the process lock leaves to do.)
+pub use std::collections::{BTreeMap, VecDeque};
+
 // Provide your custom HashMap
 #[derive(Clone)]  // if you want cloning
 pub struct HashMap<K, V, const CAP: usize> {
@@ -91,6 +99,11 @@ impl<K: PartialEq, V, const CAP: usize> HashMap<K, V, CAP> {
         self.get(key).is_some()
     }
 
+    // No free slot left; `insert` of a new key would be dropped
+    pub fn is_full(&self) -> bool {
+        self.entries.iter().all(|slot| slot.is_some())
+    }
+
     pub fn clear(&mut self) {
         for slot in &mut self.entries {
             *slot = None;
@@ -98,3 +111,69 @@ impl<K: PartialEq, V, const CAP: usize> HashMap<K, V, CAP> {
     }
 }
 
+
+// A FIFO byte queue, used for pipe buffers and the like.
+// Reads advance `head` instead of shifting the whole Vec; the consumed
+// prefix is dropped once it makes up at least half of the storage, so
+// pushes and pops stay amortized O(1).
+#[derive(Clone, Debug, Default)]
+pub struct ByteQueue {
+    data: Vec<u8>,
+    head: usize,
+}
+
+impl ByteQueue {
+    pub fn new() -> Self {
+        Self { data: Vec::new(), head: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() - self.head
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The queued bytes, oldest first
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data[self.head..]
+    }
+
+    pub fn push_slice(&mut self, bytes: &[u8]) {
+        if self.head > 0 && self.head >= self.data.len() / 2 {
+            self.data.drain(..self.head);
+            self.head = 0;
+        }
+        self.data.extend_from_slice(bytes);
+    }
+
+    // Drop `n` bytes from the front of the queue
+    pub fn consume(&mut self, n: usize) {
+        let n = if n > self.len() { self.len() } else { n };
+        self.head += n;
+        if self.head == self.data.len() {
+            self.data.clear();
+            self.head = 0;
+        }
+    }
+
+    // Copy up to `out.len()` bytes from the front without consuming them
+    pub fn peek_into(&self, out: &mut [u8]) -> usize {
+        let src = self.as_slice();
+        let n = if out.len() < src.len() { out.len() } else { src.len() };
+        out[..n].copy_from_slice(&src[..n]);
+        n
+    }
+
+    pub fn pop_into(&mut self, out: &mut [u8]) -> usize {
+        let n = self.peek_into(out);
+        self.consume(n);
+        n
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.head = 0;
+    }
+}
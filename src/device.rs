@@ -0,0 +1,284 @@
+// device.rs
+#![allow(dead_code)]
+
+use core::fmt;
+
+use crate::collections::HashMap;
+use crate::errno::{ENOSPC, ENOTTY, ENXIO};
+
+// Major numbers, from Linux's Documentation/admin-guide/devices.txt
+pub const MEM_MAJOR: u32 = 1;
+pub const TTYAUX_MAJOR: u32 = 5;
+pub const UNIX98_PTY_SLAVE_MAJOR: u32 = 136;
+
+// Minors under MEM_MAJOR
+pub const NULL_MINOR: u32 = 3;
+pub const ZERO_MINOR: u32 = 5;
+pub const FULL_MINOR: u32 = 7;
+pub const RANDOM_MINOR: u32 = 8;
+pub const URANDOM_MINOR: u32 = 9;
+
+// Minor of /dev/tty under TTYAUX_MAJOR
+pub const TTY_MINOR: u32 = 0;
+
+// Readiness bits reported by Device::poll
+pub use crate::poll::{POLLERR, POLLHUP, POLLIN, POLLOUT};
+
+// glibc's encoding of a dev_t, so st_rdev decodes with major()/minor()
+pub fn makedev(major: u32, minor: u32) -> u64 {
+    let major = major as u64;
+    let minor = minor as u64;
+    ((major & 0xfffff000) << 32) | ((major & 0xfff) << 8) | ((minor & 0xffffff00) << 12) | (minor & 0xff)
+}
+
+pub fn major(dev: u64) -> u32 {
+    (((dev >> 32) & 0xfffff000) | ((dev >> 8) & 0xfff)) as u32
+}
+
+pub fn minor(dev: u64) -> u32 {
+    (((dev >> 12) & 0xffffff00) | (dev & 0xff)) as u32
+}
+
+#[cfg(feature = "host-random")]
+#[link(wasm_import_module = "env")]
+extern "C" {
+    /// Host function that fills `len` bytes at `ptr` with random data.
+    fn box_host_random_bytes(ptr: *mut u8, len: usize);
+}
+
+// Backs /dev/random and /dev/urandom. A seeded source is a splitmix64
+// stream, so runs are reproducible; the host source asks the embedder.
+#[derive(Debug, Clone)]
+pub enum RandomSource {
+    Seeded(u64),
+    #[cfg(feature = "host-random")]
+    Host,
+}
+
+impl RandomSource {
+    // Seed used when nothing else is configured
+    pub const DEFAULT_SEED: u64 = 0x853c_49e6_748f_ea9b;
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        match self {
+            RandomSource::Seeded(state) => {
+                for chunk in buf.chunks_mut(8) {
+                    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                    let mut z = *state;
+                    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    z ^= z >> 31;
+                    chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+                }
+            }
+            #[cfg(feature = "host-random")]
+            RandomSource::Host => unsafe {
+                box_host_random_bytes(buf.as_mut_ptr(), buf.len());
+            },
+        }
+    }
+}
+
+// A driver behind character or block device inodes. Each call gets
+// the minor number of the node being used, so one driver can serve a
+// whole major. `offset` is the file position for block devices and 0
+// for character devices. Errors are errno values. Called with the
+// process lock held; see "Re-entrancy" on `get_or_init_proc`.
+pub trait Device {
+    fn open(&mut self, _minor: u32, _flags: i32) -> Result<(), i32> {
+        Ok(())
+    }
+
+    fn read(&mut self, minor: u32, buf: &mut [u8], offset: u64) -> Result<usize, i32>;
+
+    fn write(&mut self, minor: u32, data: &[u8], offset: u64) -> Result<usize, i32>;
+
+    fn ioctl(&mut self, _minor: u32, _request: u32, _arg: *mut u8) -> Result<i32, i32> {
+        Err(ENOTTY)
+    }
+
+    // Readiness as POLLIN/POLLOUT/... bits; by default always ready
+    fn poll(&mut self, _minor: u32) -> i16 {
+        POLLIN | POLLOUT
+    }
+
+    // Called when the last open descriptor for a node closes, however
+    // many times it was opened or dup'd
+    fn close(&mut self, _minor: u32) {}
+
+    // Size reported for block devices (SEEK_END)
+    fn size(&self, _minor: u32) -> u64 {
+        0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeviceClass {
+    Char,
+    Block,
+}
+
+// `minor: None` registers a driver for every minor of the major
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DeviceKey {
+    class: DeviceClass,
+    major: u32,
+    minor: Option<u32>,
+}
+
+const DEVICES_CAP: usize = 64;
+
+// Drivers by (class, major, minor), consulted when a device node is opened
+pub struct DeviceRegistry {
+    drivers: HashMap<DeviceKey, Box<dyn Device>, DEVICES_CAP>,
+}
+
+impl fmt::Debug for DeviceRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys: Vec<&DeviceKey> = self.drivers.iter().map(|(k, _)| k).collect();
+        f.debug_struct("DeviceRegistry")
+         .field("drivers", &keys)
+         .finish()
+    }
+}
+
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceRegistry {
+    pub fn new() -> Self {
+        Self { drivers: HashMap::init() }
+    }
+
+    // Replaces any driver already registered under the same numbers
+    pub fn register(&mut self, class: DeviceClass, major: u32, minor: Option<u32>, driver: Box<dyn Device>) {
+        self.drivers.insert(DeviceKey { class, major, minor }, driver);
+    }
+
+    pub fn unregister(&mut self, class: DeviceClass, major: u32, minor: Option<u32>) -> Option<Box<dyn Device>> {
+        self.drivers.remove(&DeviceKey { class, major, minor })
+    }
+
+    // An exact (major, minor) registration wins over a whole-major one
+    pub fn get_mut(&mut self, class: DeviceClass, major: u32, minor: u32) -> Option<&mut Box<dyn Device>> {
+        let exact = DeviceKey { class, major, minor: Some(minor) };
+        if self.drivers.contains_key(&exact) {
+            return self.drivers.get_mut(&exact);
+        }
+        self.drivers.get_mut(&DeviceKey { class, major, minor: None })
+    }
+}
+
+// The memory devices under major 1: null, zero, full, random, urandom
+pub struct MemDevice {
+    random: RandomSource,
+}
+
+impl MemDevice {
+    pub fn new(random: RandomSource) -> Self {
+        Self { random }
+    }
+}
+
+impl Device for MemDevice {
+    fn read(&mut self, minor: u32, buf: &mut [u8], _offset: u64) -> Result<usize, i32> {
+        match minor {
+            NULL_MINOR => Ok(0),
+            ZERO_MINOR | FULL_MINOR => {
+                buf.fill(0);
+                Ok(buf.len())
+            }
+            RANDOM_MINOR | URANDOM_MINOR => {
+                self.random.fill(buf);
+                Ok(buf.len())
+            }
+            _ => Err(ENXIO),
+        }
+    }
+
+    // Writes to the random devices are accepted and dropped, like
+    // unprivileged entropy input
+    fn write(&mut self, minor: u32, data: &[u8], _offset: u64) -> Result<usize, i32> {
+        match minor {
+            NULL_MINOR | ZERO_MINOR | RANDOM_MINOR | URANDOM_MINOR => Ok(data.len()),
+            FULL_MINOR => Err(ENOSPC),
+            _ => Err(ENXIO),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dev_numbers_round_trip() {
+        for (maj, min) in [(1, 9), (8, 1), (0x1234, 0x56789), (UNIX98_PTY_SLAVE_MAJOR, 0)] {
+            let dev = makedev(maj, min);
+            assert_eq!((major(dev), minor(dev)), (maj, min));
+        }
+        assert_eq!(makedev(1, 3), 0x103);
+    }
+
+    #[test]
+    fn mem_devices_behave_like_linux() {
+        let mut mem = MemDevice::new(RandomSource::Seeded(1));
+        let mut buf = [7u8; 16];
+        assert_eq!(mem.read(NULL_MINOR, &mut buf, 0), Ok(0));
+        assert_eq!(mem.read(ZERO_MINOR, &mut buf, 0), Ok(16));
+        assert_eq!(buf, [0u8; 16]);
+        assert_eq!(mem.write(NULL_MINOR, &buf, 0), Ok(16));
+        assert_eq!(mem.write(FULL_MINOR, &buf, 0), Err(ENOSPC));
+        assert_eq!(mem.read(URANDOM_MINOR, &mut buf, 0), Ok(16));
+        assert_ne!(buf, [0u8; 16]);
+        assert_eq!(mem.read(42, &mut buf, 0), Err(ENXIO));
+    }
+
+    #[test]
+    fn seeded_random_is_reproducible() {
+        let (mut a, mut b) = ([0u8; 32], [0u8; 32]);
+        RandomSource::Seeded(5).fill(&mut a);
+        RandomSource::Seeded(5).fill(&mut b);
+        assert_eq!(a, b);
+        RandomSource::Seeded(6).fill(&mut b);
+        assert_ne!(a, b);
+    }
+
+    // Answers every ioctl with its tag, to tell drivers apart
+    struct Tagged(i32);
+
+    impl Device for Tagged {
+        fn read(&mut self, _minor: u32, _buf: &mut [u8], _offset: u64) -> Result<usize, i32> {
+            Ok(0)
+        }
+
+        fn write(&mut self, _minor: u32, data: &[u8], _offset: u64) -> Result<usize, i32> {
+            Ok(data.len())
+        }
+
+        fn ioctl(&mut self, _minor: u32, _request: u32, _arg: *mut u8) -> Result<i32, i32> {
+            Ok(self.0)
+        }
+    }
+
+    fn tag(registry: &mut DeviceRegistry, class: DeviceClass, major: u32, minor: u32) -> Option<i32> {
+        registry.get_mut(class, major, minor).map(|d| d.ioctl(minor, 0, core::ptr::null_mut()).unwrap())
+    }
+
+    #[test]
+    fn exact_minor_wins_over_the_whole_major() {
+        let mut registry = DeviceRegistry::new();
+        registry.register(DeviceClass::Char, 8, None, Box::new(Tagged(1)));
+        registry.register(DeviceClass::Char, 8, Some(3), Box::new(Tagged(2)));
+        assert_eq!(tag(&mut registry, DeviceClass::Char, 8, 0), Some(1));
+        assert_eq!(tag(&mut registry, DeviceClass::Char, 8, 3), Some(2));
+        assert_eq!(tag(&mut registry, DeviceClass::Block, 8, 0), None);
+
+        assert!(registry.unregister(DeviceClass::Char, 8, Some(3)).is_some());
+        assert!(registry.unregister(DeviceClass::Char, 8, Some(3)).is_none());
+        assert_eq!(tag(&mut registry, DeviceClass::Char, 8, 3), Some(1));
+    }
+}
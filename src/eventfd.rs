@@ -0,0 +1,101 @@
+// eventfd.rs
+#![allow(dead_code)]
+
+// eventfd counters: a 64-bit value that writes add to and reads take
+// from, used by event loops to wake themselves up.
+
+use crate::errno::{EAGAIN, EINVAL};
+
+pub const EFD_SEMAPHORE: i32 = 0x1;
+
+// The counter never goes past this; a write that would is EAGAIN
+pub const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+#[derive(Debug, Clone)]
+pub struct EventFd {
+    counter: u64,
+    semaphore: bool,
+}
+
+impl EventFd {
+    pub fn new(initval: u32, semaphore: bool) -> Self {
+        Self { counter: initval as u64, semaphore }
+    }
+
+    // The whole count, or 1 in semaphore mode. A zero counter would
+    // block, so it is EAGAIN.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, i32> {
+        if buf.len() < 8 {
+            return Err(EINVAL);
+        }
+        if self.counter == 0 {
+            return Err(EAGAIN);
+        }
+        let value = if self.semaphore { 1 } else { self.counter };
+        self.counter -= value;
+        buf[..8].copy_from_slice(&value.to_ne_bytes());
+        Ok(8)
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        if data.len() < 8 {
+            return Err(EINVAL);
+        }
+        let value = u64::from_ne_bytes([data[0], data[1], data[2], data[3], data[4], data[5], data[6], data[7]]);
+        if value == u64::MAX {
+            return Err(EINVAL);
+        }
+        if value > EVENTFD_MAX - self.counter {
+            return Err(EAGAIN);
+        }
+        self.counter += value;
+        Ok(8)
+    }
+
+    pub fn readable(&self) -> bool {
+        self.counter > 0
+    }
+
+    pub fn writable(&self) -> bool {
+        self.counter < EVENTFD_MAX
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(efd: &mut EventFd) -> Result<u64, i32> {
+        let mut buf = [0u8; 8];
+        efd.read(&mut buf).map(|_| u64::from_ne_bytes(buf))
+    }
+
+    #[test]
+    fn reads_take_the_count() {
+        let mut efd = EventFd::new(3, false);
+        assert_eq!(efd.write(&2u64.to_ne_bytes()), Ok(8));
+        assert_eq!(read(&mut efd), Ok(5));
+        assert_eq!(read(&mut efd), Err(EAGAIN));
+        assert!(!efd.readable());
+        assert_eq!(efd.read(&mut [0u8; 4]), Err(EINVAL));
+        assert_eq!(efd.write(&[1u8; 4]), Err(EINVAL));
+    }
+
+    #[test]
+    fn semaphores_count_down_by_one() {
+        let mut efd = EventFd::new(2, true);
+        assert_eq!(read(&mut efd), Ok(1));
+        assert_eq!(read(&mut efd), Ok(1));
+        assert_eq!(read(&mut efd), Err(EAGAIN));
+    }
+
+    #[test]
+    fn the_counter_stops_short_of_the_maximum() {
+        let mut efd = EventFd::new(1, false);
+        assert_eq!(efd.write(&u64::MAX.to_ne_bytes()), Err(EINVAL));
+        assert_eq!(efd.write(&EVENTFD_MAX.to_ne_bytes()), Err(EAGAIN));
+        assert_eq!(efd.write(&(EVENTFD_MAX - 1).to_ne_bytes()), Ok(8));
+        assert!(!efd.writable());
+        assert_eq!(read(&mut efd), Ok(EVENTFD_MAX));
+    }
+}
@@ -0,0 +1,72 @@
+// fdobject.rs
+#![allow(dead_code)]
+
+// Descriptors implemented outside the VFS, such as sockets from
+// wasm-net. `Proc::install_object` puts one in the fd table, after which
+// read, write, close, dup, fcntl, ioctl, fstat and poll/epoll reach it
+// like any other descriptor.
+
+use core::any::Any;
+use core::fmt;
+
+use crate::errno::ENOTTY;
+use crate::filesystem::Stat;
+use crate::poll::{POLLIN, POLLOUT};
+
+// Errors are errno values. Nothing in the VFS blocks, so neither should
+// an object: with nothing to read or no room to write it returns
+// EAGAIN, and reports through `poll` when that changes. Called with
+// the process lock held; see "Re-entrancy" on `get_or_init_proc`.
+pub trait FdObject: Any {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i32>;
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, i32>;
+
+    fn ioctl(&mut self, _request: u32, _arg: *mut u8) -> Result<i32, i32> {
+        Err(ENOTTY)
+    }
+
+    // Readiness as POLLIN/POLLOUT/... bits; by default always ready
+    fn poll(&mut self) -> i16 {
+        POLLIN | POLLOUT
+    }
+
+    // Called once, when the last descriptor referring to it is closed
+    fn close(&mut self) {}
+
+    // `stat` arrives filled in from the object's anonymous inode (a
+    // regular file, 0600); a socket would set S_IFSOCK in st_mode
+    fn stat(&self, _stat: &mut Stat) {}
+}
+
+impl fmt::Debug for dyn FdObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FdObject")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Sink;
+
+    impl FdObject for Sink {
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize, i32> {
+            Ok(0)
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+            Ok(data.len())
+        }
+    }
+
+    #[test]
+    fn defaults_are_always_ready_with_no_ioctls() {
+        let mut sink = Sink;
+        assert_eq!(sink.poll(), POLLIN | POLLOUT);
+        assert_eq!(sink.ioctl(0x541B, core::ptr::null_mut()), Err(ENOTTY));
+        let object: Box<dyn FdObject> = Box::new(Sink);
+        assert_eq!(format!("{:?}", object), "FdObject");
+    }
+}
@@ -6,8 +6,10 @@ pub struct CStr {
 }
 
 impl CStr {
-    pub unsafe fn from_ptr<'a>(ptr: *const i8) -> &'a Self {
-        &*(ptr as *const CStr)
+    pub unsafe fn from_ptr(ptr: *const i8) -> Self {
+        // Wrap the pointer itself; casting `ptr` to `*const CStr` would
+        // read the string's first bytes as if they were the pointer.
+        Self { bytes: ptr }
     }
 
     pub unsafe fn to_string_lossy(&self) -> String {
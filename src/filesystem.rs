@@ -2,10 +2,21 @@
 #![allow(dead_code)]
 
 use serde::{Serialize, Deserialize};
+use core::cell::RefCell;
+use std::rc::{Rc, Weak};
 
 // In project implementations - replaces rust's std crates:
 use crate::path::PathBuf;
 use crate::collections::HashMap;
+use crate::pipe::Pipe;
+use crate::device::{self, Device, DeviceClass, DeviceRegistry, MemDevice, RandomSource};
+use crate::provider::FileProvider;
+use crate::errno::{EDQUOT, ENOMEM, ENOSPC, EXDEV};
+use crate::mmap::Mapping;
+use crate::quota::{Charge, Ledger, Limits, QuotaId, Quotas, Usage};
+use crate::statfs::{self, MountUsage};
+use crate::storage::{FileData, PAGE_SIZE};
+use crate::xattr::Xattrs;
 
 // In a unix filesystems, the field below would likely
 // be an i_block, with one or more pointers to the actual
@@ -17,6 +28,15 @@ pub enum InodeKind {
     File,
     Directory,
     SymbolicLink(PathBuf),
+    // Anonymous pipe created by pipe()/pipe2(); never reachable by path
+    Pipe,
+    // Named pipe from mkfifo()/mknod(). Its buffer is created in `pipes`
+    // on first open, so every Proc using this FileSystem shares it.
+    Fifo,
+    // Character device; I/O goes to the driver for (major, minor)
+    CharDevice { major: u32, minor: u32 },
+    // Block device; like CharDevice, but with a seekable position
+    BlockDevice { major: u32, minor: u32 },
 }
 
 #[derive(Debug, Default, PartialEq, Clone, Hash, Eq, Serialize )]
@@ -33,6 +53,14 @@ pub struct Permissions {
     pub other: Permission,
 }
 
+impl Permissions {
+    // The rwxrwxrwx bits
+    pub fn mode(&self) -> u16 {
+        let bits = |p: &Permission| ((p.read as u16) << 2) | ((p.write as u16) << 1) | p.execute as u16;
+        (bits(&self.owner) << 6) | (bits(&self.group) << 3) | bits(&self.other)
+    }
+}
+
 impl From<u16> for Permissions {
     fn from(mode: u16) -> Self {
         Self {
@@ -66,6 +94,8 @@ pub struct Inode {
     pub mtime: u64,
     pub atime: u64,
     pub kind: InodeKind,
+    // Extended attributes (user.*, trusted.*, security.*, system.*)
+    pub xattrs: Xattrs,
 }
 
 impl Inode {
@@ -82,6 +112,7 @@ impl Inode {
             mtime,
             atime,
             kind,
+            xattrs: Xattrs::new(),
         }
     }
 }
@@ -89,6 +120,67 @@ impl Inode {
 // A minimal capacity for your path_map, say 256:
 const PATH_MAP_CAP: usize = 256;
 const FILES_CAP: usize = 256;
+const PIPES_CAP: usize = 64;
+const PROVIDERS_CAP: usize = 64;
+
+// Where shm_open() keeps its objects; created along with /dev
+pub const SHM_DIR: &str = "/dev/shm";
+
+// One entry of the mount table, as listed in /proc/mounts
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub source: String,
+    pub target: PathBuf,
+    pub fstype: String,
+    pub options: String,
+    // Capacity reported by statfs; None means as much as the guest can
+    // address (bytes) or the path table holds (inodes)
+    pub max_bytes: Option<u64>,
+    pub max_inodes: Option<u64>,
+}
+
+impl Mount {
+    pub fn new(source: &str, target: &str, fstype: &str, options: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            target: PathBuf::from(target),
+            fstype: fstype.to_string(),
+            options: options.to_string(),
+            max_bytes: None,
+            max_inodes: None,
+        }
+    }
+}
+
+// Options for FileSystem::with_config
+#[derive(Debug, Clone)]
+pub struct FsConfig {
+    // Create /dev with null, zero, full, random, urandom and tty, and
+    // the /dev/shm directory
+    pub populate_dev: bool,
+    // Seed for /dev/random and /dev/urandom. When None, the host import
+    // is used with the `host-random` feature, or a fixed seed without it.
+    pub random_seed: Option<u64>,
+}
+
+impl Default for FsConfig {
+    fn default() -> Self {
+        Self {
+            populate_dev: true,
+            random_seed: None,
+        }
+    }
+}
+
+// What one Proc using a FileSystem has of it that the other Procs
+// need to know about: inodes it holds open or mapped, which stay
+// allocated after their last name goes, and its MAP_SHARED file
+// mappings, which other Procs' writes to the file are copied into.
+#[derive(Debug, Default)]
+pub struct Attachment {
+    pub held: Vec<u64>,
+    pub shared_mappings: Vec<Mapping>,
+}
 
 #[derive(Debug)]
 pub struct FileSystem {
@@ -97,11 +189,26 @@ pub struct FileSystem {
     // i.e. inode.number == index.
     pub inodes: Vec<Inode>,
     pub next_inode_number: u64,
-    pub current_directory: PathBuf,
     pub root_inode: Inode,
     // Instead of std::collections::HashMap, we do custom HashMap
-    pub files: HashMap<u64, Vec<u8>, FILES_CAP>,
+    pub files: HashMap<u64, FileData, FILES_CAP>,
     pub path_map: HashMap<PathBuf, u64, PATH_MAP_CAP>,
+    // Pipe buffers, keyed by the inode number of the pipe
+    pub pipes: HashMap<u64, Pipe, PIPES_CAP>,
+    // Drivers for device inodes
+    pub devices: DeviceRegistry,
+    // What is mounted where; the root filesystem comes first
+    pub mounts: Vec<Mount>,
+    // Regular files whose content comes from a FileProvider rather
+    // than `files`, keyed by inode number
+    pub providers: HashMap<u64, Box<dyn FileProvider>, PROVIDERS_CAP>,
+    // Space and inode limits, checked as files grow or are created
+    pub quotas: Quotas,
+    // What every inode counts toward; see recharge
+    ledger: Ledger,
+    // One per Proc using this filesystem; a Proc detaches when it is
+    // dropped
+    attachments: Vec<Weak<RefCell<Attachment>>>,
 }
 
 impl Default for FileSystem {
@@ -112,10 +219,21 @@ impl Default for FileSystem {
 
 impl FileSystem {
     pub fn new() -> Self {
+        Self::with_config(FsConfig::default())
+    }
+
+    pub fn with_config(config: FsConfig) -> Self {
+        let random = match config.random_seed {
+            Some(seed) => RandomSource::Seeded(seed),
+            #[cfg(feature = "host-random")]
+            None => RandomSource::Host,
+            #[cfg(not(feature = "host-random"))]
+            None => RandomSource::Seeded(RandomSource::DEFAULT_SEED),
+        };
         let root_inode = Inode::new(
             0,
             0,
-            Permissions::default(),
+            Permissions::from(0o755),
             0,
             0,
             0,
@@ -126,16 +244,118 @@ impl FileSystem {
         let mut fs = Self {
             inodes: vec![root_inode.clone()],
             next_inode_number: 1,
-            current_directory: PathBuf::from("/"),
             root_inode: root_inode,
             files: HashMap::init(),
             path_map: HashMap::init(),
+            pipes: HashMap::init(),
+            devices: DeviceRegistry::new(),
+            mounts: vec![Mount::new("wasmvfs", "/", "wasmvfs", "rw")],
+            providers: HashMap::init(),
+            quotas: Quotas::new(),
+            ledger: Ledger::new(),
+            attachments: Vec::new(),
         };
+        fs.devices.register(DeviceClass::Char, device::MEM_MAJOR, None, Box::new(MemDevice::new(random)));
         // Insert root dir
         fs.path_map.insert(PathBuf::from("/"), 0);
+        if config.populate_dev {
+            fs.populate_dev();
+        }
+        fs.recount();
         fs
     }
 
+    // The standard nodes with Linux's numbers and permissions
+    fn populate_dev(&mut self) {
+        self.create_node(&PathBuf::from("/dev"), 0o755, InodeKind::Directory);
+        self.mounts.push(Mount::new("devtmpfs", "/dev", "devtmpfs", "rw,nosuid"));
+        let nodes = [
+            ("/dev/null", device::MEM_MAJOR, device::NULL_MINOR),
+            ("/dev/zero", device::MEM_MAJOR, device::ZERO_MINOR),
+            ("/dev/full", device::MEM_MAJOR, device::FULL_MINOR),
+            ("/dev/random", device::MEM_MAJOR, device::RANDOM_MINOR),
+            ("/dev/urandom", device::MEM_MAJOR, device::URANDOM_MINOR),
+            ("/dev/tty", device::TTYAUX_MAJOR, device::TTY_MINOR),
+        ];
+        for (path, major, minor) in nodes {
+            self.create_node(&PathBuf::from(path), 0o666, InodeKind::CharDevice { major, minor });
+        }
+        // POSIX shared memory objects are files here (see shm_open)
+        self.create_node(&PathBuf::from(SHM_DIR), 0o777, InodeKind::Directory);
+        self.mounts.push(Mount::new("tmpfs", SHM_DIR, "tmpfs", "rw,nosuid,nodev"));
+    }
+
+    /// Routes I/O on character device nodes numbered (major, minor) to
+    /// `driver`; with `minor: None` it serves every minor of the major
+    pub fn register_char_device(&mut self, major: u32, minor: Option<u32>, driver: Box<dyn Device>) {
+        self.devices.register(DeviceClass::Char, major, minor, driver);
+    }
+
+    pub fn register_block_device(&mut self, major: u32, minor: Option<u32>, driver: Box<dyn Device>) {
+        self.devices.register(DeviceClass::Block, major, minor, driver);
+    }
+
+    pub fn unregister_device(&mut self, class: DeviceClass, major: u32, minor: Option<u32>) -> Option<Box<dyn Device>> {
+        self.devices.unregister(class, major, minor)
+    }
+
+    /// Serves the regular file at `path` from `provider`. An existing
+    /// file at that path keeps its inode but loses its stored content.
+    /// Fails with ENOMEM, changing nothing, once PROVIDERS_CAP files
+    /// have providers.
+    pub fn register_provider(&mut self, path: &PathBuf, mode: u32, provider: Box<dyn FileProvider>) -> Result<u64, i32> {
+        let existing = self.lookup_inode_by_path(path);
+        let replacing = existing.is_some_and(|ino| self.providers.contains_key(&ino));
+        if !replacing && self.providers.is_full() {
+            return Err(ENOMEM);
+        }
+        let inode_number = match existing {
+            Some(ino) => ino,
+            None => self.create_node(path, mode, InodeKind::File),
+        };
+        self.files.remove(&inode_number);
+        self.providers.insert(inode_number, provider);
+        self.recharge(inode_number);
+        Ok(inode_number)
+    }
+
+    /// Detaches the provider behind `path`; the file becomes an empty
+    /// regular file
+    pub fn unregister_provider(&mut self, path: &PathBuf) -> Option<Box<dyn FileProvider>> {
+        let inode_number = self.lookup_inode_by_path(path)?;
+        let provider = self.providers.remove(&inode_number)?;
+        self.files.insert(inode_number, FileData::new());
+        self.recharge(inode_number);
+        Some(provider)
+    }
+
+    // A named inode of any kind; regular files and directories also
+    // get an (empty) entry in `files`
+    pub fn create_node(&mut self, path: &PathBuf, mode: u32, kind: InodeKind) -> u64 {
+        let inode_number = self.next_inode_number;
+        self.next_inode_number += 1;
+
+        let has_data = matches!(kind, InodeKind::File | InodeKind::Directory);
+        let inode = Inode::new(
+            inode_number,
+            0,
+            Permissions::from(mode as u16),
+            0,
+            0,
+            0,
+            0,
+            0,
+            kind
+        );
+        self.inodes.push(inode);
+        self.path_map.insert(path.clone(), inode_number);
+        if has_data {
+            self.files.insert(inode_number, FileData::new());
+        }
+        self.recharge(inode_number);
+        inode_number
+    }
+
     pub fn lookup_inode_by_path(&self, path: &PathBuf) -> Option<u64> {
         self.path_map.get(path).copied()
     }
@@ -157,9 +377,274 @@ impl FileSystem {
         );
         self.inodes.push(inode.clone());
         self.path_map.insert(path.clone(), inode_number);
-        self.files.insert(inode_number, Vec::new());
+        self.files.insert(inode_number, FileData::new());
+        self.recharge(inode_number);
         inode_number
     }
+
+    // An inode that has no entry in path_map, such as the one behind
+    // an anonymous pipe
+    pub fn create_anon_inode(&mut self, kind: InodeKind, mode: u32) -> u64 {
+        let inode_number = self.next_inode_number;
+        self.next_inode_number += 1;
+
+        let inode = Inode::new(
+            inode_number,
+            0,
+            Permissions::from(mode as u16),
+            0,
+            0,
+            0,
+            0,
+            0,
+            kind
+        );
+        self.inodes.push(inode);
+        inode_number
+    }
+
+    // Index of the mount `path` is on: the one with the longest target
+    // that is a prefix of it
+    pub fn mount_index(&self, path: &PathBuf) -> usize {
+        self.mounts.iter().enumerate()
+            .filter(|(_, m)| path.starts_with(&m.target))
+            .max_by_key(|(_, m)| m.target.to_string_lossy().len())
+            .map(|(i, _)| i)
+            .unwrap_or(0)
+    }
+
+    /// Sets the capacity statfs reports for the mount at `target`.
+    /// Returns false if nothing is mounted there.
+    pub fn set_mount_limits(&mut self, target: &PathBuf, max_bytes: Option<u64>, max_inodes: Option<u64>) -> bool {
+        match self.mounts.iter_mut().find(|m| &m.target == target) {
+            Some(mount) => {
+                mount.max_bytes = max_bytes;
+                mount.max_inodes = max_inodes;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Registers a Proc using this filesystem
+    pub fn attach(&mut self) -> Rc<RefCell<Attachment>> {
+        self.attachments.retain(|a| a.strong_count() > 0);
+        let attachment = Rc::new(RefCell::new(Attachment::default()));
+        self.attachments.push(Rc::downgrade(&attachment));
+        attachment
+    }
+
+    /// The Procs using this filesystem that still exist
+    pub fn attachments(&self) -> impl Iterator<Item = Rc<RefCell<Attachment>>> + '_ {
+        self.attachments.iter().filter_map(Weak::upgrade)
+    }
+
+    /// Whether some Proc has an inode open or mapped
+    pub fn is_held(&self, inode_number: u64) -> bool {
+        self.attachments().any(|a| a.borrow().held.contains(&inode_number))
+    }
+
+    /// First name of an inode, if it has one
+    pub fn first_path(&self, inode_number: u64) -> Option<PathBuf> {
+        if inode_number == 0 {
+            return Some(PathBuf::from("/"));
+        }
+        self.path_map.iter()
+            .find_map(|(p, ino)| if *ino == inode_number { Some(p.clone()) } else { None })
+    }
+
+    // What an inode counts toward, or None if it takes no space. An
+    // inode counts once however many names it has, toward the mount of
+    // its first name. Unnamed files (unlinked but open, memfds) are on
+    // the root filesystem; pseudo filesystems take no space.
+    fn charge_for(&self, inode_number: u64) -> Option<Charge> {
+        let path = self.first_path(inode_number);
+        let mount = match &path {
+            Some(path) => self.mount_index(path),
+            None if self.files.contains_key(&inode_number) => 0,
+            None => return None,
+        };
+        if self.mounts[mount].fstype == "proc" {
+            return None;
+        }
+        let inode = self.inodes.get(inode_number as usize)?;
+        Some(Charge {
+            bytes: self.bytes_of(inode_number),
+            uid: inode.user_id,
+            gid: inode.group_id,
+            mount,
+            projects: path.map(|p| self.quotas.projects_of(&p)).unwrap_or_default(),
+        })
+    }
+
+    fn bytes_of(&self, inode_number: u64) -> u64 {
+        self.files.get(&inode_number).map(|data| data.allocated_pages() * PAGE_SIZE).unwrap_or(0)
+    }
+
+    /// Brings an inode's share of the usage up to date. Whatever
+    /// changes its data, owner or names calls this afterwards.
+    pub fn recharge(&mut self, inode_number: u64) {
+        let charge = self.charge_for(inode_number);
+        self.ledger.set(inode_number, charge);
+    }
+
+    /// Recounts all usage from the inode table, for changes that move
+    /// many inodes at once (mounts, project roots)
+    pub fn recount(&mut self) {
+        self.ledger.clear();
+        for inode_number in 0..self.inodes.len() as u64 {
+            self.recharge(inode_number);
+        }
+    }
+
+    /// Usage of every mount, in mount table order. Bytes are allocated
+    /// pages, so holes are free.
+    pub fn usage(&self) -> Vec<MountUsage> {
+        self.mounts.iter().enumerate().map(|(index, mount)| {
+            let pseudo = mount.fstype == "proc";
+            let used = self.ledger.mount(index);
+            MountUsage {
+                target: mount.target.clone(),
+                fstype: mount.fstype.clone(),
+                id: index as u32,
+                block_size: PAGE_SIZE,
+                bytes_used: used.bytes,
+                bytes_total: if pseudo { 0 } else { mount.max_bytes.unwrap_or(statfs::DEFAULT_CAPACITY_BYTES) },
+                inodes_used: used.inodes,
+                inodes_total: if pseudo { 0 } else { mount.max_inodes.unwrap_or(PATH_MAP_CAP as u64) },
+                flags: statfs::flags_of(&mount.options),
+            }
+        }).collect()
+    }
+
+    /// What a user, group or project has stored. A project holds what
+    /// is named inside its subtree.
+    pub fn quota_usage(&self, id: QuotaId) -> Usage {
+        self.ledger.id(id)
+    }
+
+    /// What the whole filesystem stores, the usage `quotas.total` limits
+    pub fn total_usage(&self) -> Usage {
+        self.ledger.total()
+    }
+
+    pub fn quota(&self, id: QuotaId) -> Limits {
+        self.quotas.get(id)
+    }
+
+    /// Limits `id` to `limits`; usage already over them stays, but can
+    /// only shrink
+    pub fn set_quota(&mut self, id: QuotaId, limits: Limits) {
+        self.quotas.set(id, limits);
+        if limits == Limits::default() {
+            self.ledger.untrack(id);
+        } else {
+            self.ledger.track(id);
+        }
+    }
+
+    /// Makes everything named under `root` count toward `project`
+    pub fn set_project(&mut self, project: u32, root: &PathBuf) {
+        self.quotas.set_project(project, root.clone());
+        self.recount();
+    }
+
+    pub fn remove_project(&mut self, project: u32) -> Option<PathBuf> {
+        let root = self.quotas.remove_project(project);
+        self.recount();
+        root
+    }
+
+    /// Whether an inode owned by `uid`/`gid`, named `path` (None for an
+    /// unnamed file), may be created or grow by `bytes`. Running out of
+    /// filesystem or mount capacity is ENOSPC; going over a user, group
+    /// or project limit is EDQUOT. A mount without `max_bytes` holds
+    /// DEFAULT_CAPACITY_BYTES, so a huge fallocate fails up front
+    /// instead of exhausting memory.
+    pub fn check_charge(&self, uid: u32, gid: u32, path: Option<&PathBuf>, bytes: u64, inodes: u64) -> Result<(), i32> {
+        let mount = path.map(|p| self.mount_index(p)).unwrap_or(0);
+        let projects = path.map(|p| self.quotas.projects_of(p)).unwrap_or_default();
+        self.check(uid, gid, mount, &projects, bytes, inodes)
+    }
+
+    /// check_charge for `bytes` more in an existing inode
+    pub fn check_growth(&self, inode_number: u64, bytes: u64) -> Result<(), i32> {
+        match self.ledger.charge(inode_number) {
+            Some(c) => self.check(c.uid, c.gid, c.mount, &c.projects, bytes, 0),
+            None => Ok(()),
+        }
+    }
+
+    fn check(&self, uid: u32, gid: u32, mount: usize, projects: &[u32], bytes: u64, inodes: u64) -> Result<(), i32> {
+        if bytes == 0 && inodes == 0 {
+            return Ok(());
+        }
+        let m = &self.mounts[mount];
+        if m.fstype == "proc" {
+            return Ok(());
+        }
+        if self.quotas.total.exceeded(self.ledger.total(), bytes, inodes) {
+            return Err(ENOSPC);
+        }
+        let capacity = Limits {
+            bytes: Some(m.max_bytes.unwrap_or(statfs::DEFAULT_CAPACITY_BYTES)),
+            inodes: m.max_inodes,
+        };
+        if capacity.exceeded(self.ledger.mount(mount), bytes, inodes) {
+            return Err(ENOSPC);
+        }
+        // Nothing else to look up unless some id has limits
+        if !self.quotas.any() {
+            return Ok(());
+        }
+        let ids = [QuotaId::User(uid), QuotaId::Group(gid)].into_iter()
+            .chain(projects.iter().map(|p| QuotaId::Project(*p)));
+        self.check_ids(ids, bytes, inodes)
+    }
+
+    fn check_ids(&self, ids: impl Iterator<Item = QuotaId>, bytes: u64, inodes: u64) -> Result<(), i32> {
+        for id in ids {
+            let limits = self.quotas.get(id);
+            if limits != Limits::default() && limits.exceeded(self.ledger.id(id), bytes, inodes) {
+                return Err(EDQUOT);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `uid` and `gid` have room to be given an inode; only
+    /// the one that changes is charged
+    pub fn check_chown(&self, inode_number: u64, uid: u32, gid: u32) -> Result<(), i32> {
+        if !self.quotas.any() {
+            return Ok(());
+        }
+        let charge = match self.ledger.charge(inode_number) {
+            Some(charge) => charge,
+            None => return Ok(()),
+        };
+        let user = (uid != charge.uid).then_some(QuotaId::User(uid));
+        let group = (gid != charge.gid).then_some(QuotaId::Group(gid));
+        self.check_ids(user.into_iter().chain(group), charge.bytes, 1)
+    }
+
+    /// Whether a name can move from `from` to `to` (rename, link).
+    /// Usage can't follow it into another project, so as on Linux that
+    /// is EXDEV.
+    pub fn check_project_move(&self, from: &PathBuf, to: &PathBuf) -> Result<(), i32> {
+        let mut a = self.quotas.projects_of(from);
+        let mut b = self.quotas.projects_of(to);
+        a.sort_unstable();
+        b.sort_unstable();
+        if a != b {
+            return Err(EXDEV);
+        }
+        Ok(())
+    }
+
+    /// Usage of the mount `path` is on
+    pub fn usage_of(&self, path: &PathBuf) -> MountUsage {
+        self.usage().swap_remove(self.mount_index(path))
+    }
 }
 
 // POSIX-like Stat structure
@@ -180,6 +665,14 @@ pub struct Stat {
     pub st_ctime: i64,
 }
 
+// Scatter/gather element, as used by vmsplice and readv/writev
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Iovec {
+    pub iov_base: *mut u8,
+    pub iov_len: usize,
+}
+
 // Linux-like dirent structures for getdents calls
 #[repr(C)]
 pub struct Dirent {
@@ -199,3 +692,42 @@ pub struct Dirent64 {
     pub d_name: [u8; 256],
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::StaticProvider;
+
+    #[test]
+    fn dev_is_populated_unless_turned_off() {
+        let fs = FileSystem::with_config(FsConfig::default());
+        for node in ["/dev/null", "/dev/zero", "/dev/full", "/dev/urandom", "/dev/tty", "/dev/shm"] {
+            assert!(fs.lookup_inode_by_path(&PathBuf::from(node)).is_some(), "{}", node);
+        }
+        let fs = FileSystem::with_config(FsConfig { populate_dev: false, random_seed: Some(1) });
+        assert!(fs.lookup_inode_by_path(&PathBuf::from("/dev")).is_none());
+    }
+
+
+    #[test]
+    fn provider_table_is_bounded() {
+        let mut fs = FileSystem::with_config(FsConfig { populate_dev: false, random_seed: Some(1) });
+        let make = || Box::new(StaticProvider::new(b"x".to_vec()));
+        let mut i = 0;
+        let full = loop {
+            match fs.register_provider(&PathBuf::from(format!("/p{}", i)), 0o444, make()) {
+                Ok(_) => i += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(full, ENOMEM);
+        assert!(i > 0);
+        // Replacing an existing provider needs no new slot
+        assert!(fs.register_provider(&PathBuf::from("/p0"), 0o444, make()).is_ok());
+
+        // Unregistering leaves an ordinary, empty file behind
+        let ino = fs.lookup_inode_by_path(&PathBuf::from("/p1")).unwrap();
+        assert!(fs.unregister_provider(&PathBuf::from("/p1")).is_some());
+        assert_eq!(fs.files.get(&ino).map(|d| d.len()), Some(0));
+        assert!(fs.unregister_provider(&PathBuf::from("/p1")).is_none());
+    }
+}
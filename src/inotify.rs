@@ -0,0 +1,285 @@
+// inotify.rs
+#![allow(dead_code)]
+
+// inotify instances. Each one is a set of watches plus a queue of
+// pending events; `Proc` feeds events in from the mutating calls and
+// read() drains them as Linux `struct inotify_event` records.
+
+use crate::collections::VecDeque;
+
+use crate::errno::{EAGAIN, EINVAL, ENOSPC};
+
+// Events
+pub const IN_ACCESS: u32 = 0x0000_0001;
+pub const IN_MODIFY: u32 = 0x0000_0002;
+pub const IN_ATTRIB: u32 = 0x0000_0004;
+pub const IN_CLOSE_WRITE: u32 = 0x0000_0008;
+pub const IN_CLOSE_NOWRITE: u32 = 0x0000_0010;
+pub const IN_OPEN: u32 = 0x0000_0020;
+pub const IN_MOVED_FROM: u32 = 0x0000_0040;
+pub const IN_MOVED_TO: u32 = 0x0000_0080;
+pub const IN_CREATE: u32 = 0x0000_0100;
+pub const IN_DELETE: u32 = 0x0000_0200;
+pub const IN_DELETE_SELF: u32 = 0x0000_0400;
+pub const IN_MOVE_SELF: u32 = 0x0000_0800;
+pub const IN_ALL_EVENTS: u32 = 0x0000_0fff;
+
+// Only ever set by the kernel side
+pub const IN_UNMOUNT: u32 = 0x0000_2000;
+pub const IN_Q_OVERFLOW: u32 = 0x0000_4000;
+pub const IN_IGNORED: u32 = 0x0000_8000;
+pub const IN_ISDIR: u32 = 0x4000_0000;
+
+// inotify_add_watch flags
+pub const IN_ONLYDIR: u32 = 0x0100_0000;
+pub const IN_DONT_FOLLOW: u32 = 0x0200_0000;
+pub const IN_EXCL_UNLINK: u32 = 0x0400_0000;
+pub const IN_MASK_CREATE: u32 = 0x1000_0000;
+pub const IN_MASK_ADD: u32 = 0x2000_0000;
+pub const IN_ONESHOT: u32 = 0x8000_0000;
+pub const IN_WATCH_FLAGS: u32 = IN_ONLYDIR | IN_DONT_FOLLOW | IN_EXCL_UNLINK
+    | IN_MASK_CREATE | IN_MASK_ADD | IN_ONESHOT;
+
+// Events a directory watch receives about its entries, always with
+// the entry's name; the rest are also reported to a watch on the
+// object itself
+pub const IN_CHILD_ONLY: u32 = IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+// Events only a watch on the object itself receives
+pub const IN_SELF_ONLY: u32 = IN_DELETE_SELF | IN_MOVE_SELF;
+
+// Defaults of /proc/sys/fs/inotify
+pub const MAX_USER_WATCHES: usize = 8192;
+pub const MAX_QUEUED_EVENTS: usize = 16384;
+
+// sizeof(struct inotify_event), without the name
+pub const EVENT_SIZE: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct Watch {
+    pub wd: i32,
+    pub inode: u64,
+    pub mask: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub wd: i32,
+    pub mask: u32,
+    pub cookie: u32,
+    pub name: Option<String>,
+}
+
+impl Event {
+    // The name is NUL terminated and padded so the next record stays
+    // aligned, as Linux does
+    fn name_len(&self) -> usize {
+        match &self.name {
+            Some(name) => (name.len() + 1).div_ceil(EVENT_SIZE) * EVENT_SIZE,
+            None => 0,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        EVENT_SIZE + self.name_len()
+    }
+
+    fn encode(&self, out: &mut [u8]) {
+        let name_len = self.name_len();
+        out[0..4].copy_from_slice(&self.wd.to_ne_bytes());
+        out[4..8].copy_from_slice(&self.mask.to_ne_bytes());
+        out[8..12].copy_from_slice(&self.cookie.to_ne_bytes());
+        out[12..16].copy_from_slice(&(name_len as u32).to_ne_bytes());
+        let name = &mut out[EVENT_SIZE..EVENT_SIZE + name_len];
+        name.fill(0);
+        if let Some(n) = &self.name {
+            name[..n.len()].copy_from_slice(n.as_bytes());
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Inotify {
+    watches: Vec<Watch>,
+    next_wd: i32,
+    queue: VecDeque<Event>,
+}
+
+impl Inotify {
+    pub fn new() -> Self {
+        Self { watches: Vec::new(), next_wd: 1, queue: VecDeque::new() }
+    }
+
+    // inotify_add_watch on a resolved inode. A second watch on the same
+    // inode updates the first and returns its descriptor.
+    pub fn add_watch(&mut self, inode: u64, mask: u32) -> Result<i32, i32> {
+        if mask & IN_ALL_EVENTS == 0 || (mask & IN_MASK_ADD != 0 && mask & IN_MASK_CREATE != 0) {
+            return Err(EINVAL);
+        }
+        let events = mask & (IN_ALL_EVENTS | IN_EXCL_UNLINK | IN_ONESHOT);
+        if let Some(watch) = self.watches.iter_mut().find(|w| w.inode == inode) {
+            if mask & IN_MASK_CREATE != 0 {
+                return Err(crate::errno::EEXIST);
+            }
+            watch.mask = if mask & IN_MASK_ADD != 0 { watch.mask | events } else { events };
+            return Ok(watch.wd);
+        }
+        if self.watches.len() >= MAX_USER_WATCHES {
+            return Err(ENOSPC);
+        }
+        let wd = self.next_wd;
+        self.next_wd += 1;
+        self.watches.push(Watch { wd, inode, mask: events });
+        Ok(wd)
+    }
+
+    // inotify_rm_watch: the watch goes away with an IN_IGNORED event
+    pub fn rm_watch(&mut self, wd: i32) -> Result<(), i32> {
+        let index = self.watches.iter().position(|w| w.wd == wd).ok_or(EINVAL)?;
+        self.watches.remove(index);
+        self.push(Event { wd, mask: IN_IGNORED, cookie: 0, name: None });
+        Ok(())
+    }
+
+    pub fn watches_inode(&self, inode: u64) -> bool {
+        self.watches.iter().any(|w| w.inode == inode)
+    }
+
+    // Queues `mask` for every watch on `inode` interested in it. A
+    // one-shot watch is removed after its first event.
+    pub fn deliver(&mut self, inode: u64, mask: u32, cookie: u32, name: Option<&str>) {
+        let event_bits = mask & IN_ALL_EVENTS;
+        let mut fired = Vec::new();
+        for watch in self.watches.iter().filter(|w| w.inode == inode && w.mask & event_bits != 0) {
+            fired.push((watch.wd, watch.mask & IN_ONESHOT != 0));
+        }
+        for (wd, oneshot) in fired {
+            self.push(Event { wd, mask, cookie, name: name.map(|n| n.to_string()) });
+            if oneshot {
+                let _ = self.rm_watch(wd);
+            }
+        }
+    }
+
+    // The inode behind the watches is gone: each sends IN_IGNORED
+    pub fn drop_inode(&mut self, inode: u64) {
+        let wds: Vec<i32> = self.watches.iter().filter(|w| w.inode == inode).map(|w| w.wd).collect();
+        for wd in wds {
+            let _ = self.rm_watch(wd);
+        }
+    }
+
+    // Identical events back to back are merged, and a full queue ends
+    // in a single IN_Q_OVERFLOW
+    fn push(&mut self, event: Event) {
+        if self.queue.back() == Some(&event) {
+            return;
+        }
+        if self.queue.len() >= MAX_QUEUED_EVENTS {
+            let overflow = Event { wd: -1, mask: IN_Q_OVERFLOW, cookie: 0, name: None };
+            if self.queue.back() != Some(&overflow) {
+                self.queue.push_back(overflow);
+            }
+            return;
+        }
+        self.queue.push_back(event);
+    }
+
+    // Bytes a read would return if the buffer were big enough, for
+    // FIONREAD
+    pub fn pending_bytes(&self) -> usize {
+        self.queue.iter().map(|e| e.size()).sum()
+    }
+
+    pub fn is_readable(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    // Copies out as many whole events as fit. A buffer too small for
+    // the first one is EINVAL; an empty queue is EAGAIN, since there is
+    // no way to block.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, i32> {
+        let first = self.queue.front().ok_or(EAGAIN)?;
+        if first.size() > buf.len() {
+            return Err(EINVAL);
+        }
+        let mut used = 0;
+        while let Some(event) = self.queue.front() {
+            let size = event.size();
+            if used + size > buf.len() {
+                break;
+            }
+            event.encode(&mut buf[used..used + size]);
+            used += size;
+            self.queue.pop_front();
+        }
+        Ok(used)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watches_are_shared_per_inode() {
+        let mut inotify = Inotify::new();
+        assert_eq!(inotify.add_watch(7, 0), Err(EINVAL));
+        assert_eq!(inotify.add_watch(7, IN_OPEN | IN_MASK_ADD | IN_MASK_CREATE), Err(EINVAL));
+        assert_eq!(inotify.add_watch(7, IN_OPEN), Ok(1));
+        assert_eq!(inotify.add_watch(7, IN_MODIFY | IN_MASK_ADD), Ok(1));
+        assert_eq!(inotify.add_watch(7, IN_OPEN | IN_MASK_CREATE), Err(crate::errno::EEXIST));
+        assert_eq!(inotify.add_watch(8, IN_OPEN), Ok(2));
+        inotify.deliver(7, IN_MODIFY, 0, None);
+        inotify.deliver(7, IN_ACCESS, 0, None);
+        assert_eq!(inotify.pending_bytes(), EVENT_SIZE);
+        assert_eq!(inotify.rm_watch(1), Ok(()));
+        assert_eq!(inotify.rm_watch(1), Err(EINVAL));
+        assert!(!inotify.watches_inode(7));
+        assert!(inotify.watches_inode(8));
+    }
+
+    #[test]
+    fn oneshot_and_dropped_inodes_send_ignored() {
+        let mut inotify = Inotify::new();
+        let oneshot = inotify.add_watch(1, IN_OPEN | IN_ONESHOT).unwrap();
+        let other = inotify.add_watch(2, IN_ALL_EVENTS).unwrap();
+        inotify.deliver(1, IN_OPEN, 0, None);
+        inotify.deliver(1, IN_OPEN, 0, None);
+        inotify.drop_inode(2);
+        let mut buf = [0u8; 4 * EVENT_SIZE];
+        assert_eq!(inotify.read(&mut buf), Ok(3 * EVENT_SIZE));
+        let field = |i: usize, at: usize| u32::from_ne_bytes(buf[i * EVENT_SIZE + at..i * EVENT_SIZE + at + 4].try_into().unwrap());
+        assert_eq!((field(0, 0) as i32, field(0, 4)), (oneshot, IN_OPEN));
+        assert_eq!((field(1, 0) as i32, field(1, 4)), (oneshot, IN_IGNORED));
+        assert_eq!((field(2, 0) as i32, field(2, 4)), (other, IN_IGNORED));
+        assert_eq!(inotify.read(&mut buf), Err(EAGAIN));
+    }
+
+    #[test]
+    fn names_are_padded_and_reads_take_whole_events() {
+        let mut inotify = Inotify::new();
+        inotify.add_watch(1, IN_CREATE).unwrap();
+        inotify.deliver(1, IN_CREATE, 0, Some("a"));
+        inotify.deliver(1, IN_CREATE, 0, Some("a_name_of_sixteen"));
+        assert_eq!(inotify.pending_bytes(), 2 * EVENT_SIZE + 3 * EVENT_SIZE);
+        let mut small = [0u8; EVENT_SIZE];
+        assert_eq!(inotify.read(&mut small), Err(EINVAL));
+        let mut buf = [0u8; 3 * EVENT_SIZE];
+        assert_eq!(inotify.read(&mut buf), Ok(2 * EVENT_SIZE));
+        assert_eq!(u32::from_ne_bytes(buf[12..16].try_into().unwrap()), EVENT_SIZE as u32);
+        assert_eq!(&buf[16..18], b"a\0");
+        assert_eq!(inotify.read(&mut buf), Ok(3 * EVENT_SIZE));
+        assert!(!inotify.is_readable());
+    }
+
+    #[test]
+    fn a_full_queue_ends_in_one_overflow() {
+        let mut inotify = Inotify::new();
+        inotify.add_watch(1, IN_MODIFY).unwrap();
+        for cookie in 0..MAX_QUEUED_EVENTS as u32 + 10 {
+            inotify.deliver(1, IN_MODIFY, cookie, None);
+        }
+        assert_eq!(inotify.queue.len(), MAX_QUEUED_EVENTS + 1);
+        assert_eq!(inotify.queue.back().map(|e| e.mask), Some(IN_Q_OVERFLOW));
+    }
+}
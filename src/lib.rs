@@ -2,8 +2,29 @@
 
 extern crate core;
 
+pub mod acl;
+pub mod changes;
+pub mod clock;
+pub mod device;
+pub mod errno;
+mod eventfd;
+pub mod fdobject;
 pub mod filesystem;
+mod inotify;
+mod memfd;
+mod mmap;
+mod pipe;
+pub mod poll;
+mod procfs;
+pub mod provider;
+pub mod quota;
+pub mod statfs;
+pub mod stdio;
+pub mod storage;
 mod system;
+mod timerfd;
+pub mod tty;
+pub mod xattr;
 
 pub mod cmp;
 pub mod collections;
@@ -12,5 +33,13 @@ pub mod lazy_static;
 pub mod path;
 pub mod sync;
 
-pub use filesystem::{FileSystem, Inode, InodeKind, Permissions};
-pub use system::Proc;
+pub use changes::{Batching, Change, ChangeLog, ChangeObserver, ChangeOp};
+pub use device::{Device, DeviceClass};
+pub use fdobject::FdObject;
+pub use filesystem::{FileSystem, FsConfig, Inode, InodeKind, Mount, Permissions};
+pub use provider::FileProvider;
+pub use quota::{Limits, QuotaId, Usage};
+pub use statfs::MountUsage;
+pub use system::{get_or_init_proc, Proc};
+pub use stdio::{BufferMode, CaptureStream, DiscardStream, Stream};
+pub use tty::{Termios, Tty, Winsize};
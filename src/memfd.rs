@@ -0,0 +1,114 @@
+// memfd.rs
+#![allow(dead_code)]
+
+// Regular files without a name: memfd_create() and open(O_TMPFILE).
+// Their data lives in `fs.files` like any other file's; this module
+// keeps what sets them apart, including the seals a memfd can carry.
+
+use crate::errno::{EINVAL, EPERM};
+use crate::path::PathBuf;
+
+pub const MFD_CLOEXEC: u32 = 0x1;
+pub const MFD_ALLOW_SEALING: u32 = 0x2;
+
+// memfd names show up as "memfd:<name>", which has to fit NAME_MAX
+pub const MFD_NAME_MAX: usize = 249;
+
+pub const F_SEAL_SEAL: u32 = 0x1;
+pub const F_SEAL_SHRINK: u32 = 0x2;
+pub const F_SEAL_GROW: u32 = 0x4;
+pub const F_SEAL_WRITE: u32 = 0x8;
+pub const F_SEAL_FUTURE_WRITE: u32 = 0x10;
+
+pub const F_SEAL_ALL: u32 = F_SEAL_SEAL | F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_WRITE | F_SEAL_FUTURE_WRITE;
+
+#[derive(Debug, Clone)]
+pub struct Memfd {
+    pub name: String,
+    pub seals: u32,
+}
+
+impl Memfd {
+    // Without MFD_ALLOW_SEALING the file starts sealed against seals
+    pub fn new(name: String, allow_sealing: bool) -> Self {
+        Self { name, seals: if allow_sealing { 0 } else { F_SEAL_SEAL } }
+    }
+
+    pub fn add_seals(&mut self, seals: u32) -> Result<(), i32> {
+        if seals & !F_SEAL_ALL != 0 {
+            return Err(EINVAL);
+        }
+        if self.seals & F_SEAL_SEAL != 0 {
+            return Err(EPERM);
+        }
+        self.seals |= seals;
+        Ok(())
+    }
+
+    pub fn write_sealed(&self) -> bool {
+        self.seals & (F_SEAL_WRITE | F_SEAL_FUTURE_WRITE) != 0
+    }
+
+    // Writing `len` bytes at `offset` of a file of `size` bytes
+    pub fn check_write(&self, size: u64, offset: u64, len: u64) -> Result<(), i32> {
+        if self.write_sealed() {
+            return Err(EPERM);
+        }
+        self.check_resize(size, core::cmp::max(size, offset.saturating_add(len)))
+    }
+
+    pub fn check_resize(&self, size: u64, new_size: u64) -> Result<(), i32> {
+        if (new_size < size && self.seals & F_SEAL_SHRINK != 0) || (new_size > size && self.seals & F_SEAL_GROW != 0) {
+            return Err(EPERM);
+        }
+        Ok(())
+    }
+}
+
+// An O_TMPFILE file, until it is given a name
+#[derive(Debug, Clone)]
+pub struct TmpFile {
+    // Where it was made, and where /proc/self/fd shows it
+    pub dir: PathBuf,
+    // linkat(AT_EMPTY_PATH) may name it; O_EXCL rules that out
+    pub linkable: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealing_has_to_be_allowed() {
+        let mut memfd = Memfd::new("m".to_string(), false);
+        assert_eq!(memfd.add_seals(F_SEAL_WRITE), Err(EPERM));
+        let mut memfd = Memfd::new("m".to_string(), true);
+        assert_eq!(memfd.add_seals(0x100), Err(EINVAL));
+        assert_eq!(memfd.add_seals(F_SEAL_SHRINK | F_SEAL_SEAL), Ok(()));
+        assert_eq!(memfd.add_seals(F_SEAL_GROW), Err(EPERM));
+        assert_eq!(memfd.seals, F_SEAL_SHRINK | F_SEAL_SEAL);
+    }
+
+    #[test]
+    fn size_seals_limit_writes_and_resizes() {
+        let mut memfd = Memfd::new("m".to_string(), true);
+        memfd.add_seals(F_SEAL_GROW | F_SEAL_SHRINK).unwrap();
+        assert_eq!(memfd.check_write(10, 0, 10), Ok(()));
+        assert_eq!(memfd.check_write(10, 5, 10), Err(EPERM));
+        assert_eq!(memfd.check_write(10, u64::MAX, 1), Err(EPERM));
+        assert_eq!(memfd.check_resize(10, 10), Ok(()));
+        assert_eq!(memfd.check_resize(10, 9), Err(EPERM));
+        assert_eq!(memfd.check_resize(10, 11), Err(EPERM));
+    }
+
+    #[test]
+    fn write_seals_stop_every_write() {
+        for seal in [F_SEAL_WRITE, F_SEAL_FUTURE_WRITE] {
+            let mut memfd = Memfd::new("m".to_string(), true);
+            memfd.add_seals(seal).unwrap();
+            assert!(memfd.write_sealed());
+            assert_eq!(memfd.check_write(10, 0, 1), Err(EPERM));
+            assert_eq!(memfd.check_resize(10, 20), Ok(()));
+        }
+    }
+}
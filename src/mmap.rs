@@ -0,0 +1,171 @@
+// mmap.rs
+#![allow(dead_code)]
+
+// Memory mappings. There is no MMU to trap on, so a mapping is a block
+// of the guest's linear memory filled from the file when it is made;
+// MAP_SHARED changes reach the file when they are written back by
+// msync, munmap or close. The table of mappings lives on `Proc`.
+
+use std::alloc::{self, Layout};
+
+// Granularity of addresses, lengths and file offsets
+pub const PAGE_SIZE: usize = 4096;
+
+pub const PROT_NONE: i32 = 0x0;
+pub const PROT_READ: i32 = 0x1;
+pub const PROT_WRITE: i32 = 0x2;
+pub const PROT_EXEC: i32 = 0x4;
+
+pub const MAP_SHARED: i32 = 0x01;
+pub const MAP_PRIVATE: i32 = 0x02;
+pub const MAP_SHARED_VALIDATE: i32 = 0x03;
+pub const MAP_TYPE: i32 = 0x0f;
+pub const MAP_FIXED: i32 = 0x10;
+pub const MAP_ANONYMOUS: i32 = 0x20;
+pub const MAP_NORESERVE: i32 = 0x4000;
+pub const MAP_POPULATE: i32 = 0x8000;
+// Flags MAP_SHARED_VALIDATE accepts; each is either honoured or has no
+// meaning here (everything is populated up front)
+pub const MAP_KNOWN: i32 = MAP_TYPE | MAP_FIXED | MAP_ANONYMOUS | MAP_NORESERVE | MAP_POPULATE;
+
+pub const MS_ASYNC: i32 = 1;
+pub const MS_INVALIDATE: i32 = 2;
+pub const MS_SYNC: i32 = 4;
+
+pub const MREMAP_MAYMOVE: i32 = 1;
+pub const MREMAP_FIXED: i32 = 2;
+
+// What mmap and mremap return on failure, (void *)-1
+pub const MAP_FAILED: *mut u8 = usize::MAX as *mut u8;
+
+// `len` rounded up to whole pages; None if that overflows
+pub fn page_align(len: usize) -> Option<usize> {
+    len.checked_add(PAGE_SIZE - 1).map(|len| len & !(PAGE_SIZE - 1))
+}
+
+pub fn is_page_aligned(addr: usize) -> bool {
+    addr % PAGE_SIZE == 0
+}
+
+// A page-aligned allocation of linear memory backing one or more
+// mappings. It is freed once no mapping refers to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub base: usize,
+    pub size: usize,
+}
+
+impl Region {
+    pub fn alloc(size: usize) -> Option<Region> {
+        let layout = Layout::from_size_align(size, PAGE_SIZE).ok()?;
+        let base = unsafe { alloc::alloc_zeroed(layout) };
+        if base.is_null() {
+            None
+        } else {
+            Some(Region { base: base as usize, size })
+        }
+    }
+
+    pub fn end(&self) -> usize {
+        self.base + self.size
+    }
+
+    // Safety: no mapping may still refer to the region
+    pub unsafe fn free(self) {
+        let layout = Layout::from_size_align_unchecked(self.size, PAGE_SIZE);
+        alloc::dealloc(self.base as *mut u8, layout);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub addr: usize,
+    // Always a whole number of pages
+    pub len: usize,
+    pub prot: i32,
+    pub shared: bool,
+    // Backing file, or None for MAP_ANONYMOUS
+    pub inode: Option<u64>,
+    // File offset that `addr` maps
+    pub offset: u64,
+    pub region: Region,
+}
+
+impl Mapping {
+    pub fn end(&self) -> usize {
+        self.addr + self.len
+    }
+
+    pub fn overlaps(&self, addr: usize, end: usize) -> bool {
+        self.addr < end && addr < self.end()
+    }
+
+    // File offset of `addr`, which must lie within the mapping
+    pub fn file_offset(&self, addr: usize) -> u64 {
+        self.offset + (addr - self.addr) as u64
+    }
+
+    // Whether changes are written back to a file
+    pub fn writes_back(&self) -> bool {
+        self.shared && self.inode.is_some()
+    }
+
+    // Cuts the mapping at `addr` (page aligned, strictly inside it),
+    // keeping the lower part and returning the upper one
+    pub fn split_at(&mut self, addr: usize) -> Mapping {
+        let mut upper = self.clone();
+        upper.addr = addr;
+        upper.len = self.end() - addr;
+        upper.offset = self.file_offset(addr);
+        self.len = addr - self.addr;
+        upper
+    }
+}
+
+// The mapped bytes at `[addr, addr + len)`.
+// Safety: the range must lie within a live mapping and not be
+// borrowed elsewhere.
+pub unsafe fn memory<'a>(addr: usize, len: usize) -> &'a mut [u8] {
+    core::slice::from_raw_parts_mut(addr as *mut u8, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(addr: usize, len: usize, offset: u64) -> Mapping {
+        let region = Region { base: addr, size: len };
+        Mapping { addr, len, prot: PROT_READ, shared: true, inode: Some(1), offset, region }
+    }
+
+    #[test]
+    fn lengths_round_up_to_pages() {
+        assert_eq!(page_align(1), Some(PAGE_SIZE));
+        assert_eq!(page_align(PAGE_SIZE), Some(PAGE_SIZE));
+        assert_eq!(page_align(usize::MAX), None);
+        assert!(is_page_aligned(3 * PAGE_SIZE));
+        assert!(!is_page_aligned(PAGE_SIZE + 1));
+    }
+
+    #[test]
+    fn split_keeps_file_offsets_in_step() {
+        let mut lower = mapping(0x10000, 3 * PAGE_SIZE, 8192);
+        let upper = lower.split_at(0x10000 + PAGE_SIZE);
+        assert_eq!((lower.addr, lower.len), (0x10000, PAGE_SIZE));
+        assert_eq!((upper.addr, upper.len), (0x10000 + PAGE_SIZE, 2 * PAGE_SIZE));
+        assert_eq!(upper.offset, 8192 + PAGE_SIZE as u64);
+        assert_eq!(upper.region, lower.region);
+        assert!(upper.overlaps(upper.end() - 1, upper.end()));
+        assert!(!lower.overlaps(upper.addr, upper.end()));
+        assert!(lower.writes_back());
+    }
+
+    #[test]
+    fn regions_are_page_aligned_and_zeroed() {
+        let region = Region::alloc(2 * PAGE_SIZE).unwrap();
+        assert!(is_page_aligned(region.base));
+        let bytes = unsafe { memory(region.base, region.size) };
+        assert!(bytes.iter().all(|&b| b == 0));
+        unsafe { region.free() };
+    }
+}
@@ -81,6 +81,16 @@ impl PathBuf {
         }
     }
 
+    /// Whether `base` is this path or one of its ancestors, comparing
+    /// whole components ("/a/bc" is not under "/a/b")
+    pub fn starts_with(&self, base: &PathBuf) -> bool {
+        let base = base.inner.trim_end_matches('/');
+        match self.inner.strip_prefix(base) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    }
+
     /// Minimal version of "lossy" for debug or string conversion
     pub fn to_string_lossy(&self) -> String {
         // In real code, you'd handle UTF-8 properly. We'll do a direct clone:
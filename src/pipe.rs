@@ -0,0 +1,191 @@
+// pipe.rs
+#![allow(dead_code)]
+
+use crate::collections::ByteQueue;
+use crate::errno::{EAGAIN, EBUSY, EPERM, EPIPE};
+
+// Writes of at most PIPE_BUF bytes are atomic: all or nothing
+pub const PIPE_BUF: usize = 4096;
+// Same default as Linux: 16 pages
+pub const PIPE_DEFAULT_SIZE: usize = 65536;
+// Ceiling for F_SETPIPE_SZ, like /proc/sys/fs/pipe-max-size
+pub const PIPE_MAX_SIZE: usize = 1048576;
+
+// The buffer shared by both ends of a pipe. Pipes are kept in
+// `FileSystem::pipes`, keyed by the inode number of the pipe, so
+// every descriptor referring to that inode sees the same data.
+//
+// There is no scheduler to park a blocked caller on, so operations
+// that would block on Linux fail with EAGAIN whether or not the
+// descriptor is O_NONBLOCK; the guest is expected to retry.
+#[derive(Debug)]
+pub struct Pipe {
+    buffer: ByteQueue,
+    capacity: usize,
+    pub readers: u32,
+    pub writers: u32,
+}
+
+impl Default for Pipe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pipe {
+    pub fn new() -> Self {
+        Self {
+            buffer: ByteQueue::new(),
+            capacity: PIPE_DEFAULT_SIZE,
+            readers: 0,
+            writers: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn space(&self) -> usize {
+        self.capacity - self.buffer.len()
+    }
+
+    // Bytes currently buffered, oldest first
+    pub fn as_slice(&self) -> &[u8] {
+        self.buffer.as_slice()
+    }
+
+    pub fn consume(&mut self, n: usize) {
+        self.buffer.consume(n);
+    }
+
+    // Ok(0) is end-of-file: nothing buffered and every writer is gone
+    pub fn read(&mut self, out: &mut [u8]) -> Result<usize, i32> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        if self.buffer.is_empty() {
+            return if self.writers == 0 { Ok(0) } else { Err(EAGAIN) };
+        }
+        Ok(self.buffer.pop_into(out))
+    }
+
+    // Like read, but leaves the data in place (for tee)
+    pub fn peek(&self, out: &mut [u8]) -> Result<usize, i32> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        if self.buffer.is_empty() {
+            return if self.writers == 0 { Ok(0) } else { Err(EAGAIN) };
+        }
+        Ok(self.buffer.peek_into(out))
+    }
+
+    // Number of bytes a write of `len` bytes may take right now,
+    // honouring PIPE_BUF atomicity
+    pub fn writable(&self, len: usize) -> Result<usize, i32> {
+        if self.readers == 0 {
+            return Err(EPIPE);
+        }
+        let space = self.space();
+        if len == 0 {
+            return Ok(0);
+        }
+        if space == 0 || (len <= PIPE_BUF && space < len) {
+            return Err(EAGAIN);
+        }
+        Ok(if len < space { len } else { space })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        let n = self.writable(data.len())?;
+        self.buffer.push_slice(&data[..n]);
+        Ok(n)
+    }
+
+    // F_SETPIPE_SZ: rounded up to a power-of-two number of pages
+    pub fn set_capacity(&mut self, size: usize) -> Result<usize, i32> {
+        if size > PIPE_MAX_SIZE {
+            return Err(EPERM);
+        }
+        let mut new_capacity = PIPE_BUF;
+        while new_capacity < size {
+            new_capacity *= 2;
+        }
+        if new_capacity < self.buffer.len() {
+            return Err(EBUSY);
+        }
+        self.capacity = new_capacity;
+        Ok(new_capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_pipe() -> Pipe {
+        let mut pipe = Pipe::new();
+        pipe.readers = 1;
+        pipe.writers = 1;
+        pipe
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let mut pipe = open_pipe();
+        assert_eq!(pipe.write(b"hello"), Ok(5));
+        let mut out = [0u8; 3];
+        assert_eq!(pipe.peek(&mut out), Ok(3));
+        assert_eq!(pipe.len(), 5);
+        assert_eq!(pipe.read(&mut out), Ok(3));
+        assert_eq!(&out, b"hel");
+        assert_eq!(pipe.read(&mut out), Ok(2));
+        assert_eq!(&out[..2], b"lo");
+    }
+
+    #[test]
+    fn empty_pipe_is_eagain_until_the_writers_go() {
+        let mut pipe = open_pipe();
+        let mut out = [0u8; 4];
+        assert_eq!(pipe.read(&mut out), Err(EAGAIN));
+        pipe.writers = 0;
+        assert_eq!(pipe.read(&mut out), Ok(0));
+    }
+
+    #[test]
+    fn write_without_readers_is_epipe() {
+        let mut pipe = open_pipe();
+        pipe.readers = 0;
+        assert_eq!(pipe.write(b"x"), Err(EPIPE));
+    }
+
+    #[test]
+    fn small_writes_are_all_or_nothing() {
+        let mut pipe = open_pipe();
+        let fill = vec![0u8; PIPE_DEFAULT_SIZE - 10];
+        assert_eq!(pipe.write(&fill), Ok(fill.len()));
+        assert_eq!(pipe.write(&[1u8; 20]), Err(EAGAIN));
+        // Past PIPE_BUF a write may be cut short instead
+        assert_eq!(pipe.write(&[1u8; PIPE_BUF + 1]), Ok(10));
+        assert_eq!(pipe.write(&[1u8; PIPE_BUF + 1]), Err(EAGAIN));
+    }
+
+    #[test]
+    fn set_capacity_rounds_up_to_pages() {
+        let mut pipe = open_pipe();
+        assert_eq!(pipe.set_capacity(5000), Ok(2 * PIPE_BUF));
+        assert_eq!(pipe.set_capacity(PIPE_MAX_SIZE + 1), Err(EPERM));
+        assert_eq!(pipe.write(&[0u8; 3 * PIPE_BUF]), Ok(2 * PIPE_BUF));
+        assert_eq!(pipe.write(b"x"), Err(EAGAIN));
+        assert_eq!(pipe.set_capacity(1), Err(EBUSY));
+    }
+}
@@ -0,0 +1,256 @@
+// poll.rs
+#![allow(dead_code)]
+
+// Readiness for poll, select and epoll. `Proc` works out which of the
+// POLL* bits hold for each descriptor; this module has the ABI types
+// and the epoll interest lists built on top of them.
+
+use crate::errno::{EEXIST, EINVAL, ENOENT};
+
+// poll(2) events. EPOLL* bits have the same values.
+pub const POLLIN: i16 = 0x001;
+pub const POLLPRI: i16 = 0x002;
+pub const POLLOUT: i16 = 0x004;
+pub const POLLERR: i16 = 0x008;
+pub const POLLHUP: i16 = 0x010;
+pub const POLLNVAL: i16 = 0x020;
+pub const POLLRDNORM: i16 = 0x040;
+pub const POLLRDBAND: i16 = 0x080;
+pub const POLLWRNORM: i16 = 0x100;
+pub const POLLWRBAND: i16 = 0x200;
+
+// Reported whether asked for or not
+pub const POLL_ALWAYS: i16 = POLLERR | POLLHUP | POLLNVAL;
+
+// select(2) works on bitmaps of FD_SETSIZE descriptors
+pub const FD_SETSIZE: usize = 1024;
+
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLPRI: u32 = 0x002;
+pub const EPOLLOUT: u32 = 0x004;
+pub const EPOLLERR: u32 = 0x008;
+pub const EPOLLHUP: u32 = 0x010;
+pub const EPOLLRDNORM: u32 = 0x040;
+pub const EPOLLRDBAND: u32 = 0x080;
+pub const EPOLLWRNORM: u32 = 0x100;
+pub const EPOLLWRBAND: u32 = 0x200;
+pub const EPOLLRDHUP: u32 = 0x2000;
+pub const EPOLLEXCLUSIVE: u32 = 1 << 28;
+pub const EPOLLWAKEUP: u32 = 1 << 29;
+pub const EPOLLONESHOT: u32 = 1 << 30;
+pub const EPOLLET: u32 = 1 << 31;
+
+pub const EPOLL_CTL_ADD: i32 = 1;
+pub const EPOLL_CTL_DEL: i32 = 2;
+pub const EPOLL_CTL_MOD: i32 = 3;
+
+// Nested epoll descriptors are followed this deep, as on Linux
+pub const EPOLL_MAX_NESTS: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+// struct epoll_event. x86-64 packs it to 12 bytes; everywhere else,
+// wasm32 included, the data field is 8-byte aligned.
+#[cfg_attr(target_arch = "x86_64", repr(C, packed))]
+#[cfg_attr(not(target_arch = "x86_64"), repr(C))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+// Sets the bit of `fd` in a select() bitmap
+pub fn fd_set(set: &mut [u8], fd: usize) {
+    set[fd / 8] |= 1 << (fd % 8);
+}
+
+pub fn fd_isset(set: &[u8], fd: usize) -> bool {
+    set[fd / 8] & (1 << (fd % 8)) != 0
+}
+
+#[derive(Debug, Clone)]
+struct Interest {
+    fd: i32,
+    events: u32,
+    data: u64,
+    // EPOLLONESHOT: cleared once reported, until EPOLL_CTL_MOD
+    armed: bool,
+    // EPOLLET: the bits that held at the last check
+    last: u32,
+}
+
+// One epoll instance: descriptors of interest and how to report them
+#[derive(Debug, Default)]
+pub struct Epoll {
+    interests: Vec<Interest>,
+}
+
+impl Epoll {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ctl(&mut self, op: i32, fd: i32, event: Option<EpollEvent>) -> Result<(), i32> {
+        let index = self.interests.iter().position(|i| i.fd == fd);
+        match op {
+            EPOLL_CTL_ADD => {
+                if index.is_some() {
+                    return Err(EEXIST);
+                }
+                let event = event.ok_or(EINVAL)?;
+                self.interests.push(Interest { fd, events: event.events, data: event.data, armed: true, last: 0 });
+                Ok(())
+            }
+            EPOLL_CTL_MOD => {
+                let event = event.ok_or(EINVAL)?;
+                let interest = &mut self.interests[index.ok_or(ENOENT)?];
+                // An exclusive interest can't be modified, nor one made so
+                if event.events & EPOLLEXCLUSIVE != 0 || interest.events & EPOLLEXCLUSIVE != 0 {
+                    return Err(EINVAL);
+                }
+                *interest = Interest { fd, events: event.events, data: event.data, armed: true, last: 0 };
+                Ok(())
+            }
+            EPOLL_CTL_DEL => {
+                self.interests.remove(index.ok_or(ENOENT)?);
+                Ok(())
+            }
+            _ => Err(EINVAL),
+        }
+    }
+
+    // A descriptor was closed
+    pub fn forget(&mut self, fd: i32) {
+        self.interests.retain(|i| i.fd != fd);
+    }
+
+    pub fn fds(&self) -> Vec<i32> {
+        self.interests.iter().map(|i| i.fd).collect()
+    }
+
+    // Whether any descriptor is ready, without touching edge or
+    // one-shot state; this is the readiness of the epoll fd itself
+    pub fn any_ready(&self, mut ready: impl FnMut(i32) -> u32) -> bool {
+        self.interests.iter().any(|i| i.armed && ready(i.fd) & (i.events | EPOLLERR | EPOLLHUP) != 0)
+    }
+
+    // Fills `out` with ready descriptors. Level-triggered ones are
+    // reported for as long as they are ready; edge-triggered ones when
+    // a bit comes up that was not there at the previous check. Once
+    // `out` is full the rest are left for the next call, and the
+    // level-triggered ones reported move to the back so they don't
+    // crowd the others out.
+    pub fn collect(&mut self, out: &mut [EpollEvent], mut ready: impl FnMut(i32) -> u32) -> usize {
+        let mut n = 0;
+        let mut requeue = Vec::new();
+        for (index, interest) in self.interests.iter_mut().enumerate() {
+            if n == out.len() {
+                break;
+            }
+            if !interest.armed {
+                continue;
+            }
+            let bits = ready(interest.fd) & (interest.events | EPOLLERR | EPOLLHUP);
+            if interest.events & EPOLLET != 0 {
+                let rising = bits & !interest.last;
+                interest.last = bits;
+                if rising == 0 {
+                    continue;
+                }
+            } else if bits == 0 {
+                continue;
+            }
+            if interest.events & EPOLLONESHOT != 0 {
+                interest.armed = false;
+            }
+            out[n] = EpollEvent { events: bits, data: interest.data };
+            n += 1;
+            if interest.events & (EPOLLET | EPOLLONESHOT) == 0 {
+                requeue.push(index);
+            }
+        }
+        let moved: Vec<Interest> = requeue.into_iter().rev().map(|index| self.interests.remove(index)).collect();
+        self.interests.extend(moved.into_iter().rev());
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(events: u32, data: u64) -> Option<EpollEvent> {
+        Some(EpollEvent { events, data })
+    }
+
+    fn data(out: &[EpollEvent]) -> Vec<u64> {
+        out.iter().map(|e| e.data).collect()
+    }
+
+    #[test]
+    fn ctl_checks_membership() {
+        let mut epoll = Epoll::new();
+        assert_eq!(epoll.ctl(EPOLL_CTL_ADD, 3, None), Err(EINVAL));
+        assert_eq!(epoll.ctl(EPOLL_CTL_MOD, 3, event(EPOLLIN, 0)), Err(ENOENT));
+        assert_eq!(epoll.ctl(EPOLL_CTL_DEL, 3, None), Err(ENOENT));
+        assert_eq!(epoll.ctl(EPOLL_CTL_ADD, 3, event(EPOLLIN | EPOLLEXCLUSIVE, 0)), Ok(()));
+        assert_eq!(epoll.ctl(EPOLL_CTL_ADD, 3, event(EPOLLIN, 0)), Err(EEXIST));
+        assert_eq!(epoll.ctl(EPOLL_CTL_MOD, 3, event(EPOLLIN, 0)), Err(EINVAL));
+        assert_eq!(epoll.ctl(9, 3, event(EPOLLIN, 0)), Err(EINVAL));
+        assert_eq!(epoll.ctl(EPOLL_CTL_ADD, 4, event(EPOLLIN, 0)), Ok(()));
+        epoll.forget(3);
+        assert_eq!(epoll.fds(), [4]);
+    }
+
+    #[test]
+    fn level_edge_and_oneshot() {
+        let mut epoll = Epoll::new();
+        epoll.ctl(EPOLL_CTL_ADD, 1, event(EPOLLIN, 1)).unwrap();
+        epoll.ctl(EPOLL_CTL_ADD, 2, event(EPOLLIN | EPOLLET, 2)).unwrap();
+        epoll.ctl(EPOLL_CTL_ADD, 3, event(EPOLLIN | EPOLLONESHOT, 3)).unwrap();
+        let mut out = [EpollEvent::default(); 4];
+        let all_in = |_| EPOLLIN;
+        assert_eq!(epoll.collect(&mut out, all_in), 3);
+        assert_eq!(data(&out[..3]), [1, 2, 3]);
+        assert_eq!(epoll.collect(&mut out, all_in), 1);
+        assert_eq!(data(&out[..1]), [1]);
+        assert!(epoll.any_ready(all_in));
+        // the edge rises again once the bit has dropped
+        assert_eq!(epoll.collect(&mut out, |_| 0), 0);
+        assert_eq!(epoll.collect(&mut out, all_in), 2);
+        assert_eq!(data(&out[..2]), [2, 1]);
+        // EPOLL_CTL_MOD rearms a one-shot interest
+        epoll.ctl(EPOLL_CTL_MOD, 3, event(EPOLLIN | EPOLLONESHOT, 3)).unwrap();
+        assert_eq!(epoll.collect(&mut out, |fd| if fd == 3 { EPOLLIN } else { 0 }), 1);
+        assert!(!epoll.any_ready(|fd| if fd == 3 { EPOLLIN } else { 0 }));
+    }
+
+    #[test]
+    fn hangups_are_reported_unasked_and_full_buffers_rotate() {
+        let mut epoll = Epoll::new();
+        epoll.ctl(EPOLL_CTL_ADD, 1, event(EPOLLOUT, 1)).unwrap();
+        epoll.ctl(EPOLL_CTL_ADD, 2, event(EPOLLOUT, 2)).unwrap();
+        let mut one = [EpollEvent::default(); 1];
+        assert_eq!(epoll.collect(&mut one, |_| EPOLLIN | EPOLLHUP), 1);
+        let events = one[0].events;
+        assert_eq!(events, EPOLLHUP);
+        assert_eq!(data(&one), [1]);
+        assert_eq!(epoll.collect(&mut one, |_| EPOLLOUT), 1);
+        assert_eq!(data(&one), [2]);
+    }
+
+    #[test]
+    fn fd_sets_are_bitmaps() {
+        let mut set = [0u8; FD_SETSIZE / 8];
+        fd_set(&mut set, 9);
+        assert_eq!(set[1], 0b10);
+        assert!(fd_isset(&set, 9));
+        assert!(!fd_isset(&set, 8));
+    }
+}
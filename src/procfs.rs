@@ -0,0 +1,90 @@
+// procfs.rs
+#![allow(dead_code)]
+
+// Text of the synthetic files under /proc. The entries themselves are
+// rebuilt by `Proc` from its own state each time a /proc path is used.
+
+use crate::filesystem::Mount;
+use crate::path::PathBuf;
+
+pub const PROC_MOUNT: &str = "/proc";
+
+// A Proc is the only process there is: pid 1 with no parent
+pub const PID: u32 = 1;
+pub const PPID: u32 = 0;
+
+pub fn is_proc_path(path: &PathBuf) -> bool {
+    let path = path.to_string_lossy();
+    path == PROC_MOUNT || path.starts_with("/proc/")
+}
+
+// /proc/mounts, one `source target fstype options 0 0` line per mount
+pub fn mounts(mounts: &[Mount]) -> String {
+    let mut out = String::new();
+    for mount in mounts {
+        out.push_str(&format!(
+            "{} {} {} {} 0 0\n",
+            mount.source,
+            mount.target.to_string_lossy(),
+            mount.fstype,
+            mount.options
+        ));
+    }
+    out
+}
+
+// What /proc/self/status reports
+pub struct Status {
+    pub umask: u32,
+    pub uid: u32,
+    pub gid: u32,
+    // Size of the fd table
+    pub fd_size: usize,
+}
+
+// The subset of Linux's /proc/self/status that is meaningful here, in
+// the same `Key:\tvalue` layout
+pub fn status(status: &Status) -> String {
+    let uid = status.uid;
+    let gid = status.gid;
+    format!(
+        "Name:\twasm-vfs\n\
+         Umask:\t{:04o}\n\
+         State:\tR (running)\n\
+         Tgid:\t{}\n\
+         Pid:\t{}\n\
+         PPid:\t{}\n\
+         Uid:\t{}\t{}\t{}\t{}\n\
+         Gid:\t{}\t{}\t{}\t{}\n\
+         FDSize:\t{}\n",
+        status.umask,
+        PID,
+        PID,
+        PPID,
+        uid, uid, uid, uid,
+        gid, gid, gid, gid,
+        status.fd_size
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_paths_under_the_mount_are_proc_paths() {
+        assert!(is_proc_path(&PathBuf::from("/proc")));
+        assert!(is_proc_path(&PathBuf::from("/proc/self/fd/3")));
+        assert!(!is_proc_path(&PathBuf::from("/processes")));
+        assert!(!is_proc_path(&PathBuf::from("/")));
+    }
+
+    #[test]
+    fn status_uses_the_linux_layout() {
+        let text = status(&Status { umask: 0o27, uid: 1000, gid: 100, fd_size: 1024 });
+        assert!(text.contains("Umask:\t0027\n"), "{}", text);
+        assert!(text.contains("Uid:\t1000\t1000\t1000\t1000\n"), "{}", text);
+        assert!(text.contains("Gid:\t100\t100\t100\t100\n"), "{}", text);
+        assert!(text.contains("FDSize:\t1024\n"), "{}", text);
+    }
+}
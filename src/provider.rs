@@ -0,0 +1,125 @@
+// provider.rs
+#![allow(dead_code)]
+
+use core::fmt;
+
+use crate::errno::{EACCES, EINVAL};
+
+// Content for a regular file that is produced on demand instead of
+// being stored in `FileSystem::files`, in the spirit of sysfs
+// attributes. Offsets are absolute; errors are errno values. Called
+// with the process lock held; see "Re-entrancy" on `get_or_init_proc`.
+pub trait FileProvider {
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize, i32>;
+
+    // Default: the file is read-only
+    fn write_at(&mut self, _data: &[u8], _offset: u64) -> Result<usize, i32> {
+        Err(EACCES)
+    }
+
+    // Reported as st_size and used for SEEK_END and O_APPEND
+    fn size(&self) -> u64;
+
+    fn truncate(&mut self, _len: u64) -> Result<(), i32> {
+        Err(EINVAL)
+    }
+}
+
+impl fmt::Debug for dyn FileProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FileProvider")
+    }
+}
+
+// Serves a provider-backed file from an immutable byte buffer. Handy
+// for content that is computed once, and as a reference implementation.
+pub struct StaticProvider {
+    data: Vec<u8>,
+}
+
+impl StaticProvider {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+}
+
+impl FileProvider for StaticProvider {
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize, i32> {
+        let offset = offset as usize;
+        if offset >= self.data.len() {
+            return Ok(0);
+        }
+        let n = buf.len().min(self.data.len() - offset);
+        buf[..n].copy_from_slice(&self.data[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn size(&self) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+#[cfg(feature = "host-files")]
+#[link(wasm_import_module = "env")]
+extern "C" {
+    /// Host functions behind files registered with
+    /// `wasm_vfs_register_host_file`. `id` is the value passed at
+    /// registration. Byte counts are returned as-is and failures as a
+    /// negated errno.
+    fn box_host_file_read(id: u32, buf: *mut u8, len: usize, offset: u64) -> isize;
+    fn box_host_file_write(id: u32, buf: *const u8, len: usize, offset: u64) -> isize;
+    fn box_host_file_size(id: u32) -> u64;
+    fn box_host_file_truncate(id: u32, len: u64) -> i32;
+}
+
+// A provider whose every hook is a call to the host
+#[cfg(feature = "host-files")]
+pub struct HostProvider {
+    id: u32,
+}
+
+#[cfg(feature = "host-files")]
+impl HostProvider {
+    pub fn new(id: u32) -> Self {
+        Self { id }
+    }
+}
+
+#[cfg(feature = "host-files")]
+impl FileProvider for HostProvider {
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize, i32> {
+        let n = unsafe { box_host_file_read(self.id, buf.as_mut_ptr(), buf.len(), offset) };
+        if n < 0 { Err(-n as i32) } else { Ok(n as usize) }
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> Result<usize, i32> {
+        let n = unsafe { box_host_file_write(self.id, data.as_ptr(), data.len(), offset) };
+        if n < 0 { Err(-n as i32) } else { Ok(n as usize) }
+    }
+
+    fn size(&self) -> u64 {
+        unsafe { box_host_file_size(self.id) }
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), i32> {
+        let rc = unsafe { box_host_file_truncate(self.id, len) };
+        if rc < 0 { Err(-rc) } else { Ok(()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn static_provider_reads_and_refuses_changes() {
+        let mut provider = StaticProvider::new(b"abc\n".to_vec());
+        let mut buf = [0u8; 8];
+        assert_eq!(provider.read_at(&mut buf, 2), Ok(2));
+        assert_eq!(&buf[..2], b"c\n");
+        assert_eq!(provider.read_at(&mut buf, 9), Ok(0));
+        assert_eq!(provider.size(), 4);
+        assert_eq!(provider.write_at(b"x", 0), Err(EACCES));
+        assert_eq!(provider.truncate(0), Err(EINVAL));
+    }
+}
@@ -0,0 +1,430 @@
+// quota.rs
+#![allow(dead_code)]
+
+// Limits on how much a user, a group or a project (a directory
+// subtree) may store, and on the filesystem as a whole. Usage is kept
+// in a Ledger: each inode's charge is replaced whenever its size, owner
+// or names change, so checking a limit never walks the inode table.
+
+use crate::collections::HashMap;
+use crate::errno::EINVAL;
+use crate::path::PathBuf;
+
+// Quota types, as in Linux's include/uapi/linux/quota.h
+pub const USRQUOTA: i32 = 0;
+pub const GRPQUOTA: i32 = 1;
+pub const PRJQUOTA: i32 = 2;
+
+// quotactl() subcommands
+pub const Q_SYNC: i32 = 0x800001;
+pub const Q_GETQUOTA: i32 = 0x800007;
+pub const Q_SETQUOTA: i32 = 0x800008;
+
+pub const SUBCMDSHIFT: i32 = 8;
+pub const SUBCMDMASK: i32 = 0x00ff;
+
+// Which fields of a Dqblk are meaningful
+pub const QIF_BLIMITS: u32 = 1;
+pub const QIF_SPACE: u32 = 2;
+pub const QIF_ILIMITS: u32 = 4;
+pub const QIF_INODES: u32 = 8;
+pub const QIF_BTIME: u32 = 16;
+pub const QIF_ITIME: u32 = 32;
+pub const QIF_LIMITS: u32 = QIF_BLIMITS | QIF_ILIMITS;
+pub const QIF_USAGE: u32 = QIF_SPACE | QIF_INODES;
+
+// Block limits in a Dqblk are in units of this many bytes
+pub const QIF_DQBLKSIZE: u64 = 1024;
+
+const QUOTAS_CAP: usize = 64;
+const PROJECTS_CAP: usize = 64;
+
+pub fn qcmd(cmd: i32, kind: i32) -> i32 {
+    (cmd << SUBCMDSHIFT) | (kind & SUBCMDMASK)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum QuotaId {
+    User(u32),
+    Group(u32),
+    Project(u32),
+}
+
+impl QuotaId {
+    pub fn new(kind: i32, id: u32) -> Result<Self, i32> {
+        match kind {
+            USRQUOTA => Ok(QuotaId::User(id)),
+            GRPQUOTA => Ok(QuotaId::Group(id)),
+            PRJQUOTA => Ok(QuotaId::Project(id)),
+            _ => Err(EINVAL),
+        }
+    }
+}
+
+// Hard limits; None is no limit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub bytes: Option<u64>,
+    pub inodes: Option<u64>,
+}
+
+impl Limits {
+    // Whether `usage` grown by `bytes` and `inodes` goes over a limit.
+    // Only growth is refused, so anything can still shrink after a
+    // limit is lowered below current usage.
+    pub fn exceeded(&self, usage: Usage, bytes: u64, inodes: u64) -> bool {
+        let over = |limit: Option<u64>, used: u64, more: u64| {
+            more > 0 && limit.is_some_and(|limit| used.saturating_add(more) > limit)
+        };
+        over(self.bytes, usage.bytes, bytes) || over(self.inodes, usage.inodes, inodes)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub bytes: u64,
+    pub inodes: u64,
+}
+
+impl Usage {
+    pub fn add(&mut self, bytes: u64, inodes: u64) {
+        self.bytes += bytes;
+        self.inodes += inodes;
+    }
+
+    pub fn sub(&mut self, bytes: u64, inodes: u64) {
+        self.bytes = self.bytes.saturating_sub(bytes);
+        self.inodes = self.inodes.saturating_sub(inodes);
+    }
+}
+
+// What one inode counts toward: its allocated bytes and the inode
+// itself, for its owner, its group, the mount of its first name and the
+// projects holding that name
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Charge {
+    pub bytes: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub mount: usize,
+    pub projects: Vec<u32>,
+}
+
+impl Charge {
+    fn counts_for(&self, id: QuotaId) -> bool {
+        match id {
+            QuotaId::User(uid) => self.uid == uid,
+            QuotaId::Group(gid) => self.gid == gid,
+            QuotaId::Project(project) => self.projects.contains(&project),
+        }
+    }
+}
+
+// Running usage totals, indexed by inode number. Totals per user, group
+// or project are only kept for ids that are tracked (those with limits);
+// any other id is summed from the charges when asked.
+#[derive(Debug)]
+pub struct Ledger {
+    charges: Vec<Option<Charge>>,
+    total: Usage,
+    mounts: Vec<Usage>,
+    ids: HashMap<QuotaId, Usage, QUOTAS_CAP>,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self {
+            charges: Vec::new(),
+            total: Usage::default(),
+            mounts: Vec::new(),
+            ids: HashMap::init(),
+        }
+    }
+
+    pub fn charge(&self, inode_number: u64) -> Option<&Charge> {
+        self.charges.get(inode_number as usize).and_then(|c| c.as_ref())
+    }
+
+    // Replaces what `inode_number` counts toward; None uncharges it
+    pub fn set(&mut self, inode_number: u64, charge: Option<Charge>) {
+        let index = inode_number as usize;
+        if self.charges.len() <= index {
+            if charge.is_none() {
+                return;
+            }
+            self.charges.resize(index + 1, None);
+        }
+        if let Some(old) = self.charges[index].take() {
+            self.apply(&old, Usage::sub);
+        }
+        if let Some(new) = &charge {
+            self.apply(new, Usage::add);
+        }
+        self.charges[index] = charge;
+    }
+
+    fn apply(&mut self, charge: &Charge, op: fn(&mut Usage, u64, u64)) {
+        op(&mut self.total, charge.bytes, 1);
+        if self.mounts.len() <= charge.mount {
+            self.mounts.resize(charge.mount + 1, Usage::default());
+        }
+        op(&mut self.mounts[charge.mount], charge.bytes, 1);
+        let tracked: Vec<QuotaId> = self.ids.iter()
+            .map(|(id, _)| *id)
+            .filter(|id| charge.counts_for(*id))
+            .collect();
+        for id in tracked {
+            if let Some(usage) = self.ids.get_mut(&id) {
+                op(usage, charge.bytes, 1);
+            }
+        }
+    }
+
+    pub fn total(&self) -> Usage {
+        self.total
+    }
+
+    pub fn mount(&self, index: usize) -> Usage {
+        self.mounts.get(index).copied().unwrap_or_default()
+    }
+
+    pub fn id(&self, id: QuotaId) -> Usage {
+        if let Some(usage) = self.ids.get(&id) {
+            return *usage;
+        }
+        let mut usage = Usage::default();
+        for charge in self.charges.iter().flatten().filter(|c| c.counts_for(id)) {
+            usage.add(charge.bytes, 1);
+        }
+        usage
+    }
+
+    // Starts keeping a running total for `id`
+    pub fn track(&mut self, id: QuotaId) {
+        if !self.ids.contains_key(&id) {
+            let usage = self.id(id);
+            self.ids.insert(id, usage);
+        }
+    }
+
+    pub fn untrack(&mut self, id: QuotaId) {
+        self.ids.remove(&id);
+    }
+
+    // Forgets every charge; tracked ids stay tracked, at zero
+    pub fn clear(&mut self) {
+        self.charges.clear();
+        self.total = Usage::default();
+        self.mounts.clear();
+        let tracked: Vec<QuotaId> = self.ids.iter().map(|(id, _)| *id).collect();
+        for id in tracked {
+            if let Some(usage) = self.ids.get_mut(&id) {
+                *usage = Usage::default();
+            }
+        }
+    }
+}
+
+// Limits for one FileSystem. `total` going over is ENOSPC; the others
+// are EDQUOT.
+#[derive(Debug)]
+pub struct Quotas {
+    pub total: Limits,
+    limits: HashMap<QuotaId, Limits, QUOTAS_CAP>,
+    // Project id by the root of its subtree
+    projects: HashMap<u32, PathBuf, PROJECTS_CAP>,
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Quotas {
+    pub fn new() -> Self {
+        Self {
+            total: Limits::default(),
+            limits: HashMap::init(),
+            projects: HashMap::init(),
+        }
+    }
+
+    pub fn get(&self, id: QuotaId) -> Limits {
+        self.limits.get(&id).copied().unwrap_or_default()
+    }
+
+    // Setting no limits at all forgets the entry
+    pub fn set(&mut self, id: QuotaId, limits: Limits) {
+        if limits == Limits::default() {
+            self.limits.remove(&id);
+        } else {
+            self.limits.insert(id, limits);
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        self.limits.iter().next().is_some()
+    }
+
+    pub fn set_project(&mut self, project: u32, root: PathBuf) {
+        self.projects.insert(project, root);
+    }
+
+    pub fn remove_project(&mut self, project: u32) -> Option<PathBuf> {
+        self.projects.remove(&project)
+    }
+
+    pub fn project_root(&self, project: u32) -> Option<&PathBuf> {
+        self.projects.get(&project)
+    }
+
+    // Projects whose subtree holds `path`; they may nest
+    pub fn projects_of(&self, path: &PathBuf) -> Vec<u32> {
+        self.projects.iter()
+            .filter(|(_, root)| path.starts_with(root))
+            .map(|(project, _)| *project)
+            .collect()
+    }
+}
+
+// struct if_dqblk, what Q_GETQUOTA fills in and Q_SETQUOTA reads. Soft
+// limits and grace times are not kept: they read back as 0 and are
+// ignored when set.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dqblk {
+    pub dqb_bhardlimit: u64,
+    pub dqb_bsoftlimit: u64,
+    pub dqb_curspace: u64,
+    pub dqb_ihardlimit: u64,
+    pub dqb_isoftlimit: u64,
+    pub dqb_curinodes: u64,
+    pub dqb_btime: u64,
+    pub dqb_itime: u64,
+    pub dqb_valid: u32,
+}
+
+impl Dqblk {
+    pub fn new(limits: Limits, usage: Usage) -> Self {
+        Self {
+            dqb_bhardlimit: limits.bytes.map(|b| b.div_ceil(QIF_DQBLKSIZE)).unwrap_or(0),
+            dqb_curspace: usage.bytes,
+            dqb_ihardlimit: limits.inodes.unwrap_or(0),
+            dqb_curinodes: usage.inodes,
+            dqb_valid: QIF_LIMITS | QIF_USAGE,
+            ..Self::default()
+        }
+    }
+
+    // Applies the limits this block marks valid on top of `limits`; a
+    // limit of 0 removes it
+    pub fn apply(&self, mut limits: Limits) -> Limits {
+        let limit = |value: u64| if value == 0 { None } else { Some(value) };
+        if self.dqb_valid & QIF_BLIMITS != 0 {
+            limits.bytes = limit(self.dqb_bhardlimit.saturating_mul(QIF_DQBLKSIZE));
+        }
+        if self.dqb_valid & QIF_ILIMITS != 0 {
+            limits.inodes = limit(self.dqb_ihardlimit);
+        }
+        limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charge(bytes: u64, uid: u32, projects: &[u32]) -> Option<Charge> {
+        Some(Charge { bytes, uid, gid: 0, mount: 1, projects: projects.to_vec() })
+    }
+
+    #[test]
+    fn ids_and_commands() {
+        assert_eq!(QuotaId::new(USRQUOTA, 5), Ok(QuotaId::User(5)));
+        assert_eq!(QuotaId::new(GRPQUOTA, 5), Ok(QuotaId::Group(5)));
+        assert_eq!(QuotaId::new(PRJQUOTA, 5), Ok(QuotaId::Project(5)));
+        assert_eq!(QuotaId::new(3, 5), Err(EINVAL));
+        let cmd = qcmd(Q_GETQUOTA, GRPQUOTA);
+        assert_eq!(((cmd as u32) >> SUBCMDSHIFT) as i32, Q_GETQUOTA);
+        assert_eq!(cmd & SUBCMDMASK, GRPQUOTA);
+    }
+
+    #[test]
+    fn only_growth_can_exceed_a_limit() {
+        let limits = Limits { bytes: Some(100), inodes: Some(2) };
+        let usage = Usage { bytes: 90, inodes: 2 };
+        assert!(!limits.exceeded(usage, 10, 0));
+        assert!(limits.exceeded(usage, 11, 0));
+        assert!(limits.exceeded(usage, 0, 1));
+        // already over, but nothing more is asked for
+        assert!(!limits.exceeded(Usage { bytes: 500, inodes: 9 }, 0, 0));
+        assert!(!Limits::default().exceeded(usage, u64::MAX, u64::MAX));
+        let mut usage = Usage::default();
+        usage.add(5, 1);
+        usage.sub(10, 2);
+        assert_eq!(usage, Usage::default());
+    }
+
+    #[test]
+    fn the_ledger_keeps_running_totals() {
+        let mut ledger = Ledger::new();
+        ledger.set(3, charge(100, 7, &[1]));
+        ledger.set(5, charge(50, 8, &[]));
+        assert_eq!(ledger.total(), Usage { bytes: 150, inodes: 2 });
+        assert_eq!(ledger.mount(1), Usage { bytes: 150, inodes: 2 });
+        assert_eq!(ledger.mount(9), Usage::default());
+        // untracked ids are summed on demand, tracked ones kept up
+        assert_eq!(ledger.id(QuotaId::User(7)), Usage { bytes: 100, inodes: 1 });
+        ledger.track(QuotaId::Project(1));
+        ledger.set(3, charge(300, 7, &[1]));
+        ledger.set(6, charge(1, 9, &[1]));
+        assert_eq!(ledger.id(QuotaId::Project(1)), Usage { bytes: 301, inodes: 2 });
+        ledger.set(3, None);
+        assert_eq!(ledger.charge(3), None);
+        assert_eq!(ledger.id(QuotaId::Project(1)), Usage { bytes: 1, inodes: 1 });
+        assert_eq!(ledger.total(), Usage { bytes: 51, inodes: 2 });
+        // uncharging something never charged is a no-op
+        ledger.set(1000, None);
+        ledger.clear();
+        assert_eq!(ledger.total(), Usage::default());
+        assert_eq!(ledger.id(QuotaId::Project(1)), Usage::default());
+        ledger.untrack(QuotaId::Project(1));
+    }
+
+    #[test]
+    fn projects_nest_and_empty_limits_are_forgotten() {
+        let mut quotas = Quotas::new();
+        assert!(!quotas.any());
+        quotas.set(QuotaId::User(1), Limits { bytes: Some(1), inodes: None });
+        assert!(quotas.any());
+        quotas.set(QuotaId::User(1), Limits::default());
+        assert!(!quotas.any());
+        quotas.set_project(1, PathBuf::from("/a"));
+        quotas.set_project(2, PathBuf::from("/a/b"));
+        let mut projects = quotas.projects_of(&PathBuf::from("/a/b/c"));
+        projects.sort_unstable();
+        assert_eq!(projects, [1, 2]);
+        assert!(quotas.projects_of(&PathBuf::from("/ab")).is_empty());
+        assert_eq!(quotas.remove_project(2), Some(PathBuf::from("/a/b")));
+        assert_eq!(quotas.project_root(2), None);
+    }
+
+    #[test]
+    fn dqblk_round_trips_limits() {
+        let limits = Limits { bytes: Some(1500), inodes: Some(4) };
+        let block = Dqblk::new(limits, Usage { bytes: 10, inodes: 1 });
+        assert_eq!((block.dqb_bhardlimit, block.dqb_curspace, block.dqb_ihardlimit), (2, 10, 4));
+        assert_eq!(block.dqb_valid, QIF_LIMITS | QIF_USAGE);
+        let only_inodes = Dqblk { dqb_ihardlimit: 0, dqb_valid: QIF_ILIMITS, ..block };
+        assert_eq!(only_inodes.apply(limits), Limits { bytes: Some(1500), inodes: None });
+        let only_blocks = Dqblk { dqb_bhardlimit: 3, dqb_valid: QIF_BLIMITS, ..block };
+        assert_eq!(only_blocks.apply(limits), Limits { bytes: Some(3072), inodes: Some(4) });
+    }
+}
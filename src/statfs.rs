@@ -0,0 +1,204 @@
+// statfs.rs
+#![allow(dead_code)]
+
+// Filesystem-wide numbers for statfs(2) and statvfs(3): how much each
+// mount holds and how much it can take.
+
+use crate::path::PathBuf;
+
+// f_type magic numbers, from Linux's include/uapi/linux/magic.h. The
+// root filesystem and devtmpfs keep everything in memory, so they
+// report tmpfs like their Linux counterparts.
+pub const TMPFS_MAGIC: u64 = 0x0102_1994;
+pub const PROC_SUPER_MAGIC: u64 = 0x9fa0;
+pub const SYSFS_MAGIC: u64 = 0x6265_6572;
+
+// Mount flags in f_flags / f_flag
+pub const ST_RDONLY: u64 = 0x1;
+pub const ST_NOSUID: u64 = 0x2;
+pub const ST_NODEV: u64 = 0x4;
+pub const ST_NOEXEC: u64 = 0x8;
+// statfs() sets this to say f_flags is filled in
+pub const ST_VALID: u64 = 0x20;
+
+// Longest file name component
+pub const NAME_MAX: u64 = 255;
+
+// Capacity of a mount without a byte limit: what a wasm32 guest can
+// address
+pub const DEFAULT_CAPACITY_BYTES: u64 = 1 << 32;
+
+pub fn magic_of(fstype: &str) -> u64 {
+    match fstype {
+        "proc" => PROC_SUPER_MAGIC,
+        "sysfs" => SYSFS_MAGIC,
+        _ => TMPFS_MAGIC,
+    }
+}
+
+// ST_* bits for a mount's option string
+pub fn flags_of(options: &str) -> u64 {
+    options.split(',').fold(0, |flags, option| {
+        flags | match option {
+            "ro" => ST_RDONLY,
+            "nosuid" => ST_NOSUID,
+            "nodev" => ST_NODEV,
+            "noexec" => ST_NOEXEC,
+            _ => 0,
+        }
+    })
+}
+
+// What one mount holds and can hold, in bytes and inodes. Pseudo
+// filesystems such as proc report all zeros, as on Linux.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountUsage {
+    pub target: PathBuf,
+    pub fstype: String,
+    // Index in the mount table, reported as f_fsid
+    pub id: u32,
+    pub block_size: u64,
+    pub bytes_used: u64,
+    pub bytes_total: u64,
+    pub inodes_used: u64,
+    pub inodes_total: u64,
+    // ST_* flags
+    pub flags: u64,
+}
+
+impl MountUsage {
+    pub fn bytes_free(&self) -> u64 {
+        self.bytes_total.saturating_sub(self.bytes_used)
+    }
+
+    pub fn inodes_free(&self) -> u64 {
+        self.inodes_total.saturating_sub(self.inodes_used)
+    }
+
+    pub fn to_statfs(&self) -> Statfs {
+        let bsize = self.block_size;
+        Statfs {
+            f_type: magic_of(&self.fstype) as usize,
+            f_bsize: bsize as usize,
+            f_blocks: self.bytes_total / bsize,
+            f_bfree: self.bytes_free() / bsize,
+            f_bavail: self.bytes_free() / bsize,
+            f_files: self.inodes_total,
+            f_ffree: self.inodes_free(),
+            f_fsid: [self.id as i32, 0],
+            f_namelen: NAME_MAX as usize,
+            f_frsize: bsize as usize,
+            f_flags: (self.flags | ST_VALID) as usize,
+            f_spare: [0; 4],
+        }
+    }
+
+    pub fn to_statvfs(&self) -> Statvfs {
+        let bsize = self.block_size;
+        Statvfs {
+            f_bsize: bsize as usize,
+            f_frsize: bsize as usize,
+            f_blocks: self.bytes_total / bsize,
+            f_bfree: self.bytes_free() / bsize,
+            f_bavail: self.bytes_free() / bsize,
+            f_files: self.inodes_total,
+            f_ffree: self.inodes_free(),
+            f_favail: self.inodes_free(),
+            f_fsid: self.id as usize,
+            #[cfg(target_pointer_width = "32")]
+            f_fsid_pad: 0,
+            f_flag: self.flags as usize,
+            f_namemax: NAME_MAX as usize,
+            f_type: magic_of(&self.fstype) as u32,
+            f_reserved: [0; 5],
+        }
+    }
+}
+
+// struct statfs as in musl and wasi-libc: the `unsigned long` fields
+// follow the pointer width, block and file counts are 64-bit
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Statfs {
+    pub f_type: usize,
+    pub f_bsize: usize,
+    pub f_blocks: u64,
+    pub f_bfree: u64,
+    pub f_bavail: u64,
+    pub f_files: u64,
+    pub f_ffree: u64,
+    pub f_fsid: [i32; 2],
+    pub f_namelen: usize,
+    pub f_frsize: usize,
+    pub f_flags: usize,
+    pub f_spare: [usize; 4],
+}
+
+// struct statvfs, same conventions. musl pads f_fsid out to two ints
+// where long is 32-bit.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Statvfs {
+    pub f_bsize: usize,
+    pub f_frsize: usize,
+    pub f_blocks: u64,
+    pub f_bfree: u64,
+    pub f_bavail: u64,
+    pub f_files: u64,
+    pub f_ffree: u64,
+    pub f_favail: u64,
+    pub f_fsid: usize,
+    #[cfg(target_pointer_width = "32")]
+    pub f_fsid_pad: u32,
+    pub f_flag: usize,
+    pub f_namemax: usize,
+    pub f_type: u32,
+    pub f_reserved: [i32; 5],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage() -> MountUsage {
+        MountUsage {
+            target: PathBuf::from("/"),
+            fstype: "tmpfs".to_string(),
+            id: 3,
+            block_size: 4096,
+            bytes_used: 3 * 4096 + 1,
+            bytes_total: 10 * 4096,
+            inodes_used: 7,
+            inodes_total: 5,
+            flags: ST_NOSUID,
+        }
+    }
+
+    #[test]
+    fn magic_and_flags_come_from_the_mount_table() {
+        assert_eq!(magic_of("proc"), PROC_SUPER_MAGIC);
+        assert_eq!(magic_of("sysfs"), SYSFS_MAGIC);
+        assert_eq!(magic_of("devtmpfs"), TMPFS_MAGIC);
+        assert_eq!(flags_of("rw,nosuid,nodev,relatime"), ST_NOSUID | ST_NODEV);
+        assert_eq!(flags_of("ro,noexec"), ST_RDONLY | ST_NOEXEC);
+        assert_eq!(flags_of(""), 0);
+    }
+
+    #[test]
+    fn usage_converts_to_blocks() {
+        let u = usage();
+        // a partly used block is not free, and overuse is not negative
+        assert_eq!(u.bytes_free(), 7 * 4096 - 1);
+        assert_eq!(u.inodes_free(), 0);
+        let s = u.to_statfs();
+        assert_eq!((s.f_blocks, s.f_bfree, s.f_bavail), (10, 6, 6));
+        assert_eq!((s.f_files, s.f_ffree), (5, 0));
+        assert_eq!(s.f_fsid, [3, 0]);
+        assert_eq!(s.f_flags as u64, ST_NOSUID | ST_VALID);
+        let v = u.to_statvfs();
+        assert_eq!((v.f_blocks, v.f_bfree, v.f_favail), (10, 6, 0));
+        assert_eq!(v.f_fsid, 3);
+        assert_eq!(v.f_flag as u64, ST_NOSUID);
+        assert_eq!(v.f_type as u64, TMPFS_MAGIC);
+    }
+}
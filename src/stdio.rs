@@ -0,0 +1,273 @@
+// stdio.rs
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::collections::ByteQueue;
+
+// Size at which a fully buffered stream flushes on its own, like BUFSIZ
+pub const STDIO_BUFSIZ: usize = 4096;
+
+// Where bytes written to (or read from) a standard stream end up.
+// Errors are errno values, the same as the rest of the crate. Called
+// with the process lock held; see "Re-entrancy" on `get_or_init_proc`.
+pub trait Stream {
+    fn write(&mut self, data: &[u8]) -> Result<usize, i32>;
+
+    // Default: nothing to read, so every read is end-of-file
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, i32> {
+        Ok(0)
+    }
+
+    fn flush(&mut self) -> Result<(), i32> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferMode {
+    // Every write goes straight to the stream
+    Unbuffered,
+    // Data is held until a newline (or BUFSIZ bytes) arrives
+    Line,
+    // Data is held until BUFSIZ bytes are pending or a flush
+    Full,
+}
+
+impl BufferMode {
+    // 0/1/2, the same numbering as _IONBF/_IOLBF/_IOFBF in musl
+    pub fn from_raw(raw: i32) -> Option<Self> {
+        match raw {
+            0 => Some(BufferMode::Full),
+            1 => Some(BufferMode::Line),
+            2 => Some(BufferMode::Unbuffered),
+            _ => None,
+        }
+    }
+}
+
+// Drops all output; reads hit end-of-file
+pub struct DiscardStream;
+
+impl Stream for DiscardStream {
+    fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        Ok(data.len())
+    }
+}
+
+// In-memory stream. Output is collected for the embedder to inspect,
+// and reads are served from bytes queued with `push_input`. Clones
+// share the same buffers, so keep one around after handing the other
+// to `Proc::set_stdio`.
+#[derive(Clone, Default)]
+pub struct CaptureStream {
+    inner: Rc<RefCell<CaptureBuffers>>,
+}
+
+#[derive(Default)]
+struct CaptureBuffers {
+    output: Vec<u8>,
+    input: ByteQueue,
+}
+
+impl CaptureStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.inner.borrow_mut().input.push_slice(bytes);
+    }
+
+    // Everything written so far
+    pub fn output(&self) -> Vec<u8> {
+        self.inner.borrow().output.clone()
+    }
+
+    // Returns and clears the captured output
+    pub fn take_output(&self) -> Vec<u8> {
+        core::mem::take(&mut self.inner.borrow_mut().output)
+    }
+}
+
+impl Stream for CaptureStream {
+    fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        self.inner.borrow_mut().output.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, i32> {
+        Ok(self.inner.borrow_mut().input.pop_into(buf))
+    }
+}
+
+#[cfg(feature = "box-host-stdout")]
+#[link(wasm_import_module = "env")]
+extern "C" {
+    /// Host function that receives a single line (including the trailing newline).
+    /// The host environment can print it in xterm or a console, line by line.
+    fn box_host_write_stdout_line(ptr: *const u8, len: usize);
+}
+
+// Native test builds have no host to import from
+#[cfg(all(test, feature = "box-host-stdout"))]
+mod host {
+    #[no_mangle]
+    extern "C" fn box_host_write_stdout_line(_ptr: *const u8, _len: usize) {}
+}
+
+// Hands output to the `box_host_write_stdout_line` import. The import
+// expects whole lines, so pair it with BufferMode::Line.
+#[cfg(feature = "box-host-stdout")]
+pub struct HostStream;
+
+#[cfg(feature = "box-host-stdout")]
+impl Stream for HostStream {
+    fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        unsafe {
+            box_host_write_stdout_line(data.as_ptr(), data.len());
+        }
+        Ok(data.len())
+    }
+}
+
+// A stream plus the buffering policy in front of it
+pub struct BufferedStream {
+    stream: Box<dyn Stream>,
+    mode: BufferMode,
+    pending: Vec<u8>,
+}
+
+impl BufferedStream {
+    pub fn new(stream: Box<dyn Stream>, mode: BufferMode) -> Self {
+        Self {
+            stream,
+            mode,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> BufferMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: BufferMode) -> Result<(), i32> {
+        self.flush()?;
+        self.mode = mode;
+        Ok(())
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, i32> {
+        self.stream.read(buf)
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        match self.mode {
+            BufferMode::Unbuffered => return self.stream.write(data),
+            BufferMode::Full => {
+                self.pending.extend_from_slice(data);
+                if self.pending.len() >= STDIO_BUFSIZ {
+                    self.flush_pending()?;
+                }
+            }
+            BufferMode::Line => {
+                // Push out every complete line, keep the tail
+                for &byte in data {
+                    self.pending.push(byte);
+                    if byte == b'\n' || self.pending.len() >= STDIO_BUFSIZ {
+                        self.flush_pending()?;
+                    }
+                }
+            }
+        }
+        Ok(data.len())
+    }
+
+    fn flush_pending(&mut self) -> Result<(), i32> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = core::mem::take(&mut self.pending);
+        let mut written = 0;
+        while written < pending.len() {
+            match self.stream.write(&pending[written..]) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(e) => {
+                    self.pending = pending[written..].to_vec();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), i32> {
+        self.flush_pending()?;
+        self.stream.flush()
+    }
+}
+
+// What fds 0, 1 and 2 are wired to when a Proc starts
+pub fn default_stream(fd: i32) -> BufferedStream {
+    #[cfg(feature = "box-host-stdout")]
+    {
+        if fd == 1 || fd == 2 {
+            return BufferedStream::new(Box::new(HostStream), BufferMode::Line);
+        }
+    }
+    BufferedStream::new(Box::new(DiscardStream), BufferMode::Line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffered(mode: BufferMode) -> (CaptureStream, BufferedStream) {
+        let capture = CaptureStream::new();
+        (capture.clone(), BufferedStream::new(Box::new(capture), mode))
+    }
+
+    #[test]
+    fn line_mode_holds_the_unfinished_line() {
+        let (capture, mut stream) = buffered(BufferMode::Line);
+        assert_eq!(stream.write(b"ab"), Ok(2));
+        assert_eq!(capture.output(), b"");
+        assert_eq!(stream.write(b"c\nd"), Ok(3));
+        assert_eq!(capture.output(), b"abc\n");
+        assert_eq!(stream.flush(), Ok(()));
+        assert_eq!(capture.output(), b"abc\nd");
+    }
+
+    #[test]
+    fn full_mode_waits_for_bufsiz() {
+        let (capture, mut stream) = buffered(BufferMode::Full);
+        assert_eq!(stream.write(b"line\n"), Ok(5));
+        assert_eq!(capture.output(), b"");
+        let big = vec![b'x'; STDIO_BUFSIZ];
+        assert_eq!(stream.write(&big), Ok(big.len()));
+        assert_eq!(capture.take_output().len(), STDIO_BUFSIZ + 5);
+    }
+
+    #[test]
+    fn changing_mode_flushes_first() {
+        let (capture, mut stream) = buffered(BufferMode::Full);
+        assert_eq!(stream.write(b"held"), Ok(4));
+        assert_eq!(stream.set_mode(BufferMode::Unbuffered), Ok(()));
+        assert_eq!(capture.output(), b"held");
+        assert_eq!(stream.write(b"!"), Ok(1));
+        assert_eq!(capture.output(), b"held!");
+    }
+
+    #[test]
+    fn capture_reads_back_pushed_input() {
+        let (capture, mut stream) = buffered(BufferMode::Unbuffered);
+        capture.push_input(b"hi");
+        let mut buf = [0u8; 4];
+        assert_eq!(stream.read(&mut buf), Ok(2));
+        assert_eq!(&buf[..2], b"hi");
+        assert_eq!(BufferMode::from_raw(1), Some(BufferMode::Line));
+        assert_eq!(BufferMode::from_raw(7), None);
+    }
+}
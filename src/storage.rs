@@ -0,0 +1,418 @@
+// storage.rs
+#![allow(dead_code)]
+
+use core::fmt;
+use crate::collections::BTreeMap;
+use std::rc::Rc;
+
+// Files are stored as fixed-size pages, matching st_blksize
+pub const PAGE_SIZE: u64 = 4096;
+
+// Largest size a file can reach, the same as an off_t can address
+pub const MAX_FILE_SIZE: u64 = i64::MAX as u64;
+
+type Page = Rc<Vec<u8>>;
+
+// Contents of a regular file: a length plus the pages that have been
+// written, keyed by page index. Pages that were never written read as
+// zeros and take no memory, so a write far past the end is cheap and
+// offsets are full u64s. Both the page table and each page are shared
+// on clone and copied on the first write, so reflinks and whole-file
+// copies cost O(1) memory until one side changes.
+#[derive(Clone, Default)]
+pub struct FileData {
+    len: u64,
+    pages: Rc<BTreeMap<u64, Page>>,
+}
+
+impl FileData {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Whether another inode still refers to the same page table
+    pub fn is_shared(&self) -> bool {
+        Rc::strong_count(&self.pages) > 1
+    }
+
+    // Whether two files currently share their whole page table
+    pub fn shares_with(&self, other: &FileData) -> bool {
+        Rc::ptr_eq(&self.pages, &other.pages)
+    }
+
+    // Pages holding data, for st_blocks
+    pub fn allocated_pages(&self) -> u64 {
+        self.pages.len() as u64
+    }
+
+    // Pages in `[offset, offset + len)` that have no storage yet, so
+    // writing the range would allocate them
+    pub fn missing_pages(&self, offset: u64, len: u64) -> u64 {
+        if len == 0 {
+            return 0;
+        }
+        let first = offset / PAGE_SIZE;
+        let end = offset.saturating_add(len).div_ceil(PAGE_SIZE);
+        (end - first) - self.pages.range(first..end).count() as u64
+    }
+
+    // Start of the first data region at or after `offset`, for
+    // SEEK_DATA. None when only holes (or end of file) follow.
+    pub fn next_data(&self, offset: u64) -> Option<u64> {
+        if offset >= self.len {
+            return None;
+        }
+        let (&index, _) = self.pages.range(offset / PAGE_SIZE..).next()?;
+        let start = core::cmp::max(offset, index * PAGE_SIZE);
+        if start < self.len { Some(start) } else { None }
+    }
+
+    // Start of the first hole at or after `offset`, for SEEK_HOLE. The
+    // end of the file counts as a hole, so this is at most `len()`.
+    pub fn next_hole(&self, offset: u64) -> u64 {
+        let mut index = offset / PAGE_SIZE;
+        for (&allocated, _) in self.pages.range(index..) {
+            if allocated != index {
+                break;
+            }
+            index += 1;
+        }
+        core::cmp::min(core::cmp::max(offset, index * PAGE_SIZE), self.len)
+    }
+
+    // Copies bytes at `offset` into `buf`; holes read as zeros. Returns
+    // how many bytes were read, which is short only at end of file.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+        let n = core::cmp::min(buf.len() as u64, self.len - offset) as usize;
+        let mut done = 0;
+        while done < n {
+            let pos = offset + done as u64;
+            let index = pos / PAGE_SIZE;
+            let start = (pos % PAGE_SIZE) as usize;
+            let chunk = core::cmp::min(n - done, PAGE_SIZE as usize - start);
+            let out = &mut buf[done..done + chunk];
+            match self.pages.get(&index) {
+                Some(page) => out.copy_from_slice(&page[start..start + chunk]),
+                None => out.fill(0),
+            }
+            done += chunk;
+        }
+        n
+    }
+
+    // The bytes in `[offset, offset + len)`, clipped to the file
+    pub fn read_vec(&self, offset: u64, len: usize) -> Vec<u8> {
+        if offset >= self.len {
+            return Vec::new();
+        }
+        let n = core::cmp::min(len as u64, self.len - offset) as usize;
+        let mut out = vec![0u8; n];
+        self.read_at(&mut out, offset);
+        out
+    }
+
+    // Writes `data` at `offset`, growing the file as needed. Only the
+    // pages touched by the write are allocated (or un-shared).
+    pub fn write_at(&mut self, data: &[u8], offset: u64) -> Result<usize, i32> {
+        let end = offset.checked_add(data.len() as u64)
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(crate::errno::EFBIG)?;
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset + done as u64;
+            let index = pos / PAGE_SIZE;
+            let start = (pos % PAGE_SIZE) as usize;
+            let chunk = core::cmp::min(data.len() - done, PAGE_SIZE as usize - start);
+            let page = self.page_mut(index);
+            page[start..start + chunk].copy_from_slice(&data[done..done + chunk]);
+            done += chunk;
+        }
+        if end > self.len {
+            self.len = end;
+        }
+        Ok(data.len())
+    }
+
+    // Grows (with a hole) or shrinks the file. Pages past the new end
+    // are dropped and the tail of the last page is zeroed, so growing
+    // again later reads zeros.
+    pub fn set_len(&mut self, len: u64) {
+        if len < self.len {
+            let first_gone = len.div_ceil(PAGE_SIZE);
+            let pages = Rc::make_mut(&mut self.pages);
+            let _ = pages.split_off(&first_gone);
+            let tail = (len % PAGE_SIZE) as usize;
+            if tail != 0 {
+                if let Some(page) = pages.get_mut(&(len / PAGE_SIZE)) {
+                    Rc::make_mut(page)[tail..].fill(0);
+                }
+            }
+        }
+        self.len = len;
+    }
+
+    // Copies `len` bytes from `src` at `src_off` to `dst_off`. Whole
+    // pages that line up on both sides are shared rather than copied.
+    pub fn copy_from(&mut self, src: &FileData, src_off: u64, dst_off: u64, len: u64) -> Result<usize, i32> {
+        let len = core::cmp::min(len, src.len.saturating_sub(src_off));
+        let end = dst_off.checked_add(len)
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(crate::errno::EFBIG)?;
+        let mut done = 0;
+        while done < len {
+            let from = src_off + done;
+            let to = dst_off + done;
+            let remaining = len - done;
+            if from % PAGE_SIZE == 0 && to % PAGE_SIZE == 0 && remaining >= PAGE_SIZE {
+                let pages = Rc::make_mut(&mut self.pages);
+                match src.pages.get(&(from / PAGE_SIZE)) {
+                    Some(page) => pages.insert(to / PAGE_SIZE, page.clone()),
+                    None => pages.remove(&(to / PAGE_SIZE)),
+                };
+                done += PAGE_SIZE;
+                continue;
+            }
+            let chunk = core::cmp::min(remaining, PAGE_SIZE - from % PAGE_SIZE) as usize;
+            let bytes = src.read_vec(from, chunk);
+            self.write_at(&bytes, to)?;
+            done += chunk as u64;
+        }
+        if end > self.len {
+            self.len = end;
+        }
+        Ok(len as usize)
+    }
+
+    // Backs every page in `[offset, offset + len)` without changing the
+    // length. New pages all share one zero page, so reserving a large
+    // range costs a table entry per page until the pages are written.
+    pub fn allocate(&mut self, offset: u64, len: u64) {
+        let end = offset.saturating_add(len);
+        let zero = Rc::new(vec![0u8; PAGE_SIZE as usize]);
+        let pages = Rc::make_mut(&mut self.pages);
+        for index in offset / PAGE_SIZE..end.div_ceil(PAGE_SIZE) {
+            pages.entry(index).or_insert_with(|| zero.clone());
+        }
+    }
+
+    // Zeros `[offset, offset + len)` and frees the pages it covers
+    // entirely, leaving a hole. The length does not change.
+    pub fn punch_hole(&mut self, offset: u64, len: u64) {
+        let end = offset.saturating_add(len);
+        let first_whole = offset.div_ceil(PAGE_SIZE);
+        let last_whole = end / PAGE_SIZE;
+        if first_whole < last_whole {
+            let pages = Rc::make_mut(&mut self.pages);
+            let mut tail = pages.split_off(&first_whole);
+            let mut after = tail.split_off(&last_whole);
+            pages.append(&mut after);
+        }
+        // The partial pages at either edge keep their other bytes
+        if offset / PAGE_SIZE == end / PAGE_SIZE {
+            self.zero_in_page(offset / PAGE_SIZE, offset % PAGE_SIZE, end % PAGE_SIZE);
+        } else {
+            if offset % PAGE_SIZE != 0 {
+                self.zero_in_page(offset / PAGE_SIZE, offset % PAGE_SIZE, PAGE_SIZE);
+            }
+            if end % PAGE_SIZE != 0 {
+                self.zero_in_page(end / PAGE_SIZE, 0, end % PAGE_SIZE);
+            }
+        }
+    }
+
+    // Removes `[offset, offset + len)` and moves everything after it
+    // down. Both must be multiples of PAGE_SIZE.
+    pub fn collapse_range(&mut self, offset: u64, len: u64) {
+        let first = offset / PAGE_SIZE;
+        let count = len / PAGE_SIZE;
+        let pages = Rc::make_mut(&mut self.pages);
+        let mut moved = pages.split_off(&first);
+        let after = moved.split_off(&(first + count));
+        for (index, page) in after {
+            pages.insert(index - count, page);
+        }
+        self.len = self.len.saturating_sub(len);
+    }
+
+    // Opens a hole of `len` bytes at `offset`, moving everything from
+    // there up. Both must be multiples of PAGE_SIZE.
+    pub fn insert_range(&mut self, offset: u64, len: u64) -> Result<(), i32> {
+        let new_len = self.len.checked_add(len)
+            .filter(|new_len| *new_len <= MAX_FILE_SIZE)
+            .ok_or(crate::errno::EFBIG)?;
+        let first = offset / PAGE_SIZE;
+        let count = len / PAGE_SIZE;
+        let pages = Rc::make_mut(&mut self.pages);
+        let moved = pages.split_off(&first);
+        for (index, page) in moved {
+            pages.insert(index + count, page);
+        }
+        self.len = new_len;
+        Ok(())
+    }
+
+    fn zero_in_page(&mut self, index: u64, start: u64, stop: u64) {
+        if start < stop && self.pages.contains_key(&index) {
+            self.page_mut(index)[start as usize..stop as usize].fill(0);
+        }
+    }
+
+    fn page_mut(&mut self, index: u64) -> &mut Vec<u8> {
+        let pages = Rc::make_mut(&mut self.pages);
+        let page = pages.entry(index).or_insert_with(|| Rc::new(vec![0u8; PAGE_SIZE as usize]));
+        Rc::make_mut(page)
+    }
+}
+
+impl From<Vec<u8>> for FileData {
+    fn from(bytes: Vec<u8>) -> Self {
+        let mut data = FileData::new();
+        // A fresh file can't exceed MAX_FILE_SIZE from a Vec
+        let _ = data.write_at(&bytes, 0);
+        data
+    }
+}
+
+impl fmt::Debug for FileData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileData")
+         .field("len", &self.len)
+         .field("pages", &self.pages.len())
+         .field("shared", &self.is_shared())
+         .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(byte: u8, len: usize) -> FileData {
+        FileData::from(vec![byte; len])
+    }
+
+    #[test]
+    fn clones_share_until_written() {
+        let original = filled(7, 10000);
+        let mut copy = original.clone();
+        assert!(copy.shares_with(&original));
+        assert!(original.is_shared());
+        assert_eq!(copy.write_at(b"x", 0), Ok(1));
+        assert!(!copy.shares_with(&original));
+        assert_eq!(original.read_vec(0, 1), [7]);
+        assert_eq!(copy.read_vec(0, 2), [b'x', 7]);
+    }
+
+    #[test]
+    fn copy_from_handles_aligned_and_unaligned_ranges() {
+        let src = filled(7, 3 * PAGE_SIZE as usize);
+        let mut dst = filled(1, 10);
+        // Page-aligned on both sides: shared, past the old end
+        assert_eq!(dst.copy_from(&src, 0, PAGE_SIZE, 2 * PAGE_SIZE), Ok(2 * PAGE_SIZE as usize));
+        assert_eq!(dst.len(), 3 * PAGE_SIZE);
+        assert_eq!(dst.read_vec(PAGE_SIZE - 1, 2), [0, 7]);
+        // Unaligned: byte copy, clipped to the source
+        assert_eq!(dst.copy_from(&src, 3 * PAGE_SIZE - 2, 3, 100), Ok(2));
+        assert_eq!(dst.read_vec(0, 6), [1, 1, 1, 7, 7, 1]);
+        assert_eq!(dst.copy_from(&src, 0, MAX_FILE_SIZE, 1), Err(crate::errno::EFBIG));
+    }
+
+    #[test]
+    fn offsets_past_4gib_only_allocate_what_is_written() {
+        let mut data = FileData::new();
+        let off = 6u64 << 30;
+        assert_eq!(data.write_at(b"tail", off), Ok(4));
+        assert_eq!(data.len(), off + 4);
+        assert_eq!(data.allocated_pages(), 1);
+        assert_eq!(data.read_vec(off - 4, 8), b"\0\0\0\0tail");
+        assert_eq!(data.write_at(b"x", MAX_FILE_SIZE), Err(crate::errno::EFBIG));
+    }
+
+    #[test]
+    fn shrinking_then_growing_reads_zeros() {
+        let mut data = filled(5, 3 * PAGE_SIZE as usize);
+        data.set_len(PAGE_SIZE + 2);
+        assert_eq!(data.allocated_pages(), 2);
+        data.set_len(3 * PAGE_SIZE);
+        assert_eq!(data.read_vec(PAGE_SIZE, 4), [5, 5, 0, 0]);
+        assert_eq!(data.read_vec(2 * PAGE_SIZE, 1), [0]);
+    }
+
+    #[test]
+    fn holes_and_data_are_found_by_page() {
+        let mut data = FileData::new();
+        data.write_at(b"abc", 10000).unwrap();
+        data.write_at(b"def", 20000).unwrap();
+        data.set_len(40000);
+        assert_eq!(data.allocated_pages(), 2);
+        assert_eq!(data.next_data(0), Some(2 * PAGE_SIZE));
+        assert_eq!(data.next_data(9000), Some(9000));
+        assert_eq!(data.next_data(5 * PAGE_SIZE), None);
+        assert_eq!(data.next_hole(0), 0);
+        assert_eq!(data.next_hole(9000), 3 * PAGE_SIZE);
+        assert_eq!(data.next_hole(39999), 39999);
+        assert_eq!(data.missing_pages(0, 5 * PAGE_SIZE), 3);
+        assert_eq!(data.missing_pages(2 * PAGE_SIZE, 1), 0);
+    }
+
+    #[test]
+    fn end_of_file_is_a_hole() {
+        let data = filled(1, 5);
+        assert_eq!(data.next_hole(0), 5);
+        assert_eq!(data.next_data(2), Some(2));
+        assert_eq!(data.next_data(5), None);
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251 + 1) as u8).collect()
+    }
+
+    #[test]
+    fn allocate_backs_pages_without_growing() {
+        let mut data = FileData::new();
+        data.allocate(0, 2 * PAGE_SIZE);
+        assert_eq!((data.len(), data.allocated_pages()), (0, 2));
+        assert_eq!(data.missing_pages(0, 2 * PAGE_SIZE), 0);
+    }
+
+    #[test]
+    fn punch_hole_frees_whole_pages_and_zeros_the_edges() {
+        let bytes = pattern(4 * PAGE_SIZE as usize);
+        let mut data = FileData::from(bytes.clone());
+        data.punch_hole(100, 8900);
+        assert_eq!(data.len(), 4 * PAGE_SIZE);
+        assert_eq!(data.allocated_pages(), 3);
+        let back = data.read_vec(0, bytes.len());
+        assert_eq!(&back[..100], &bytes[..100]);
+        assert!(back[100..9000].iter().all(|&b| b == 0));
+        assert_eq!(&back[9000..], &bytes[9000..]);
+    }
+
+    #[test]
+    fn collapse_and_insert_shift_pages() {
+        let bytes = pattern(3 * PAGE_SIZE as usize);
+        let page = PAGE_SIZE as usize;
+        let mut data = FileData::from(bytes.clone());
+        data.collapse_range(PAGE_SIZE, PAGE_SIZE);
+        assert_eq!(data.len(), 2 * PAGE_SIZE);
+        assert_eq!(data.read_vec(PAGE_SIZE, page), &bytes[2 * page..]);
+
+        assert_eq!(data.insert_range(PAGE_SIZE, 2 * PAGE_SIZE), Ok(()));
+        assert_eq!(data.len(), 4 * PAGE_SIZE);
+        assert_eq!(data.read_vec(0, page), &bytes[..page]);
+        assert!(data.read_vec(PAGE_SIZE, 2 * page).iter().all(|&b| b == 0));
+        assert_eq!(data.read_vec(3 * PAGE_SIZE, page), &bytes[2 * page..]);
+        assert_eq!(data.insert_range(0, MAX_FILE_SIZE), Err(crate::errno::EFBIG));
+    }
+}
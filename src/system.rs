@@ -8,51 +8,284 @@
 
 // In project implementations - replaces rust's std crates:
 
+use core::any::Any;
+use core::cell::{Cell, Ref, RefCell, RefMut};
+
 use crate::ffi::CStr;
 use crate::path::PathBuf;
 use crate::sync::{Mutex, MutexGuard};
+use std::rc::Rc;
 use crate::collections::HashMap;
 use crate::cmp::{min, max};
 
 use crate::filesystem::{
-    FileSystem, Inode, InodeKind, Permissions, Permission, Stat, Dirent, Dirent64
+    Attachment, FileSystem, Inode, InodeKind, Mount, Permissions, Permission, Stat, Dirent, Dirent64, Iovec, SHM_DIR
 };
+use crate::errno::*;
+use crate::eventfd::{self, EventFd};
+use crate::fdobject::FdObject;
+use crate::inotify::{self, Inotify};
+use crate::memfd::{self, Memfd, TmpFile};
+use crate::mmap::{self, Mapping, Region};
+use crate::pipe::{Pipe, PIPE_BUF};
+use crate::poll::{self, Epoll, EpollEvent, PollFd, POLLERR, POLLHUP, POLLIN, POLLNVAL, POLLOUT, POLLPRI, POLL_ALWAYS};
+use crate::clock::{self, Timespec, Timeval, CLOCK_MONOTONIC};
+use crate::procfs;
+use crate::quota::{self, Dqblk, QuotaId};
+use crate::statfs::{MountUsage, Statfs, Statvfs};
+use crate::stdio::{self, BufferMode, BufferedStream, Stream};
+use crate::storage::{FileData, MAX_FILE_SIZE, PAGE_SIZE};
+use crate::timerfd::{Itimerspec, Timer};
+use crate::tty::{Termios, Tty, Winsize};
+use crate::changes::{Batching, Change, ChangeFeed, ChangeObserver, ChangeOp};
+use crate::acl::{Acl, Credentials, ACL_ACCESS_XATTR, ACL_DEFAULT_XATTR, ACL_EXECUTE, ACL_READ, ACL_WRITE};
+use crate::device::{self, DeviceClass};
+use crate::xattr::{self, Namespace, XATTR_LIST_MAX};
 
 pub type FileDescriptor = i32;
 
+// What an open descriptor reads from and writes to. For pipes and
+// FIFOs the buffer lives in `fs.pipes` under the handle's inode number.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum HandleKind {
+    File,
+    PipeReader,
+    PipeWriter,
+    // A FIFO opened O_RDWR holds both ends at once
+    PipeDuplex,
+    // Standard stream; the stream itself is `Proc::streams[inode_number]`
+    Stream,
+    // The controlling terminal, `Proc::tty`
+    Tty,
+    // Character or block device; the driver is picked from the inode's
+    // numbers in `fs.devices`
+    Device,
+    // Regular file served by `fs.providers[inode_number]`
+    Provider,
+    // inotify instance, `Proc::inotify[inode_number]`
+    Inotify,
+    // epoll instance, `Proc::epolls[inode_number]`
+    Epoll,
+    // eventfd counter, `Proc::eventfds[inode_number]`
+    EventFd,
+    // timerfd timer, `Proc::timers[inode_number]`
+    TimerFd,
+    // Descriptor installed by another component (a socket, say),
+    // `Proc::objects[inode_number]`
+    Object,
+}
+
+impl HandleKind {
+    fn reads_pipe(self) -> bool {
+        matches!(self, HandleKind::PipeReader | HandleKind::PipeDuplex)
+    }
+
+    fn writes_pipe(self) -> bool {
+        matches!(self, HandleKind::PipeWriter | HandleKind::PipeDuplex)
+    }
+}
+
+// What an open() creates and dup() shares: the file position and the
+// status flags F_SETFL changes. Descriptors that share one see each
+// other's seeks and flag changes.
+struct OpenFileDescription {
+    position: Cell<u64>,
+    append_mode: Cell<bool>,
+    nonblock: Cell<bool>,
+}
+
+impl OpenFileDescription {
+    fn new(position: u64, append_mode: bool, nonblock: bool) -> Rc<Self> {
+        Rc::new(Self {
+            position: Cell::new(position),
+            append_mode: Cell::new(append_mode),
+            nonblock: Cell::new(nonblock),
+        })
+    }
+}
+
+// A descriptor. Cloning one (dup) shares its open file description.
+#[derive(Clone)]
 struct OpenFileHandle {
     inode_number: u64,
-    position: u64,
-    append_mode: bool,
+    description: Rc<OpenFileDescription>,
+    kind: HandleKind,
+    cloexec: bool,
+    // A blocking open of a FIFO waits for the other end on Linux. We
+    // cannot wait, so the open succeeds and I/O reports EAGAIN until
+    // the peer shows up.
+    awaiting_peer: bool,
+    // Opened for writing; picks IN_CLOSE_WRITE over IN_CLOSE_NOWRITE
+    writable: bool,
+    // O_RDONLY, O_WRONLY or O_RDWR as opened, for F_GETFL
+    access: i32,
+}
+
+impl OpenFileHandle {
+    fn position(&self) -> u64 {
+        self.description.position.get()
+    }
+
+    fn set_position(&self, position: u64) {
+        self.description.position.set(position);
+    }
+
+    fn append_mode(&self) -> bool {
+        self.description.append_mode.get()
+    }
+
+    fn set_append_mode(&self, append_mode: bool) {
+        self.description.append_mode.set(append_mode);
+    }
+
+    fn nonblock(&self) -> bool {
+        self.description.nonblock.get()
+    }
+
+    fn set_nonblock(&self, nonblock: bool) {
+        self.description.nonblock.set(nonblock);
+    }
+
+    fn is_pipe(&self) -> bool {
+        matches!(self.kind, HandleKind::PipeReader | HandleKind::PipeWriter | HandleKind::PipeDuplex)
+    }
+
+    // Only regular files (and directories) have a position to seek
+    fn seekable(&self) -> bool {
+        self.kind == HandleKind::File
+    }
 }
 
 // Flags
 const O_RDONLY: i32 = 0;
 const O_WRONLY: i32 = 1;
 const O_RDWR: i32 = 2;
+const O_ACCMODE: i32 = 3;
 const O_CREAT: i32 = 64;
+const O_EXCL: i32 = 128;
 const O_TRUNC: i32 = 512;
 const O_APPEND: i32 = 1024;
+const O_NONBLOCK: i32 = 2048;
+const O_DIRECTORY: i32 = 65536;
+const O_CLOEXEC: i32 = 524288;
+// Includes O_DIRECTORY, so kernels without it refuse a plain open
+const O_TMPFILE: i32 = 4194304 | O_DIRECTORY;
 
 const SEEK_SET: i32 = 0;
 const SEEK_CUR: i32 = 1;
 const SEEK_END: i32 = 2;
+const SEEK_DATA: i32 = 3;
+const SEEK_HOLE: i32 = 4;
 
 // Mode checks for access()
 const R_OK: i32 = 4;
 const W_OK: i32 = 2;
 const X_OK: i32 = 1;
 
+// File type bits of a mode (mknod, stat)
+const S_IFMT: u32 = 0o170000;
+const S_IFIFO: u32 = 0o010000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFREG: u32 = 0o100000;
+
+// fcntl commands
+const F_DUPFD: i32 = 0;
+const F_GETFD: i32 = 1;
+const F_SETFD: i32 = 2;
+const F_GETFL: i32 = 3;
+const F_SETFL: i32 = 4;
+const F_DUPFD_CLOEXEC: i32 = 1030;
+const F_SETPIPE_SZ: i32 = 1031;
+const F_GETPIPE_SZ: i32 = 1032;
+const F_ADD_SEALS: i32 = 1033;
+const F_GET_SEALS: i32 = 1034;
+const FD_CLOEXEC: i32 = 1;
+
+// *at() flags
+const AT_FDCWD: i32 = -100;
+const AT_EMPTY_PATH: i32 = 0x1000;
+
+// Longest single path component
+const NAME_MAX: usize = 255;
+
+// ioctl requests
+const TCGETS: u32 = 0x5401;
+const TCSETS: u32 = 0x5402;
+const TCSETSW: u32 = 0x5403;
+const TCSETSF: u32 = 0x5404;
+const TCFLSH: u32 = 0x540B;
+const TIOCGPGRP: u32 = 0x540F;
+const TIOCSPGRP: u32 = 0x5410;
+const TIOCGWINSZ: u32 = 0x5413;
+const TIOCSWINSZ: u32 = 0x5414;
+const FIONREAD: u32 = 0x541B;
+// _IOW(0x94, 9, int) and _IOW(0x94, 13, struct file_clone_range)
+const FICLONE: u32 = 0x4004_9409;
+const FICLONERANGE: u32 = 0x4020_940d;
+
+// Reflink ranges must be aligned to this, like st_blksize
+const CLONE_ALIGN: u64 = 4096;
+
+// Argument of FICLONERANGE
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FileCloneRange {
+    src_fd: i64,
+    src_offset: u64,
+    src_length: u64,
+    dest_offset: u64,
+}
+
+// fallocate modes
+const FALLOC_FL_KEEP_SIZE: i32 = 0x01;
+const FALLOC_FL_PUNCH_HOLE: i32 = 0x02;
+const FALLOC_FL_COLLAPSE_RANGE: i32 = 0x08;
+const FALLOC_FL_ZERO_RANGE: i32 = 0x10;
+const FALLOC_FL_INSERT_RANGE: i32 = 0x20;
+const FALLOC_FL_SUPPORTED: i32 = FALLOC_FL_KEEP_SIZE | FALLOC_FL_PUNCH_HOLE
+    | FALLOC_FL_COLLAPSE_RANGE | FALLOC_FL_ZERO_RANGE | FALLOC_FL_INSERT_RANGE;
+
+// Vectored I/O
+const IOV_MAX: i32 = 1024;
+const RWF_HIPRI: i32 = 0x01;
+const RWF_DSYNC: i32 = 0x02;
+const RWF_SYNC: i32 = 0x04;
+const RWF_NOWAIT: i32 = 0x08;
+const RWF_APPEND: i32 = 0x10;
+const RWF_NOAPPEND: i32 = 0x20;
+const RWF_SUPPORTED: i32 = RWF_HIPRI | RWF_DSYNC | RWF_SYNC | RWF_NOWAIT | RWF_APPEND | RWF_NOAPPEND;
+
+// splice/tee/vmsplice flags
+const SPLICE_F_NONBLOCK: u32 = 2;
+
 // TODO - proc will be its own lib, used here and with /net stuff, for unix Process mgmt stuff, etc
 // Proc is intended to be instantiated from a separate Wasm module
 // however it can be used local to this module if not sharing with
 // other externel resource modules (wasm-net, etc.). This is an option.
+// Such modules put their descriptors in the fd table through
+// `install_object`.
 //
 
 const OPEN_FILES_CAP: usize = 256;
+const STREAMS_CAP: usize = 16;
+const INOTIFY_CAP: usize = 16;
+const EPOLL_CAP: usize = 16;
+const EVENTFD_CAP: usize = 64;
+const TIMERFD_CAP: usize = 64;
+const OBJECTS_CAP: usize = 64;
+const MEMFD_CAP: usize = 64;
+const TMPFILE_CAP: usize = 64;
 
 pub struct Proc {
-    pub fs: FileSystem,
+    // Shared with every other Proc made by `with_filesystem` from it
+    pub fs: Rc<RefCell<FileSystem>>,
+
+    // Working directory that relative paths start from
+    cwd: PathBuf,
+    // What the other Procs on `fs` see of this one
+    attachment: Rc<RefCell<Attachment>>,
 
     // Instead of storing Inode here, we just store the inode number.
     // In a real FS, the inode number acts like an index; we can always reference fs.inodes.
@@ -61,1522 +294,5961 @@ pub struct Proc {
     open_files: HashMap<FileDescriptor, OpenFileHandle, OPEN_FILES_CAP>,
     next_fd: FileDescriptor,
 
+    // Host-facing streams behind fds 0/1/2 (and anything dup'd from
+    // them), keyed by their anonymous inode number
+    streams: HashMap<u64, BufferedStream, STREAMS_CAP>,
+
+    // Terminal attached with `attach_tty`, shared by every Tty handle
+    tty: Option<Tty>,
+
     // For the uninitiated:
     // When a process creates a new file or directory (using calls like open() with O_CREAT, mkdir(), etc.), it supplies a mode argument specifying the intended permissions (for example, 0o666 for files, 0o777 for directories).
     // The actual permissions that the file or directory ends up with are the intended permissions minus the bits "masked out" by the process's umask.
     // The umask is a set of bits where each bit turned on (1) in the umask clears (removes) the corresponding permission bit from the file's final mode.
     // see: https://man7.org/linux/man-pages/man2/umask.2.html
     umask_value: u32,
+
+    // Reason for the last failed call, read back through wasm_vfs_errno
+    errno: i32,
+
+    // Who the process runs as: used for permission checks and as the
+    // owner of new inodes. Starts as root.
+    uid: u32,
+    gid: u32,
+    groups: Vec<u32>,
+
+    // Whether /proc is mounted; its entries are rebuilt by sync_procfs
+    procfs_mounted: bool,
+    // Inode numbers of /proc/self/fd links whose descriptors closed,
+    // handed to the next links made so lookups don't use up new ones
+    procfs_free_links: Vec<u64>,
+
+    // Live mmap()s, in no particular order; they never overlap
+    mappings: Vec<Mapping>,
+
+    // inotify instances, by the anonymous inode their descriptors share
+    inotify: HashMap<u64, Inotify, INOTIFY_CAP>,
+    // Pairs up IN_MOVED_FROM and IN_MOVED_TO
+    next_cookie: u32,
+    // epoll instances, keyed the same way
+    epolls: HashMap<u64, Epoll, EPOLL_CAP>,
+    // eventfd counters and timerfd timers, keyed the same way
+    eventfds: HashMap<u64, EventFd, EVENTFD_CAP>,
+    timers: HashMap<u64, Timer, TIMERFD_CAP>,
+    // Nanoseconds until the soonest unexpired timer the current wait
+    // looked at, so a virtual clock knows where to jump
+    next_wake: Option<u64>,
+
+    // Descriptors from `install_object`, keyed the same way
+    objects: HashMap<u64, Box<dyn FdObject>, OBJECTS_CAP>,
+
+    // Unnamed regular files, by inode: memfds keep their name and
+    // seals, O_TMPFILE files where they were made
+    memfds: HashMap<u64, Memfd, MEMFD_CAP>,
+    tmpfiles: HashMap<u64, TmpFile, TMPFILE_CAP>,
+
+    // Embedder subscriptions to filesystem changes
+    changes: ChangeFeed,
 }
 
 impl Proc {
     pub fn new() -> Self {
-        Self {
-            fs: FileSystem::new(),
+        Self::with_filesystem(Rc::new(RefCell::new(FileSystem::new())))
+    }
+
+    /// Makes a process on an existing filesystem, e.g. `Rc::clone` of
+    /// another Proc's `fs`. Paths, file contents, pipes and FIFOs are
+    /// shared; descriptors, the working directory, credentials and the
+    /// umask are the new process's own.
+    pub fn with_filesystem(fs: Rc<RefCell<FileSystem>>) -> Self {
+        let attachment = fs.borrow_mut().attach();
+        let mut proc = Self {
+            fs,
+            cwd: PathBuf::from("/"),
+            attachment,
             fd_table: [None; 1024],
             open_files: HashMap::new(),
             next_fd: 3,
+            streams: HashMap::new(),
+            tty: None,
             umask_value: 0o022,
+            errno: 0,
+            uid: 0,
+            gid: 0,
+            groups: Vec::new(),
+            procfs_mounted: false,
+            procfs_free_links: Vec::new(),
+            mappings: Vec::new(),
+            inotify: HashMap::new(),
+            next_cookie: 1,
+            epolls: HashMap::new(),
+            eventfds: HashMap::new(),
+            timers: HashMap::new(),
+            next_wake: None,
+            objects: HashMap::new(),
+            memfds: HashMap::new(),
+            tmpfiles: HashMap::new(),
+            changes: ChangeFeed::new(),
+        };
+        for fd in 0..3 {
+            // A fresh Proc has room for these
+            let _ = proc.install_stream(fd, stdio::default_stream(fd));
         }
+        proc
     }
 
-    // Find the next available FD
-    fn allocate_fd(&mut self) -> Option<FileDescriptor> {
-        for (index, slot) in self.fd_table.iter().enumerate() {
-            if slot.is_none() && index >= 3 {
-                return Some(index as FileDescriptor);
-            }
+    /// Mounts the synthetic procfs at /proc. A new Proc has none until
+    /// the host calls this. Its entries are generated from this Proc
+    /// whenever a path under /proc is opened, stat'd, read as a link or
+    /// listed.
+    pub fn mount_procfs(&mut self) {
+        if self.procfs_mounted {
+            return;
         }
-        None
+        self.procfs_mounted = true;
+        self.fs.borrow_mut().mounts.push(Mount::new("proc", procfs::PROC_MOUNT, "proc", "rw,nosuid,nodev,noexec"));
+        self.fs.borrow_mut().recount();
+        // A fresh table has room; a full one is reported by whichever
+        // lookup needs the entries
+        let _ = self.sync_procfs();
     }
 
-    fn get_absolute_path(&self, path: &PathBuf) -> PathBuf {
-        if path.is_absolute() {
-            path.clone()
-        } else {
-            self.fs.current_directory.join(path)
+    /// Removes everything under /proc, leaving an empty directory
+    pub fn unmount_procfs(&mut self) {
+        if !self.procfs_mounted {
+            return;
         }
+        self.procfs_mounted = false;
+        self.fs.borrow_mut().mounts.retain(|m| m.target.to_string_lossy() != procfs::PROC_MOUNT);
+        let stale: Vec<(PathBuf, u64)> = self.fs.borrow().path_map.iter()
+            .filter(|(p, _)| procfs::is_proc_path(p) && p.to_string_lossy() != procfs::PROC_MOUNT)
+            .map(|(p, i)| (p.clone(), *i))
+            .collect();
+        for (path, ino) in stale {
+            self.fs.borrow_mut().path_map.remove(&path);
+            self.fs.borrow_mut().files.remove(&ino);
+        }
+        self.fs.borrow_mut().recount();
     }
 
-    fn get_inode_mut(&mut self, inode_num: u64) -> Option<&mut Inode> {
-        self.fs.inodes.get_mut(inode_num as usize)
+    /// Sets the user, group and supplementary groups the process acts
+    /// as. They decide permission checks (including ACLs) and own the
+    /// inodes it creates.
+    pub fn set_credentials(&mut self, uid: u32, gid: u32, groups: &[u32]) {
+        self.uid = uid;
+        self.gid = gid;
+        self.groups = groups.to_vec();
     }
 
-    fn get_inode(&self, inode_num: u64) -> Option<&Inode> {
-        self.fs.inodes.get(inode_num as usize)
+    pub fn uid(&self) -> u32 {
+        self.uid
     }
 
-    fn set_file_size(&mut self, inode_number: u64, new_size: usize) {
-        let new_len = {
-            if let Some(data) = self.fs.files.get_mut(&inode_number) {
-                if data.len() > new_size {
-                    data.truncate(new_size);
-                } else if data.len() < new_size {
-                    data.resize(new_size, 0);
-                }
-                data.len()
-            } else {
-                0
-            }
-        };
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    pub fn procfs_mounted(&self) -> bool {
+        self.procfs_mounted
+    }
 
-        if let Some(inode) = self.get_inode_mut(inode_number) {
-            inode.size = new_len as u64;
+    // Brings /proc up to date before `path` is looked up under it
+    fn refresh_procfs(&mut self, path: &PathBuf) -> Result<(), i32> {
+        if self.procfs_mounted && procfs::is_proc_path(path) {
+            return self.sync_procfs();
         }
+        Ok(())
     }
 
-    fn check_access(&self, inode: &Inode, mode: i32) -> bool {
-        // For simplicity, we assume root.
-        let perm = &inode.permissions;
-        let p = &perm.owner;
-        let can_read = p.read;
-        let can_write = p.write;
-        let can_exec = p.execute;
-
-        if (mode & R_OK) != 0 && !can_read {
-            return false;
+    // Rebuilds the /proc tree: an fd/N link per open descriptor, the
+    // cwd link, and the text of mounts and status
+    fn sync_procfs(&mut self) -> Result<(), i32> {
+        self.procfs_entry("/proc", 0o555, InodeKind::Directory, None)?;
+        self.procfs_entry("/proc/self", 0o555, InodeKind::Directory, None)?;
+        self.procfs_entry("/proc/self/fd", 0o500, InodeKind::Directory, None)?;
+
+        let cwd = self.cwd.clone();
+        self.procfs_entry("/proc/self/cwd", 0o777, InodeKind::SymbolicLink(cwd), None)?;
+
+        let mounts = procfs::mounts(&self.fs.borrow_mut().mounts);
+        self.procfs_entry("/proc/mounts", 0o444, InodeKind::File, Some(mounts.into_bytes()))?;
+
+        let status = procfs::status(&procfs::Status {
+            umask: self.umask_value,
+            uid: self.uid,
+            gid: self.gid,
+            fd_size: self.fd_table.len(),
+        });
+        self.procfs_entry("/proc/self/status", 0o444, InodeKind::File, Some(status.into_bytes()))?;
+
+        // Drop links for descriptors that have been closed since
+        let fd_dir = PathBuf::from("/proc/self/fd");
+        let open: Vec<FileDescriptor> = (0..self.fd_table.len() as FileDescriptor)
+            .filter(|fd| self.fd_table[*fd as usize].is_some())
+            .collect();
+        let stale: Vec<PathBuf> = self.fs.borrow().path_map.iter()
+            .map(|(p, _)| p)
+            .filter(|p| p.parent().as_ref() == Some(&fd_dir))
+            .filter(|p| {
+                let fd = p.file_name().and_then(|n| n.parse::<FileDescriptor>().ok());
+                !fd.map(|fd| open.contains(&fd)).unwrap_or(false)
+            })
+            .cloned()
+            .collect();
+        for path in stale {
+            self.remove_fd_link(&path);
         }
-        if (mode & W_OK) != 0 && !can_write {
-            return false;
+        for fd in open {
+            let target = self.fd_link_target(fd);
+            self.procfs_entry(&format!("/proc/self/fd/{}", fd), 0o700, InodeKind::SymbolicLink(target), None)?;
         }
-        if (mode & X_OK) != 0 && !can_exec {
-            return false;
+        Ok(())
+    }
+
+    // Drops a /proc/self/fd link, keeping its inode number for reuse
+    fn remove_fd_link(&mut self, path: &PathBuf) {
+        let removed = self.fs.borrow_mut().path_map.remove(path);
+        if let Some(ino) = removed {
+            self.fs.borrow_mut().recharge(ino);
+            self.procfs_free_links.push(ino);
         }
+    }
 
-        true
+    // Creates or updates one generated /proc entry in place. Entries
+    // take path table slots like any file, so a full table is ENOMEM
+    // rather than a silently missing entry.
+    fn procfs_entry(&mut self, path: &str, mode: u32, kind: InodeKind, data: Option<Vec<u8>>) -> Result<(), i32> {
+        let path = PathBuf::from(path);
+        let inode_number = match self.lookup(&path) {
+            Some(ino) => {
+                if let Some(mut inode) = self.get_inode_mut(ino) {
+                    inode.kind = kind;
+                }
+                ino
+            }
+            None => {
+                if self.fs.borrow().path_map.is_full() || (data.is_some() && self.fs.borrow().files.is_full()) {
+                    return Err(ENOMEM);
+                }
+                match self.procfs_free_links.pop() {
+                    Some(ino) => {
+                        self.insert_directory_entry(&path, ino, kind);
+                        if let Some(mut inode) = self.get_inode_mut(ino) {
+                            inode.permissions = Permissions::from(mode as u16);
+                        }
+                        ino
+                    }
+                    None => self.fs.borrow_mut().create_node(&path, mode, kind),
+                }
+            }
+        };
+        if let Some(data) = data {
+            if let Some(mut inode) = self.get_inode_mut(inode_number) {
+                inode.size = data.len() as u64;
+            }
+            self.fs.borrow_mut().files.insert(inode_number, FileData::from(data));
+        }
+        Ok(())
     }
 
-    fn insert_directory_entry(&mut self, path: &PathBuf, inode_number: u64, kind: InodeKind) {
-        let inode = Inode::new(
-            inode_number,
-            0,
-            Permissions::from((0o777 & !self.umask_value) as u16),
-            0,
-            0,
-            0,
-            0,
-            0,
-            kind,
-        );
-        while self.fs.inodes.len() <= inode_number as usize {
-            self.fs.inodes.push(Inode::default());
+    // What readlink(/proc/self/fd/N) shows, in Linux's notation
+    fn fd_link_target(&self, fd: FileDescriptor) -> PathBuf {
+        let handle = match self.open_files.get(&fd) {
+            Some(h) => h,
+            None => return PathBuf::new(),
+        };
+        if handle.kind == HandleKind::Tty {
+            return PathBuf::from("/dev/pts/0");
         }
-        self.fs.inodes[inode_number as usize] = inode.clone();
-        self.fs.path_map.insert(path.clone(), inode_number);
-        if let InodeKind::Directory = inode.kind {
-            self.fs.files.insert(inode_number, vec![]);
-        } else if let InodeKind::File = inode.kind {
-            self.fs.files.insert(inode_number, vec![]);
+        if handle.kind == HandleKind::Inotify {
+            return PathBuf::from("anon_inode:inotify");
+        }
+        if handle.kind == HandleKind::Epoll {
+            return PathBuf::from("anon_inode:[eventpoll]");
+        }
+        if handle.kind == HandleKind::EventFd {
+            return PathBuf::from("anon_inode:[eventfd]");
+        }
+        if handle.kind == HandleKind::TimerFd {
+            return PathBuf::from("anon_inode:[timerfd]");
+        }
+        if let Some(memfd) = self.memfds.get(&handle.inode_number) {
+            return PathBuf::from(format!("/memfd:{} (deleted)", memfd.name));
+        }
+        if let Some(tmpfile) = self.tmpfiles.get(&handle.inode_number) {
+            return tmpfile.dir.join(&PathBuf::from(format!("#{} (deleted)", handle.inode_number)));
+        }
+        match self.path_of(handle.inode_number) {
+            Some(path) => path,
+            None if handle.is_pipe() || handle.kind == HandleKind::Stream => {
+                PathBuf::from(format!("pipe:[{}]", handle.inode_number))
+            }
+            None => PathBuf::from(format!("anon_inode:[{}]", handle.inode_number)),
         }
     }
-}
 
-//lazy_static! {
-    //static ref GLOBAL_PROC: Mutex<Option<Proc>> = Mutex::new(None);
-//}
+    // First path that refers to an inode, if it has one
+    fn path_of(&self, inode_number: u64) -> Option<PathBuf> {
+        self.fs.borrow().first_path(inode_number)
+    }
 
-// Replace old static line:
-static mut GLOBAL_PROC: Option<Mutex<Proc>> = None;
+    /// Replaces the stream behind a standard descriptor (or installs
+    /// one on any other free fd). Whatever the fd referred to before is
+    /// closed first, as with dup2. Fails with ENFILE when no more
+    /// streams fit.
+    pub fn set_stdio(&mut self, fd: FileDescriptor, stream: Box<dyn Stream>, mode: BufferMode) -> Result<(), i32> {
+        if fd < 0 || fd as usize >= self.fd_table.len() {
+            return Err(EBADF);
+        }
+        self.close_fd(fd);
+        self.install_stream(fd, BufferedStream::new(stream, mode))
+    }
 
-// Then add two helper functions:
-fn init_global_proc() {
-    unsafe {
-        if GLOBAL_PROC.is_none() {
-            GLOBAL_PROC = Some(Mutex::new(Proc::new()));
+    /// Changes the buffering of the stream behind `fd`, flushing first
+    pub fn set_stdio_buffering(&mut self, fd: FileDescriptor, mode: BufferMode) -> Result<(), i32> {
+        match self.stream_for_fd(fd) {
+            Some(stream) => stream.set_mode(mode),
+            None => Err(EBADF),
         }
     }
-}
 
-fn get_or_init_proc() -> MutexGuard<'static, Proc> {
-    unsafe {
-        if GLOBAL_PROC.is_none() {
-            GLOBAL_PROC = Some(Mutex::new(Proc::new()));
+    /// Pushes out anything held back by line or full buffering
+    pub fn flush_stdio(&mut self) {
+        for slot in 0..self.fd_table.len() {
+            if let Some(stream) = self.stream_for_fd(slot as FileDescriptor) {
+                let _ = stream.flush();
+            }
         }
-        GLOBAL_PROC.as_ref().unwrap().lock()
     }
-}
 
-fn inode_kind_to_dtype(kind: &InodeKind) -> u8 {
-    match kind {
-        InodeKind::File => 8,         // DT_REG
-        InodeKind::Directory => 4,    // DT_DIR
-        InodeKind::SymbolicLink(_) => 10, // DT_LNK
+    /// Makes fds 0, 1 and 2 a terminal that writes (and echoes) to
+    /// `output`. Keystrokes are fed in with `tty_input`.
+    pub fn attach_tty(&mut self, output: Box<dyn Stream>) {
+        self.tty = Some(Tty::new(BufferedStream::new(output, BufferMode::Unbuffered)));
+        // Shows up like /dev/pts/0 in fstat
+        let kind = InodeKind::CharDevice { major: device::UNIX98_PTY_SLAVE_MAJOR, minor: 0 };
+        let inode_number = self.fs.borrow_mut().create_anon_inode(kind, 0o620);
+        for fd in 0..3 {
+            self.close_fd(fd);
+            self.install_handle(
+                fd,
+                OpenFileHandle {
+                    inode_number,
+                    description: OpenFileDescription::new(0, false, false),
+                    kind: HandleKind::Tty,
+                    cloexec: false,
+                    awaiting_peer: false,
+                    writable: true,
+                    access: O_RDWR,
+                },
+            );
+        }
     }
-}
 
-fn fill_stat_from_inode(inode: &Inode, statbuf: *mut Stat) {
-    let mode_type = match inode.kind {
-        InodeKind::File => 0o100000,       // regular file
-        InodeKind::Directory => 0o040000,  // directory
-        InodeKind::SymbolicLink(_) => 0o120000, // symlink
-    };
-    let perms = &inode.permissions;
-    let mut mode_perms = 0;
-    // owner
-    if perms.owner.read { mode_perms |= 0o400; }
-    if perms.owner.write { mode_perms |= 0o200; }
-    if perms.owner.execute { mode_perms |= 0o100; }
-    // group
-    if perms.group.read { mode_perms |= 0o040; }
-    if perms.group.write { mode_perms |= 0o020; }
-    if perms.group.execute { mode_perms |= 0o010; }
-    // other
-    if perms.other.read { mode_perms |= 0o004; }
-    if perms.other.write { mode_perms |= 0o002; }
-    if perms.other.execute { mode_perms |= 0o001; }
+    /// Reports every change at or below `root` to `observer`, either as
+    /// it happens or in batches. Returns the id for
+    /// `unsubscribe_changes`.
+    pub fn subscribe_changes(&mut self, root: &str, batching: Batching, observer: Box<dyn ChangeObserver>) -> u32 {
+        let root = self.get_absolute_path(&PathBuf::from(root));
+        self.changes.subscribe(root, batching, observer)
+    }
 
-    let st_mode = mode_type | mode_perms;
+    /// Ends a subscription, delivering anything it still holds
+    pub fn unsubscribe_changes(&mut self, id: u32) -> Result<(), i32> {
+        self.changes.unsubscribe(id)
+    }
 
-    unsafe {
-        (*statbuf).st_dev = 0;
-        (*statbuf).st_ino = inode.number;
-        (*statbuf).st_mode = st_mode;
-        (*statbuf).st_nlink = 1;
-        (*statbuf).st_uid = inode.user_id;
-        (*statbuf).st_gid = inode.group_id;
-        (*statbuf).st_rdev = 0;
-        (*statbuf).st_size = inode.size as i64;
-        (*statbuf).st_blksize = 4096;
-        (*statbuf).st_blocks = (inode.size as i64 + 511)/512;
-        (*statbuf).st_atime = inode.atime as i64;
-        (*statbuf).st_mtime = inode.mtime as i64;
-        (*statbuf).st_ctime = inode.ctime as i64;
+    /// Puts `object` in the fd table at the lowest free descriptor.
+    /// `flags` may hold O_NONBLOCK and O_CLOEXEC; the object is closed
+    /// along with its last descriptor. Fails with ENFILE once
+    /// OBJECTS_CAP objects are open.
+    pub fn install_object(&mut self, object: Box<dyn FdObject>, flags: i32) -> Result<FileDescriptor, i32> {
+        if flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+            return Err(EINVAL);
+        }
+        if self.objects.is_full() {
+            return Err(ENFILE);
+        }
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        let inode_number = self.fs.borrow_mut().create_anon_inode(InodeKind::File, 0o600);
+        self.objects.insert(inode_number, object);
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, (flags & O_NONBLOCK) == O_NONBLOCK),
+                kind: HandleKind::Object,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer: false,
+                writable: true,
+                access: O_RDWR,
+            },
+        );
+        Ok(fd)
     }
-}
 
-#[no_mangle]
-pub unsafe extern "C" fn wasm_vfs_init_proc(_size: u32) -> *const u8 {
-    unimplemented!()
-}
+    /// The object behind `fd`, for calls the fd table doesn't route
+    /// (bind, connect, ...). Any other open descriptor is EINVAL.
+    pub fn object_mut(&mut self, fd: FileDescriptor) -> Result<&mut dyn FdObject, i32> {
+        let handle = self.open_files.get(&fd).ok_or(EBADF)?;
+        if handle.kind != HandleKind::Object {
+            return Err(EINVAL);
+        }
+        let inode_number = handle.inode_number;
+        self.objects.get_mut(&inode_number).map(|object| object.as_mut()).ok_or(EBADF)
+    }
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_open(path: *const i8, flags: i32, mode: u32) -> i32 {
-    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
-    let path_buf = PathBuf::from(path_str);
+    /// `object_mut` for an object of a known type; another type is
+    /// EINVAL too
+    pub fn object_as<T: FdObject>(&mut self, fd: FileDescriptor) -> Result<&mut T, i32> {
+        let object: &mut dyn Any = self.object_mut(fd)?;
+        object.downcast_mut::<T>().ok_or(EINVAL)
+    }
 
-    let mut proc = get_or_init_proc();
+    /// Delivers the changes batched subscriptions are holding back
+    pub fn flush_changes(&mut self) {
+        self.changes.flush();
+    }
 
-    let should_create = (flags & O_CREAT) == O_CREAT;
-    let should_truncate = (flags & O_TRUNC) == O_TRUNC;
-    let append_mode = (flags & O_APPEND) == O_APPEND;
+    pub fn tty(&mut self) -> Option<&mut Tty> {
+        self.tty.as_mut()
+    }
 
-    // 1) Determine the inode_number
-    let inode_number = if let Some(inode_num) = proc.fs.lookup_inode_by_path(&path_buf) {
-        inode_num
-    } else if should_create {
-        proc.fs.create_file(&path_buf, mode)
-    } else {
-        return -1;
-    };
+    /// Host keystrokes for the terminal; false if none is attached
+    pub fn tty_input(&mut self, bytes: &[u8]) -> bool {
+        match self.tty.as_mut() {
+            Some(tty) => {
+                tty.input(bytes);
+                true
+            }
+            None => false,
+        }
+    }
 
-    // 2) Possibly truncate
-    if should_truncate {
-        if let Some(data) = proc.fs.files.get_mut(&inode_number) {
-            data.clear();
+    // ENFILE when the stream table is full, EMFILE when the handle
+    // table is
+    fn install_stream(&mut self, fd: FileDescriptor, stream: BufferedStream) -> Result<(), i32> {
+        if self.streams.is_full() {
+            return Err(ENFILE);
         }
-    } else {
-        if !proc.fs.files.contains_key(&inode_number) {
-            proc.fs.files.insert(inode_number, vec![]);
+        if self.open_files.is_full() {
+            return Err(EMFILE);
         }
+        // Host streams look like the pipes a redirected process sees
+        let inode_number = self.fs.borrow_mut().create_anon_inode(InodeKind::Pipe, 0o600);
+        self.streams.insert(inode_number, stream);
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, false),
+                kind: HandleKind::Stream,
+                cloexec: false,
+                awaiting_peer: false,
+                writable: true,
+                access: O_RDWR,
+            },
+        );
+        Ok(())
     }
 
-    // 3) Allocate FD
-    let fd = match proc.allocate_fd() {
-        Some(x) => x,
-        None => return -1,
-    };
-    proc.fd_table[fd as usize] = Some(inode_number);
+    fn stream_for_fd(&mut self, fd: FileDescriptor) -> Option<&mut BufferedStream> {
+        let inode_number = match self.open_files.get(&fd) {
+            Some(h) if h.kind == HandleKind::Stream => h.inode_number,
+            _ => return None,
+        };
+        self.streams.get_mut(&inode_number)
+    }
 
-    // 4) If append_mode, figure out the file’s length in a short scope
-    let initial_pos = if append_mode {
-        let data_len = proc
-            .fs
-            .files
-            .get(&inode_number)
-            .map(|v| v.len())
-            .unwrap_or(0);
-        data_len as u64
-    } else {
-        0
-    };
+    fn set_errno(&mut self, errno: i32) {
+        self.errno = errno;
+    }
 
-    // 5) Finally insert the handle
-    proc.open_files.insert(
-        fd,
-        OpenFileHandle {
-            inode_number,
-            position: initial_pos,
-            append_mode,
-        },
-    );
+    // Take another reference on whatever the handle points at
+    fn retain_handle(&mut self, handle: &OpenFileHandle) {
+        if matches!(handle.kind, HandleKind::File | HandleKind::Provider) {
+            self.hold(handle.inode_number);
+        }
+        if let Some(pipe) = self.fs.borrow_mut().pipes.get_mut(&handle.inode_number) {
+            if handle.kind.reads_pipe() {
+                pipe.readers += 1;
+            }
+            if handle.kind.writes_pipe() {
+                pipe.writers += 1;
+            }
+        }
+    }
 
-    fd
-}
+    // Drop a reference taken by open/pipe/dup; the pipe buffer goes
+    // away with its last descriptor
+    fn release_handle(&mut self, handle: &OpenFileHandle) {
+        let unused = match self.fs.borrow_mut().pipes.get_mut(&handle.inode_number) {
+            Some(pipe) => {
+                if handle.kind.reads_pipe() {
+                    pipe.readers = pipe.readers.saturating_sub(1);
+                }
+                if handle.kind.writes_pipe() {
+                    pipe.writers = pipe.writers.saturating_sub(1);
+                }
+                pipe.readers == 0 && pipe.writers == 0
+            }
+            None => false,
+        };
+        if unused {
+            self.fs.borrow_mut().pipes.remove(&handle.inode_number);
+        }
 
+        if handle.kind == HandleKind::File {
+            self.write_back_inode(handle.inode_number);
+        }
+        if matches!(handle.kind, HandleKind::File | HandleKind::Provider) {
+            self.release_unnamed(handle.inode_number);
+        }
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_close(fd: i32) -> i32 {
-    let mut proc = get_or_init_proc();
+        match handle.kind {
+            HandleKind::Inotify | HandleKind::Epoll | HandleKind::EventFd | HandleKind::TimerFd => {
+                let still_open = self.open_files.iter()
+                    .any(|(_, h)| h.kind == handle.kind && h.inode_number == handle.inode_number);
+                if !still_open {
+                    self.inotify.remove(&handle.inode_number);
+                    self.epolls.remove(&handle.inode_number);
+                    self.eventfds.remove(&handle.inode_number);
+                    self.timers.remove(&handle.inode_number);
+                }
+            }
+            HandleKind::Object => {
+                let still_open = self.open_files.iter()
+                    .any(|(_, h)| h.kind == HandleKind::Object && h.inode_number == handle.inode_number);
+                if !still_open {
+                    if let Some(mut object) = self.objects.remove(&handle.inode_number) {
+                        object.close();
+                    }
+                }
+            }
+            _ => {
+                let mask = if handle.writable { inotify::IN_CLOSE_WRITE } else { inotify::IN_CLOSE_NOWRITE };
+                self.notify_inode(handle.inode_number, mask);
+            }
+        }
 
-    if (fd as usize) < proc.fd_table.len() && proc.fd_table[fd as usize].is_some() {
-        proc.fd_table[fd as usize] = None;
-        proc.open_files.remove(&fd);
-        0
-    } else {
-        -1
+        if handle.kind == HandleKind::Device {
+            // dup'd descriptors and other opens of the node keep it open
+            let still_open = self.open_files.iter()
+                .any(|(_, h)| h.kind == HandleKind::Device && h.inode_number == handle.inode_number);
+            let numbers = if still_open { None } else { self.device_numbers(handle.inode_number) };
+            if let Some((class, major, minor)) = numbers {
+                if let Some(driver) = self.fs.borrow_mut().devices.get_mut(class, major, minor) {
+                    driver.close(minor);
+                }
+            }
+        }
+
+        if handle.kind == HandleKind::Stream {
+            let still_open = self.open_files.iter()
+                .any(|(_, h)| h.kind == HandleKind::Stream && h.inode_number == handle.inode_number);
+            if !still_open {
+                if let Some(mut stream) = self.streams.remove(&handle.inode_number) {
+                    let _ = stream.flush();
+                }
+            }
+        }
     }
-}
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_creat(path: *const i8, mode: u32) -> i32 {
-    wasm_vfs_open(path, O_WRONLY | O_CREAT | O_TRUNC, mode)
-}
-
-#[no_mangle]
-pub extern "C" fn wasm_vfs_read(fd: i32, buf: *mut u8, count: usize) -> isize {
-    let mut proc = get_or_init_proc();
-
-    let (inode_num, position) = {
-        let h = match proc.open_files.get_mut(&fd) {
-            Some(x) => x,
-            None => return -1,
-        };
-        (h.inode_number, h.position)
-    };
-
-    let data = match proc.fs.files.get(&inode_num) {
-        Some(d) => d,
-        None => return -1,
-    };
-    if position as usize >= data.len() {
-        return 0; // EOF
+    // An inode without names (unlinked, or a memfd or O_TMPFILE file)
+    // goes away once no descriptor or mapping uses it. Its number stays
+    // taken, but its data, xattrs and provider are freed. Called after
+    // any name goes, it also moves the inode's usage to wherever its
+    // remaining first name is.
+    fn release_unnamed(&mut self, inode_number: u64) {
+        let used_here = self.open_files.iter().any(|(_, h)| h.inode_number == inode_number)
+            || self.mappings.iter().any(|m| m.inode == Some(inode_number));
+        if !used_here {
+            self.attachment.borrow_mut().held.retain(|ino| *ino != inode_number);
+        }
+        let unused = self.path_of(inode_number).is_none() && !self.fs.borrow().is_held(inode_number);
+        if unused {
+            self.memfds.remove(&inode_number);
+            self.tmpfiles.remove(&inode_number);
+            self.fs.borrow_mut().files.remove(&inode_number);
+            self.fs.borrow_mut().providers.remove(&inode_number);
+            if let Some(mut inode) = self.get_inode_mut(inode_number) {
+                inode.xattrs = xattr::Xattrs::new();
+            }
+        }
+        self.fs.borrow_mut().recharge(inode_number);
     }
 
-    let to_read = core::cmp::min(count, data.len() - position as usize);
-    unsafe {
-        std::ptr::copy_nonoverlapping(data.as_ptr().add(position as usize), buf, to_read);
+    // Tells the other Procs on the filesystem that this one has the
+    // inode open or mapped, so they don't free it when it is unlinked
+    fn hold(&self, inode_number: u64) {
+        let mut attachment = self.attachment.borrow_mut();
+        if !attachment.held.contains(&inode_number) {
+            attachment.held.push(inode_number);
+        }
     }
 
-
-    if let Some(h2) = proc.open_files.get_mut(&fd) {
-        h2.position += to_read as u64;
+    fn install_handle(&mut self, fd: FileDescriptor, handle: OpenFileHandle) {
+        self.retain_handle(&handle);
+        self.fd_table[fd as usize] = Some(handle.inode_number);
+        self.open_files.insert(fd, handle);
     }
 
-    to_read as isize
-}
-
-// TODO - this is all just until we do more sophisticated, (more closely resembling native)
-//        things with stdout (stderr, and stdin)
-#[link(wasm_import_module = "env")]
-extern "C" {
-    /// Host function that receives a single line (including the trailing newline).
-    /// The host environment can print it in xterm or a console, line by line.
-    fn box_host_write_stdout_line(ptr: *const u8, len: usize);
-}
-
-static mut STDOUT_LINE_ACCUM: Vec<u8> = Vec::new();
+    // Completes a deferred FIFO rendezvous (see `awaiting_peer`)
+    fn check_rendezvous(&mut self, fd: FileDescriptor) -> Result<(), i32> {
+        let (inode_number, kind) = match self.open_files.get(&fd) {
+            Some(h) if h.awaiting_peer => (h.inode_number, h.kind),
+            _ => return Ok(()),
+        };
+        let peer_present = match self.fs.borrow().pipes.get(&inode_number) {
+            Some(pipe) if kind == HandleKind::PipeReader => pipe.writers > 0,
+            Some(pipe) => pipe.readers > 0,
+            None => false,
+        };
+        if !peer_present {
+            return Err(EAGAIN);
+        }
+        if let Some(h) = self.open_files.get_mut(&fd) {
+            h.awaiting_peer = false;
+        }
+        Ok(())
+    }
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_write(fd: i32, buf: *const u8, count: usize) -> isize {
-    // Special case: FD == 1 => "stdout"
-    if fd == 1 {
-        unsafe {
-            // Convert the incoming pointer+length into a slice
-            let incoming = core::slice::from_raw_parts(buf, count);
+    // Opens one end of a FIFO, creating the shared buffer for the first
+    // opener. Follows fifo(7): a non-blocking writer with no reader gets
+    // ENXIO, a non-blocking reader always succeeds.
+    fn open_fifo(&mut self, inode_number: u64, flags: i32) -> Result<FileDescriptor, i32> {
+        let kind = match flags & 3 {
+            O_RDONLY => HandleKind::PipeReader,
+            O_WRONLY => HandleKind::PipeWriter,
+            O_RDWR => HandleKind::PipeDuplex,
+            _ => return Err(EINVAL),
+        };
+        let nonblock = (flags & O_NONBLOCK) == O_NONBLOCK;
 
-            // Go through each byte, appending to STDOUT_LINE_ACCUM.
-            // Whenever we see '\n', we flush that line to the host function.
-            for &byte in incoming.iter() {
-                STDOUT_LINE_ACCUM.push(byte);
-                if byte == b'\n' {
-                    // We have a full line in STDOUT_LINE_ACCUM
-                    box_host_write_stdout_line(
-                        STDOUT_LINE_ACCUM.as_ptr(),
-                        STDOUT_LINE_ACCUM.len(),
-                    );
-                    // Clear the accumulator
-                    STDOUT_LINE_ACCUM.clear();
-                }
+        if !self.fs.borrow().pipes.contains_key(&inode_number) {
+            if self.fs.borrow().pipes.is_full() {
+                return Err(ENFILE);
             }
+            self.fs.borrow_mut().pipes.insert(inode_number, Pipe::new());
         }
-        return count as isize;
-    }
-
-    let mut proc = get_or_init_proc();
+        let (readers, writers) = match self.fs.borrow().pipes.get(&inode_number) {
+            Some(pipe) => (pipe.readers, pipe.writers),
+            None => return Err(ENFILE),
+        };
+        let awaiting_peer = match kind {
+            HandleKind::PipeReader => !nonblock && writers == 0,
+            HandleKind::PipeWriter if readers == 0 => {
+                if nonblock {
+                    if writers == 0 {
+                        self.fs.borrow_mut().pipes.remove(&inode_number);
+                    }
+                    return Err(ENXIO);
+                }
+                true
+            }
+            _ => false,
+        };
 
-    let (inode_num, old_pos, append_mode) = {
-        let h = match proc.open_files.get_mut(&fd) {
+        let fd = match self.allocate_fd() {
             Some(x) => x,
-            None => return -1,
+            None => {
+                if readers == 0 && writers == 0 {
+                    self.fs.borrow_mut().pipes.remove(&inode_number);
+                }
+                return Err(EMFILE);
+            }
         };
-        (h.inode_number, h.position, h.append_mode)
-    };
-
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, nonblock),
+                kind,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer,
+                writable: kind.writes_pipe(),
+                access: flags & O_ACCMODE,
+            },
+        );
+        Ok(fd)
+    }
 
-    let new_position = {
-        let data = match proc.fs.files.get_mut(&inode_num) {
-            Some(d) => d,
-            None => return -1,
-        };
-        let mut actual_pos = if append_mode { data.len() as u64 } else { old_pos };
-        if actual_pos as usize + count > data.len() {
-            data.resize(actual_pos as usize + count, 0);
-        }
-        unsafe {
-            core::ptr::copy_nonoverlapping(buf, data.as_mut_ptr().add(actual_pos as usize), count);
+    fn open_provider(&mut self, inode_number: u64, flags: i32) -> Result<FileDescriptor, i32> {
+        {
+            let mut fs = self.fs.borrow_mut();
+            let provider = fs.providers.get_mut(&inode_number).ok_or(EBADF)?;
+            if (flags & O_TRUNC) == O_TRUNC {
+                provider.truncate(0)?;
+            }
         }
-        actual_pos += count as u64;
-        actual_pos
-    };
-
-    if let Some(handle2) = proc.open_files.get_mut(&fd) {
-        handle2.position = new_position;
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, (flags & O_APPEND) == O_APPEND, (flags & O_NONBLOCK) == O_NONBLOCK),
+                kind: HandleKind::Provider,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer: false,
+                writable: (flags & O_ACCMODE) != O_RDONLY,
+                access: flags & O_ACCMODE,
+            },
+        );
+        Ok(fd)
     }
-    let final_size = proc.fs.files.get(&inode_num).map(|v| v.len()).unwrap_or(0);
-    if let Some(inode) = proc.fs.inodes.get_mut(inode_num as usize) {
-        inode.size = final_size as u64;
+
+    fn provider_read(&mut self, inode_number: u64, buf: &mut [u8], offset: u64) -> Result<usize, i32> {
+        let mut fs = self.fs.borrow_mut();
+        let provider = fs.providers.get_mut(&inode_number).ok_or(EBADF)?;
+        provider.read_at(buf, offset)
     }
-    count as isize
-}
 
+    // Appends go to the provider's current size, like O_APPEND on a file
+    fn provider_write(&mut self, inode_number: u64, data: &[u8], offset: u64, append: bool) -> Result<(usize, u64), i32> {
+        let mut fs = self.fs.borrow_mut();
+        let provider = fs.providers.get_mut(&inode_number).ok_or(EBADF)?;
+        let offset = if append { provider.size() } else { offset };
+        let n = provider.write_at(data, offset)?;
+        Ok((n, offset + n as u64))
+    }
 
+    // Provider files report whatever size the provider has right now
+    fn refresh_provider_size(&mut self, inode_number: u64) {
+        let size = match self.fs.borrow().providers.get(&inode_number) {
+            Some(provider) => provider.size(),
+            None => return,
+        };
+        if let Some(mut inode) = self.get_inode_mut(inode_number) {
+            inode.size = size;
+        }
+    }
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_openat(_dirfd: i32, pathname: *const i8, flags: i32, mode: u32) -> i32 {
-    wasm_vfs_open(pathname, flags, mode)
-}
+    // st_blocks for an inode. Stored files count only their allocated
+    // pages, so holes take no blocks; other inodes are assumed dense.
+    fn blocks_of(&self, inode_number: u64) -> u64 {
+        if !self.fs.borrow().providers.contains_key(&inode_number) {
+            if let Some(data) = self.fs.borrow().files.get(&inode_number) {
+                return data.allocated_pages() * (PAGE_SIZE / 512);
+            }
+        }
+        self.get_inode(inode_number).map(|inode| inode.size.div_ceil(512)).unwrap_or(0)
+    }
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_dup(oldfd: i32) -> i32 {
-    let mut proc = get_or_init_proc();
+    // Opens a device node. /dev/tty resolves to the attached terminal,
+    // so it shares state with fds 0-2; everything else goes to the
+    // registered driver, and ENXIO if there is none.
+    fn open_device(&mut self, inode_number: u64, class: DeviceClass, major: u32, minor: u32, flags: i32) -> Result<FileDescriptor, i32> {
+        let kind = if class == DeviceClass::Char && (major, minor) == (device::TTYAUX_MAJOR, device::TTY_MINOR) {
+            if self.tty.is_none() {
+                return Err(ENXIO);
+            }
+            HandleKind::Tty
+        } else {
+            let mut fs = self.fs.borrow_mut();
+            let driver = fs.devices.get_mut(class, major, minor).ok_or(ENXIO)?;
+            driver.open(minor, flags)?;
+            HandleKind::Device
+        };
 
-    if (oldfd as usize) < proc.fd_table.len() {
-        if let Some(inode_number) = proc.fd_table[oldfd as usize] {
-            let (inode_number, position, append_mode) = {
-                let old_handle = match proc.open_files.get(&oldfd) {
-                    Some(h) => h,
-                    None => return -1,
-                };
-                (old_handle.inode_number, old_handle.position, old_handle.append_mode)
-            };
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, (flags & O_NONBLOCK) == O_NONBLOCK),
+                kind,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer: false,
+                writable: (flags & O_ACCMODE) != O_RDONLY,
+                access: flags & O_ACCMODE,
+            },
+        );
+        Ok(fd)
+    }
 
-            if let Some(new_fd) = proc.allocate_fd() {
-                proc.fd_table[new_fd as usize] = Some(inode_number);
-                let new_handle = OpenFileHandle {
-                    inode_number,
-                    position,
-                    append_mode,
-                };
-                proc.open_files.insert(new_fd, new_handle);
-                return new_fd;
-            }
+    fn device_numbers(&self, inode_number: u64) -> Option<(DeviceClass, u32, u32)> {
+        match self.kind_of(inode_number) {
+            Some(InodeKind::CharDevice { major, minor }) => Some((DeviceClass::Char, major, minor)),
+            Some(InodeKind::BlockDevice { major, minor }) => Some((DeviceClass::Block, major, minor)),
+            _ => None,
         }
     }
-    -1
-}
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_dup2(oldfd: i32, newfd: i32) -> i32 {
-    let mut proc = get_or_init_proc();
+    // Character devices have no position, so they always see offset 0
+    fn device_read(&mut self, inode_number: u64, buf: &mut [u8], offset: u64) -> Result<usize, i32> {
+        let (class, major, minor) = self.device_numbers(inode_number).ok_or(ENXIO)?;
+        let offset = if class == DeviceClass::Block { offset } else { 0 };
+        let mut fs = self.fs.borrow_mut();
+        let driver = fs.devices.get_mut(class, major, minor).ok_or(ENXIO)?;
+        driver.read(minor, buf, offset)
+    }
 
-    if (oldfd as usize) < proc.fd_table.len() && (newfd as usize) < proc.fd_table.len() {
-        if let Some(inode_number) = proc.fd_table[oldfd as usize] {
-            if oldfd == newfd {
-                return newfd;
-            }
+    fn device_write(&mut self, inode_number: u64, data: &[u8], offset: u64) -> Result<usize, i32> {
+        let (class, major, minor) = self.device_numbers(inode_number).ok_or(ENXIO)?;
+        let offset = if class == DeviceClass::Block { offset } else { 0 };
+        let mut fs = self.fs.borrow_mut();
+        let driver = fs.devices.get_mut(class, major, minor).ok_or(ENXIO)?;
+        driver.write(minor, data, offset)
+    }
 
-            if proc.fd_table[newfd as usize].is_some() {
-                proc.fd_table[newfd as usize] = None;
-                proc.open_files.remove(&newfd);
+    // read/write on a block device moves the file position
+    fn advance_device(&mut self, fd: FileDescriptor, n: usize) {
+        let inode_number = match self.open_files.get(&fd) {
+            Some(h) => h.inode_number,
+            None => return,
+        };
+        if let Some((DeviceClass::Block, _, _)) = self.device_numbers(inode_number) {
+            if let Some(h) = self.open_files.get_mut(&fd) {
+                h.set_position(h.position() + n as u64);
             }
+        }
+    }
 
-            proc.fd_table[newfd as usize] = Some(inode_number);
+    // mkfifo/mknod: a FIFO or device node, with the umask applied
+    fn make_node(&mut self, path: &PathBuf, mode: u32, kind: InodeKind) -> Result<u64, i32> {
+        if self.lookup(path).is_some() {
+            return Err(EEXIST);
+        }
+        self.check_parent_access(path)?;
+        // Device nodes need CAP_MKNOD, which only root has here
+        if matches!(kind, InodeKind::CharDevice { .. } | InodeKind::BlockDevice { .. }) && self.uid != 0 {
+            return Err(EPERM);
+        }
+        self.charge_create(Some(path))?;
+        let inode_number = self.fs.borrow().next_inode_number;
+        self.fs.borrow_mut().next_inode_number += 1;
+
+        self.insert_directory_entry(path, inode_number, kind);
+        let adjusted_mode = (mode & 0o777 & !self.umask_value) as u16;
+        if let Some(mut inode) = self.get_inode_mut(inode_number) {
+            inode.permissions = Permissions::from(adjusted_mode);
+        }
+        self.init_new_inode(path, inode_number, mode);
+        Ok(inode_number)
+    }
 
-            if let Some(old_handle) = proc.open_files.get(&oldfd) {
-                let new_handle = OpenFileHandle {
-                    inode_number: old_handle.inode_number,
-                    position: old_handle.position,
-                    append_mode: old_handle.append_mode,
-                };
-                proc.open_files.insert(newfd, new_handle);
+    fn close_fd(&mut self, fd: FileDescriptor) -> bool {
+        if fd < 0 || (fd as usize) >= self.fd_table.len() || self.fd_table[fd as usize].is_none() {
+            return false;
+        }
+        self.fd_table[fd as usize] = None;
+        if let Some(handle) = self.open_files.remove(&fd) {
+            self.release_handle(&handle);
+        }
+        // Its /proc/self/fd link goes now rather than at the next lookup
+        if self.procfs_mounted {
+            self.remove_fd_link(&PathBuf::from(format!("/proc/self/fd/{}", fd)));
+        }
+        // A closed descriptor leaves every epoll interest list
+        let epolls: Vec<u64> = self.epolls.iter().map(|(ino, _)| *ino).collect();
+        for ino in epolls {
+            if let Some(epoll) = self.epolls.get_mut(&ino) {
+                epoll.forget(fd);
             }
-
-            return newfd;
         }
+        true
     }
-    -1
-}
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_pread64(fd: i32, buf: *mut u8, count: usize, offset: i64) -> isize {
-    let mut proc = get_or_init_proc();
+    // Copies a byte range of a regular file into a pipe. Only the
+    // bytes that fit are touched; the file is never cloned.
+    fn file_to_pipe(&mut self, file_ino: u64, pos: u64, pipe_ino: u64, len: usize) -> Result<usize, i32> {
+        let mut fs = self.fs.borrow_mut();
+        let FileSystem { files, pipes, .. } = &mut *fs;
+        let data = files.get(&file_ino).ok_or(EBADF)?;
+        let pipe = pipes.get_mut(&pipe_ino).ok_or(EBADF)?;
+        if pos >= data.len() {
+            return Ok(0);
+        }
+        let available = core::cmp::min(len as u64, data.len() - pos) as usize;
+        let n = pipe.writable(available)?;
+        pipe.write(&data.read_vec(pos, n))
+    }
 
-    let inode_num = {
-        let h = match proc.open_files.get(&fd) {
-            Some(x) => x,
-            None => return -1,
+    // Drains up to `len` bytes of a pipe into a regular file at `pos`
+    fn pipe_to_file(&mut self, pipe_ino: u64, file_ino: u64, pos: u64, len: usize) -> Result<usize, i32> {
+        let available = self.fs.borrow().pipes.get(&pipe_ino).map(|pipe| min(len, pipe.len())).unwrap_or(0);
+        if available > 0 {
+            self.check_seals_write(file_ino, pos, available as u64)?;
+            self.charge_write(file_ino, pos, available as u64)?;
+        }
+        let n = {
+            let mut fs = self.fs.borrow_mut();
+            let FileSystem { files, pipes, .. } = &mut *fs;
+            let pipe = pipes.get_mut(&pipe_ino).ok_or(EBADF)?;
+            let data = files.get_mut(&file_ino).ok_or(EBADF)?;
+            if pipe.is_empty() {
+                return if pipe.writers == 0 { Ok(0) } else { Err(EAGAIN) };
+            }
+            let n = min(len, pipe.len());
+            data.write_at(&pipe.as_slice()[..n], pos)?;
+            pipe.consume(n);
+            n
         };
-        h.inode_number
-    };
-
-    let data = match proc.fs.files.get(&inode_num) {
-        Some(d) => d,
-        None => return -1,
-    };
-
-    let position = offset as usize;
-    if position >= data.len() {
-        return 0;
-    }
-    let to_read = core::cmp::min(count, data.len() - position);
-    unsafe {
-        std::ptr::copy_nonoverlapping(data.as_ptr().add(position), buf, to_read);
+        self.sync_file_size(file_ino);
+        self.data_changed(file_ino, pos, n as u64);
+        Ok(n)
     }
-    to_read as isize
-}
-
-#[no_mangle]
-pub extern "C" fn wasm_vfs_pwrite64(fd: i32, buf: *const u8, count: usize, offset: i64) -> isize {
-    let mut proc = get_or_init_proc();
-
 
-    let inode_num = {
-        let handle = match proc.open_files.get(&fd) {
-            Some(h) => h,
-            None => return -1,
+    // Copies a range between two regular files. Page-aligned stretches
+    // are shared copy-on-write; the rest is copied a page at a time.
+    fn file_to_file(&mut self, from: u64, read_pos: u64, to: u64, write_pos: u64, len: u64) -> Result<usize, i32> {
+        // Cloning the source is O(1) and lets it be the destination too
+        let src = self.fs.borrow().files.get(&from).ok_or(EBADF)?.clone();
+        let n = core::cmp::min(len, src.len().saturating_sub(read_pos));
+        if n > 0 {
+            self.check_seals_write(to, write_pos, n)?;
+        }
+        self.charge_write(to, write_pos, n)?;
+        let n = {
+            let mut fs = self.fs.borrow_mut();
+            let out = fs.files.get_mut(&to).ok_or(EBADF)?;
+            out.copy_from(&src, read_pos, write_pos, len)?
         };
-        handle.inode_number
-    };
+        self.sync_file_size(to);
+        self.data_changed(to, write_pos, n as u64);
+        Ok(n)
+    }
 
-    {
-        let data = match proc.fs.files.get_mut(&inode_num) {
-            Some(d) => d,
-            None => return -1,
+    // Keeps inode.size and the quota usage in step with the stored data
+    fn sync_file_size(&mut self, inode_number: u64) {
+        let len = match self.fs.borrow().files.get(&inode_number) {
+            Some(data) => data.len(),
+            None => return,
         };
-        let position = offset as usize;
-        if position + count > data.len() {
-            data.resize(position + count, 0);
-        }
-        unsafe {
-            std::ptr::copy_nonoverlapping(buf, data.as_mut_ptr().add(position), count);
+        if let Some(mut inode) = self.get_inode_mut(inode_number) {
+            inode.size = len;
         }
+        self.fs.borrow_mut().recharge(inode_number);
     }
 
-
-    {
-        let final_len = proc.fs.files.get(&inode_num).map(|v| v.len()).unwrap_or(0);
-        if let Some(inode) = proc.fs.inodes.get_mut(inode_num as usize) {
-            inode.size = final_len as u64;
+    // The regular file behind `fd`, for copy_file_range and reflinks.
+    // `access` is O_RDONLY for a source and O_WRONLY for a destination;
+    // a descriptor not opened for it is EBADF.
+    fn regular_file(&self, fd: FileDescriptor, access: i32) -> Result<(u64, u64, bool), i32> {
+        let handle = self.open_files.get(&fd).ok_or(EBADF)?;
+        let denied = if access == O_RDONLY { handle.access == O_WRONLY } else { handle.access == O_RDONLY };
+        if denied {
+            return Err(EBADF);
         }
+        match self.kind_of(handle.inode_number) {
+            Some(InodeKind::Directory) => return Err(EISDIR),
+            Some(InodeKind::File) if handle.kind == HandleKind::File
+                && self.fs.borrow().files.contains_key(&handle.inode_number) => {}
+            _ => return Err(EINVAL),
+        }
+        Ok((handle.inode_number, handle.position(), handle.append_mode()))
     }
 
-    count as isize
-}
+    // Copies up to `len` bytes between regular files. When the range is
+    // the whole source and covers all of the destination, the two
+    // inodes share the whole file copy-on-write; otherwise aligned
+    // pages are shared one by one.
+    fn clone_range(&mut self, from: u64, src_off: u64, to: u64, dst_off: u64, len: u64) -> Result<usize, i32> {
+        let src_len = self.fs.borrow().files.get(&from).ok_or(EBADF)?.len();
+        if src_off >= src_len {
+            return Ok(0);
+        }
+        let n = core::cmp::min(len, src_len - src_off);
+        self.check_seals_write(to, dst_off, n)?;
+        let dst_len = self.fs.borrow().files.get(&to).ok_or(EBADF)?.len();
+        if from != to && src_off == 0 && dst_off == 0 && n == src_len && dst_len <= n {
+            let shared = self.fs.borrow().files.get(&from).ok_or(EBADF)?.clone();
+            let dst_pages = self.fs.borrow().files.get(&to).ok_or(EBADF)?.allocated_pages();
+            self.charge(to, shared.allocated_pages().saturating_sub(dst_pages) * PAGE_SIZE)?;
+            self.fs.borrow_mut().files.insert(to, shared);
+            self.sync_file_size(to);
+            self.data_changed(to, 0, n);
+            return Ok(n as usize);
+        }
+        self.file_to_file(from, src_off, to, dst_off, n)
+    }
 
+    // copy_file_range: descriptors without an explicit offset use and
+    // advance their file position
+    fn copy_file_range(&mut self, fd_in: FileDescriptor, off_in: Option<i64>, fd_out: FileDescriptor, off_out: Option<i64>, len: usize) -> Result<usize, i32> {
+        let (from, in_pos, _) = self.regular_file(fd_in, O_RDONLY)?;
+        let (to, out_pos, out_append) = self.regular_file(fd_out, O_WRONLY)?;
+        if out_append {
+            return Err(EBADF);
+        }
+        let read_pos = off_in.unwrap_or(in_pos as i64);
+        let write_pos = off_out.unwrap_or(out_pos as i64);
+        if read_pos < 0 || write_pos < 0 {
+            return Err(EINVAL);
+        }
+        let (read_pos, write_pos, len) = (read_pos as u64, write_pos as u64, len as u64);
+        let read_end = read_pos.checked_add(len).ok_or(EOVERFLOW)?;
+        let write_end = write_pos.checked_add(len).ok_or(EOVERFLOW)?;
+        if from == to && read_pos < write_end && write_pos < read_end {
+            return Err(EINVAL);
+        }
+        let n = self.clone_range(from, read_pos, to, write_pos, len)?;
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_sendfile(
-    out_fd: i32,
-    in_fd: i32,
-    offset: *mut i64,
-    count: usize
-) -> isize {
-    let mut proc = get_or_init_proc();
+        if off_in.is_none() {
+            if let Some(h) = self.open_files.get_mut(&fd_in) {
+                h.set_position(h.position() + n as u64);
+            }
+        }
+        if off_out.is_none() {
+            if let Some(h) = self.open_files.get_mut(&fd_out) {
+                h.set_position(h.position() + n as u64);
+            }
+        }
+        Ok(n)
+    }
 
-    let (in_inode_number, in_pos, in_app) = {
-        let h_in = match proc.open_files.get(&in_fd) {
-            Some(h) => h,
-            None => return -1,
+    // FICLONE (whole file) and FICLONERANGE onto `dest_fd`. Offsets
+    // and length must be block aligned, except a range ending at EOF.
+    fn reflink(&mut self, dest_fd: FileDescriptor, src_fd: FileDescriptor, range: Option<FileCloneRange>) -> Result<(), i32> {
+        let (from, _, _) = self.regular_file(src_fd, O_RDONLY)?;
+        let (to, _, append) = self.regular_file(dest_fd, O_WRONLY)?;
+        if append {
+            return Err(EBADF);
+        }
+        let src_len = self.fs.borrow().files.get(&from).ok_or(EBADF)?.len();
+        let (src_off, dst_off, len) = match range {
+            None => (0, 0, src_len),
+            Some(r) => {
+                let len = if r.src_length == 0 { src_len.saturating_sub(r.src_offset) } else { r.src_length };
+                (r.src_offset, r.dest_offset, len)
+            }
         };
-        (h_in.inode_number, h_in.position, h_in.append_mode)
-    };
+        let end = src_off.checked_add(len).ok_or(EINVAL)?;
+        let dst_end = dst_off.checked_add(len).ok_or(EINVAL)?;
+        if end > src_len {
+            return Err(EINVAL);
+        }
+        let aligned = src_off % CLONE_ALIGN == 0 && dst_off % CLONE_ALIGN == 0
+            && (len % CLONE_ALIGN == 0 || end == src_len);
+        if !aligned {
+            return Err(EINVAL);
+        }
+        // Overlapping ranges within one file can't be shared
+        if from == to && src_off < dst_end && dst_off < end {
+            return Err(EINVAL);
+        }
+        self.clone_range(from, src_off, to, dst_off, len)?;
+        Ok(())
+    }
 
-    let (out_inode_number, out_pos, out_app) = {
-        let h_out = match proc.open_files.get(&out_fd) {
-            Some(h) => h,
-            None => return -1,
-        };
-        (h_out.inode_number, h_out.position, h_out.append_mode)
-    };
+    // fallocate(2) on a regular file. The mode checks follow Linux's
+    // order, so unsupported combinations fail the same way.
+    fn fallocate(&mut self, fd: FileDescriptor, mode: i32, offset: i64, len: i64) -> Result<(), i32> {
+        if offset < 0 || len <= 0 {
+            return Err(EINVAL);
+        }
+        if mode & !FALLOC_FL_SUPPORTED != 0 {
+            return Err(EOPNOTSUPP);
+        }
+        if mode & FALLOC_FL_PUNCH_HOLE != 0 && mode & FALLOC_FL_ZERO_RANGE != 0 {
+            return Err(EOPNOTSUPP);
+        }
+        if mode & FALLOC_FL_PUNCH_HOLE != 0 && mode & FALLOC_FL_KEEP_SIZE == 0 {
+            return Err(EOPNOTSUPP);
+        }
+        for exclusive in [FALLOC_FL_COLLAPSE_RANGE, FALLOC_FL_INSERT_RANGE] {
+            if mode & exclusive != 0 && mode != exclusive {
+                return Err(EINVAL);
+            }
+        }
 
-    let in_data = match proc.fs.files.get(&in_inode_number) {
-        Some(d) => d.clone(),
-        None => return -1,
-    };
+        let handle = self.open_files.get(&fd).ok_or(EBADF)?;
+        if !handle.writable {
+            return Err(EBADF);
+        }
+        // Shifting data would rewrite what an appender already wrote
+        if handle.append_mode() && mode & (FALLOC_FL_COLLAPSE_RANGE | FALLOC_FL_INSERT_RANGE) != 0 {
+            return Err(EPERM);
+        }
+        let inode_number = handle.inode_number;
+        match self.kind_of(inode_number) {
+            Some(InodeKind::Pipe | InodeKind::Fifo) => return Err(ESPIPE),
+            Some(InodeKind::Directory) => return Err(EISDIR),
+            Some(InodeKind::File) if handle.kind == HandleKind::File => {}
+            Some(InodeKind::File) => return Err(EOPNOTSUPP),
+            _ => return Err(ENODEV),
+        }
+        let (offset, len) = (offset as u64, len as u64);
+        let end = offset.checked_add(len)
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(EFBIG)?;
+
+        let size = self.file_len(inode_number);
+        if mode & (FALLOC_FL_PUNCH_HOLE | FALLOC_FL_ZERO_RANGE | FALLOC_FL_COLLAPSE_RANGE | FALLOC_FL_INSERT_RANGE) != 0 {
+            self.check_seals_write(inode_number, offset, 0)?;
+        }
+        let new_size = match mode {
+            FALLOC_FL_COLLAPSE_RANGE => size.saturating_sub(len),
+            FALLOC_FL_INSERT_RANGE => size.saturating_add(len),
+            _ if mode & FALLOC_FL_KEEP_SIZE == 0 => core::cmp::max(size, end),
+            _ => size,
+        };
+        self.check_seals_resize(inode_number, new_size)?;
+        if mode & (FALLOC_FL_PUNCH_HOLE | FALLOC_FL_COLLAPSE_RANGE | FALLOC_FL_INSERT_RANGE) == 0 {
+            self.charge_write(inode_number, offset, len)?;
+        }
 
-    let mut read_pos = if !offset.is_null() {
-        unsafe { *offset as usize }
-    } else {
-        in_pos as usize
-    };
-    if read_pos >= in_data.len() {
-        return 0;
+        {
+            let mut fs = self.fs.borrow_mut();
+            let data = fs.files.get_mut(&inode_number).ok_or(EBADF)?;
+            if mode & (FALLOC_FL_COLLAPSE_RANGE | FALLOC_FL_INSERT_RANGE) != 0 {
+                if offset % PAGE_SIZE != 0 || len % PAGE_SIZE != 0 {
+                    return Err(EINVAL);
+                }
+                if mode == FALLOC_FL_COLLAPSE_RANGE {
+                    // The range can't reach EOF; that would be a truncate
+                    if end >= data.len() {
+                        return Err(EINVAL);
+                    }
+                    data.collapse_range(offset, len);
+                } else {
+                    if offset >= data.len() {
+                        return Err(EINVAL);
+                    }
+                    data.insert_range(offset, len)?;
+                }
+            } else {
+                if mode & (FALLOC_FL_PUNCH_HOLE | FALLOC_FL_ZERO_RANGE) != 0 {
+                    data.punch_hole(offset, len);
+                }
+                if mode & FALLOC_FL_PUNCH_HOLE == 0 {
+                    data.allocate(offset, len);
+                    if mode & FALLOC_FL_KEEP_SIZE == 0 && end > data.len() {
+                        data.set_len(end);
+                    }
+                }
+            }
+        }
+        self.sync_file_size(inode_number);
+        // Everything from `offset` on may have moved or been zeroed
+        self.data_changed(inode_number, offset, u64::MAX);
+        Ok(())
     }
 
-    let to_copy = core::cmp::min(count, in_data.len() - read_pos);
-
-    {
-        let out_data = match proc.fs.files.get_mut(&out_inode_number) {
-            Some(d) => d,
-            None => return -1,
+    // Sets up a mapping of at least `len` bytes and returns its address.
+    // Without MAP_FIXED the address is only a hint and is ignored; with
+    // it, the range must already be mapped from a single region, whose
+    // memory is then reused.
+    fn mmap(&mut self, addr: usize, len: usize, prot: i32, flags: i32, fd: FileDescriptor, offset: i64) -> Result<usize, i32> {
+        let shared = match flags & mmap::MAP_TYPE {
+            mmap::MAP_SHARED => true,
+            mmap::MAP_SHARED_VALIDATE if flags & !mmap::MAP_KNOWN != 0 => return Err(EOPNOTSUPP),
+            mmap::MAP_SHARED_VALIDATE => true,
+            mmap::MAP_PRIVATE => false,
+            _ => return Err(EINVAL),
         };
+        if len == 0 || offset < 0 || !mmap::is_page_aligned(offset as usize) {
+            return Err(EINVAL);
+        }
+        let size = mmap::page_align(len).ok_or(ENOMEM)?;
+        (offset as u64).checked_add(size as u64)
+            .filter(|end| *end <= MAX_FILE_SIZE)
+            .ok_or(EOVERFLOW)?;
 
-        let real_out_pos = if out_app {
-            out_data.len()
+        let inode = if flags & mmap::MAP_ANONYMOUS != 0 {
+            None
         } else {
-            out_pos as usize
+            let handle = self.open_files.get(&fd).ok_or(EBADF)?;
+            let inode_number = handle.inode_number;
+            let mappable = handle.kind == HandleKind::File
+                && matches!(self.kind_of(inode_number), Some(InodeKind::File))
+                && self.fs.borrow().files.contains_key(&inode_number);
+            if !mappable {
+                return Err(ENODEV);
+            }
+            // Every mapping reads the file; a shared writable one also
+            // writes it back
+            if shared && prot & mmap::PROT_WRITE != 0 && (handle.access == O_RDONLY || handle.append_mode()) {
+                return Err(EACCES);
+            }
+            if handle.access == O_WRONLY {
+                return Err(EACCES);
+            }
+            let write_sealed = self.memfds.get(&inode_number).map(|m| m.write_sealed()).unwrap_or(false);
+            if shared && prot & mmap::PROT_WRITE != 0 && write_sealed {
+                return Err(EPERM);
+            }
+            Some(inode_number)
         };
 
-        if real_out_pos + to_copy > out_data.len() {
-            out_data.resize(real_out_pos + to_copy, 0);
-        }
-        out_data[real_out_pos..real_out_pos + to_copy]
-            .copy_from_slice(&in_data[read_pos..read_pos + to_copy]);
-
-        if !offset.is_null() {
-            unsafe {
-                *offset += to_copy as i64;
+        let fixed = flags & mmap::MAP_FIXED != 0;
+        let (addr, region, replaced) = if fixed {
+            if !mmap::is_page_aligned(addr) {
+                return Err(EINVAL);
             }
+            let end = addr.checked_add(size).ok_or(ENOMEM)?;
+            let region = self.region_of(addr, end).ok_or(EINVAL)?;
+            (addr, region, self.unmap_range(addr, end))
         } else {
+            let region = Region::alloc(size).ok_or(ENOMEM)?;
+            (region.base, region, Vec::new())
+        };
+        let mapping = Mapping { addr, len: size, prot, shared, inode, offset: offset as u64, region };
+        self.populate(&mapping, mapping.addr, mapping.end());
+        if let Some(inode_number) = inode {
+            self.hold(inode_number);
         }
+        self.mappings.push(mapping);
+        self.publish_mappings();
+        self.release_regions(replaced);
+        Ok(addr)
     }
 
-
-    if offset.is_null() {
-        if let Some(h_in_mut) = proc.open_files.get_mut(&in_fd) {
-            h_in_mut.position = in_pos + to_copy as u64;
+    fn munmap(&mut self, addr: usize, len: usize) -> Result<(), i32> {
+        if len == 0 || !mmap::is_page_aligned(addr) {
+            return Err(EINVAL);
         }
+        let end = mmap::page_align(len).and_then(|len| addr.checked_add(len)).ok_or(EINVAL)?;
+        let regions = self.unmap_range(addr, end);
+        self.release_regions(regions);
+        Ok(())
     }
 
-    if let Some(h_out_mut) = proc.open_files.get_mut(&out_fd) {
-        if out_app {
-            let new_len = {
-            };
-        } else {
-            h_out_mut.position = out_pos + to_copy as u64;
+    // Writes MAP_SHARED changes in the range back to their files. Other
+    // shared mappings of a file are updated as part of every write-back,
+    // so MS_INVALIDATE needs no extra work.
+    fn msync(&mut self, addr: usize, len: usize, flags: i32) -> Result<(), i32> {
+        let known = mmap::MS_ASYNC | mmap::MS_INVALIDATE | mmap::MS_SYNC;
+        if flags & !known != 0 || (flags & mmap::MS_ASYNC != 0 && flags & mmap::MS_SYNC != 0) {
+            return Err(EINVAL);
         }
-    }
-
-    let new_len = {
-        if let Some(d) = proc.fs.files.get(&out_inode_number) {
-            d.len()
-        } else {
-            0
+        if !mmap::is_page_aligned(addr) {
+            return Err(EINVAL);
         }
-    };
-
-    if let Some(h_out_mut) = proc.open_files.get_mut(&out_fd) {
-        if out_app {
-            h_out_mut.position = new_len as u64;
-        } else {
-            h_out_mut.position = out_pos + to_copy as u64;
+        let end = mmap::page_align(len).and_then(|len| addr.checked_add(len)).ok_or(ENOMEM)?;
+        if !self.is_mapped(addr, end) {
+            return Err(ENOMEM);
         }
+        let synced: Vec<Mapping> = self.mappings.iter().filter(|m| m.overlaps(addr, end)).cloned().collect();
+        for mapping in synced {
+            self.write_back(&mapping, addr, end);
+        }
+        Ok(())
     }
 
-    to_copy as isize
-}
-
-
-#[no_mangle]
-pub extern "C" fn wasm_vfs_sendfile64(out_fd: i32, in_fd: i32, offset: *mut i64, count: usize) -> isize {
-    wasm_vfs_sendfile(out_fd, in_fd, offset, count)
-}
-
-//#[no_mangle]
-//pub extern "C" fn wasm_vfs_splice(fd_in: i32, off_in: *mut i64, fd_out: i32, off_out: *mut i64, len: usize, _flags: u32) -> isize {
-    //let mut proc = get_or_init_proc();
+    // Protection is recorded (and kept across splits) but not enforced
+    fn mprotect(&mut self, addr: usize, len: usize, prot: i32) -> Result<(), i32> {
+        if !mmap::is_page_aligned(addr) || prot & !(mmap::PROT_READ | mmap::PROT_WRITE | mmap::PROT_EXEC) != 0 {
+            return Err(EINVAL);
+        }
+        let end = mmap::page_align(len).and_then(|len| addr.checked_add(len)).ok_or(ENOMEM)?;
+        if !self.is_mapped(addr, end) {
+            return Err(ENOMEM);
+        }
+        if prot & mmap::PROT_WRITE != 0 {
+            let sealed = self.mappings.iter()
+                .filter(|m| m.overlaps(addr, end) && m.shared)
+                .filter_map(|m| m.inode)
+                .any(|ino| self.memfds.get(&ino).map(|m| m.write_sealed()).unwrap_or(false));
+            if sealed {
+                return Err(EACCES);
+            }
+        }
+        self.split_mappings(addr, end);
+        for mapping in self.mappings.iter_mut().filter(|m| m.overlaps(addr, end)) {
+            mapping.prot = prot;
+        }
+        Ok(())
+    }
 
-    //let (in_inode_number, in_position, in_append_mode) = {
-        //let in_handle = proc.open_files.get(&fd_in).ok_or(-1).unwrap();
-        //(in_handle.inode_number, in_handle.position, in_handle.append_mode)
-    //};
+    // Resizes the mapping starting at `old_addr`. It grows in place when
+    // its region has room, otherwise it moves if MREMAP_MAYMOVE allows.
+    // MREMAP_FIXED is not supported.
+    fn mremap(&mut self, old_addr: usize, old_size: usize, new_size: usize, flags: i32) -> Result<usize, i32> {
+        if !mmap::is_page_aligned(old_addr) || flags & !mmap::MREMAP_MAYMOVE != 0 {
+            return Err(EINVAL);
+        }
+        let old_size = mmap::page_align(old_size).ok_or(EINVAL)?;
+        let new_size = mmap::page_align(new_size).ok_or(ENOMEM)?;
+        if old_size == 0 || new_size == 0 {
+            return Err(EINVAL);
+        }
+        let old_end = old_addr.checked_add(old_size).ok_or(EFAULT)?;
+        if !self.mappings.iter().any(|m| m.addr <= old_addr && old_end <= m.end()) {
+            return Err(EFAULT);
+        }
+        self.split_mappings(old_addr, old_end);
+        let index = self.mappings.iter().position(|m| m.addr == old_addr).ok_or(EFAULT)?;
 
-    //let (out_inode_number, out_position, out_append_mode) = {
-        //let out_handle = proc.open_files.get(&fd_out).ok_or(-1).unwrap();
-        //(out_handle.inode_number, out_handle.position, out_handle.append_mode)
-    //};
+        if new_size <= old_size {
+            let regions = self.unmap_range(old_addr + new_size, old_end);
+            self.release_regions(regions);
+            return Ok(old_addr);
+        }
 
-    //let in_data = proc.fs.files.get(&in_inode_number).unwrap().clone();
+        let old = self.mappings[index].clone();
+        let new_end = old_addr.checked_add(new_size).ok_or(ENOMEM)?;
+        let room = new_end <= old.region.end()
+            && !self.mappings.iter().any(|m| m.overlaps(old_end, new_end));
+        if room {
+            self.mappings[index].len = new_size;
+            self.publish_mappings();
+            let grown = self.mappings[index].clone();
+            self.populate(&grown, old_end, new_end);
+            return Ok(old_addr);
+        }
+        if flags & mmap::MREMAP_MAYMOVE == 0 {
+            return Err(ENOMEM);
+        }
 
-    //let mut in_pos = if !off_in.is_null() {
-        //unsafe { *off_in as usize }
-    //} else {
-        //in_position as usize
-    //};
+        let region = Region::alloc(new_size).ok_or(ENOMEM)?;
+        unsafe {
+            core::ptr::copy_nonoverlapping(old_addr as *const u8, region.base as *mut u8, old_size);
+        }
+        let moved = Mapping { addr: region.base, len: new_size, region, ..old.clone() };
+        self.populate(&moved, moved.addr + old_size, moved.end());
+        self.mappings[index] = moved;
+        self.publish_mappings();
+        self.release_regions(vec![old.region]);
+        Ok(region.base)
+    }
 
-    //if in_pos >= in_data.len() {
-        //return 0;
-    //}
+    // Whether every page in `[addr, end)` belongs to some mapping
+    fn is_mapped(&self, addr: usize, end: usize) -> bool {
+        let mut pos = addr;
+        while pos < end {
+            match self.mappings.iter().find(|m| m.addr <= pos && pos < m.end()) {
+                Some(mapping) => pos = mapping.end(),
+                None => return false,
+            }
+        }
+        true
+    }
 
-    //let to_copy = min(len, in_data.len() - in_pos);
+    // The one region holding all of `[addr, end)`, if the range is fully
+    // mapped from it
+    fn region_of(&self, addr: usize, end: usize) -> Option<Region> {
+        if !self.is_mapped(addr, end) {
+            return None;
+        }
+        let region = self.mappings.iter().find(|m| m.overlaps(addr, end))?.region;
+        let single = self.mappings.iter()
+            .filter(|m| m.overlaps(addr, end))
+            .all(|m| m.region == region);
+        if single { Some(region) } else { None }
+    }
 
-    //{
-        //let out_data = proc.fs.files.get_mut(&out_inode_number).unwrap();
-        //let mut out_pos = if !off_out.is_null() {
-            //unsafe { *off_out as usize }
-        //} else if out_append_mode {
-            //out_data.len()
-        //} else {
-            //out_position as usize
-        //};
+    // Splits mappings that straddle `addr` or `end`, so that every
+    // mapping is either inside the range or outside it
+    fn split_mappings(&mut self, addr: usize, end: usize) {
+        let mut split = Vec::with_capacity(self.mappings.len() + 2);
+        for mut mapping in core::mem::take(&mut self.mappings) {
+            if mapping.addr < addr && addr < mapping.end() {
+                let upper = mapping.split_at(addr);
+                split.push(mapping);
+                mapping = upper;
+            }
+            if mapping.addr < end && end < mapping.end() {
+                let upper = mapping.split_at(end);
+                split.push(upper);
+            }
+            split.push(mapping);
+        }
+        self.mappings = split;
+        self.publish_mappings();
+    }
 
-        //if out_pos + to_copy > out_data.len() {
-            //out_data.resize(out_pos + to_copy, 0);
-        //}
+    // Drops the mappings in `[addr, end)`, writing back shared ones.
+    // Returns the regions they used, for `release_regions`.
+    fn unmap_range(&mut self, addr: usize, end: usize) -> Vec<Region> {
+        self.split_mappings(addr, end);
+        let (gone, kept): (Vec<Mapping>, Vec<Mapping>) = core::mem::take(&mut self.mappings)
+            .into_iter()
+            .partition(|m| m.overlaps(addr, end));
+        self.mappings = kept;
+        self.publish_mappings();
+        let mut regions = Vec::new();
+        for mapping in gone {
+            self.write_back(&mapping, mapping.addr, mapping.end());
+            if let Some(inode_number) = mapping.inode {
+                self.release_unnamed(inode_number);
+            }
+            regions.push(mapping.region);
+        }
+        regions
+    }
 
-        //out_data[out_pos..out_pos+to_copy].copy_from_slice(&in_data[in_pos..in_pos+to_copy]);
+    // Shows the other Procs on the filesystem this one's MAP_SHARED file
+    // mappings, for refresh_mappings. Called whenever the mappings
+    // change, before any region they used is freed.
+    fn publish_mappings(&self) {
+        self.attachment.borrow_mut().shared_mappings = self.mappings.iter()
+            .filter(|m| m.writes_back())
+            .cloned()
+            .collect();
+    }
 
-        //if !off_in.is_null() {
-            //unsafe {
-                //*off_in += to_copy as i64;
-            //}
-        //} else {
-            //if let Some(in_handle_mut) = proc.open_files.get_mut(&fd_in) {
-                //in_handle_mut.position = in_position + to_copy as u64;
-            //}
-        //}
+    // Frees whichever of `regions` no mapping uses any more
+    fn release_regions(&mut self, mut regions: Vec<Region>) {
+        regions.sort_by_key(|r| r.base);
+        regions.dedup();
+        for region in regions {
+            if !self.mappings.iter().any(|m| m.region == region) {
+                unsafe { region.free() };
+            }
+        }
+    }
 
-        //if !off_out.is_null() {
-            //unsafe {
-                //*off_out += to_copy as i64;
-            //}
-        //} else {
-            //if let Some(out_handle_mut) = proc.open_files.get_mut(&fd_out) {
-                //if out_append_mode {
-                    //out_handle_mut.position = out_data.len() as u64;
-                //} else {
-                    //out_handle_mut.position = out_position + to_copy as u64;
-                //}
-            //}
-        //}
+    // Fills `[from, to)` of a mapping: file data where the file has it,
+    // zeros elsewhere
+    fn populate(&self, mapping: &Mapping, from: usize, to: usize) {
+        let memory = unsafe { mmap::memory(from, to - from) };
+        memory.fill(0);
+        let fs = self.fs.borrow();
+        if let Some(data) = mapping.inode.and_then(|ino| fs.files.get(&ino)) {
+            data.read_at(memory, mapping.file_offset(from));
+        }
+    }
 
-        //let new_size = out_data.len();
-        //drop(out_data);
+    // Copies changed bytes in `[from, to)` of a MAP_SHARED mapping into
+    // its file, then into the other shared mappings of that file so they
+    // stay coherent. Bytes past end of file are not written, as on Linux.
+    fn write_back(&mut self, mapping: &Mapping, from: usize, to: usize) {
+        let inode_number = match mapping.inode {
+            Some(ino) if mapping.shared => ino,
+            _ => return,
+        };
+        let from = max(from, mapping.addr);
+        let to = min(to, mapping.end());
+
+        let mut changed = Vec::new();
+        let mut addr = from;
+        while addr < to {
+            let offset = mapping.file_offset(addr);
+            let len = match self.fs.borrow().files.get(&inode_number) {
+                Some(data) => data.len(),
+                None => return,
+            };
+            if offset >= len {
+                break;
+            }
+            let n = core::cmp::min(min(mmap::PAGE_SIZE, to - addr) as u64, len - offset) as usize;
+            let memory = unsafe { mmap::memory(addr, n) };
+            let differs = self.fs.borrow().files.get(&inode_number).is_some_and(|data| data.read_vec(offset, n) != memory);
+            // A store into a hole that no quota has room for stays in
+            // the mapping only; Linux would have raised SIGBUS for it
+            if differs && self.charge_write(inode_number, offset, n as u64).is_ok() {
+                if let Some(data) = self.fs.borrow_mut().files.get_mut(&inode_number) {
+                    // Can't fail: the range is inside the file
+                    let _ = data.write_at(memory, offset);
+                }
+                changed.push((offset, n));
+            }
+            addr += n;
+        }
+        if !changed.is_empty() {
+            self.fs.borrow_mut().recharge(inode_number);
+        }
 
-        //if let Some(out_inode) = proc.get_inode_mut(out_inode_number) {
-            //out_inode.size = new_size as u64;
-        //}
-    //}
+        for (offset, n) in changed {
+            self.refresh_mappings(inode_number, offset, n as u64, Some(mapping.addr));
+            self.record_inode_change(ChangeOp::Write { offset, len: n as u64 }, inode_number);
+        }
+    }
 
-    //to_copy as isize
-//}
-//
+    fn write_back_inode(&mut self, inode_number: u64) {
+        let mappings: Vec<Mapping> = self.mappings.iter()
+            .filter(|m| m.inode == Some(inode_number))
+            .cloned()
+            .collect();
+        for mapping in mappings {
+            self.write_back(&mapping, mapping.addr, mapping.end());
+        }
+    }
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_splice(
-    fd_in: i32,
-    off_in: *mut i64,
-    fd_out: i32,
-    off_out: *mut i64,
-    len: usize,
-    _flags: u32
-) -> isize {
-    let mut proc = get_or_init_proc();
+    // Reloads `[offset, offset + len)` of a file into its MAP_SHARED
+    // mappings after the file changed, skipping the one at `except`.
+    // That covers the mappings of every Proc on the filesystem.
+    fn refresh_mappings(&self, inode_number: u64, offset: u64, len: u64, except: Option<usize>) {
+        let fs = self.fs.borrow();
+        let data = match fs.files.get(&inode_number) {
+            Some(data) => data,
+            None => return,
+        };
+        let end = offset.saturating_add(len);
+        for attachment in fs.attachments() {
+            for mapping in attachment.borrow().shared_mappings.iter() {
+                if !mapping.writes_back() || mapping.inode != Some(inode_number) || Some(mapping.addr) == except {
+                    continue;
+                }
+                let start = core::cmp::max(offset, mapping.offset);
+                let stop = core::cmp::min(end, mapping.offset + mapping.len as u64);
+                if start >= stop {
+                    continue;
+                }
+                let addr = mapping.addr + (start - mapping.offset) as usize;
+                let memory = unsafe { mmap::memory(addr, (stop - start) as usize) };
+                data.read_at(memory, start);
+            }
+        }
+    }
 
-    // 1) Input FD info
-    let (in_ino, in_pos, in_app) = {
-        let h = match proc.open_files.get(&fd_in) {
-            Some(x) => x,
-            None => return -1,
+    // Which POLL* bits hold for `fd` right now, or POLLNVAL if it is
+    // not open. Regular files and standard streams are always ready;
+    // `depth` counts nested epoll descriptors.
+    fn poll_fd(&mut self, fd: FileDescriptor, depth: u32) -> i16 {
+        let (inode_number, kind, awaiting_peer) = match self.open_files.get(&fd) {
+            Some(h) => (h.inode_number, h.kind, h.awaiting_peer),
+            None => return POLLNVAL,
         };
-        (h.inode_number, h.position, h.append_mode)
-    };
+        let ready = match kind {
+            HandleKind::File | HandleKind::Provider | HandleKind::Stream => POLLIN | POLLOUT,
+            HandleKind::Tty => match self.tty.as_ref() {
+                Some(tty) if tty.readable() => POLLIN | POLLOUT,
+                Some(_) => POLLOUT,
+                None => POLLERR,
+            },
+            HandleKind::Device => {
+                let numbers = self.device_numbers(inode_number);
+                let mut fs = self.fs.borrow_mut();
+                let driver = numbers
+                    .and_then(|(class, major, minor)| fs.devices.get_mut(class, major, minor).map(|d| (d, minor)));
+                match driver {
+                    Some((driver, minor)) => driver.poll(minor),
+                    None => POLLERR,
+                }
+            }
+            HandleKind::PipeReader | HandleKind::PipeWriter | HandleKind::PipeDuplex => {
+                let fs = self.fs.borrow();
+                let pipe = match fs.pipes.get(&inode_number) {
+                    Some(pipe) => pipe,
+                    None => return POLLERR,
+                };
+                // A FIFO end still waiting for its peer has nothing to
+                // report yet
+                let mut bits = 0;
+                if kind.reads_pipe() {
+                    if !pipe.is_empty() {
+                        bits |= POLLIN;
+                    }
+                    if pipe.writers == 0 && !awaiting_peer {
+                        bits |= POLLHUP;
+                    }
+                }
+                if kind.writes_pipe() {
+                    if pipe.readers == 0 && !awaiting_peer {
+                        bits |= POLLERR;
+                    } else if pipe.space() >= PIPE_BUF {
+                        bits |= POLLOUT;
+                    }
+                }
+                bits
+            }
+            HandleKind::Inotify => match self.inotify.get(&inode_number) {
+                Some(instance) if instance.is_readable() => POLLIN,
+                _ => 0,
+            },
+            HandleKind::Epoll => {
+                if depth < poll::EPOLL_MAX_NESTS && self.epoll_ready(inode_number, depth + 1) { POLLIN } else { 0 }
+            }
+            HandleKind::EventFd => match self.eventfds.get(&inode_number) {
+                Some(counter) => {
+                    let mut bits = 0;
+                    if counter.readable() {
+                        bits |= POLLIN;
+                    }
+                    if counter.writable() {
+                        bits |= POLLOUT;
+                    }
+                    bits
+                }
+                None => POLLERR,
+            },
+            HandleKind::Object => match self.objects.get_mut(&inode_number) {
+                Some(object) => object.poll(),
+                None => POLLERR,
+            },
+            HandleKind::TimerFd => match self.timers.get_mut(&inode_number) {
+                Some(timer) => {
+                    if timer.readable() {
+                        POLLIN
+                    } else {
+                        // Not due yet: the wait should wake up when it is
+                        if let Some(ns) = timer.time_to_expiry() {
+                            self.next_wake = Some(self.next_wake.map_or(ns, |wake| core::cmp::min(wake, ns)));
+                        }
+                        0
+                    }
+                }
+                None => POLLERR,
+            },
+        };
+        let mut ready = ready;
+        if ready & POLLIN != 0 {
+            ready |= poll::POLLRDNORM;
+        }
+        if ready & POLLOUT != 0 {
+            ready |= poll::POLLWRNORM;
+        }
+        ready
+    }
 
-    // 2) Output FD info
-    let (out_ino, out_pos, out_app) = {
-        let h = match proc.open_files.get(&fd_out) {
-            Some(x) => x,
-            None => return -1,
+    fn epoll_ready(&mut self, inode_number: u64, depth: u32) -> bool {
+        // Taken out while its descriptors are checked, which also stops
+        // an epoll that contains itself from recursing
+        let epoll = match self.epolls.remove(&inode_number) {
+            Some(epoll) => epoll,
+            None => return false,
         };
-        (h.inode_number, h.position, h.append_mode)
-    };
+        let ready = epoll.any_ready(|fd| self.poll_fd(fd, depth) as u16 as u32);
+        self.epolls.insert(inode_number, epoll);
+        ready
+    }
 
-    // 3) Clone input
-    let in_data = match proc.fs.files.get(&in_ino) {
-        Some(d) => d.clone(),
-        None => return -1,
-    };
+    // One pass of poll(): fills in every revents and returns how many
+    // are non-zero
+    fn poll_once(&mut self, fds: &mut [PollFd]) -> usize {
+        let mut count = 0;
+        for pfd in fds.iter_mut() {
+            pfd.revents = 0;
+            if pfd.fd < 0 {
+                continue;
+            }
+            pfd.revents = self.poll_fd(pfd.fd, 0) & (pfd.events | POLL_ALWAYS);
+            if pfd.revents != 0 {
+                count += 1;
+            }
+        }
+        count
+    }
 
-    let mut read_pos = if !off_in.is_null() {
-        unsafe { *off_in as usize }
-    } else {
-        in_pos as usize
-    };
-    if read_pos >= in_data.len() {
-        return 0;
+    // One pass of select() over the requested sets in `sets` (read,
+    // write, except). Ready bits go to `out`; the count is of bits set.
+    fn select_once(&mut self, nfds: usize, sets: &[Option<Vec<u8>>; 3], out: &mut [Vec<u8>; 3]) -> Result<usize, i32> {
+        const WANTED: [i16; 3] = [POLLIN | POLLHUP | POLLERR, POLLOUT | POLLERR, POLLPRI];
+        let mut count = 0;
+        for bits in out.iter_mut() {
+            bits.fill(0);
+        }
+        for fd in 0..nfds {
+            let requested: Vec<usize> = (0..3)
+                .filter(|i| sets[*i].as_ref().map(|set| poll::fd_isset(set, fd)).unwrap_or(false))
+                .collect();
+            if requested.is_empty() {
+                continue;
+            }
+            let ready = self.poll_fd(fd as FileDescriptor, 0);
+            if ready & POLLNVAL != 0 {
+                return Err(EBADF);
+            }
+            for i in requested {
+                if ready & WANTED[i] != 0 {
+                    poll::fd_set(&mut out[i], fd);
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
     }
 
-    let to_copy = core::cmp::min(len, in_data.len() - read_pos);
+    fn epoll_create(&mut self, flags: i32) -> Result<FileDescriptor, i32> {
+        if flags & !O_CLOEXEC != 0 {
+            return Err(EINVAL);
+        }
+        if self.epolls.is_full() {
+            return Err(ENFILE);
+        }
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        let inode_number = self.fs.borrow_mut().create_anon_inode(InodeKind::File, 0o600);
+        self.epolls.insert(inode_number, Epoll::new());
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, false),
+                kind: HandleKind::Epoll,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer: false,
+                writable: false,
+                access: O_RDWR,
+            },
+        );
+        Ok(fd)
+    }
 
-    {
-        // 4) Mutably borrow out_data in a short block
-        let out_data = match proc.fs.files.get_mut(&out_ino) {
-            Some(d) => d,
-            None => return -1,
+    fn epoll_inode(&self, epfd: FileDescriptor) -> Result<u64, i32> {
+        match self.open_files.get(&epfd) {
+            Some(h) if h.kind == HandleKind::Epoll => Ok(h.inode_number),
+            Some(_) => Err(EINVAL),
+            None => Err(EBADF),
+        }
+    }
+
+    // Regular files can't be watched (they are always ready), and an
+    // epoll may not end up inside itself
+    fn epoll_ctl(&mut self, epfd: FileDescriptor, op: i32, fd: FileDescriptor, event: Option<EpollEvent>) -> Result<(), i32> {
+        let inode_number = self.epoll_inode(epfd)?;
+        let target = self.open_files.get(&fd).ok_or(EBADF)?;
+        if fd == epfd {
+            return Err(EINVAL);
+        }
+        if matches!(target.kind, HandleKind::File | HandleKind::Provider) {
+            return Err(EPERM);
+        }
+        if op == poll::EPOLL_CTL_ADD && target.kind == HandleKind::Epoll && self.epoll_reaches(target.inode_number, inode_number, 0) {
+            return Err(ELOOP);
+        }
+        self.epolls.get_mut(&inode_number).ok_or(EBADF)?.ctl(op, fd, event)
+    }
+
+    // Whether the epoll `from` watches `to`, directly or through
+    // nested epolls
+    fn epoll_reaches(&self, from: u64, to: u64, depth: u32) -> bool {
+        if from == to {
+            return true;
+        }
+        if depth > poll::EPOLL_MAX_NESTS {
+            return false;
+        }
+        let fds = match self.epolls.get(&from) {
+            Some(epoll) => epoll.fds(),
+            None => return false,
         };
+        fds.iter().any(|fd| match self.open_files.get(fd) {
+            Some(h) if h.kind == HandleKind::Epoll => self.epoll_reaches(h.inode_number, to, depth + 1),
+            _ => false,
+        })
+    }
 
-        let mut write_pos = if !off_out.is_null() {
-            unsafe { *off_out as usize }
-        } else if out_app {
-            out_data.len()
-        } else {
-            out_pos as usize
+    fn epoll_collect(&mut self, inode_number: u64, out: &mut [EpollEvent]) -> usize {
+        let mut epoll = match self.epolls.remove(&inode_number) {
+            Some(epoll) => epoll,
+            None => return 0,
         };
+        let n = epoll.collect(out, |fd| self.poll_fd(fd, 1) as u16 as u32);
+        self.epolls.insert(inode_number, epoll);
+        n
+    }
 
-        if write_pos + to_copy > out_data.len() {
-            out_data.resize(write_pos + to_copy, 0);
+    fn memfd_create(&mut self, name: &str, flags: u32) -> Result<FileDescriptor, i32> {
+        if flags & !(memfd::MFD_CLOEXEC | memfd::MFD_ALLOW_SEALING) != 0 || name.len() > memfd::MFD_NAME_MAX {
+            return Err(EINVAL);
+        }
+        self.charge_create(None)?;
+        if self.memfds.is_full() || self.fs.borrow().files.is_full() {
+            return Err(ENFILE);
         }
-        out_data[write_pos..write_pos + to_copy]
-            .copy_from_slice(&in_data[read_pos..read_pos + to_copy]);
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        let inode_number = self.create_unnamed_file(0o777);
+        self.memfds.insert(inode_number, Memfd::new(String::from(name), (flags & memfd::MFD_ALLOW_SEALING) != 0));
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, false),
+                kind: HandleKind::File,
+                cloexec: (flags & memfd::MFD_CLOEXEC) != 0,
+                awaiting_peer: false,
+                writable: true,
+                access: O_RDWR,
+            },
+        );
+        Ok(fd)
+    }
 
-        if !off_in.is_null() {
-            unsafe { *off_in += to_copy as i64; }
+    // open(dir, O_TMPFILE): a file in `dir` that has no name until
+    // linkat(AT_EMPTY_PATH) gives it one
+    fn open_tmpfile(&mut self, dir: &PathBuf, flags: i32, mode: u32) -> Result<FileDescriptor, i32> {
+        if (flags & O_ACCMODE) == O_RDONLY {
+            return Err(EINVAL);
         }
-        if !off_out.is_null() {
-            unsafe { *off_out += to_copy as i64; }
+        let dir = self.get_absolute_path(dir);
+        let dir_inode = self.lookup(&dir).ok_or(ENOENT)?;
+        if !matches!(self.kind_of(dir_inode), Some(InodeKind::Directory)) {
+            return Err(ENOTDIR);
         }
+        self.charge_create(Some(&dir))?;
+        if self.tmpfiles.is_full() || self.fs.borrow().files.is_full() {
+            return Err(ENFILE);
+        }
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        let inode_number = self.create_unnamed_file(mode & 0o777 & !self.umask_value);
+        self.tmpfiles.insert(inode_number, TmpFile { dir, linkable: (flags & O_EXCL) == 0 });
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, (flags & O_APPEND) == O_APPEND, (flags & O_NONBLOCK) == O_NONBLOCK),
+                kind: HandleKind::File,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer: false,
+                writable: true,
+                access: flags & O_ACCMODE,
+            },
+        );
+        Ok(fd)
     }
 
-    if off_in.is_null() {
-        if let Some(in_handle) = proc.open_files.get_mut(&fd_in) {
-            in_handle.position = in_pos + to_copy as u64;
+    fn create_unnamed_file(&mut self, mode: u32) -> u64 {
+        let inode_number = self.fs.borrow_mut().create_anon_inode(InodeKind::File, mode);
+        self.fs.borrow_mut().files.insert(inode_number, FileData::new());
+        let (uid, gid) = (self.uid, self.gid);
+        if let Some(mut inode) = self.get_inode_mut(inode_number) {
+            inode.user_id = uid;
+            inode.group_id = gid;
         }
+        self.fs.borrow_mut().recharge(inode_number);
+        inode_number
     }
 
-    let (out_app_local, old_out_pos) = {
-        if let Some(out_handle) = proc.open_files.get_mut(&fd_out) {
-            (out_handle.append_mode, out_handle.position)
-        } else {
-            // If no handle, bail out
-            return -1;
+    // linkat(fd, "", newpath, AT_EMPTY_PATH). A file that lost its last
+    // name can't get a new one; only an O_TMPFILE file can start out
+    // without one.
+    fn link_fd(&mut self, fd: FileDescriptor, new_path: &PathBuf) -> Result<(), i32> {
+        let inode_number = self.open_files.get(&fd).ok_or(EBADF)?.inode_number;
+        if matches!(self.kind_of(inode_number), Some(InodeKind::Directory)) {
+            return Err(EPERM);
         }
-    };
-
-    let length_copy = if out_app_local && off_out.is_null() {
-        if let Some(od) = proc.fs.files.get(&out_ino) {
-            od.len()
+        let name = self.path_of(inode_number);
+        let named = name.is_some();
+        let linkable = self.tmpfiles.get(&inode_number).map(|t| t.linkable).unwrap_or(false);
+        if !named && !linkable {
+            return Err(ENOENT);
+        }
+        let new_abs = self.get_absolute_path(new_path);
+        if self.lookup(&new_abs).is_some() {
+            return Err(EEXIST);
+        }
+        // An O_TMPFILE file belongs with the directory it was made in
+        let from = name.or_else(|| self.tmpfiles.get(&inode_number).map(|t| t.dir.clone()));
+        if let Some(from) = from {
+            self.fs.borrow().check_project_move(&from, &new_abs)?;
+        }
+        self.fs.borrow_mut().path_map.insert(new_abs.clone(), inode_number);
+        self.fs.borrow_mut().recharge(inode_number);
+        if named {
+            self.notify_self(inode_number, inotify::IN_ATTRIB);
+            self.notify(&new_abs, inode_number, inotify::IN_CREATE, 0);
+            self.record_change(ChangeOp::Link, &new_abs, inode_number);
         } else {
-            0
+            self.tmpfiles.remove(&inode_number);
+            self.notify(&new_abs, inode_number, inotify::IN_CREATE, 0);
+            self.record_change(ChangeOp::Create, &new_abs, inode_number);
         }
-    } else {
-        old_out_pos as usize
-    };
+        Ok(())
+    }
 
-    if let Some(out_handle) = proc.open_files.get_mut(&fd_out) {
-        if out_app_local && off_out.is_null() {
-            out_handle.position = length_copy as u64;
-        } else if off_out.is_null() {
-            out_handle.position = old_out_pos + to_copy as u64;
+    // st_nlink: the names a regular file has, which is 0 for one that
+    // is unnamed or unlinked; everything else reports 1
+    fn nlink_of(&self, inode_number: u64) -> u32 {
+        if !self.fs.borrow().files.contains_key(&inode_number)
+            || !matches!(self.kind_of(inode_number), Some(InodeKind::File)) {
+            return 1;
         }
+        self.fs.borrow().path_map.iter().filter(|(_, ino)| **ino == inode_number).count() as u32
     }
 
-    to_copy as isize
-}
+    fn memfd_seals(&mut self, fd: FileDescriptor) -> Result<&mut Memfd, i32> {
+        let inode_number = self.open_files.get(&fd).ok_or(EBADF)?.inode_number;
+        self.memfds.get_mut(&inode_number).ok_or(EINVAL)
+    }
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_getdents(fd: i32, dirp: *mut Dirent, count: usize) -> isize {
-    let mut proc = get_or_init_proc();
+    // F_ADD_SEALS needs a writable descriptor, and F_SEAL_WRITE can't
+    // be set while a writable shared mapping exists
+    fn add_seals(&mut self, fd: FileDescriptor, seals: u32) -> Result<(), i32> {
+        let handle = self.open_files.get(&fd).ok_or(EBADF)?;
+        let (inode_number, writable) = (handle.inode_number, handle.writable);
+        if !self.memfds.contains_key(&inode_number) {
+            return Err(EINVAL);
+        }
+        if !writable {
+            return Err(EPERM);
+        }
+        let mapped_writable = self.mappings.iter()
+            .any(|m| m.inode == Some(inode_number) && m.shared && m.prot & mmap::PROT_WRITE != 0);
+        if seals & memfd::F_SEAL_WRITE != 0 && mapped_writable {
+            return Err(EBUSY);
+        }
+        self.memfd_seals(fd)?.add_seals(seals)
+    }
 
+    // Whether seals allow writing `len` bytes at `offset`; files that
+    // aren't memfds have none
+    fn check_seals_write(&self, inode_number: u64, offset: u64, len: u64) -> Result<(), i32> {
+        match self.memfds.get(&inode_number) {
+            Some(memfd) => memfd.check_write(self.file_len(inode_number), offset, len),
+            None => Ok(()),
+        }
+    }
 
-    let inode_number = {
-        let handle = match proc.open_files.get_mut(&fd) {
-            Some(h) => h,
-            None => return -1,
-        };
-        let ino = handle.inode_number;
-        ino
-    };
+    fn check_seals_resize(&self, inode_number: u64, new_size: u64) -> Result<(), i32> {
+        match self.memfds.get(&inode_number) {
+            Some(memfd) => memfd.check_resize(self.file_len(inode_number), new_size),
+            None => Ok(()),
+        }
+    }
 
-    let inode = match proc.get_inode(inode_number) {
-        Some(i) => i,
-        None => return -1,
-    };
+    fn file_len(&self, inode_number: u64) -> u64 {
+        self.fs.borrow().files.get(&inode_number).map(|data| data.len()).unwrap_or(0)
+    }
 
-    if let InodeKind::Directory = inode.kind {
+    // Whether a file may grow by `bytes`, given its owner's quotas and
+    // the space left; see FileSystem::check_growth
+    fn charge(&self, inode_number: u64, bytes: u64) -> Result<(), i32> {
+        self.fs.borrow().check_growth(inode_number, bytes)
+    }
 
-    } else {
-        return -1;
+    // `charge` for the pages a write of `len` bytes at `offset` would
+    // allocate; rewriting stored data is always allowed
+    fn charge_write(&self, inode_number: u64, offset: u64, len: u64) -> Result<(), i32> {
+        let missing = self.fs.borrow().files.get(&inode_number)
+            .map(|data| data.missing_pages(offset, len))
+            .unwrap_or(0);
+        self.charge(inode_number, missing * PAGE_SIZE)
     }
 
-    let dir_prefix = if inode.number == 0 {
-        PathBuf::from("/")
-    } else {
-        proc.fs.path_map.iter()
-            .find_map(|(pp, ino)| if *ino == inode.number { Some(pp.clone()) } else { None })
-            .unwrap_or(PathBuf::from("/"))
-    };
+    // Whether the process may create an inode named `path`, or an
+    // unnamed one
+    fn charge_create(&self, path: Option<&PathBuf>) -> Result<(), i32> {
+        self.fs.borrow().check_charge(self.uid, self.gid, path, 0, 1)
+    }
 
-    let dir_path = proc.fs.path_map.iter().filter(|(p, _)| {
-        if p.as_path() == dir_prefix.as_path() {
-            false
-        } else {
-            p.parent().map(|pp| pp == dir_prefix).unwrap_or(false)
+    // The file behind a shared memory object. As in glibc, leading
+    // slashes are dropped and what remains must be one path component.
+    fn shm_path(&self, name: &str) -> Result<PathBuf, i32> {
+        let name = name.trim_start_matches('/');
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(EINVAL);
         }
-    });
+        if name.len() > NAME_MAX {
+            return Err(ENAMETOOLONG);
+        }
+        let dir = PathBuf::from(SHM_DIR);
+        match self.lookup(&dir).and_then(|ino| self.get_inode(ino)) {
+            Some(inode) if inode.kind == InodeKind::Directory => Ok(dir.join(&PathBuf::from(name))),
+            // No /dev/shm (FsConfig::populate_dev was off)
+            _ => Err(ENOSYS),
+        }
+    }
 
-    let entries: Vec<(PathBuf, u64)> = dir_path.map(|(p,i)| (p.clone(), *i)).collect();
+    // The checks open() leaves out: O_EXCL and a missing object
+    fn shm_open_path(&self, name: &str, flags: i32) -> Result<PathBuf, i32> {
+        let path = self.shm_path(name)?;
+        let exists = self.lookup(&path).is_some();
+        if exists && (flags & (O_CREAT | O_EXCL)) == (O_CREAT | O_EXCL) {
+            return Err(EEXIST);
+        }
+        if !exists && (flags & O_CREAT) == 0 {
+            return Err(ENOENT);
+        }
+        Ok(path)
+    }
 
+    // The object goes on for descriptors and mappings that still use it
+    fn shm_unlink(&mut self, name: &str) -> Result<(), i32> {
+        let path = self.shm_path(name)?;
+        let inode_number = self.lookup(&path).ok_or(ENOENT)?;
+        self.fs.borrow_mut().path_map.remove(&path);
+        self.notify_unlinked(&path, inode_number);
+        self.release_unnamed(inode_number);
+        Ok(())
+    }
 
-    let position = {
-        let handle = proc.open_files.get_mut(&fd).unwrap();
-        handle.position
+    // quotactl(2) on this process's FileSystem. Anyone may read their
+    // own user and group quotas; everything else takes root.
+    fn quotactl(&mut self, cmd: i32, id: u32, addr: *mut Dqblk) -> Result<(), i32> {
+        // QCMD() sets the sign bit, so shift it as the unsigned int it is
+        let subcmd = ((cmd as u32) >> quota::SUBCMDSHIFT) as i32;
+        if subcmd == quota::Q_SYNC {
+            return Ok(());
+        }
+        let id = QuotaId::new(cmd & quota::SUBCMDMASK, id)?;
+        let own = match id {
+            QuotaId::User(uid) => uid == self.uid,
+            QuotaId::Group(gid) => gid == self.gid || self.groups.contains(&gid),
+            QuotaId::Project(_) => false,
+        };
+        match subcmd {
+            quota::Q_GETQUOTA => {
+                if !own && self.uid != 0 {
+                    return Err(EPERM);
+                }
+                if addr.is_null() {
+                    return Err(EFAULT);
+                }
+                let dqblk = Dqblk::new(self.fs.borrow().quota(id), self.fs.borrow().quota_usage(id));
+                unsafe { core::ptr::write_unaligned(addr, dqblk) };
+                Ok(())
+            }
+            quota::Q_SETQUOTA => {
+                if self.uid != 0 {
+                    return Err(EPERM);
+                }
+                if addr.is_null() {
+                    return Err(EFAULT);
+                }
+                let dqblk = unsafe { core::ptr::read_unaligned(addr) };
+                let limits = dqblk.apply(self.fs.borrow().quota(id));
+                self.fs.borrow_mut().set_quota(id, limits);
+                Ok(())
+            }
+            _ => Err(EINVAL),
+        }
+    }
+
+    fn eventfd_create(&mut self, initval: u32, flags: i32) -> Result<FileDescriptor, i32> {
+        if flags & !(eventfd::EFD_SEMAPHORE | O_NONBLOCK | O_CLOEXEC) != 0 {
+            return Err(EINVAL);
+        }
+        if self.eventfds.is_full() {
+            return Err(ENFILE);
+        }
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        let inode_number = self.fs.borrow_mut().create_anon_inode(InodeKind::File, 0o600);
+        self.eventfds.insert(inode_number, EventFd::new(initval, (flags & eventfd::EFD_SEMAPHORE) != 0));
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, (flags & O_NONBLOCK) == O_NONBLOCK),
+                kind: HandleKind::EventFd,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer: false,
+                writable: true,
+                access: O_RDWR,
+            },
+        );
+        Ok(fd)
+    }
+
+    fn timerfd_create(&mut self, clockid: i32, flags: i32) -> Result<FileDescriptor, i32> {
+        if !clock::is_valid(clockid) || flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+            return Err(EINVAL);
+        }
+        if self.timers.is_full() {
+            return Err(ENFILE);
+        }
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        let inode_number = self.fs.borrow_mut().create_anon_inode(InodeKind::File, 0o600);
+        self.timers.insert(inode_number, Timer::new(clockid));
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, (flags & O_NONBLOCK) == O_NONBLOCK),
+                kind: HandleKind::TimerFd,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer: false,
+                writable: false,
+                access: O_RDWR,
+            },
+        );
+        Ok(fd)
+    }
+
+    // The timer behind a timerfd descriptor; any other open descriptor
+    // is EINVAL
+    fn timer(&mut self, fd: FileDescriptor) -> Result<&mut Timer, i32> {
+        let handle = self.open_files.get(&fd).ok_or(EBADF)?;
+        if handle.kind != HandleKind::TimerFd {
+            return Err(EINVAL);
+        }
+        let inode_number = handle.inode_number;
+        self.timers.get_mut(&inode_number).ok_or(EBADF)
+    }
+
+    fn inotify_init(&mut self, flags: i32) -> Result<FileDescriptor, i32> {
+        if flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+            return Err(EINVAL);
+        }
+        // Linux's per-user instance limit is also EMFILE
+        if self.inotify.is_full() {
+            return Err(EMFILE);
+        }
+        let fd = self.allocate_fd().ok_or(EMFILE)?;
+        let inode_number = self.fs.borrow_mut().create_anon_inode(InodeKind::File, 0o600);
+        self.inotify.insert(inode_number, Inotify::new());
+        self.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, (flags & O_NONBLOCK) == O_NONBLOCK),
+                kind: HandleKind::Inotify,
+                cloexec: (flags & O_CLOEXEC) == O_CLOEXEC,
+                awaiting_peer: false,
+                writable: false,
+                access: O_RDONLY,
+            },
+        );
+        Ok(fd)
+    }
+
+    // The instance behind an inotify descriptor; any other open
+    // descriptor is EINVAL
+    fn inotify_instance(&mut self, fd: FileDescriptor) -> Result<&mut Inotify, i32> {
+        let handle = self.open_files.get(&fd).ok_or(EBADF)?;
+        if handle.kind != HandleKind::Inotify {
+            return Err(EINVAL);
+        }
+        let inode_number = handle.inode_number;
+        self.inotify.get_mut(&inode_number).ok_or(EBADF)
+    }
+
+    // Watching needs read permission on the target. IN_DONT_FOLLOW
+    // watches a symlink itself, and IN_ONLYDIR refuses anything but a
+    // directory.
+    fn inotify_add_watch(&mut self, fd: FileDescriptor, pathname: *const i8, mask: u32) -> Result<i32, i32> {
+        self.inotify_instance(fd)?;
+        let inode_number = self.path_inode(pathname, mask & inotify::IN_DONT_FOLLOW == 0)?;
+        let inode = self.get_inode(inode_number).ok_or(ENOENT)?;
+        if mask & inotify::IN_ONLYDIR != 0 && inode.kind != InodeKind::Directory {
+            return Err(ENOTDIR);
+        }
+        if !self.check_access(&inode, R_OK) {
+            return Err(EACCES);
+        }
+        drop(inode);
+        self.inotify_instance(fd)?.add_watch(inode_number, mask)
+    }
+
+    // A write or fallocate changed `[offset, offset + len)` of a file:
+    // shared mappings pick up the new bytes, watchers get IN_MODIFY and
+    // change subscribers the range
+    fn data_changed(&mut self, inode_number: u64, offset: u64, len: u64) {
+        self.refresh_mappings(inode_number, offset, len, None);
+        self.notify_inode(inode_number, inotify::IN_MODIFY);
+        self.record_inode_change(ChangeOp::Write { offset, len }, inode_number);
+    }
+
+    // Queues an inotify event about the inode `path` names. Watches on
+    // the inode see it without a name and, unless it only concerns the
+    // inode itself, a watch on the parent directory sees it with the
+    // entry's name.
+    fn notify(&mut self, path: &PathBuf, inode_number: u64, mask: u32, cookie: u32) {
+        if self.inotify.iter().next().is_none() {
+            return;
+        }
+        let mut mask = mask;
+        if matches!(self.kind_of(inode_number), Some(InodeKind::Directory)) {
+            mask |= inotify::IN_ISDIR;
+        }
+        let parent = path.parent().and_then(|p| self.lookup(&p));
+        let name = path.file_name().map(|n| n.to_string());
+        let instances: Vec<u64> = self.inotify.iter().map(|(ino, _)| *ino).collect();
+        for ino in instances {
+            let instance = match self.inotify.get_mut(&ino) {
+                Some(instance) => instance,
+                None => continue,
+            };
+            if mask & inotify::IN_CHILD_ONLY == 0 {
+                instance.deliver(inode_number, mask, cookie, None);
+            }
+            if let Some(parent) = parent.filter(|_| mask & inotify::IN_SELF_ONLY == 0) {
+                instance.deliver(parent, mask, cookie, name.as_deref());
+            }
+        }
+    }
+
+    // `notify` for an inode reached through its first path. An inode
+    // with no path left only has its own watches to tell.
+    fn notify_inode(&mut self, inode_number: u64, mask: u32) {
+        if self.inotify.iter().next().is_none() {
+            return;
+        }
+        match self.path_of(inode_number) {
+            Some(path) => self.notify(&path, inode_number, mask, 0),
+            None => self.notify_self(inode_number, mask),
+        }
+    }
+
+    fn notify_self(&mut self, inode_number: u64, mask: u32) {
+        let instances: Vec<u64> = self.inotify.iter().map(|(ino, _)| *ino).collect();
+        for ino in instances {
+            if let Some(instance) = self.inotify.get_mut(&ino) {
+                instance.deliver(inode_number, mask, 0, None);
+            }
+        }
+    }
+
+    // `path` was removed from its directory. Once the inode has no
+    // names left its watches get IN_DELETE_SELF and are dropped.
+    // Change subscribers see an Unlink either way.
+    fn notify_unlinked(&mut self, path: &PathBuf, inode_number: u64) {
+        self.record_change(ChangeOp::Unlink, path, inode_number);
+        let is_dir = matches!(self.kind_of(inode_number), Some(InodeKind::Directory));
+        if !is_dir {
+            self.notify_self(inode_number, inotify::IN_ATTRIB);
+        }
+        self.notify(path, inode_number, inotify::IN_DELETE, 0);
+        if self.path_of(inode_number).is_some() {
+            return;
+        }
+        self.notify_self(inode_number, inotify::IN_DELETE_SELF);
+        self.drop_watches(inode_number);
+    }
+
+    fn drop_watches(&mut self, inode_number: u64) {
+        let instances: Vec<u64> = self.inotify.iter().map(|(ino, _)| *ino).collect();
+        for ino in instances {
+            if let Some(instance) = self.inotify.get_mut(&ino) {
+                instance.drop_inode(inode_number);
+            }
+        }
+    }
+
+    // Passes a change to the embedder's subscriptions
+    fn record_change(&mut self, op: ChangeOp, path: &PathBuf, inode_number: u64) {
+        if !self.changes.is_empty() {
+            self.changes.publish(Change { op, path: path.clone(), inode: inode_number });
+        }
+    }
+
+    // `record_change` for an inode reached through its first path. An
+    // inode with no path left is no longer part of the tree.
+    fn record_inode_change(&mut self, op: ChangeOp, inode_number: u64) {
+        if self.changes.is_empty() {
+            return;
+        }
+        if let Some(path) = self.path_of(inode_number) {
+            self.record_change(op, &path, inode_number);
+        }
+    }
+
+    // The inode a path names. With `follow`, symlinks in the last
+    // component are followed (up to 40 of them, as on Linux).
+    fn path_inode(&mut self, path: *const i8, follow: bool) -> Result<u64, i32> {
+        let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
+        let mut path = self.get_absolute_path(&PathBuf::from(path_str));
+        for _ in 0..40 {
+            self.refresh_procfs(&path)?;
+            let inode_number = self.lookup(&path).ok_or(ENOENT)?;
+            let target = match self.kind_of(inode_number) {
+                Some(InodeKind::SymbolicLink(target)) if follow => target.clone(),
+                _ => return Ok(inode_number),
+            };
+            path = if target.is_absolute() {
+                target
+            } else {
+                path.parent().unwrap_or(PathBuf::from("/")).join(&target)
+            };
+        }
+        Err(ELOOP)
+    }
+
+    fn fd_inode(&self, fd: FileDescriptor) -> Result<u64, i32> {
+        self.open_files.get(&fd).map(|h| h.inode_number).ok_or(EBADF)
+    }
+
+    // Usage of the mount an inode is on. Pipes and other inodes
+    // without a name report the root filesystem.
+    fn inode_usage(&self, inode_number: u64) -> MountUsage {
+        let path = self.path_of(inode_number).unwrap_or(PathBuf::from("/"));
+        self.fs.borrow().usage_of(&path)
+    }
+
+    fn getxattr(&self, inode_number: u64, name: *const i8, value: *mut u8, size: usize) -> Result<usize, i32> {
+        let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+        Namespace::of(&name)?;
+        let inode = self.get_inode(inode_number).ok_or(ENOENT)?;
+        xattr::copy_out(inode.xattrs.get(&name)?, value, size)
+    }
+
+    // Who may set or remove an attribute depends on its namespace, as
+    // on Linux: ACLs are the owner's (or root's), user.* attributes need
+    // write permission and exist only on regular files and directories,
+    // and trusted.* and security.* are root's alone.
+    fn xattr_write_check(&self, inode_number: u64, namespace: Namespace) -> Result<(), i32> {
+        let inode = self.get_inode(inode_number).ok_or(ENOENT)?;
+        match namespace {
+            Namespace::System => self.acl_owner_check(inode_number),
+            Namespace::User if !matches!(inode.kind, InodeKind::File | InodeKind::Directory) => Err(EPERM),
+            Namespace::User if !self.check_access(&inode, W_OK) => Err(EACCES),
+            Namespace::User => Ok(()),
+            Namespace::Trusted | Namespace::Security if self.uid != 0 => Err(EPERM),
+            Namespace::Trusted | Namespace::Security => Ok(()),
+        }
+    }
+
+    fn setxattr(&mut self, inode_number: u64, name: *const i8, value: *const u8, size: usize, flags: i32) -> Result<(), i32> {
+        let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+        let namespace = Namespace::of(&name)?;
+        self.xattr_write_check(inode_number, namespace)?;
+        let value = if size == 0 { &[][..] } else { unsafe { core::slice::from_raw_parts(value, size) } };
+        if namespace == Namespace::System {
+            self.set_acl(inode_number, &name, value, flags)?;
+        } else {
+            let mut inode = self.get_inode_mut(inode_number).ok_or(ENOENT)?;
+            inode.xattrs.set(&name, value, flags)?;
+        }
+        self.notify_inode(inode_number, inotify::IN_ATTRIB);
+        self.record_inode_change(ChangeOp::Attrib, inode_number);
+        Ok(())
+    }
+
+    fn listxattr(&self, inode_number: u64, list: *mut u8, size: usize) -> Result<usize, i32> {
+        let inode = self.get_inode(inode_number).ok_or(ENOENT)?;
+        let names = inode.xattrs.list();
+        if names.len() > XATTR_LIST_MAX {
+            return Err(E2BIG);
+        }
+        xattr::copy_out(&names, list, size)
+    }
+
+    fn removexattr(&mut self, inode_number: u64, name: *const i8) -> Result<(), i32> {
+        let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+        self.xattr_write_check(inode_number, Namespace::of(&name)?)?;
+        self.get_inode_mut(inode_number).ok_or(ENOENT)?.xattrs.remove(&name)?;
+        self.notify_inode(inode_number, inotify::IN_ATTRIB);
+        self.record_inode_change(ChangeOp::Attrib, inode_number);
+        Ok(())
+    }
+
+    // Only the owner (or root) may change an inode's ACLs
+    fn acl_owner_check(&self, inode_number: u64) -> Result<(), i32> {
+        let inode = self.get_inode(inode_number).ok_or(ENOENT)?;
+        if self.uid != 0 && self.uid != inode.user_id {
+            return Err(EPERM);
+        }
+        Ok(())
+    }
+
+    // The system.* namespace only holds the two ACLs. Setting an access
+    // ACL also sets the permission bits, and one that says no more than
+    // the bits do is not kept. A default ACL is only valid on a
+    // directory; an empty one removes it.
+    fn set_acl(&mut self, inode_number: u64, name: &str, value: &[u8], flags: i32) -> Result<(), i32> {
+        let is_default = match name {
+            ACL_ACCESS_XATTR => false,
+            ACL_DEFAULT_XATTR => true,
+            _ => return Err(EOPNOTSUPP),
+        };
+        self.acl_owner_check(inode_number)?;
+        let mut inode = self.get_inode_mut(inode_number).ok_or(ENOENT)?;
+        if is_default {
+            if inode.kind != InodeKind::Directory {
+                return Err(EACCES);
+            }
+            if value.is_empty() {
+                let _ = inode.xattrs.remove(name);
+                return Ok(());
+            }
+            let acl = Acl::from_xattr(value)?;
+            return inode.xattrs.set(name, &acl.to_xattr(), flags);
+        }
+        let acl = Acl::from_xattr(value)?;
+        inode.xattrs.set(name, &acl.to_xattr(), flags)?;
+        inode.permissions = Permissions::from(acl.mode());
+        if acl.is_equivalent_mode() {
+            let _ = inode.xattrs.remove(name);
+        }
+        Ok(())
+    }
+
+    // Moves (or with `keep`, copies as tee does) buffered bytes from
+    // one pipe to another. At most one pipe's worth is staged at once.
+    fn pipe_to_pipe(&mut self, from: u64, to: u64, len: usize, keep: bool) -> Result<usize, i32> {
+        if from == to {
+            return Err(EINVAL);
+        }
+        let staged = {
+            let fs = self.fs.borrow();
+            let src = fs.pipes.get(&from).ok_or(EBADF)?;
+            let dst = fs.pipes.get(&to).ok_or(EBADF)?;
+            if src.is_empty() {
+                return if src.writers == 0 { Ok(0) } else { Err(EAGAIN) };
+            }
+            let n = dst.writable(min(len, src.len()))?;
+            src.as_slice()[..n].to_vec()
+        };
+        let n = self.fs.borrow_mut().pipes.get_mut(&to).ok_or(EBADF)?.write(&staged)?;
+        if !keep {
+            if let Some(src) = self.fs.borrow_mut().pipes.get_mut(&from) {
+                src.consume(n);
+            }
+        }
+        Ok(n)
+    }
+
+    // Find the next available FD. 0-2 are ordinary slots, so once
+    // stdin/stdout/stderr are closed they get reused like on Linux.
+    fn allocate_fd(&mut self) -> Option<FileDescriptor> {
+        self.allocate_fd_from(0)
+    }
+
+    // Lowest free FD that is >= `lowest`, as F_DUPFD wants. None also
+    // when `open_files` has no room for another handle.
+    fn allocate_fd_from(&mut self, lowest: FileDescriptor) -> Option<FileDescriptor> {
+        if self.open_files.is_full() {
+            return None;
+        }
+        for (index, slot) in self.fd_table.iter().enumerate() {
+            if slot.is_none() && index >= lowest as usize {
+                return Some(index as FileDescriptor);
+            }
+        }
+        None
+    }
+
+    fn get_absolute_path(&self, path: &PathBuf) -> PathBuf {
+        if path.is_absolute() {
+            path.clone()
+        } else {
+            self.cwd.join(path)
+        }
+    }
+
+    fn get_inode_mut(&mut self, inode_num: u64) -> Option<RefMut<'_, Inode>> {
+        RefMut::filter_map(self.fs.borrow_mut(), |fs| fs.inodes.get_mut(inode_num as usize)).ok()
+    }
+
+    fn lookup(&self, path: &PathBuf) -> Option<u64> {
+        self.fs.borrow().lookup_inode_by_path(path)
+    }
+
+    fn kind_of(&self, inode_num: u64) -> Option<InodeKind> {
+        self.get_inode(inode_num).map(|i| i.kind.clone())
+    }
+
+    fn get_inode(&self, inode_num: u64) -> Option<Ref<'_, Inode>> {
+        Ref::filter_map(self.fs.borrow(), |fs| fs.inodes.get(inode_num as usize)).ok()
+    }
+
+    fn set_file_size(&mut self, inode_number: u64, new_size: u64) {
+        let new_len = {
+            if let Some(data) = self.fs.borrow_mut().files.get_mut(&inode_number) {
+                data.set_len(new_size);
+                data.len()
+            } else {
+                0
+            }
+        };
+
+        if let Some(mut inode) = self.get_inode_mut(inode_number) {
+            inode.size = new_len;
+        }
+        self.fs.borrow_mut().recharge(inode_number);
+    }
+
+    // truncate/ftruncate; provider files decide for themselves.
+    // Growing leaves a hole and shrinking frees pages, so no quota
+    // ever refuses it.
+    fn truncate_inode(&mut self, inode_number: u64, len: u64) -> i32 {
+        let provided = self.fs.borrow_mut().providers.get_mut(&inode_number).map(|provider| provider.truncate(len));
+        let result = match provided {
+            Some(result) => result,
+            None => self.check_seals_resize(inode_number, len).map(|_| self.set_file_size(inode_number, len)),
+        };
+        match result {
+            Ok(()) => {
+                self.notify_inode(inode_number, inotify::IN_MODIFY);
+                self.record_inode_change(ChangeOp::Truncate { size: len }, inode_number);
+                0
+            }
+            Err(e) => {
+                self.set_errno(e);
+                -1
+            }
+        }
+    }
+
+    // Checks `mode` (R_OK etc.) for the process's credentials. An
+    // access ACL decides when the inode has one; otherwise the owner,
+    // group or other bits do. There are no capabilities, so root gets
+    // no special treatment.
+    fn check_access(&self, inode: &Inode, mode: i32) -> bool {
+        let mut want = 0;
+        if (mode & R_OK) != 0 { want |= ACL_READ; }
+        if (mode & W_OK) != 0 { want |= ACL_WRITE; }
+        if (mode & X_OK) != 0 { want |= ACL_EXECUTE; }
+
+        let acl = inode.xattrs.get(ACL_ACCESS_XATTR).ok()
+            .and_then(|bytes| Acl::from_xattr(bytes).ok())
+            .unwrap_or_else(|| Acl::from_mode(inode.permissions.mode()));
+        let cred = Credentials { uid: self.uid, gid: self.gid, groups: &self.groups };
+        acl.permits(&cred, inode.user_id, inode.group_id, want)
+    }
+
+    // Adding or removing an entry needs write and search permission on
+    // the directory that holds it
+    fn check_parent_access(&self, path: &PathBuf) -> Result<(), i32> {
+        let parent = path.parent().ok_or(ENOENT)?;
+        let dir = self.lookup(&parent)
+            .and_then(|ino| self.get_inode(ino))
+            .ok_or(ENOENT)?;
+        if dir.kind != InodeKind::Directory {
+            return Err(ENOTDIR);
+        }
+        if !self.check_access(&dir, W_OK | X_OK) {
+            return Err(EACCES);
+        }
+        Ok(())
+    }
+
+    // Ownership and inherited ACLs for a freshly created inode. When
+    // the parent directory has a default ACL it takes the place of the
+    // umask: the new inode's access ACL is the default masked by
+    // `mode`, and a new directory also inherits the default itself.
+    // Watchers of the directory are told with IN_CREATE.
+    fn init_new_inode(&mut self, path: &PathBuf, inode_number: u64, mode: u32) {
+        self.notify(path, inode_number, inotify::IN_CREATE, 0);
+        self.record_change(ChangeOp::Create, path, inode_number);
+        let (uid, gid) = (self.uid, self.gid);
+        let default = path.parent()
+            .and_then(|parent| self.lookup(&parent))
+            .and_then(|dir| self.get_inode(dir).and_then(|dir| dir.xattrs.get(ACL_DEFAULT_XATTR).ok().map(|b| b.to_vec())))
+            .and_then(|bytes| Acl::from_xattr(&bytes).ok());
+        match self.get_inode_mut(inode_number) {
+            Some(mut inode) => {
+                inode.user_id = uid;
+                inode.group_id = gid;
+            }
+            None => return,
+        }
+        self.fs.borrow_mut().recharge(inode_number);
+        let mut inode = match self.get_inode_mut(inode_number) {
+            Some(inode) => inode,
+            None => return,
+        };
+        let default = match default {
+            Some(acl) if !matches!(inode.kind, InodeKind::SymbolicLink(_)) => acl,
+            _ => return,
+        };
+        let access = default.create_masq((mode & 0o777) as u16);
+        inode.permissions = Permissions::from(access.mode());
+        if !access.is_equivalent_mode() {
+            let _ = inode.xattrs.set(ACL_ACCESS_XATTR, &access.to_xattr(), 0);
+        }
+        if inode.kind == InodeKind::Directory {
+            let _ = inode.xattrs.set(ACL_DEFAULT_XATTR, &default.to_xattr(), 0);
+        }
+    }
+
+    // chmod: only the owner (or root) may; an access ACL follows the
+    // new bits, with the group bits landing on its mask entry
+    fn chmod_inode(&mut self, inode_number: u64, mode: u32) -> Result<(), i32> {
+        self.acl_owner_check(inode_number)?;
+        let mut inode = self.get_inode_mut(inode_number).ok_or(ENOENT)?;
+        inode.permissions = Permissions::from((mode & 0o777) as u16);
+        if let Some(mut acl) = inode.xattrs.get(ACL_ACCESS_XATTR).ok().and_then(|b| Acl::from_xattr(b).ok()) {
+            acl.chmod((mode & 0o777) as u16);
+            let _ = inode.xattrs.set(ACL_ACCESS_XATTR, &acl.to_xattr(), 0);
+        }
+        drop(inode);
+        self.notify_inode(inode_number, inotify::IN_ATTRIB);
+        self.record_inode_change(ChangeOp::Attrib, inode_number);
+        Ok(())
+    }
+
+    // chown: giving a file away takes root here, whoever owns it, and
+    // room in the new owner's quotas
+    fn chown_inode(&mut self, inode_number: u64, owner: u32, group: u32) -> Result<(), i32> {
+        if self.uid != 0 {
+            return Err(EPERM);
+        }
+        self.fs.borrow().check_chown(inode_number, owner, group)?;
+        let mut inode = self.get_inode_mut(inode_number).ok_or(ENOENT)?;
+        inode.user_id = owner;
+        inode.group_id = group;
+        drop(inode);
+        self.fs.borrow_mut().recharge(inode_number);
+        self.notify_inode(inode_number, inotify::IN_ATTRIB);
+        self.record_inode_change(ChangeOp::Attrib, inode_number);
+        Ok(())
+    }
+
+    fn insert_directory_entry(&mut self, path: &PathBuf, inode_number: u64, kind: InodeKind) {
+        let inode = Inode::new(
+            inode_number,
+            0,
+            Permissions::from((0o777 & !self.umask_value) as u16),
+            0,
+            0,
+            0,
+            0,
+            0,
+            kind,
+        );
+        while self.fs.borrow().inodes.len() <= inode_number as usize {
+            self.fs.borrow_mut().inodes.push(Inode::default());
+        }
+        self.fs.borrow_mut().inodes[inode_number as usize] = inode.clone();
+        self.fs.borrow_mut().path_map.insert(path.clone(), inode_number);
+        if let InodeKind::Directory = inode.kind {
+            self.fs.borrow_mut().files.insert(inode_number, FileData::new());
+        } else if let InodeKind::File = inode.kind {
+            self.fs.borrow_mut().files.insert(inode_number, FileData::new());
+        }
+        self.fs.borrow_mut().recharge(inode_number);
+    }
+}
+
+// A Proc going away closes its descriptors and mappings, as an exiting
+// process would, so the Procs it shared a FileSystem with see its pipe
+// ends close and the unlinked files it held freed.
+impl Drop for Proc {
+    fn drop(&mut self) {
+        let fds: Vec<FileDescriptor> = self.open_files.iter().map(|(fd, _)| *fd).collect();
+        for fd in fds {
+            self.close_fd(fd);
+        }
+        let regions = self.unmap_range(0, usize::MAX);
+        self.release_regions(regions);
+    }
+}
+
+//lazy_static! {
+    //static ref GLOBAL_PROC: Mutex<Option<Proc>> = Mutex::new(None);
+//}
+
+// Replace old static line:
+static mut GLOBAL_PROC: Option<Mutex<Proc>> = None;
+
+// Then add two helper functions:
+fn init_global_proc() {
+    unsafe {
+        if GLOBAL_PROC.is_none() {
+            GLOBAL_PROC = Some(Mutex::new(Proc::new()));
+        }
+    }
+}
+
+/// Locks the global process, creating it on first use.
+///
+/// # Re-entrancy
+///
+/// The lock is not reentrant: taking it again on the same thread spins
+/// forever. The traits that plug code into the VFS (`Stream` and the
+/// like) are called while it is held, so their methods must not call
+/// `wasm_vfs_*` functions or `get_or_init_proc`. Queue the work and do
+/// it once the VFS call has returned instead.
+pub fn get_or_init_proc() -> MutexGuard<'static, Proc> {
+    unsafe {
+        if GLOBAL_PROC.is_none() {
+            GLOBAL_PROC = Some(Mutex::new(Proc::new()));
+        }
+        GLOBAL_PROC.as_ref().unwrap().lock()
+    }
+}
+
+fn inode_kind_to_dtype(kind: &InodeKind) -> u8 {
+    match kind {
+        InodeKind::File => 8,         // DT_REG
+        InodeKind::Directory => 4,    // DT_DIR
+        InodeKind::SymbolicLink(_) => 10, // DT_LNK
+        InodeKind::Pipe | InodeKind::Fifo => 1, // DT_FIFO
+        InodeKind::CharDevice { .. } => 2, // DT_CHR
+        InodeKind::BlockDevice { .. } => 6, // DT_BLK
+    }
+}
+
+// `blocks` is st_blocks, in 512-byte units
+fn fill_stat_from_inode(inode: &Inode, blocks: u64, nlink: u32, statbuf: *mut Stat) {
+    let mode_type = match inode.kind {
+        InodeKind::File => 0o100000,       // regular file
+        InodeKind::Directory => 0o040000,  // directory
+        InodeKind::SymbolicLink(_) => 0o120000, // symlink
+        InodeKind::Pipe | InodeKind::Fifo => 0o010000, // fifo
+        InodeKind::CharDevice { .. } => 0o020000, // character device
+        InodeKind::BlockDevice { .. } => 0o060000, // block device
+    };
+    let rdev = match inode.kind {
+        InodeKind::CharDevice { major, minor } | InodeKind::BlockDevice { major, minor } => device::makedev(major, minor),
+        _ => 0,
+    };
+    let perms = &inode.permissions;
+    let mut mode_perms = 0;
+    // owner
+    if perms.owner.read { mode_perms |= 0o400; }
+    if perms.owner.write { mode_perms |= 0o200; }
+    if perms.owner.execute { mode_perms |= 0o100; }
+    // group
+    if perms.group.read { mode_perms |= 0o040; }
+    if perms.group.write { mode_perms |= 0o020; }
+    if perms.group.execute { mode_perms |= 0o010; }
+    // other
+    if perms.other.read { mode_perms |= 0o004; }
+    if perms.other.write { mode_perms |= 0o002; }
+    if perms.other.execute { mode_perms |= 0o001; }
+
+    let st_mode = mode_type | mode_perms;
+
+    unsafe {
+        (*statbuf).st_dev = 0;
+        (*statbuf).st_ino = inode.number;
+        (*statbuf).st_mode = st_mode;
+        (*statbuf).st_nlink = nlink;
+        (*statbuf).st_uid = inode.user_id;
+        (*statbuf).st_gid = inode.group_id;
+        (*statbuf).st_rdev = rdev;
+        (*statbuf).st_size = inode.size as i64;
+        (*statbuf).st_blksize = 4096;
+        (*statbuf).st_blocks = blocks as i64;
+        (*statbuf).st_atime = inode.atime as i64;
+        (*statbuf).st_mtime = inode.mtime as i64;
+        (*statbuf).st_ctime = inode.ctime as i64;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn wasm_vfs_init_proc(_size: u32) -> *const u8 {
+    unimplemented!()
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_open(path: *const i8, flags: i32, mode: u32) -> i32 {
+    open_fd(&mut get_or_init_proc(), path, flags, mode)
+}
+
+fn open_fd(proc: &mut Proc, path: *const i8, flags: i32, mode: u32) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
+
+    if path_str.is_empty() {
+        proc.set_errno(ENOENT);
+        return -1;
+    }
+    let path_buf = PathBuf::from(path_str);
+    if let Err(e) = proc.refresh_procfs(&path_buf) {
+        proc.set_errno(e);
+        return -1;
+    }
+
+    if (flags & O_TMPFILE) == O_TMPFILE {
+        return match proc.open_tmpfile(&path_buf, flags, mode) {
+            Ok(fd) => fd,
+            Err(e) => {
+                proc.set_errno(e);
+                -1
+            }
+        };
+    }
+
+    let should_create = (flags & O_CREAT) == O_CREAT;
+    let should_truncate = (flags & O_TRUNC) == O_TRUNC;
+    let append_mode = (flags & O_APPEND) == O_APPEND;
+    let nonblock = (flags & O_NONBLOCK) == O_NONBLOCK;
+    let cloexec = (flags & O_CLOEXEC) == O_CLOEXEC;
+
+    // Everything under /proc is read-only
+    if proc.procfs_mounted && procfs::is_proc_path(&path_buf) && (should_create || (flags & O_ACCMODE) != O_RDONLY) {
+        proc.set_errno(EACCES);
+        return -1;
+    }
+
+    // 1) Determine the inode_number
+    let inode_number = if let Some(inode_num) = proc.lookup(&path_buf) {
+        let mut want = match flags & O_ACCMODE {
+            O_RDONLY => R_OK,
+            O_WRONLY => W_OK,
+            _ => R_OK | W_OK,
+        };
+        if should_truncate {
+            want |= W_OK;
+        }
+        let permitted = proc.get_inode(inode_num).map(|inode| proc.check_access(&inode, want)).unwrap_or(false);
+        if !permitted {
+            proc.set_errno(EACCES);
+            return -1;
+        }
+        inode_num
+    } else if should_create {
+        let abs_path = proc.get_absolute_path(&path_buf);
+        if let Err(e) = proc.check_parent_access(&abs_path).and_then(|_| proc.charge_create(Some(&path_buf))) {
+            proc.set_errno(e);
+            return -1;
+        }
+        let inode_num = proc.fs.borrow_mut().create_file(&path_buf, mode);
+        proc.init_new_inode(&path_buf, inode_num, mode);
+        inode_num
+    } else {
+        proc.set_errno(ENOENT);
+        return -1;
+    };
+
+    // FIFOs never touch `files`; they share a buffer in `fs.pipes`.
+    // Device nodes hand their I/O to a driver, and provider files to
+    // their FileProvider.
+    let special = match proc.kind_of(inode_number) {
+        Some(InodeKind::Fifo) => Some(proc.open_fifo(inode_number, flags)),
+        Some(InodeKind::CharDevice { major, minor }) => Some(proc.open_device(inode_number, DeviceClass::Char, major, minor, flags)),
+        Some(InodeKind::BlockDevice { major, minor }) => Some(proc.open_device(inode_number, DeviceClass::Block, major, minor, flags)),
+        Some(InodeKind::File) if proc.fs.borrow().providers.contains_key(&inode_number) => Some(proc.open_provider(inode_number, flags)),
+        _ => None,
+    };
+    if let Some(result) = special {
+        return match result {
+            Ok(fd) => {
+                proc.notify(&path_buf, inode_number, inotify::IN_OPEN, 0);
+                fd
+            }
+            Err(e) => {
+                proc.set_errno(e);
+                -1
+            }
+        };
+    }
+
+    // 2) Possibly truncate
+    if should_truncate {
+        if let Some(data) = proc.fs.borrow_mut().files.get_mut(&inode_number) {
+            data.set_len(0);
+        }
+        proc.fs.borrow_mut().recharge(inode_number);
+        proc.notify(&path_buf, inode_number, inotify::IN_MODIFY, 0);
+        proc.record_change(ChangeOp::Truncate { size: 0 }, &path_buf, inode_number);
+    } else {
+        if !proc.fs.borrow().files.contains_key(&inode_number) {
+            proc.fs.borrow_mut().files.insert(inode_number, FileData::new());
+        }
+    }
+
+    // 3) Allocate FD
+    let fd = match proc.allocate_fd() {
+        Some(x) => x,
+        None => {
+            proc.set_errno(EMFILE);
+            return -1;
+        }
+    };
+
+    // 4) If append_mode, figure out the file’s length in a short scope
+    let initial_pos = if append_mode {
+        let data_len = proc
+            .fs
+            .borrow()
+            .files
+            .get(&inode_number)
+            .map(|v| v.len())
+            .unwrap_or(0);
+        data_len as u64
+    } else {
+        0
+    };
+
+    // 5) Finally insert the handle
+    proc.install_handle(
+        fd,
+        OpenFileHandle {
+            inode_number,
+            description: OpenFileDescription::new(initial_pos, append_mode, nonblock),
+            kind: HandleKind::File,
+            cloexec,
+            awaiting_peer: false,
+            writable: (flags & O_ACCMODE) != O_RDONLY,
+            access: flags & O_ACCMODE,
+        },
+    );
+    proc.notify(&path_buf, inode_number, inotify::IN_OPEN, 0);
+
+    fd
+}
+
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_close(fd: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    if proc.close_fd(fd) {
+        0
+    } else {
+        proc.set_errno(EBADF);
+        -1
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_creat(path: *const i8, mode: u32) -> i32 {
+    wasm_vfs_open(path, O_WRONLY | O_CREAT | O_TRUNC, mode)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_read(fd: i32, buf: *mut u8, count: usize) -> isize {
+    read_fd(&mut get_or_init_proc(), fd, buf, count)
+}
+
+fn read_fd(proc: &mut Proc, fd: i32, buf: *mut u8, count: usize) -> isize {
+
+    let (inode_num, position, kind) = {
+        let h = match proc.open_files.get_mut(&fd) {
+            Some(x) => x,
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+        };
+        (h.inode_number, h.position(), h.kind)
+    };
+
+    match kind {
+        HandleKind::File => {}
+        HandleKind::PipeReader | HandleKind::PipeDuplex => {
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            let result = proc.check_rendezvous(fd).and_then(|_| {
+                match proc.fs.borrow_mut().pipes.get_mut(&inode_num) {
+                    Some(pipe) => pipe.read(out),
+                    None => Err(EBADF),
+                }
+            });
+            return match result {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Stream | HandleKind::Tty => {
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            let result = if kind == HandleKind::Tty {
+                match proc.tty.as_mut() {
+                    Some(tty) => tty.read(out),
+                    None => Err(EBADF),
+                }
+            } else {
+                match proc.streams.get_mut(&inode_num) {
+                    Some(stream) => stream.read(out),
+                    None => Err(EBADF),
+                }
+            };
+            return match result {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Device => {
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            return match proc.device_read(inode_num, out, position) {
+                Ok(n) => {
+                    proc.advance_device(fd, n);
+                    n as isize
+                }
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Provider => {
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            return match proc.provider_read(inode_num, out, position) {
+                Ok(n) => {
+                    if let Some(h) = proc.open_files.get_mut(&fd) {
+                        h.set_position(h.position() + n as u64);
+                    }
+                    n as isize
+                }
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Inotify => {
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            let result = match proc.inotify.get_mut(&inode_num) {
+                Some(instance) => instance.read(out),
+                None => Err(EBADF),
+            };
+            return match result {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Epoll => {
+            proc.set_errno(EINVAL);
+            return -1;
+        }
+        HandleKind::Object => {
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            let result = match proc.objects.get_mut(&inode_num) {
+                Some(object) => object.read(out),
+                None => Err(EBADF),
+            };
+            return match result {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::EventFd | HandleKind::TimerFd => {
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            let result = match kind {
+                HandleKind::EventFd => proc.eventfds.get_mut(&inode_num).map(|counter| counter.read(out)),
+                _ => proc.timers.get_mut(&inode_num).map(|timer| timer.read(out)),
+            };
+            return match result.unwrap_or(Err(EBADF)) {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::PipeWriter => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    }
+
+    let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+    let read = proc.fs.borrow().files.get(&inode_num).map(|data| data.read_at(out, position));
+    let to_read = match read {
+        Some(n) => n,
+        None => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    };
+
+
+    if let Some(h2) = proc.open_files.get_mut(&fd) {
+        h2.set_position(h2.position() + to_read as u64);
+    }
+    proc.notify_inode(inode_num, inotify::IN_ACCESS);
+
+    to_read as isize
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_write(fd: i32, buf: *const u8, count: usize) -> isize {
+    write_fd(&mut get_or_init_proc(), fd, buf, count)
+}
+
+fn write_fd(proc: &mut Proc, fd: i32, buf: *const u8, count: usize) -> isize {
+
+    let (inode_num, old_pos, append_mode, kind) = {
+        let h = match proc.open_files.get_mut(&fd) {
+            Some(x) => x,
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+        };
+        (h.inode_number, h.position(), h.append_mode(), h.kind)
+    };
+
+    match kind {
+        HandleKind::File => {}
+        HandleKind::PipeWriter | HandleKind::PipeDuplex => {
+            let data = unsafe { core::slice::from_raw_parts(buf, count) };
+            let result = proc.check_rendezvous(fd).and_then(|_| {
+                match proc.fs.borrow_mut().pipes.get_mut(&inode_num) {
+                    Some(pipe) => pipe.write(data),
+                    None => Err(EBADF),
+                }
+            });
+            return match result {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Stream | HandleKind::Tty => {
+            let data = unsafe { core::slice::from_raw_parts(buf, count) };
+            let result = if kind == HandleKind::Tty {
+                match proc.tty.as_mut() {
+                    Some(tty) => tty.write(data),
+                    None => Err(EBADF),
+                }
+            } else {
+                match proc.streams.get_mut(&inode_num) {
+                    Some(stream) => stream.write(data),
+                    None => Err(EBADF),
+                }
+            };
+            return match result {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Device => {
+            let data = unsafe { core::slice::from_raw_parts(buf, count) };
+            return match proc.device_write(inode_num, data, old_pos) {
+                Ok(n) => {
+                    proc.advance_device(fd, n);
+                    n as isize
+                }
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Provider => {
+            let data = unsafe { core::slice::from_raw_parts(buf, count) };
+            return match proc.provider_write(inode_num, data, old_pos, append_mode) {
+                Ok((n, end)) => {
+                    if let Some(h) = proc.open_files.get_mut(&fd) {
+                        h.set_position(end);
+                    }
+                    n as isize
+                }
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Object => {
+            let data = unsafe { core::slice::from_raw_parts(buf, count) };
+            let result = match proc.objects.get_mut(&inode_num) {
+                Some(object) => object.write(data),
+                None => Err(EBADF),
+            };
+            return match result {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::EventFd => {
+            let data = unsafe { core::slice::from_raw_parts(buf, count) };
+            let result = match proc.eventfds.get_mut(&inode_num) {
+                Some(counter) => counter.write(data),
+                None => Err(EBADF),
+            };
+            return match result {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        HandleKind::Inotify | HandleKind::Epoll | HandleKind::TimerFd => {
+            proc.set_errno(EINVAL);
+            return -1;
+        }
+        HandleKind::PipeReader => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    }
+
+
+    let new_position = {
+        let len = proc.fs.borrow().files.get(&inode_num).map(|d| d.len());
+        let actual_pos = match len {
+            Some(len) if append_mode => len,
+            Some(_) => old_pos,
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+        };
+        let allowed = proc.check_seals_write(inode_num, actual_pos, count as u64)
+            .and_then(|_| proc.charge_write(inode_num, actual_pos, count as u64));
+        if let Err(e) = allowed {
+            proc.set_errno(e);
+            return -1;
+        }
+        let bytes = unsafe { core::slice::from_raw_parts(buf, count) };
+        let written = proc.fs.borrow_mut().files.get_mut(&inode_num).map(|data| data.write_at(bytes, actual_pos));
+        match written {
+            Some(Ok(n)) => actual_pos + n as u64,
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+            Some(Err(e)) => {
+                proc.set_errno(e);
+                return -1;
+            }
+        }
+    };
+
+    if let Some(handle2) = proc.open_files.get_mut(&fd) {
+        handle2.set_position(new_position);
+    }
+    proc.sync_file_size(inode_num);
+    proc.data_changed(inode_num, new_position - count as u64, count as u64);
+    count as isize
+}
+
+
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_openat(dirfd: i32, pathname: *const i8, flags: i32, mode: u32) -> i32 {
+    // Relative paths still resolve against the cwd, but a dirfd that
+    // isn't open is refused as Linux would.
+    let relative = unsafe { *pathname != b'/' as i8 };
+    if relative && dirfd != AT_FDCWD {
+        let mut proc = get_or_init_proc();
+        if !proc.open_files.contains_key(&dirfd) {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    }
+    wasm_vfs_open(pathname, flags, mode)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_dup(oldfd: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    if (oldfd as usize) < proc.fd_table.len() {
+        if let Some(inode_number) = proc.fd_table[oldfd as usize] {
+            let mut new_handle = match proc.open_files.get(&oldfd) {
+                Some(h) => h.clone(),
+                None => return -1,
+            };
+            // FD_CLOEXEC belongs to the descriptor, not the open file
+            new_handle.cloexec = false;
+
+            if let Some(new_fd) = proc.allocate_fd() {
+                proc.install_handle(new_fd, new_handle);
+                return new_fd;
+            }
+        }
+    }
+    -1
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_dup2(oldfd: i32, newfd: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    if (oldfd as usize) < proc.fd_table.len() && (newfd as usize) < proc.fd_table.len() {
+        if let Some(inode_number) = proc.fd_table[oldfd as usize] {
+            if oldfd == newfd {
+                return newfd;
+            }
+
+            proc.close_fd(newfd);
+
+            if let Some(old_handle) = proc.open_files.get(&oldfd) {
+                let mut new_handle = old_handle.clone();
+                new_handle.cloexec = false;
+                proc.install_handle(newfd, new_handle);
+            } else {
+                proc.fd_table[newfd as usize] = Some(inode_number);
+            }
+
+            return newfd;
+        }
+    }
+    -1
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_pread64(fd: i32, buf: *mut u8, count: usize, offset: i64) -> isize {
+    pread_fd(&mut get_or_init_proc(), fd, buf, count, offset)
+}
+
+fn pread_fd(proc: &mut Proc, fd: i32, buf: *mut u8, count: usize, offset: i64) -> isize {
+
+    let inode_num = {
+        let h = match proc.open_files.get(&fd) {
+            Some(x) => x,
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+        };
+        if h.kind == HandleKind::Device {
+            let inode_num = h.inode_number;
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            return match proc.device_read(inode_num, out, offset as u64) {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        if h.kind == HandleKind::Provider {
+            let inode_num = h.inode_number;
+            let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+            return match proc.provider_read(inode_num, out, offset as u64) {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        if !h.seekable() {
+            proc.set_errno(ESPIPE);
+            return -1;
+        }
+        h.inode_number
+    };
+
+    if !proc.fs.borrow().files.contains_key(&inode_num) {
+        proc.set_errno(EBADF);
+        return -1;
+    }
+
+    if offset < 0 {
+        proc.set_errno(EINVAL);
+        return -1;
+    }
+    let out = unsafe { core::slice::from_raw_parts_mut(buf, count) };
+    let n = proc.fs.borrow().files.get(&inode_num).map_or(0, |data| data.read_at(out, offset as u64));
+    proc.notify_inode(inode_num, inotify::IN_ACCESS);
+    n as isize
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_pwrite64(fd: i32, buf: *const u8, count: usize, offset: i64) -> isize {
+    pwrite_fd(&mut get_or_init_proc(), fd, buf, count, offset)
+}
+
+fn pwrite_fd(proc: &mut Proc, fd: i32, buf: *const u8, count: usize, offset: i64) -> isize {
+
+
+    let inode_num = {
+        let handle = match proc.open_files.get(&fd) {
+            Some(h) => h,
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+        };
+        if handle.kind == HandleKind::Device {
+            let inode_num = handle.inode_number;
+            let data = unsafe { core::slice::from_raw_parts(buf, count) };
+            return match proc.device_write(inode_num, data, offset as u64) {
+                Ok(n) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        if handle.kind == HandleKind::Provider {
+            let inode_num = handle.inode_number;
+            let data = unsafe { core::slice::from_raw_parts(buf, count) };
+            return match proc.provider_write(inode_num, data, offset as u64, false) {
+                Ok((n, _)) => n as isize,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            };
+        }
+        if !handle.seekable() {
+            proc.set_errno(ESPIPE);
+            return -1;
+        }
+        handle.inode_number
+    };
+
+    if offset < 0 {
+        proc.set_errno(EINVAL);
+        return -1;
+    }
+    let allowed = proc.check_seals_write(inode_num, offset as u64, count as u64)
+        .and_then(|_| proc.charge_write(inode_num, offset as u64, count as u64));
+    if let Err(e) = allowed {
+        proc.set_errno(e);
+        return -1;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(buf, count) };
+    let written = proc.fs.borrow_mut().files.get_mut(&inode_num).map(|data| data.write_at(bytes, offset as u64));
+    let result = match written {
+        Some(result) => result,
+        None => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    };
+    if let Err(e) = result {
+        proc.set_errno(e);
+        return -1;
+    }
+    proc.sync_file_size(inode_num);
+    proc.data_changed(inode_num, offset as u64, count as u64);
+
+    count as isize
+}
+
+
+// The iovec array of a readv/writev or vmsplice call. Like Linux, reject
+// negative or oversized counts, a null array and totals that don't fit
+// the return value.
+fn iovec_slice<'a>(iov: *const Iovec, iovcnt: i32) -> Result<&'a [Iovec], i32> {
+    if !(0..=IOV_MAX).contains(&iovcnt) {
+        return Err(EINVAL);
+    }
+    if iovcnt == 0 {
+        return Ok(&[]);
+    }
+    if iov.is_null() {
+        return Err(EFAULT);
+    }
+    let iovs = unsafe { core::slice::from_raw_parts(iov, iovcnt as usize) };
+    let mut total: usize = 0;
+    for v in iovs {
+        total = total.checked_add(v.iov_len).filter(|t| *t <= isize::MAX as usize).ok_or(EINVAL)?;
+    }
+    Ok(iovs)
+}
+
+fn set_errno_and_fail(errno: i32) -> isize {
+    get_or_init_proc().set_errno(errno);
+    -1
+}
+
+// A vectored read fills each buffer straight from the file in turn,
+// all under one lock. A short transfer ends it early. An error after
+// some bytes went through returns those bytes instead, as on Linux.
+fn vectored(iovs: &[Iovec], mut transfer: impl FnMut(*mut u8, usize, i64) -> isize) -> isize {
+    // With nothing to move, the descriptor is still checked
+    if iovs.iter().all(|v| v.iov_len == 0) {
+        return transfer(core::ptr::NonNull::dangling().as_ptr(), 0, 0);
+    }
+    let mut done: isize = 0;
+    for v in iovs.iter().filter(|v| v.iov_len > 0) {
+        let n = transfer(v.iov_base, v.iov_len, done as i64);
+        if n < 0 {
+            return if done > 0 { done } else { n };
+        }
+        done += n;
+        if (n as usize) < v.iov_len {
+            break;
+        }
+    }
+    done
+}
+
+fn vectored_read(fd: i32, iov: *const Iovec, iovcnt: i32, offset: Option<i64>) -> isize {
+    let iovs = match iovec_slice(iov, iovcnt) {
+        Ok(v) => v,
+        Err(e) => return set_errno_and_fail(e),
+    };
+    let mut proc = get_or_init_proc();
+    vectored(iovs, |buf, len, done| match offset {
+        Some(off) => pread_fd(&mut proc, fd, buf, len, off + done),
+        None => read_fd(&mut proc, fd, buf, len),
+    })
+}
+
+// A vectored write gathers its buffers and writes them in one call, so
+// it is checked and charged once, lands at a single offset (one end of
+// file under O_APPEND) and is as atomic on a pipe as a plain write.
+fn vectored_write(fd: i32, iov: *const Iovec, iovcnt: i32, offset: Option<i64>) -> isize {
+    let iovs = match iovec_slice(iov, iovcnt) {
+        Ok(v) => v,
+        Err(e) => return set_errno_and_fail(e),
+    };
+    let mut data = Vec::with_capacity(iovs.iter().map(|v| v.iov_len).sum());
+    for v in iovs.iter().filter(|v| v.iov_len > 0) {
+        data.extend_from_slice(unsafe { core::slice::from_raw_parts(v.iov_base, v.iov_len) });
+    }
+    let mut proc = get_or_init_proc();
+    match offset {
+        Some(off) => pwrite_fd(&mut proc, fd, data.as_ptr(), data.len(), off),
+        None => write_fd(&mut proc, fd, data.as_ptr(), data.len()),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_readv(fd: i32, iov: *const Iovec, iovcnt: i32) -> isize {
+    vectored_read(fd, iov, iovcnt, None)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_writev(fd: i32, iov: *const Iovec, iovcnt: i32) -> isize {
+    vectored_write(fd, iov, iovcnt, None)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_preadv(fd: i32, iov: *const Iovec, iovcnt: i32, offset: i64) -> isize {
+    if offset < 0 {
+        return set_errno_and_fail(EINVAL);
+    }
+    vectored_read(fd, iov, iovcnt, Some(offset))
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_pwritev(fd: i32, iov: *const Iovec, iovcnt: i32, offset: i64) -> isize {
+    if offset < 0 {
+        return set_errno_and_fail(EINVAL);
+    }
+    vectored_write(fd, iov, iovcnt, Some(offset))
+}
+
+// An offset of -1 means the current file position, as with readv.
+// Reads never block here, so RWF_NOWAIT needs no extra handling.
+#[no_mangle]
+pub extern "C" fn wasm_vfs_preadv2(fd: i32, iov: *const Iovec, iovcnt: i32, offset: i64, flags: i32) -> isize {
+    if flags & !RWF_SUPPORTED != 0 {
+        return set_errno_and_fail(EOPNOTSUPP);
+    }
+    match offset {
+        -1 => vectored_read(fd, iov, iovcnt, None),
+        off if off < 0 => set_errno_and_fail(EINVAL),
+        off => vectored_read(fd, iov, iovcnt, Some(off)),
+    }
+}
+
+// RWF_APPEND appends whatever the offset and O_APPEND say, and only
+// moves the file position when the offset is -1; RWF_NOAPPEND ignores
+// O_APPEND for this call.
+#[no_mangle]
+pub extern "C" fn wasm_vfs_pwritev2(fd: i32, iov: *const Iovec, iovcnt: i32, offset: i64, flags: i32) -> isize {
+    if flags & !RWF_SUPPORTED != 0 {
+        return set_errno_and_fail(EOPNOTSUPP);
+    }
+    if offset < -1 {
+        return set_errno_and_fail(EINVAL);
+    }
+    let append = if flags & RWF_APPEND != 0 {
+        Some(true)
+    } else if flags & RWF_NOAPPEND != 0 {
+        Some(false)
+    } else {
+        None
+    };
+
+    let (saved_append, end) = {
+        let mut proc = get_or_init_proc();
+        let (inode_num, saved, seekable) = match proc.open_files.get(&fd) {
+            Some(h) => (h.inode_number, h.append_mode(), h.seekable() || h.kind == HandleKind::Provider),
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+        };
+        if !seekable {
+            // Pipes, streams and devices have no end to append at
+            drop(proc);
+            return match offset {
+                -1 => vectored_write(fd, iov, iovcnt, None),
+                off => vectored_write(fd, iov, iovcnt, Some(off)),
+            };
+        }
+        let end = match proc.fs.borrow().providers.get(&inode_num) {
+            Some(provider) => provider.size(),
+            None => proc.fs.borrow().files.get(&inode_num).map(|d| d.len() as u64).unwrap_or(0),
+        };
+        if offset == -1 {
+            if let (Some(append), Some(h)) = (append, proc.open_files.get_mut(&fd)) {
+                h.set_append_mode(append);
+            }
+        }
+        (saved, end)
+    };
+
+    if offset != -1 {
+        let at = if append == Some(true) { end as i64 } else { offset };
+        return vectored_write(fd, iov, iovcnt, Some(at));
+    }
+    let n = vectored_write(fd, iov, iovcnt, None);
+    if let Some(h) = get_or_init_proc().open_files.get_mut(&fd) {
+        h.set_append_mode(saved_append);
+    }
+    n
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_sendfile(
+    out_fd: i32,
+    in_fd: i32,
+    offset: *mut i64,
+    count: usize
+) -> isize {
+    let mut proc = get_or_init_proc();
+
+    let (in_inode_number, in_pos, in_app) = {
+        let h_in = match proc.open_files.get(&in_fd) {
+            Some(h) => h,
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+        };
+        (h_in.inode_number, h_in.position(), h_in.append_mode())
+    };
+
+    let (out_inode_number, out_pos, out_app) = {
+        let h_out = match proc.open_files.get(&out_fd) {
+            Some(h) => h,
+            None => {
+                proc.set_errno(EBADF);
+                return -1;
+            }
+        };
+        (h_out.inode_number, h_out.position(), h_out.append_mode())
+    };
+
+    let in_len = proc.fs.borrow().files.get(&in_inode_number).map(|d| d.len());
+    let in_len = match in_len {
+        Some(len) => len,
+        None => {
+            proc.set_errno(EINVAL);
+            return -1;
+        }
+    };
+
+    let read_pos = if !offset.is_null() {
+        unsafe { *offset as u64 }
+    } else {
+        in_pos
+    };
+    if read_pos >= in_len {
+        return 0;
+    }
+
+    let real_out_pos = if out_app {
+        match proc.fs.borrow().files.get(&out_inode_number) {
+            Some(d) => d.len(),
+            None => return -1,
+        }
+    } else {
+        out_pos
+    };
+
+    let to_copy = match proc.file_to_file(in_inode_number, read_pos, out_inode_number, real_out_pos, count as u64) {
+        Ok(n) => n,
+        Err(e) => {
+            proc.set_errno(e);
+            return -1;
+        }
+    };
+
+    if !offset.is_null() {
+        unsafe {
+            *offset += to_copy as i64;
+        }
+    } else if let Some(h_in_mut) = proc.open_files.get_mut(&in_fd) {
+        h_in_mut.set_position(in_pos + to_copy as u64);
+    }
+
+    if let Some(h_out_mut) = proc.open_files.get_mut(&out_fd) {
+        h_out_mut.set_position(real_out_pos + to_copy as u64);
+    }
+
+    to_copy as isize
+}
+
+
+// Copies between two regular files without going through the caller.
+// A null offset pointer means the descriptor's position, which then
+// advances; otherwise the pointed-to offset is used and updated.
+#[no_mangle]
+pub extern "C" fn wasm_vfs_copy_file_range(
+    fd_in: i32,
+    off_in: *mut i64,
+    fd_out: i32,
+    off_out: *mut i64,
+    len: usize,
+    flags: u32,
+) -> isize {
+    let mut proc = get_or_init_proc();
+
+    if flags != 0 {
+        proc.set_errno(EINVAL);
+        return -1;
+    }
+    let in_offset = if off_in.is_null() { None } else { Some(unsafe { *off_in }) };
+    let out_offset = if off_out.is_null() { None } else { Some(unsafe { *off_out }) };
+    match proc.copy_file_range(fd_in, in_offset, fd_out, out_offset, len) {
+        Ok(n) => {
+            if !off_in.is_null() {
+                unsafe { *off_in += n as i64; }
+            }
+            if !off_out.is_null() {
+                unsafe { *off_out += n as i64; }
+            }
+            n as isize
+        }
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_sendfile64(out_fd: i32, in_fd: i32, offset: *mut i64, count: usize) -> isize {
+    wasm_vfs_sendfile(out_fd, in_fd, offset, count)
+}
+
+//#[no_mangle]
+//pub extern "C" fn wasm_vfs_splice(fd_in: i32, off_in: *mut i64, fd_out: i32, off_out: *mut i64, len: usize, _flags: u32) -> isize {
+    //let mut proc = get_or_init_proc();
+
+    //let (in_inode_number, in_position, in_append_mode) = {
+        //let in_handle = proc.open_files.get(&fd_in).ok_or(-1).unwrap();
+        //(in_handle.inode_number, in_handle.position, in_handle.append_mode)
+    //};
+
+    //let (out_inode_number, out_position, out_append_mode) = {
+        //let out_handle = proc.open_files.get(&fd_out).ok_or(-1).unwrap();
+        //(out_handle.inode_number, out_handle.position, out_handle.append_mode)
+    //};
+
+    //let in_data = proc.fs.borrow().files.get(&in_inode_number).unwrap().clone();
+
+    //let mut in_pos = if !off_in.is_null() {
+        //unsafe { *off_in as usize }
+    //} else {
+        //in_position as usize
+    //};
+
+    //if in_pos >= in_data.len() {
+        //return 0;
+    //}
+
+    //let to_copy = min(len, in_data.len() - in_pos);
+
+    //{
+        //let out_data = proc.fs.borrow_mut().files.get_mut(&out_inode_number).unwrap();
+        //let mut out_pos = if !off_out.is_null() {
+            //unsafe { *off_out as usize }
+        //} else if out_append_mode {
+            //out_data.len()
+        //} else {
+            //out_position as usize
+        //};
+
+        //if out_pos + to_copy > out_data.len() {
+            //out_data.resize(out_pos + to_copy, 0);
+        //}
+
+        //out_data[out_pos..out_pos+to_copy].copy_from_slice(&in_data[in_pos..in_pos+to_copy]);
+
+        //if !off_in.is_null() {
+            //unsafe {
+                //*off_in += to_copy as i64;
+            //}
+        //} else {
+            //if let Some(in_handle_mut) = proc.open_files.get_mut(&fd_in) {
+                //in_handle_mut.position = in_position + to_copy as u64;
+            //}
+        //}
+
+        //if !off_out.is_null() {
+            //unsafe {
+                //*off_out += to_copy as i64;
+            //}
+        //} else {
+            //if let Some(out_handle_mut) = proc.open_files.get_mut(&fd_out) {
+                //if out_append_mode {
+                    //out_handle_mut.position = out_data.len() as u64;
+                //} else {
+                    //out_handle_mut.position = out_position + to_copy as u64;
+                //}
+            //}
+        //}
+
+        //let new_size = out_data.len();
+        //drop(out_data);
+
+        //if let Some(out_inode) = proc.get_inode_mut(out_inode_number) {
+            //out_inode.size = new_size as u64;
+        //}
+    //}
+
+    //to_copy as isize
+//}
+//
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_splice(
+    fd_in: i32,
+    off_in: *mut i64,
+    fd_out: i32,
+    off_out: *mut i64,
+    len: usize,
+    _flags: u32
+) -> isize {
+    let mut proc = get_or_init_proc();
+
+    // 1) Input FD info
+    let (in_ino, in_pos, in_kind) = match proc.open_files.get(&fd_in) {
+        Some(h) => (h.inode_number, h.position(), h.kind),
+        None => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    };
+
+    // 2) Output FD info
+    let (out_ino, out_pos, out_app, out_kind) = match proc.open_files.get(&fd_out) {
+        Some(h) => (h.inode_number, h.position(), h.append_mode(), h.kind),
+        None => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    };
+
+    // 3) Explicit offsets are only meaningful on the file side
+    if (in_kind != HandleKind::File && !off_in.is_null())
+        || (out_kind != HandleKind::File && !off_out.is_null())
+    {
+        proc.set_errno(ESPIPE);
+        return -1;
+    }
+
+    let read_pos = if !off_in.is_null() {
+        unsafe { *off_in as u64 }
+    } else {
+        in_pos
+    };
+    let write_pos = if !off_out.is_null() {
+        unsafe { *off_out as u64 }
+    } else if out_app {
+        proc.fs.borrow().files.get(&out_ino).map(|d| d.len()).unwrap_or(0)
+    } else {
+        out_pos
+    };
+
+    // 4) Move the data. Blocking never happens here, so SPLICE_F_NONBLOCK
+    //    changes nothing: an empty source or full sink is EAGAIN either way.
+    let result = proc.check_rendezvous(fd_in)
+        .and_then(|_| proc.check_rendezvous(fd_out))
+        .and_then(|_| match (in_kind, out_kind) {
+            (HandleKind::File, HandleKind::File) => proc.file_to_file(in_ino, read_pos, out_ino, write_pos, len as u64),
+            (HandleKind::File, k) if k.writes_pipe() => proc.file_to_pipe(in_ino, read_pos, out_ino, len),
+            (k, HandleKind::File) if k.reads_pipe() => proc.pipe_to_file(in_ino, out_ino, write_pos, len),
+            (a, b) if a.reads_pipe() && b.writes_pipe() => proc.pipe_to_pipe(in_ino, out_ino, len, false),
+            _ => Err(EBADF),
+        });
+    let copied = match result {
+        Ok(n) => n,
+        Err(e) => {
+            proc.set_errno(e);
+            return -1;
+        }
+    };
+
+    // 5) Advance whichever offsets belong to file ends
+    if in_kind == HandleKind::File {
+        if !off_in.is_null() {
+            unsafe { *off_in += copied as i64; }
+        } else if let Some(in_handle) = proc.open_files.get_mut(&fd_in) {
+            in_handle.set_position(read_pos + copied as u64);
+        }
+    }
+    if out_kind == HandleKind::File {
+        if !off_out.is_null() {
+            unsafe { *off_out += copied as i64; }
+        } else if let Some(out_handle) = proc.open_files.get_mut(&fd_out) {
+            out_handle.set_position(write_pos + copied as u64);
+        }
+    }
+
+    copied as isize
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_tee(fd_in: i32, fd_out: i32, len: usize, _flags: u32) -> isize {
+    let mut proc = get_or_init_proc();
+
+    let ends = (
+        proc.open_files.get(&fd_in).map(|h| (h.inode_number, h.kind)),
+        proc.open_files.get(&fd_out).map(|h| (h.inode_number, h.kind)),
+    );
+    let result = match ends {
+        (Some((in_ino, a)), Some((out_ino, b))) if a.reads_pipe() && b.writes_pipe() => {
+            proc.check_rendezvous(fd_in)
+                .and_then(|_| proc.check_rendezvous(fd_out))
+                .and_then(|_| proc.pipe_to_pipe(in_ino, out_ino, len, true))
+        }
+        (Some(_), Some(_)) => Err(EINVAL),
+        _ => Err(EBADF),
+    };
+    match result {
+        Ok(n) => n as isize,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_vmsplice(fd: i32, iov: *const Iovec, nr_segs: usize, _flags: u32) -> isize {
+    let mut proc = get_or_init_proc();
+
+    let (inode_num, kind) = match proc.open_files.get(&fd) {
+        Some(h) => (h.inode_number, h.kind),
+        None => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    };
+    let segments = match iovec_slice(iov, i32::try_from(nr_segs).unwrap_or(i32::MAX)) {
+        Ok(segments) => segments,
+        Err(e) => {
+            proc.set_errno(e);
+            return -1;
+        }
+    };
+
+    if let Err(e) = proc.check_rendezvous(fd) {
+        proc.set_errno(e);
+        return -1;
+    }
+    let result = match (proc.fs.borrow_mut().pipes.get_mut(&inode_num), kind) {
+        // Gather user memory into the pipe, stopping once it is full
+        (Some(pipe), HandleKind::PipeWriter | HandleKind::PipeDuplex) => {
+            let mut total = 0;
+            let mut outcome = Ok(0);
+            for seg in segments {
+                let bytes = unsafe { core::slice::from_raw_parts(seg.iov_base, seg.iov_len) };
+                match pipe.write(bytes) {
+                    Ok(n) => {
+                        total += n;
+                        outcome = Ok(total);
+                        if n < bytes.len() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        if total == 0 {
+                            outcome = Err(e);
+                        }
+                        break;
+                    }
+                }
+            }
+            outcome
+        }
+        // Scatter buffered data out into user memory
+        (Some(pipe), HandleKind::PipeReader) => {
+            let mut total = 0;
+            let mut outcome = Ok(0);
+            for seg in segments {
+                let out = unsafe { core::slice::from_raw_parts_mut(seg.iov_base, seg.iov_len) };
+                match pipe.read(out) {
+                    Ok(n) => {
+                        total += n;
+                        outcome = Ok(total);
+                        if n < out.len() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        if total == 0 {
+                            outcome = Err(e);
+                        }
+                        break;
+                    }
+                }
+            }
+            outcome
+        }
+        _ => Err(EBADF),
+    };
+    match result {
+        Ok(n) => n as isize,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_pipe(fds: *mut i32) -> i32 {
+    wasm_vfs_pipe2(fds, 0)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_pipe2(fds: *mut i32, flags: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    if flags & !(O_CLOEXEC | O_NONBLOCK) != 0 {
+        proc.set_errno(EINVAL);
+        return -1;
+    }
+    let nonblock = (flags & O_NONBLOCK) == O_NONBLOCK;
+    let cloexec = (flags & O_CLOEXEC) == O_CLOEXEC;
+
+    if proc.fs.borrow().pipes.is_full() {
+        proc.set_errno(ENFILE);
+        return -1;
+    }
+    let inode_number = proc.fs.borrow_mut().create_anon_inode(InodeKind::Pipe, 0o600);
+    proc.fs.borrow_mut().pipes.insert(inode_number, Pipe::new());
+
+    let mut ends = [0; 2];
+    for (i, kind) in [HandleKind::PipeReader, HandleKind::PipeWriter].into_iter().enumerate() {
+        let fd = match proc.allocate_fd() {
+            Some(x) => x,
+            None => {
+                if i == 1 {
+                    proc.close_fd(ends[0]);
+                } else {
+                    proc.fs.borrow_mut().pipes.remove(&inode_number);
+                }
+                proc.set_errno(EMFILE);
+                return -1;
+            }
+        };
+        proc.install_handle(
+            fd,
+            OpenFileHandle {
+                inode_number,
+                description: OpenFileDescription::new(0, false, nonblock),
+                kind,
+                cloexec,
+                awaiting_peer: false,
+                writable: kind.writes_pipe(),
+                access: if kind == HandleKind::PipeWriter { O_WRONLY } else { O_RDONLY },
+            },
+        );
+        ends[i] = fd;
+    }
+
+    unsafe {
+        *fds = ends[0];
+        *fds.add(1) = ends[1];
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fcntl(fd: i32, cmd: i32, arg: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    let handle = match proc.open_files.get(&fd) {
+        Some(h) => h.clone(),
+        None => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    };
+
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            if arg < 0 || arg as usize >= proc.fd_table.len() {
+                proc.set_errno(EINVAL);
+                return -1;
+            }
+            let new_fd = match proc.allocate_fd_from(arg) {
+                Some(x) => x,
+                None => {
+                    proc.set_errno(EMFILE);
+                    return -1;
+                }
+            };
+            let mut new_handle = handle;
+            new_handle.cloexec = cmd == F_DUPFD_CLOEXEC;
+            proc.install_handle(new_fd, new_handle);
+            new_fd
+        }
+        F_GETFD => {
+            if handle.cloexec { FD_CLOEXEC } else { 0 }
+        }
+        F_SETFD => {
+            if let Some(h) = proc.open_files.get_mut(&fd) {
+                h.cloexec = (arg & FD_CLOEXEC) != 0;
+            }
+            0
+        }
+        F_GETFL => {
+            let mut fl = handle.access;
+            if handle.append_mode() { fl |= O_APPEND; }
+            if handle.nonblock() { fl |= O_NONBLOCK; }
+            fl
+        }
+        F_SETFL => {
+            if let Some(h) = proc.open_files.get_mut(&fd) {
+                h.set_append_mode((arg & O_APPEND) != 0);
+                h.set_nonblock((arg & O_NONBLOCK) != 0);
+            }
+            0
+        }
+        F_GETPIPE_SZ | F_SETPIPE_SZ => {
+            let result = match proc.fs.borrow_mut().pipes.get_mut(&handle.inode_number) {
+                Some(pipe) if handle.is_pipe() => {
+                    if cmd == F_GETPIPE_SZ {
+                        Ok(pipe.capacity())
+                    } else if arg < 0 {
+                        Err(EINVAL)
+                    } else {
+                        pipe.set_capacity(arg as usize)
+                    }
+                }
+                _ => Err(EBADF),
+            };
+            match result {
+                Ok(n) => n as i32,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            }
+        }
+        F_ADD_SEALS | F_GET_SEALS => {
+            let result = if cmd == F_ADD_SEALS {
+                proc.add_seals(fd, arg as u32).map(|_| 0)
+            } else {
+                proc.memfd_seals(fd).map(|memfd| memfd.seals as i32)
+            };
+            match result {
+                Ok(n) => n,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            }
+        }
+        _ => {
+            proc.set_errno(EINVAL);
+            -1
+        }
+    }
+}
+
+// Sinks selectable from the host side; in-memory capture needs a
+// handle to read back, so it is only offered through Proc::set_stdio
+const STDIO_SINK_DISCARD: i32 = 0;
+const STDIO_SINK_HOST: i32 = 1;
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_stdio_set_sink(fd: i32, sink: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    let stream: Box<dyn Stream> = match sink {
+        STDIO_SINK_DISCARD => Box::new(stdio::DiscardStream),
+        #[cfg(feature = "box-host-stdout")]
+        STDIO_SINK_HOST => Box::new(stdio::HostStream),
+        _ => {
+            proc.set_errno(EINVAL);
+            return -1;
+        }
+    };
+    match proc.set_stdio(fd, stream, BufferMode::Line) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+// `mode` uses the setvbuf numbering: 0 full, 1 line, 2 unbuffered
+#[no_mangle]
+pub extern "C" fn wasm_vfs_stdio_set_buffering(fd: i32, mode: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    let result = match BufferMode::from_raw(mode) {
+        Some(mode) => proc.set_stdio_buffering(fd, mode),
+        None => Err(EINVAL),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+// The struct an ioctl argument points to. Guest pointers promise no
+// alignment, and a null one is EFAULT.
+fn ioctl_read<T: Copy>(arg: *mut u8) -> Result<T, i32> {
+    if arg.is_null() {
+        return Err(EFAULT);
+    }
+    Ok(unsafe { core::ptr::read_unaligned(arg as *const T) })
+}
+
+// Stores an ioctl's result through its argument; 0 on success
+fn ioctl_write<T>(arg: *mut u8, value: T) -> Result<i32, i32> {
+    if arg.is_null() {
+        return Err(EFAULT);
+    }
+    unsafe { core::ptr::write_unaligned(arg as *mut T, value) };
+    Ok(0)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_ioctl(fd: i32, request: u32, arg: *mut u8) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    let (inode_num, kind) = match proc.open_files.get(&fd) {
+        Some(h) => (h.inode_number, h.kind),
+        None => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    };
+
+    if request == FICLONE || request == FICLONERANGE {
+        let result = if request == FICLONE {
+            proc.reflink(fd, arg as usize as FileDescriptor, None)
+        } else {
+            ioctl_read::<FileCloneRange>(arg)
+                .and_then(|range| proc.reflink(fd, range.src_fd as FileDescriptor, Some(range)))
+        };
+        return match result {
+            Ok(()) => 0,
+            Err(e) => {
+                proc.set_errno(e);
+                -1
+            }
+        };
+    }
+
+    if kind == HandleKind::Device {
+        let result = match proc.device_numbers(inode_num) {
+            Some((class, major, minor)) => match proc.fs.borrow_mut().devices.get_mut(class, major, minor) {
+                Some(driver) => driver.ioctl(minor, request, arg),
+                None => Err(ENXIO),
+            },
+            None => Err(ENXIO),
+        };
+        return match result {
+            Ok(n) => n,
+            Err(e) => {
+                proc.set_errno(e);
+                -1
+            }
+        };
+    }
+
+    if kind == HandleKind::Object {
+        let result = match proc.objects.get_mut(&inode_num) {
+            Some(object) => object.ioctl(request, arg),
+            None => Err(EBADF),
+        };
+        return match result {
+            Ok(n) => n,
+            Err(e) => {
+                proc.set_errno(e);
+                -1
+            }
+        };
+    }
+
+    // FIONREAD also makes sense on pipes and inotify descriptors
+    if request == FIONREAD && kind != HandleKind::Tty {
+        let buffered = proc.fs.borrow().pipes.get(&inode_num).map(|pipe| pipe.len());
+        let available = match buffered {
+            Some(len) if kind.reads_pipe() => len,
+            _ if kind == HandleKind::Inotify => {
+                proc.inotify.get(&inode_num).map(|i| i.pending_bytes()).unwrap_or(0)
+            }
+            _ => {
+                proc.set_errno(ENOTTY);
+                return -1;
+            }
+        };
+        return match ioctl_write(arg, available as i32) {
+            Ok(n) => n,
+            Err(e) => {
+                proc.set_errno(e);
+                -1
+            }
+        };
+    }
+
+    let tty = match proc.tty.as_mut() {
+        Some(tty) if kind == HandleKind::Tty => tty,
+        _ => {
+            proc.set_errno(ENOTTY);
+            return -1;
+        }
+    };
+    let result = match request {
+        TCGETS => ioctl_write(arg, tty.termios),
+        // Output is never queued, so TCSETSW has nothing to drain
+        TCSETS | TCSETSW | TCSETSF => ioctl_read::<Termios>(arg).map(|termios| {
+            tty.set_termios(termios, request == TCSETSF);
+            0
+        }),
+        TIOCGWINSZ => ioctl_write(arg, tty.winsize),
+        TIOCSWINSZ => ioctl_read::<Winsize>(arg).map(|winsize| {
+            tty.set_winsize(winsize);
+            0
+        }),
+        FIONREAD => ioctl_write(arg, tty.available() as i32),
+        TCFLSH => {
+            // TCIFLUSH (0) and TCIOFLUSH (2) drop pending input
+            let queue = arg as usize;
+            if queue == 0 || queue == 2 {
+                tty.flush_input();
+            }
+            Ok(0)
+        }
+        // Single process, single session: it is always the foreground group
+        TIOCGPGRP => ioctl_write(arg, 1i32),
+        TIOCSPGRP => Ok(0),
+        _ => Err(EINVAL),
+    };
+    match result {
+        Ok(n) => n,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_isatty(fd: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.open_files.get(&fd).map(|h| h.kind) {
+        Some(HandleKind::Tty) => 1,
+        Some(_) => {
+            proc.set_errno(ENOTTY);
+            0
+        }
+        None => {
+            proc.set_errno(EBADF);
+            0
+        }
+    }
+}
+
+// Attaches the terminal with output going to the host import (or
+// nowhere, without the `box-host-stdout` feature)
+#[no_mangle]
+pub extern "C" fn wasm_vfs_tty_attach() -> i32 {
+    let mut proc = get_or_init_proc();
+
+    #[cfg(feature = "box-host-stdout")]
+    let output: Box<dyn Stream> = Box::new(stdio::HostStream);
+    #[cfg(not(feature = "box-host-stdout"))]
+    let output: Box<dyn Stream> = Box::new(stdio::DiscardStream);
+
+    proc.attach_tty(output);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_tty_input(buf: *const u8, len: usize) -> isize {
+    let mut proc = get_or_init_proc();
+    let bytes = unsafe { core::slice::from_raw_parts(buf, len) };
+    if proc.tty_input(bytes) {
+        len as isize
+    } else {
+        proc.set_errno(ENOTTY);
+        -1
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_tty_set_winsize(rows: u16, cols: u16) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.tty.as_mut() {
+        Some(tty) => {
+            let winsize = Winsize { ws_row: rows, ws_col: cols, ..tty.winsize };
+            tty.set_winsize(winsize);
+            0
+        }
+        None => {
+            proc.set_errno(ENOTTY);
+            -1
+        }
+    }
+}
+
+// Signal raised by ^C/^\/^Z or a resize since the last call, 0 if none
+#[no_mangle]
+pub extern "C" fn wasm_vfs_tty_take_signal() -> i32 {
+    let mut proc = get_or_init_proc();
+    proc.tty.as_mut().map(|tty| tty.take_signal()).unwrap_or(0)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_errno() -> i32 {
+    get_or_init_proc().errno
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_getdents(fd: i32, dirp: *mut Dirent, count: usize) -> isize {
+    let mut proc = get_or_init_proc();
+
+
+    let inode_number = {
+        let handle = match proc.open_files.get_mut(&fd) {
+            Some(h) => h,
+            None => return -1,
+        };
+        let ino = handle.inode_number;
+        ino
+    };
+
+    let kind = match proc.kind_of(inode_number) {
+        Some(kind) => kind,
+        None => return -1,
+    };
+
+    if let InodeKind::Directory = kind {
+
+    } else {
+        return -1;
+    }
+
+    let dir_prefix = if inode_number == 0 {
+        PathBuf::from("/")
+    } else {
+        proc.fs.borrow().path_map.iter()
+            .find_map(|(pp, ino)| if *ino == inode_number { Some(pp.clone()) } else { None })
+            .unwrap_or(PathBuf::from("/"))
+    };
+
+    // /proc/self/fd lists the descriptors open right now
+    if let Err(e) = proc.refresh_procfs(&dir_prefix) {
+        proc.set_errno(e);
+        return -1;
+    }
+
+    let entries: Vec<(PathBuf, u64)> = proc.fs.borrow().path_map.iter().filter(|(p, _)| {
+        if p.as_path() == dir_prefix.as_path() {
+            false
+        } else {
+            p.parent().map(|pp| pp == dir_prefix).unwrap_or(false)
+        }
+    }).map(|(p,i)| (p.clone(), *i)).collect();
+
+
+    let position = {
+        let handle = proc.open_files.get_mut(&fd).unwrap();
+        handle.position()
+    };
+
+    let start = position as usize;
+    if start >= entries.len() {
+        return 0;
+    }
+
+    let mut written = 0;
+    let mut dirp_ptr = dirp as *mut u8;
+
+    for (path, ino) in entries.iter().skip(start) {
+        let name = path.file_name().unwrap_or_default().to_string();
+        let d_type = inode_kind_to_dtype(&proc.get_inode(*ino).unwrap().kind);
+
+        let mut d: Dirent = Dirent {
+            d_ino: *ino,
+            d_off: (position + 1) as i64,
+            d_reclen: 0,
+            d_type,
+            d_name: [0;256],
+        };
+
+        let bytes = name.as_bytes();
+        if bytes.len() >= 256 {
+            continue;
+        }
+        d.d_name[..bytes.len()].copy_from_slice(bytes);
+        d.d_name[bytes.len()] = 0;
+
+        let record_len = std::mem::size_of::<Dirent>();
+        if written + record_len > count {
+            break;
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(&d as *const Dirent as *const u8, dirp_ptr, record_len);
+        }
+        written += record_len;
+        dirp_ptr = unsafe { dirp_ptr.add(record_len) };
+
+
+        {
+            let handle = proc.open_files.get_mut(&fd).unwrap();
+            handle.set_position(handle.position() + 1);
+        }
+    }
+
+    written as isize
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_getdents64(fd: i32, dirp: *mut Dirent64, count: usize) -> isize {
+
+    let mut buf = vec![0u8; count];
+    let ret = wasm_vfs_getdents(fd, buf.as_mut_ptr() as *mut Dirent, count);
+    if ret <= 0 {
+        return ret;
+    }
+
+
+    let used = ret as usize;
+    unsafe {
+        std::ptr::copy_nonoverlapping(buf.as_ptr(), dirp as *mut u8, used);
+    }
+    ret
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_lseek(fd: i32, offset: i64, whence: i32) -> i64 {
+    let mut proc = get_or_init_proc();
+
+    let (inode_num, old_pos, kind) = {
+        let h = match proc.open_files.get_mut(&fd) {
+            Some(x) => x,
+            None => return -1,
+        };
+        if h.kind != HandleKind::Device && h.kind != HandleKind::Provider && !h.seekable() {
+            proc.set_errno(ESPIPE);
+            return -1;
+        }
+        (h.inode_number, h.position(), h.kind)
+    };
+
+    let size = if kind == HandleKind::Provider {
+        match proc.fs.borrow().providers.get(&inode_num) {
+            Some(provider) => provider.size() as i64,
+            None => return -1,
+        }
+    } else if kind == HandleKind::Device {
+        match proc.device_numbers(inode_num) {
+            // Block devices seek over the size their driver reports
+            Some((DeviceClass::Block, major, minor)) => match proc.fs.borrow_mut().devices.get_mut(DeviceClass::Block, major, minor) {
+                Some(driver) => driver.size(minor) as i64,
+                None => return -1,
+            },
+            // Seeking a character device succeeds and does nothing, as on /dev/null
+            _ => return 0,
+        }
+    } else {
+        match proc.fs.borrow().files.get(&inode_num) {
+            Some(d) => d.len() as i64,
+            None => return -1,
+        }
+    };
+
+    let new_pos = match whence {
+        0 => offset,              // SEEK_SET
+        1 => old_pos as i64 + offset, // SEEK_CUR
+        2 => size + offset,       // SEEK_END
+        SEEK_DATA | SEEK_HOLE => {
+            if offset < 0 || offset >= size {
+                proc.set_errno(ENXIO);
+                return -1;
+            }
+            // Only stored files have holes; anything else is one data
+            // region followed by the implicit hole at end of file
+            let found = match proc.fs.borrow().files.get(&inode_num) {
+                Some(d) if kind == HandleKind::File => if whence == SEEK_DATA {
+                    d.next_data(offset as u64)
+                } else {
+                    Some(d.next_hole(offset as u64))
+                },
+                _ => Some(if whence == SEEK_DATA { offset as u64 } else { size as u64 }),
+            };
+            match found {
+                Some(pos) => pos as i64,
+                None => {
+                    proc.set_errno(ENXIO);
+                    return -1;
+                }
+            }
+        }
+        _ => {
+            proc.set_errno(EINVAL);
+            return -1;
+        }
+    };
+    if new_pos < 0 {
+        proc.set_errno(EINVAL);
+        return -1;
+    }
+
+
+    if let Some(h2) = proc.open_files.get_mut(&fd) {
+        h2.set_position(new_pos as u64);
+    }
+
+    new_pos
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_stat(path: *const i8, statbuf: *mut Stat) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
+
+    let mut proc = get_or_init_proc();
+
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+    if let Err(e) = proc.refresh_procfs(&abs_path) {
+        proc.set_errno(e);
+        return -1;
+    }
+    let inode_num = match proc.lookup(&abs_path) {
+        Some(i) => i,
+        None => return -1,
+    };
+
+    proc.refresh_provider_size(inode_num);
+    let blocks = proc.blocks_of(inode_num);
+    let nlink = proc.nlink_of(inode_num);
+    let inode = proc.get_inode(inode_num).unwrap();
+    fill_stat_from_inode(&inode, blocks, nlink, statbuf);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fstat(fd: i32, statbuf: *mut Stat) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    let inode_num = match proc.fd_table.get(fd as usize) {
+        Some(Some(i)) => *i,
+        _ => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
+    };
+    if statbuf.is_null() {
+        proc.set_errno(EFAULT);
+        return -1;
+    }
+
+    proc.refresh_provider_size(inode_num);
+    let blocks = proc.blocks_of(inode_num);
+    let nlink = proc.nlink_of(inode_num);
+    let inode = proc.get_inode(inode_num).unwrap();
+    fill_stat_from_inode(&inode, blocks, nlink, statbuf);
+    if let Some(object) = proc.objects.get(&inode_num) {
+        object.stat(unsafe { &mut *statbuf });
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_lstat(path: *const i8, statbuf: *mut Stat) -> i32 {
+    wasm_vfs_stat(path, statbuf)
+}
+
+// Filesystem usage for the mount a path or fd is on; see FileSystem::usage
+#[no_mangle]
+pub extern "C" fn wasm_vfs_statfs(path: *const i8, buf: *mut Statfs) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.path_inode(path, true) {
+        Ok(ino) => {
+            unsafe { core::ptr::write_unaligned(buf, proc.inode_usage(ino).to_statfs()) };
+            0
+        }
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fstatfs(fd: i32, buf: *mut Statfs) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.fd_inode(fd) {
+        Ok(ino) => {
+            unsafe { core::ptr::write_unaligned(buf, proc.inode_usage(ino).to_statfs()) };
+            0
+        }
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_statvfs(path: *const i8, buf: *mut Statvfs) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.path_inode(path, true) {
+        Ok(ino) => {
+            unsafe { core::ptr::write_unaligned(buf, proc.inode_usage(ino).to_statvfs()) };
+            0
+        }
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fstatvfs(fd: i32, buf: *mut Statvfs) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.fd_inode(fd) {
+        Ok(ino) => {
+            unsafe { core::ptr::write_unaligned(buf, proc.inode_usage(ino).to_statvfs()) };
+            0
+        }
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fstatat(dirfd: i32, pathname: *const i8, statbuf: *mut Stat, _flags: i32) -> i32 {
+    wasm_vfs_stat(pathname, statbuf)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_getcwd(buf: *mut i8, size: usize) -> *mut i8 {
+    let proc = get_or_init_proc();
+    let cwd_str = proc.cwd.to_string_lossy();
+
+    let bytes = cwd_str.as_bytes();
+    if bytes.len() + 1 > size {
+        return core::ptr::null_mut();
+    }
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), buf as *mut u8, bytes.len());
+        *buf.add(bytes.len()) = 0;
+    }
+    buf
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_chdir(path: *const i8) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
+
+    let mut proc = get_or_init_proc();
+
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+    if let Err(e) = proc.refresh_procfs(&abs_path) {
+        proc.set_errno(e);
+        return -1;
+    }
+    let inode_num = match proc.lookup(&abs_path) {
+        Some(i) => i,
+        None => return -1,
+    };
+
+    if let Some(InodeKind::Directory) = proc.kind_of(inode_num) {
+        proc.cwd = abs_path;
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fchdir(fd: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    let inode_num = match proc.fd_table.get(fd as usize) {
+        Some(Some(i)) => *i,
+        _ => return -1,
+    };
+    if let Some(InodeKind::Directory) = proc.kind_of(inode_num) {
+        let path = proc.fs.borrow().path_map.iter().find_map(|(p, i)| if *i == inode_num { Some(p.clone()) } else { None }).unwrap();
+        proc.cwd = path;
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_chmod(path: *const i8, mode: u32) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
+
+    let mut proc = get_or_init_proc();
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+
+    let inode_num = match proc.lookup(&abs_path) {
+        Some(i) => i,
+        None => {
+            proc.set_errno(ENOENT);
+            return -1;
+        }
+    };
+
+    match proc.chmod_inode(inode_num, mode) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fchmod(fd: i32, mode: u32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    let inode_num = match proc.fd_table.get(fd as usize) {
+        Some(Some(i)) => *i,
+        _ => {
+            proc.set_errno(EBADF);
+            return -1;
+        }
     };
 
-    let start = position as usize;
-    if start >= entries.len() {
-        return 0;
+    match proc.chmod_inode(inode_num, mode) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
     }
+}
 
-    let mut written = 0;
-    let mut dirp_ptr = dirp as *mut u8;
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fchmodat(_dirfd: i32, pathname: *const i8, mode: u32, _flags: i32) -> i32 {
+    wasm_vfs_chmod(pathname, mode)
+}
 
-    for (path, ino) in entries.iter().skip(start) {
-        let name = path.file_name().unwrap_or_default().to_string();
-        let d_type = inode_kind_to_dtype(&proc.get_inode(*ino).unwrap().kind);
+#[no_mangle]
+pub extern "C" fn wasm_vfs_chown(path: *const i8, owner: u32, group: u32) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
 
-        let mut d: Dirent = Dirent {
-            d_ino: *ino,
-            d_off: (position + 1) as i64,
-            d_reclen: 0,
-            d_type,
-            d_name: [0;256],
-        };
+    let mut proc = get_or_init_proc();
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
 
-        let bytes = name.as_bytes();
-        if bytes.len() >= 256 {
-            continue;
+    let inode_num = match proc.lookup(&abs_path) {
+        Some(i) => i,
+        None => {
+            proc.set_errno(ENOENT);
+            return -1;
         }
-        d.d_name[..bytes.len()].copy_from_slice(bytes);
-        d.d_name[bytes.len()] = 0;
+    };
 
-        let record_len = std::mem::size_of::<Dirent>();
-        if written + record_len > count {
-            break;
+    match proc.chown_inode(inode_num, owner, group) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
         }
+    }
+}
 
-        unsafe {
-            std::ptr::copy_nonoverlapping(&d as *const Dirent as *const u8, dirp_ptr, record_len);
+#[no_mangle]
+pub extern "C" fn wasm_vfs_lchown(path: *const i8, owner: u32, group: u32) -> i32 {
+    wasm_vfs_chown(path, owner, group)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fchown(fd: i32, owner: u32, group: u32) -> i32 {
+    let mut proc = get_or_init_proc();
+
+    let inode_num = match proc.fd_table.get(fd as usize) {
+        Some(Some(i)) => *i,
+        _ => {
+            proc.set_errno(EBADF);
+            return -1;
         }
-        written += record_len;
-        dirp_ptr = unsafe { dirp_ptr.add(record_len) };
+    };
 
+    match proc.chown_inode(inode_num, owner, group) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
 
-        {
-            let handle = proc.open_files.get_mut(&fd).unwrap();
-            handle.position += 1;
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fchownat(_dirfd: i32, pathname: *const i8, owner: u32, group: u32, _flags: i32) -> i32 {
+    wasm_vfs_chown(pathname, owner, group)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_access(path: *const i8, mode: i32) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
+    let mut proc = get_or_init_proc();
+
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+    if let Err(e) = proc.refresh_procfs(&abs_path) {
+        proc.set_errno(e);
+        return -1;
+    }
+    let inode_num = match proc.lookup(&abs_path) {
+        Some(i) => i,
+        None => {
+            proc.set_errno(ENOENT);
+            return -1;
+        }
+    };
+
+    let permitted = proc.check_access(&proc.get_inode(inode_num).unwrap(), mode);
+    if permitted {
+        0
+    } else {
+        proc.set_errno(EACCES);
+        -1
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_faccessat(_dirfd: i32, pathname: *const i8, mode: i32, _flags: i32) -> i32 {
+    wasm_vfs_access(pathname, mode)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_umask(mask: u32) -> u32 {
+    let mut proc = get_or_init_proc();
+    let old = proc.umask_value;
+    proc.umask_value = mask & 0o777;
+    old
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_getuid() -> u32 {
+    get_or_init_proc().uid
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_geteuid() -> u32 {
+    get_or_init_proc().uid
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_getgid() -> u32 {
+    get_or_init_proc().gid
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_getegid() -> u32 {
+    get_or_init_proc().gid
+}
+
+// Supplementary groups. A size of 0 returns how many there are.
+#[no_mangle]
+pub extern "C" fn wasm_vfs_getgroups(size: i32, list: *mut u32) -> i32 {
+    let mut proc = get_or_init_proc();
+    let count = proc.groups.len();
+    if size == 0 {
+        return count as i32;
+    }
+    if size < 0 || (size as usize) < count {
+        proc.set_errno(EINVAL);
+        return -1;
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(proc.groups.as_ptr(), list, count);
+    }
+    count as i32
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_rename(oldpath: *const i8, newpath: *const i8) -> i32 {
+    let old_str = unsafe { CStr::from_ptr(oldpath).to_string_lossy() };
+    let new_str = unsafe { CStr::from_ptr(newpath).to_string_lossy() };
+
+    let mut proc = get_or_init_proc();
+
+    let old_abs = proc.get_absolute_path(&PathBuf::from(old_str));
+    let new_abs = proc.get_absolute_path(&PathBuf::from(new_str));
+
+    let inode_num = match proc.lookup(&old_abs) {
+        Some(i) => i,
+        None => {
+            proc.set_errno(ENOENT);
+            return -1;
+        }
+    };
+    let replaced = proc.lookup(&new_abs).filter(|ino| *ino != inode_num);
+    let checked = proc.check_parent_access(&old_abs)
+        .and_then(|_| proc.check_parent_access(&new_abs))
+        .and_then(|_| proc.fs.borrow().check_project_move(&old_abs, &new_abs));
+    if let Err(e) = checked {
+        proc.set_errno(e);
+        return -1;
+    }
+
+    proc.fs.borrow_mut().path_map.remove(&old_abs);
+
+    proc.fs.borrow_mut().path_map.insert(new_abs.clone(), inode_num);
+    proc.fs.borrow_mut().recharge(inode_num);
+
+    let cookie = proc.next_cookie;
+    proc.next_cookie = proc.next_cookie.wrapping_add(1).max(1);
+    proc.notify(&old_abs, inode_num, inotify::IN_MOVED_FROM, cookie);
+    proc.notify(&new_abs, inode_num, inotify::IN_MOVED_TO, cookie);
+    proc.notify(&new_abs, inode_num, inotify::IN_MOVE_SELF, 0);
+    proc.record_change(ChangeOp::Rename { from: old_abs.clone() }, &new_abs, inode_num);
+    if let Some(replaced) = replaced {
+        if proc.path_of(replaced).is_none() {
+            proc.notify_self(replaced, inotify::IN_DELETE_SELF);
+            proc.drop_watches(replaced);
+        }
+        proc.release_unnamed(replaced);
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_renameat(olddirfd: i32, oldpath: *const i8, newdirfd: i32, newpath: *const i8) -> i32 {
+    wasm_vfs_rename(oldpath, newpath)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_renameat2(olddirfd: i32, oldpath: *const i8, newdirfd: i32, newpath: *const i8, _flags: u32) -> i32 {
+    wasm_vfs_renameat(olddirfd, oldpath, newdirfd, newpath)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_link(oldpath: *const i8, newpath: *const i8) -> i32 {
+    let old_str = unsafe { CStr::from_ptr(oldpath).to_string_lossy() };
+    let new_str = unsafe { CStr::from_ptr(newpath).to_string_lossy() };
+
+    let mut proc = get_or_init_proc();
+
+    let old_abs = proc.get_absolute_path(&PathBuf::from(old_str));
+    let new_abs = proc.get_absolute_path(&PathBuf::from(new_str));
+
+    let inode_num = match proc.lookup(&old_abs) {
+        Some(i) => i,
+        None => {
+            proc.set_errno(ENOENT);
+            return -1;
+        }
+    };
+    if let Err(e) = proc.check_parent_access(&new_abs).and_then(|_| proc.fs.borrow().check_project_move(&old_abs, &new_abs)) {
+        proc.set_errno(e);
+        return -1;
+    }
+    proc.fs.borrow_mut().path_map.insert(new_abs.clone(), inode_num);
+    proc.fs.borrow_mut().recharge(inode_num);
+    proc.notify_self(inode_num, inotify::IN_ATTRIB);
+    proc.notify(&new_abs, inode_num, inotify::IN_CREATE, 0);
+    proc.record_change(ChangeOp::Link, &new_abs, inode_num);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_linkat(olddirfd: i32, oldpath: *const i8, newdirfd: i32, newpath: *const i8, flags: i32) -> i32 {
+    let empty = unsafe { *oldpath == 0 };
+    if (flags & AT_EMPTY_PATH) == 0 || !empty {
+        return wasm_vfs_link(oldpath, newpath);
+    }
+    let new_str = unsafe { CStr::from_ptr(newpath).to_string_lossy() };
+    let mut proc = get_or_init_proc();
+    match proc.link_fd(olddirfd, &PathBuf::from(new_str)) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_unlink(pathname: *const i8) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(pathname).to_string_lossy() };
+    let mut proc = get_or_init_proc();
+
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+    let inode_num = match proc.lookup(&abs_path) {
+        Some(i) => i,
+        None => {
+            proc.set_errno(ENOENT);
+            return -1;
+        }
+    };
+
+    if let Some(InodeKind::Directory) = proc.kind_of(inode_num) {
+        proc.set_errno(EISDIR);
+        return -1;
+    }
+    if let Err(e) = proc.check_parent_access(&abs_path) {
+        proc.set_errno(e);
+        return -1;
+    }
+
+    proc.fs.borrow_mut().path_map.remove(&abs_path);
+    proc.notify_unlinked(&abs_path, inode_num);
+    proc.release_unnamed(inode_num);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_unlinkat(dirfd: i32, pathname: *const i8, flags: i32) -> i32 {
+    // ignoring flags and dirfd
+    wasm_vfs_unlink(pathname)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_symlink(target: *const i8, linkpath: *const i8) -> i32 {
+    let target_str = unsafe { CStr::from_ptr(target).to_string_lossy() };
+    let link_str = unsafe { CStr::from_ptr(linkpath).to_string_lossy() };
+
+    let mut proc = get_or_init_proc();
+
+    let link_abs = proc.get_absolute_path(&PathBuf::from(link_str));
+    if let Err(e) = proc.check_parent_access(&link_abs).and_then(|_| proc.charge_create(Some(&link_abs))) {
+        proc.set_errno(e);
+        return -1;
+    }
+    let inode_number = proc.fs.borrow().next_inode_number;
+    proc.fs.borrow_mut().next_inode_number += 1;
+
+    let kind = InodeKind::SymbolicLink(PathBuf::from(target_str));
+    proc.insert_directory_entry(&link_abs, inode_number, kind);
+    proc.init_new_inode(&link_abs, inode_number, 0o777);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_symlinkat(target: *const i8, newdirfd: i32, linkpath: *const i8) -> i32 {
+    wasm_vfs_symlink(target, linkpath)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_readlink(path: *const i8, buf: *mut i8, bufsize: usize) -> isize {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
+    let mut proc = get_or_init_proc();
+
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+    if let Err(e) = proc.refresh_procfs(&abs_path) {
+        proc.set_errno(e);
+        return -1;
+    }
+    let inode_num = match proc.lookup(&abs_path) {
+        Some(i) => i,
+        None => {
+            proc.set_errno(ENOENT);
+            return -1;
+        }
+    };
+    match proc.kind_of(inode_num).unwrap() {
+        InodeKind::SymbolicLink(t) => {
+            let loss = t.to_string_lossy();
+            let bytes = loss.as_bytes();
+            let to_copy = min(bytes.len(), bufsize);
+            unsafe {
+                core::ptr::copy_nonoverlapping(bytes.as_ptr(), buf as *mut u8, to_copy);
+            }
+            to_copy as isize
+        }
+        _ => {
+            proc.set_errno(EINVAL);
+            -1
         }
     }
-
-    written as isize
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_getdents64(fd: i32, dirp: *mut Dirent64, count: usize) -> isize {
-
-    let mut buf = vec![0u8; count];
-    let ret = wasm_vfs_getdents(fd, buf.as_mut_ptr() as *mut Dirent, count);
-    if ret <= 0 {
-        return ret;
-    }
-
-
-    let used = ret as usize;
-    unsafe {
-        std::ptr::copy_nonoverlapping(buf.as_ptr(), dirp as *mut u8, used);
-    }
-    ret
+pub extern "C" fn wasm_vfs_readlinkat(dirfd: i32, pathname: *const i8, buf: *mut i8, bufsize: usize) -> isize {
+    wasm_vfs_readlink(pathname, buf, bufsize)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_lseek(fd: i32, offset: i64, whence: i32) -> i64 {
-    let mut proc = get_or_init_proc();
-
-    let (inode_num, old_pos) = {
-        let h = match proc.open_files.get_mut(&fd) {
-            Some(x) => x,
-            None => return -1,
-        };
-        (h.inode_number, h.position)
-    };
+pub extern "C" fn wasm_vfs_mkdir(path: *const i8, mode: u32) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
 
+    let mut proc = get_or_init_proc();
 
-    let size = match proc.fs.files.get(&inode_num) {
-        Some(d) => d.len() as i64,
-        None => return -1,
-    };
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
 
-    let new_pos = match whence {
-        0 => offset,              // SEEK_SET
-        1 => old_pos as i64 + offset, // SEEK_CUR
-        2 => size + offset,       // SEEK_END
-        _ => return -1,
-    };
-    if new_pos < 0 {
+    if proc.lookup(&abs_path).is_some() {
+        return -1;
+    }
+    if let Err(e) = proc.check_parent_access(&abs_path).and_then(|_| proc.charge_create(Some(&abs_path))) {
+        proc.set_errno(e);
         return -1;
     }
 
+    let inode_number = proc.fs.borrow().next_inode_number;
+    proc.fs.borrow_mut().next_inode_number += 1;
 
-    if let Some(h2) = proc.open_files.get_mut(&fd) {
-        h2.position = new_pos as u64;
-    }
+    let adjusted_mode = (mode as u16) & !(proc.umask_value as u16);
+    let kind = InodeKind::Directory;
+    proc.insert_directory_entry(&abs_path, inode_number, kind);
+    proc.get_inode_mut(inode_number).unwrap().permissions = Permissions::from(adjusted_mode);
+    proc.init_new_inode(&abs_path, inode_number, mode);
+    0
+}
 
-    new_pos
+#[no_mangle]
+pub extern "C" fn wasm_vfs_mkdirat(dirfd: i32, pathname: *const i8, mode: u32) -> i32 {
+    wasm_vfs_mkdir(pathname, mode)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_stat(path: *const i8, statbuf: *mut Stat) -> i32 {
+pub extern "C" fn wasm_vfs_mkfifo(path: *const i8, mode: u32) -> i32 {
     let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
 
-    let proc = get_or_init_proc();
+    let mut proc = get_or_init_proc();
 
     let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
-        Some(i) => i,
-        None => return -1,
-    };
-
-    let inode = proc.get_inode(inode_num).unwrap();
-    fill_stat_from_inode(inode, statbuf);
-    0
+    match proc.make_node(&abs_path, mode, InodeKind::Fifo) {
+        Ok(_) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
+/// Makes `path` a regular file whose reads, writes, size and truncation
+/// go to the `box_host_file_*` imports, tagged with `id`
+#[cfg(feature = "host-files")]
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fstat(fd: i32, statbuf: *mut Stat) -> i32 {
-    let proc = get_or_init_proc();
+pub extern "C" fn wasm_vfs_register_host_file(path: *const i8, mode: u32, id: u32) -> i32 {
+    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
 
-    let inode_num = match proc.fd_table.get(fd as usize) {
-        Some(Some(i)) => *i,
-        _ => return -1,
-    };
+    let mut proc = get_or_init_proc();
 
-    let inode = proc.get_inode(inode_num).unwrap();
-    fill_stat_from_inode(inode, statbuf);
-    0
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+    if let Some(ino) = proc.lookup(&abs_path) {
+        if !matches!(proc.kind_of(ino), Some(InodeKind::File)) {
+            proc.set_errno(EEXIST);
+            return -1;
+        }
+    }
+    let masked = mode & 0o777 & !proc.umask_value;
+    match proc.fs.borrow_mut().register_provider(&abs_path, masked, Box::new(crate::provider::HostProvider::new(id))) {
+        Ok(_) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_lstat(path: *const i8, statbuf: *mut Stat) -> i32 {
-    wasm_vfs_stat(path, statbuf)
+pub extern "C" fn wasm_vfs_mkfifoat(_dirfd: i32, pathname: *const i8, mode: u32) -> i32 {
+    wasm_vfs_mkfifo(pathname, mode)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fstatat(dirfd: i32, pathname: *const i8, statbuf: *mut Stat, _flags: i32) -> i32 {
-    wasm_vfs_stat(pathname, statbuf)
-}
+pub extern "C" fn wasm_vfs_mknod(path: *const i8, mode: u32, dev: u64) -> i32 {
+    match mode & S_IFMT {
+        S_IFIFO => wasm_vfs_mkfifo(path, mode),
+        0 | S_IFREG => {
+            let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_getcwd(buf: *mut i8, size: usize) -> *mut i8 {
-    let proc = get_or_init_proc();
-    let cwd_str = proc.fs.current_directory.to_string_lossy();
+            let mut proc = get_or_init_proc();
 
-    let bytes = cwd_str.as_bytes();
-    if bytes.len() + 1 > size {
-        return core::ptr::null_mut();
-    }
+            let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+            if proc.lookup(&abs_path).is_some() {
+                proc.set_errno(EEXIST);
+                return -1;
+            }
+            if let Err(e) = proc.check_parent_access(&abs_path).and_then(|_| proc.charge_create(Some(&abs_path))) {
+                proc.set_errno(e);
+                return -1;
+            }
+            let masked = mode & 0o777 & !proc.umask_value;
+            let inode_number = proc.fs.borrow_mut().create_file(&abs_path, masked);
+            proc.init_new_inode(&abs_path, inode_number, mode);
+            0
+        }
+        // The node can exist without a driver; opening it gives ENXIO
+        // until one is registered for its numbers
+        S_IFCHR | S_IFBLK => {
+            let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
 
-    unsafe {
-        core::ptr::copy_nonoverlapping(bytes.as_ptr(), buf as *mut u8, bytes.len());
-        *buf.add(bytes.len()) = 0;
+            let mut proc = get_or_init_proc();
+
+            let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+            let (major, minor) = (device::major(dev), device::minor(dev));
+            let kind = if mode & S_IFMT == S_IFCHR {
+                InodeKind::CharDevice { major, minor }
+            } else {
+                InodeKind::BlockDevice { major, minor }
+            };
+            match proc.make_node(&abs_path, mode, kind) {
+                Ok(_) => 0,
+                Err(e) => {
+                    proc.set_errno(e);
+                    -1
+                }
+            }
+        }
+        S_IFDIR => {
+            get_or_init_proc().set_errno(EPERM);
+            -1
+        }
+        _ => {
+            get_or_init_proc().set_errno(EINVAL);
+            -1
+        }
     }
-    buf
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_chdir(path: *const i8) -> i32 {
+pub extern "C" fn wasm_vfs_mknodat(_dirfd: i32, pathname: *const i8, mode: u32, dev: u64) -> i32 {
+    wasm_vfs_mknod(pathname, mode, dev)
+}
+
+#[no_mangle]
+pub extern "C" fn wasm_vfs_rmdir(path: *const i8) -> i32 {
     let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
 
     let mut proc = get_or_init_proc();
 
     let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
+
+    let inode_num = match proc.lookup(&abs_path) {
         Some(i) => i,
-        None => return -1,
+        None => {
+            proc.set_errno(ENOENT);
+            return -1;
+        }
     };
+    match proc.kind_of(inode_num).unwrap() {
+        InodeKind::Directory => {
 
-    let inode = proc.get_inode(inode_num).unwrap();
-    if let InodeKind::Directory = inode.kind {
-        proc.fs.current_directory = abs_path;
-        0
-    } else {
-        -1
-    }
-}
-
-#[no_mangle]
-pub extern "C" fn wasm_vfs_fchdir(fd: i32) -> i32 {
-    let mut proc = get_or_init_proc();
-
-    let inode_num = match proc.fd_table.get(fd as usize) {
-        Some(Some(i)) => *i,
-        _ => return -1,
-    };
-    let inode = proc.get_inode(inode_num).unwrap();
-    if let InodeKind::Directory = inode.kind {
-        let path = proc.fs.path_map.iter().find_map(|(p, i)| if *i == inode_num { Some(p.clone()) } else { None }).unwrap();
-        proc.fs.current_directory = path;
-        0
-    } else {
-        -1
+            let entries = proc.fs.borrow().path_map.iter().filter(|(p,_)| {
+                p.parent().map(|pp| pp == abs_path).unwrap_or(false)
+            }).count();
+            if entries > 0 {
+                proc.set_errno(ENOTEMPTY);
+                return -1;
+            }
+            if let Err(e) = proc.check_parent_access(&abs_path) {
+                proc.set_errno(e);
+                return -1;
+            }
+            proc.fs.borrow_mut().path_map.remove(&abs_path);
+            proc.notify_unlinked(&abs_path, inode_num);
+            proc.release_unnamed(inode_num);
+            0
+        },
+        _ => {
+            proc.set_errno(ENOTDIR);
+            -1
+        }
     }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_chmod(path: *const i8, mode: u32) -> i32 {
+pub extern "C" fn wasm_vfs_truncate(path: *const i8, length: i64) -> i32 {
     let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
-
     let mut proc = get_or_init_proc();
-    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
 
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
+    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
+    let inode_num = match proc.lookup(&abs_path) {
         Some(i) => i,
         None => return -1,
     };
-
-    if let Some(inode) = proc.get_inode_mut(inode_num) {
-        inode.permissions = Permissions::from((mode & 0o777) as u16);
-        0
-    } else {
-        -1
+    if length < 0 {
+        return -1;
     }
+    proc.truncate_inode(inode_num, length as u64)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fchmod(fd: i32, mode: u32) -> i32 {
-    let mut proc = get_or_init_proc();
+pub extern "C" fn wasm_vfs_ftruncate(fd: i32, length: i64) -> i32 {
+    if length < 0 {
+        return -1;
+    }
 
+    let mut proc = get_or_init_proc();
     let inode_num = match proc.fd_table.get(fd as usize) {
         Some(Some(i)) => *i,
         _ => return -1,
     };
-
-    if let Some(inode) = proc.get_inode_mut(inode_num) {
-        inode.permissions = Permissions::from((mode & 0o777) as u16);
-        0
-    } else {
-        -1
-    }
+    proc.truncate_inode(inode_num, length as u64)
 }
 
+// Preallocates, punches, zeroes, collapses or inserts a range of a
+// regular file, according to the FALLOC_FL_* bits in `mode`
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fchmodat(_dirfd: i32, pathname: *const i8, mode: u32, _flags: i32) -> i32 {
-    wasm_vfs_chmod(pathname, mode)
+pub extern "C" fn wasm_vfs_fallocate(fd: i32, mode: i32, offset: i64, len: i64) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.fallocate(fd, mode, offset, len) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
+// Plain allocation. Like the libc function, it returns the error
+// number instead of setting errno.
 #[no_mangle]
-pub extern "C" fn wasm_vfs_chown(path: *const i8, owner: u32, group: u32) -> i32 {
-    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
-
+pub extern "C" fn wasm_vfs_posix_fallocate(fd: i32, offset: i64, len: i64) -> i32 {
     let mut proc = get_or_init_proc();
-    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
-        Some(i) => i,
-        None => return -1,
-    };
-
-    if let Some(inode) = proc.get_inode_mut(inode_num) {
-        inode.user_id = owner;
-        inode.group_id = group;
-        0
-    } else {
-        -1
+    match proc.fallocate(fd, 0, offset, len) {
+        Ok(()) => 0,
+        Err(e) => e,
     }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_lchown(path: *const i8, owner: u32, group: u32) -> i32 {
-    wasm_vfs_chown(path, owner, group)
+pub extern "C" fn wasm_vfs_flock(fd: i32, _operation: i32) -> i32 {
+    // no-op
+    0
 }
 
+// Extended attributes. The plain calls follow a trailing symlink, the
+// l* ones act on the link itself and the f* ones on an open file.
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fchown(fd: i32, owner: u32, group: u32) -> i32 {
+pub extern "C" fn wasm_vfs_getxattr(path: *const i8, name: *const i8, value: *mut u8, size: usize) -> isize {
     let mut proc = get_or_init_proc();
-
-    let inode_num = match proc.fd_table.get(fd as usize) {
-        Some(Some(i)) => *i,
-        _ => return -1,
-    };
-
-    if let Some(inode) = proc.get_inode_mut(inode_num) {
-        inode.user_id = owner;
-        inode.group_id = group;
-        0
-    } else {
-        -1
-    }
+    let result = proc.path_inode(path, true).and_then(|ino| proc.getxattr(ino, name, value, size));
+    xattr_result(&mut proc, result)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fchownat(_dirfd: i32, pathname: *const i8, owner: u32, group: u32, _flags: i32) -> i32 {
-    wasm_vfs_chown(pathname, owner, group)
+pub extern "C" fn wasm_vfs_lgetxattr(path: *const i8, name: *const i8, value: *mut u8, size: usize) -> isize {
+    let mut proc = get_or_init_proc();
+    let result = proc.path_inode(path, false).and_then(|ino| proc.getxattr(ino, name, value, size));
+    xattr_result(&mut proc, result)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_access(path: *const i8, mode: i32) -> i32 {
-    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
-    let proc = get_or_init_proc();
-
-    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
-        Some(i) => i,
-        None => return -1,
-    };
-
-    let inode = proc.get_inode(inode_num).unwrap();
-    if proc.check_access(inode, mode) {
-        0
-    } else {
-        -1
-    }
+pub extern "C" fn wasm_vfs_fgetxattr(fd: i32, name: *const i8, value: *mut u8, size: usize) -> isize {
+    let mut proc = get_or_init_proc();
+    let result = proc.fd_inode(fd).and_then(|ino| proc.getxattr(ino, name, value, size));
+    xattr_result(&mut proc, result)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_faccessat(_dirfd: i32, pathname: *const i8, mode: i32, _flags: i32) -> i32 {
-    wasm_vfs_access(pathname, mode)
+pub extern "C" fn wasm_vfs_setxattr(path: *const i8, name: *const i8, value: *const u8, size: usize, flags: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+    let result = proc.path_inode(path, true).and_then(|ino| proc.setxattr(ino, name, value, size, flags));
+    xattr_result(&mut proc, result.map(|_| 0)) as i32
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_umask(mask: u32) -> u32 {
+pub extern "C" fn wasm_vfs_lsetxattr(path: *const i8, name: *const i8, value: *const u8, size: usize, flags: i32) -> i32 {
     let mut proc = get_or_init_proc();
-    let old = proc.umask_value;
-    proc.umask_value = mask & 0o777;
-    old
+    let result = proc.path_inode(path, false).and_then(|ino| proc.setxattr(ino, name, value, size, flags));
+    xattr_result(&mut proc, result.map(|_| 0)) as i32
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_rename(oldpath: *const i8, newpath: *const i8) -> i32 {
-    let old_str = unsafe { CStr::from_ptr(oldpath).to_string_lossy() };
-    let new_str = unsafe { CStr::from_ptr(newpath).to_string_lossy() };
-
+pub extern "C" fn wasm_vfs_fsetxattr(fd: i32, name: *const i8, value: *const u8, size: usize, flags: i32) -> i32 {
     let mut proc = get_or_init_proc();
+    let result = proc.fd_inode(fd).and_then(|ino| proc.setxattr(ino, name, value, size, flags));
+    xattr_result(&mut proc, result.map(|_| 0)) as i32
+}
 
-    let old_abs = proc.get_absolute_path(&PathBuf::from(old_str));
-    let new_abs = proc.get_absolute_path(&PathBuf::from(new_str));
-
-    let inode_num = match proc.fs.lookup_inode_by_path(&old_abs) {
-        Some(i) => i,
-        None => return -1,
-    };
-
+#[no_mangle]
+pub extern "C" fn wasm_vfs_listxattr(path: *const i8, list: *mut u8, size: usize) -> isize {
+    let mut proc = get_or_init_proc();
+    let result = proc.path_inode(path, true).and_then(|ino| proc.listxattr(ino, list, size));
+    xattr_result(&mut proc, result)
+}
 
-    proc.fs.path_map.remove(&old_abs);
+#[no_mangle]
+pub extern "C" fn wasm_vfs_llistxattr(path: *const i8, list: *mut u8, size: usize) -> isize {
+    let mut proc = get_or_init_proc();
+    let result = proc.path_inode(path, false).and_then(|ino| proc.listxattr(ino, list, size));
+    xattr_result(&mut proc, result)
+}
 
-    proc.fs.path_map.insert(new_abs, inode_num);
-    0
+#[no_mangle]
+pub extern "C" fn wasm_vfs_flistxattr(fd: i32, list: *mut u8, size: usize) -> isize {
+    let mut proc = get_or_init_proc();
+    let result = proc.fd_inode(fd).and_then(|ino| proc.listxattr(ino, list, size));
+    xattr_result(&mut proc, result)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_renameat(olddirfd: i32, oldpath: *const i8, newdirfd: i32, newpath: *const i8) -> i32 {
-    wasm_vfs_rename(oldpath, newpath)
+pub extern "C" fn wasm_vfs_removexattr(path: *const i8, name: *const i8) -> i32 {
+    let mut proc = get_or_init_proc();
+    let result = proc.path_inode(path, true).and_then(|ino| proc.removexattr(ino, name));
+    xattr_result(&mut proc, result.map(|_| 0)) as i32
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_renameat2(olddirfd: i32, oldpath: *const i8, newdirfd: i32, newpath: *const i8, _flags: u32) -> i32 {
-    wasm_vfs_renameat(olddirfd, oldpath, newdirfd, newpath)
+pub extern "C" fn wasm_vfs_lremovexattr(path: *const i8, name: *const i8) -> i32 {
+    let mut proc = get_or_init_proc();
+    let result = proc.path_inode(path, false).and_then(|ino| proc.removexattr(ino, name));
+    xattr_result(&mut proc, result.map(|_| 0)) as i32
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_link(oldpath: *const i8, newpath: *const i8) -> i32 {
-    let old_str = unsafe { CStr::from_ptr(oldpath).to_string_lossy() };
-    let new_str = unsafe { CStr::from_ptr(newpath).to_string_lossy() };
-
+pub extern "C" fn wasm_vfs_fremovexattr(fd: i32, name: *const i8) -> i32 {
     let mut proc = get_or_init_proc();
+    let result = proc.fd_inode(fd).and_then(|ino| proc.removexattr(ino, name));
+    xattr_result(&mut proc, result.map(|_| 0)) as i32
+}
 
-    let old_abs = proc.get_absolute_path(&PathBuf::from(old_str));
-    let new_abs = proc.get_absolute_path(&PathBuf::from(new_str));
-
-    let inode_num = match proc.fs.lookup_inode_by_path(&old_abs) {
-        Some(i) => i,
-        None => return -1,
-    };
-    proc.fs.path_map.insert(new_abs, inode_num);
-    0
+fn xattr_result(proc: &mut Proc, result: Result<usize, i32>) -> isize {
+    match result {
+        Ok(n) => n as isize,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
+// Maps a file (or anonymous memory) into linear memory. Returns
+// MAP_FAILED, (void *)-1, on error.
 #[no_mangle]
-pub extern "C" fn wasm_vfs_linkat(olddirfd: i32, oldpath: *const i8, newdirfd: i32, newpath: *const i8, _flags: i32) -> i32 {
-    wasm_vfs_link(oldpath, newpath)
+pub extern "C" fn wasm_vfs_mmap(addr: *mut u8, length: usize, prot: i32, flags: i32, fd: i32, offset: isize) -> *mut u8 {
+    let mut proc = get_or_init_proc();
+    match proc.mmap(addr as usize, length, prot, flags, fd, offset as i64) {
+        Ok(addr) => addr as *mut u8,
+        Err(e) => {
+            proc.set_errno(e);
+            mmap::MAP_FAILED
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_unlink(pathname: *const i8) -> i32 {
-    let path_str = unsafe { CStr::from_ptr(pathname).to_string_lossy() };
+pub extern "C" fn wasm_vfs_munmap(addr: *mut u8, length: usize) -> i32 {
     let mut proc = get_or_init_proc();
-
-    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
-        Some(i) => i,
-        None => return -1,
-    };
-
-    let inode = proc.get_inode(inode_num).unwrap();
-    if let InodeKind::Directory = inode.kind {
-        return -1;
+    match proc.munmap(addr as usize, length) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
     }
-
-    proc.fs.path_map.remove(&abs_path);
-    0
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_unlinkat(dirfd: i32, pathname: *const i8, flags: i32) -> i32 {
-    // ignoring flags and dirfd
-    wasm_vfs_unlink(pathname)
+pub extern "C" fn wasm_vfs_mremap(old_address: *mut u8, old_size: usize, new_size: usize, flags: i32, _new_address: *mut u8) -> *mut u8 {
+    let mut proc = get_or_init_proc();
+    match proc.mremap(old_address as usize, old_size, new_size, flags) {
+        Ok(addr) => addr as *mut u8,
+        Err(e) => {
+            proc.set_errno(e);
+            mmap::MAP_FAILED
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_symlink(target: *const i8, linkpath: *const i8) -> i32 {
-    let target_str = unsafe { CStr::from_ptr(target).to_string_lossy() };
-    let link_str = unsafe { CStr::from_ptr(linkpath).to_string_lossy() };
-
+pub extern "C" fn wasm_vfs_mprotect(addr: *mut u8, length: usize, prot: i32) -> i32 {
     let mut proc = get_or_init_proc();
+    match proc.mprotect(addr as usize, length, prot) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
 
-    let link_abs = proc.get_absolute_path(&PathBuf::from(link_str));
-    let inode_number = proc.fs.next_inode_number;
-    proc.fs.next_inode_number += 1;
-
-    let kind = InodeKind::SymbolicLink(PathBuf::from(target_str));
-    proc.insert_directory_entry(&link_abs, inode_number, kind);
+#[no_mangle]
+pub extern "C" fn wasm_vfs_mlock(_addr: *const u8, _len: usize) -> i32 {
     0
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_symlinkat(target: *const i8, newdirfd: i32, linkpath: *const i8) -> i32 {
-    wasm_vfs_symlink(target, linkpath)
+pub extern "C" fn wasm_vfs_munlock(_addr: *const u8, _len: usize) -> i32 {
+    0
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_readlink(path: *const i8, buf: *mut i8, bufsize: usize) -> isize {
-    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
-    let proc = get_or_init_proc();
-
-    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
-        Some(i) => i,
-        None => return -1,
-    };
-    let inode = proc.get_inode(inode_num).unwrap();
-    match &inode.kind {
-        InodeKind::SymbolicLink(t) => {
-            let loss = t.to_string_lossy();
-            let bytes = loss.as_bytes();
-            let to_copy = min(bytes.len(), bufsize);
-            unsafe {
-                core::ptr::copy_nonoverlapping(bytes.as_ptr(), buf as *mut u8, to_copy);
-            }
-            to_copy as isize
+pub extern "C" fn wasm_vfs_msync(addr: *mut u8, length: usize, flags: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.msync(addr as usize, length, flags) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
         }
-        _ => -1,
     }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_readlinkat(dirfd: i32, pathname: *const i8, buf: *mut i8, bufsize: usize) -> isize {
-    wasm_vfs_readlink(pathname, buf, bufsize)
+pub extern "C" fn wasm_vfs_sync() {
+    // File data is already in memory; only buffered stdio and batched
+    // change feeds need pushing out
+    let mut proc = get_or_init_proc();
+    proc.flush_stdio();
+    proc.flush_changes();
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_mkdir(path: *const i8, mode: u32) -> i32 {
-    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
-
+pub extern "C" fn wasm_vfs_fsync(fd: i32) -> i32 {
     let mut proc = get_or_init_proc();
-
-    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-
-    if proc.fs.lookup_inode_by_path(&abs_path).is_some() {
+    let is_tty = proc.open_files.get(&fd).map(|h| h.kind == HandleKind::Tty).unwrap_or(false);
+    let result = if is_tty {
+        proc.tty.as_mut().map(|tty| tty.flush_output()).unwrap_or(Ok(()))
+    } else {
+        proc.stream_for_fd(fd).map(|stream| stream.flush()).unwrap_or(Ok(()))
+    };
+    if let Err(e) = result {
+        proc.set_errno(e);
         return -1;
     }
+    0
+}
 
-    let inode_number = proc.fs.next_inode_number;
-    proc.fs.next_inode_number += 1;
+#[no_mangle]
+pub extern "C" fn wasm_vfs_fdatasync(fd: i32) -> i32 {
+    wasm_vfs_fsync(fd)
+}
 
-    let adjusted_mode = (mode as u16) & !(proc.umask_value as u16);
-    let kind = InodeKind::Directory;
-    proc.insert_directory_entry(&abs_path, inode_number, kind);
-    let inode = proc.get_inode_mut(inode_number).unwrap();
-    inode.permissions = Permissions::from(adjusted_mode);
+#[no_mangle]
+pub extern "C" fn wasm_vfs_syncfs(_fd: i32) -> i32 {
     0
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_mkdirat(dirfd: i32, pathname: *const i8, mode: u32) -> i32 {
-    wasm_vfs_mkdir(pathname, mode)
+pub extern "C" fn wasm_vfs_inotify_init() -> i32 {
+    wasm_vfs_inotify_init1(0)
 }
 
+// IN_NONBLOCK and IN_CLOEXEC are O_NONBLOCK and O_CLOEXEC. Reads never
+// block either way: with nothing queued they fail with EAGAIN.
 #[no_mangle]
-pub extern "C" fn wasm_vfs_rmdir(path: *const i8) -> i32 {
-    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
-
+pub extern "C" fn wasm_vfs_inotify_init1(flags: i32) -> i32 {
     let mut proc = get_or_init_proc();
+    match proc.inotify_init(flags) {
+        Ok(fd) => fd,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
 
-    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
-        Some(i) => i,
-        None => return -1,
-    };
-    let inode = proc.get_inode(inode_num).unwrap();
-    match inode.kind {
-        InodeKind::Directory => {
-
-            let entries = proc.fs.path_map.iter().filter(|(p,_)| {
-                p.parent().map(|pp| pp == abs_path).unwrap_or(false)
-            }).count();
-            if entries > 0 {
-                return -1;
-            }
-            proc.fs.path_map.remove(&abs_path);
-            0
-        },
-        _ => -1,
+#[no_mangle]
+pub extern "C" fn wasm_vfs_inotify_add_watch(fd: i32, pathname: *const i8, mask: u32) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.inotify_add_watch(fd, pathname, mask) {
+        Ok(wd) => wd,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
     }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_truncate(path: *const i8, length: i64) -> i32 {
-    let path_str = unsafe { CStr::from_ptr(path).to_string_lossy() };
+pub extern "C" fn wasm_vfs_inotify_rm_watch(fd: i32, wd: i32) -> i32 {
     let mut proc = get_or_init_proc();
+    let result = proc.inotify_instance(fd).and_then(|instance| instance.rm_watch(wd));
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
+}
 
-    let abs_path = proc.get_absolute_path(&PathBuf::from(path_str));
-    let inode_num = match proc.fs.lookup_inode_by_path(&abs_path) {
-        Some(i) => i,
-        None => return -1,
-    };
-    if length < 0 {
-        return -1;
+// Runs `check` until it finds something ready or `timeout` (None for
+// no limit) runs out. The process lock is let go between checks so the
+// host can deliver input while the guest sleeps. A virtual clock jumps
+// to the deadline instead, or to the first timerfd among the checked
+// descriptors to fire if that comes sooner; with neither nothing can
+// change, so the wait ends at once.
+fn wait_ready(timeout: Option<u64>, mut check: impl FnMut(&mut Proc) -> Result<usize, i32>) -> Result<usize, i32> {
+    let deadline = timeout.map(|ns| clock::now(CLOCK_MONOTONIC).saturating_add(ns));
+    loop {
+        let (ready, wake) = {
+            let mut proc = get_or_init_proc();
+            proc.next_wake = None;
+            let ready = check(&mut proc)?;
+            (ready, proc.next_wake)
+        };
+        if ready > 0 {
+            return Ok(ready);
+        }
+        let now = clock::now(CLOCK_MONOTONIC);
+        let limit = match deadline {
+            Some(deadline) if now >= deadline => return Ok(0),
+            Some(deadline) => Some(deadline - now),
+            None => None,
+        };
+        let until = match (limit, wake) {
+            (Some(limit), Some(wake)) => Some(core::cmp::min(limit, wake)),
+            (limit, wake) => limit.or(wake),
+        };
+        let step = match until {
+            Some(ns) if clock::is_virtual() => ns,
+            Some(ns) => core::cmp::min(ns, clock::WAIT_SLICE_NS),
+            None if clock::is_virtual() => return Ok(0),
+            None => clock::WAIT_SLICE_NS,
+        };
+        clock::sleep(step);
     }
-    proc.set_file_size(inode_num, length as usize);
-    0
 }
 
-#[no_mangle]
-pub extern "C" fn wasm_vfs_ftruncate(fd: i32, length: i64) -> i32 {
-    if length < 0 {
-        return -1;
+fn wait_result(result: Result<usize, i32>) -> i32 {
+    match result {
+        Ok(n) => n as i32,
+        Err(e) => {
+            get_or_init_proc().set_errno(e);
+            -1
+        }
     }
+}
 
-    let mut proc = get_or_init_proc();
-    let inode_num = match proc.fd_table.get(fd as usize) {
-        Some(Some(i)) => *i,
-        _ => return -1,
-    };
-    proc.set_file_size(inode_num, length as usize);
-    0
+#[no_mangle]
+pub extern "C" fn wasm_vfs_poll(fds: *mut PollFd, nfds: usize, timeout: i32) -> i32 {
+    wait_result(poll_fds(fds, nfds, clock::from_millis(timeout)))
 }
 
+// The signal mask is ignored: there are no signals to block
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fallocate(fd: i32, _mode: i32, offset: i64, len: i64) -> i32 {
-    if offset < 0 || len < 0 {
-        return -1;
+pub extern "C" fn wasm_vfs_ppoll(fds: *mut PollFd, nfds: usize, timeout: *const Timespec, _sigmask: *const u8) -> i32 {
+    let timeout = if timeout.is_null() { Ok(None) } else { clock::from_timespec(unsafe { &*timeout }).map(Some) };
+    wait_result(timeout.and_then(|timeout| poll_fds(fds, nfds, timeout)))
+}
+
+fn poll_fds(fds: *mut PollFd, nfds: usize, timeout: Option<u64>) -> Result<usize, i32> {
+    if nfds > get_or_init_proc().fd_table.len() {
+        return Err(EINVAL);
     }
+    let fds = if nfds == 0 { &mut [][..] } else { unsafe { core::slice::from_raw_parts_mut(fds, nfds) } };
+    wait_ready(timeout, |proc| Ok(proc.poll_once(fds)))
+}
 
-    let mut proc = get_or_init_proc();
-    let inode_num = match proc.fd_table.get(fd as usize) {
-        Some(Some(i)) => *i,
-        _ => return -1,
-    };
+#[no_mangle]
+pub extern "C" fn wasm_vfs_select(nfds: i32, readfds: *mut u8, writefds: *mut u8, exceptfds: *mut u8, timeout: *mut Timeval) -> i32 {
+    let timeout = if timeout.is_null() { Ok(None) } else { clock::from_timeval(unsafe { &*timeout }).map(Some) };
+    wait_result(timeout.and_then(|timeout| select_fds(nfds, [readfds, writefds, exceptfds], timeout)))
+}
 
-    let end = (offset + len) as usize;
+#[no_mangle]
+pub extern "C" fn wasm_vfs_pselect(nfds: i32, readfds: *mut u8, writefds: *mut u8, exceptfds: *mut u8, timeout: *const Timespec, _sigmask: *const u8) -> i32 {
+    let timeout = if timeout.is_null() { Ok(None) } else { clock::from_timespec(unsafe { &*timeout }).map(Some) };
+    wait_result(timeout.and_then(|timeout| select_fds(nfds, [readfds, writefds, exceptfds], timeout)))
+}
 
-    {
-        let data = proc.fs.files.get_mut(&inode_num).unwrap();
-        if data.len() < end {
-            data.resize(end, 0);
+// The sets are read once up front and only overwritten with the result
+fn select_fds(nfds: i32, sets: [*mut u8; 3], timeout: Option<u64>) -> Result<usize, i32> {
+    if nfds < 0 || nfds as usize > poll::FD_SETSIZE {
+        return Err(EINVAL);
+    }
+    let nfds = nfds as usize;
+    let bytes = nfds.div_ceil(8);
+    let requested = sets.map(|set| {
+        if set.is_null() { None } else { Some(unsafe { core::slice::from_raw_parts(set, bytes) }.to_vec()) }
+    });
+    let mut ready = [vec![0u8; bytes], vec![0u8; bytes], vec![0u8; bytes]];
+    let n = wait_ready(timeout, |proc| proc.select_once(nfds, &requested, &mut ready))?;
+    for (i, set) in sets.iter().enumerate() {
+        if set.is_null() {
+            continue;
+        }
+        let out = unsafe { core::slice::from_raw_parts_mut(*set, bytes) };
+        for fd in 0..nfds {
+            out[fd / 8] &= !(1 << (fd % 8));
+        }
+        for (byte, bits) in out.iter_mut().zip(ready[i].iter()) {
+            *byte |= bits;
         }
     }
+    Ok(n)
+}
 
-    let new_size = proc.fs.files.get(&inode_num).unwrap().len();
-    if let Some(inode) = proc.get_inode_mut(inode_num) {
-        inode.size = new_size as u64;
+// `size` is only checked, as on Linux
+#[no_mangle]
+pub extern "C" fn wasm_vfs_epoll_create(size: i32) -> i32 {
+    if size <= 0 {
+        get_or_init_proc().set_errno(EINVAL);
+        return -1;
     }
-
-    0
+    wasm_vfs_epoll_create1(0)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_posix_fallocate(fd: i32, offset: i64, len: i64) -> i32 {
-    wasm_vfs_fallocate(fd, 0, offset, len)
+pub extern "C" fn wasm_vfs_epoll_create1(flags: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.epoll_create(flags) {
+        Ok(fd) => fd,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_flock(fd: i32, _operation: i32) -> i32 {
-    // no-op
-    0
+pub extern "C" fn wasm_vfs_epoll_ctl(epfd: i32, op: i32, fd: i32, event: *mut EpollEvent) -> i32 {
+    let mut proc = get_or_init_proc();
+    let event = if event.is_null() { None } else { Some(unsafe { core::ptr::read_unaligned(event) }) };
+    match proc.epoll_ctl(epfd, op, fd, event) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_mmap(_addr: *mut u8, _length: usize, _prot: i32, _flags: i32, _fd: i32, _offset: isize) -> *mut u8 {
-    // not supported in this in-memory fs
-    core::ptr::null_mut()
+pub extern "C" fn wasm_vfs_epoll_wait(epfd: i32, events: *mut EpollEvent, maxevents: i32, timeout: i32) -> i32 {
+    wasm_vfs_epoll_pwait(epfd, events, maxevents, timeout, core::ptr::null())
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_munmap(_addr: *mut u8, _length: usize) -> i32 {
-    // no-op
-    0
+pub extern "C" fn wasm_vfs_epoll_pwait(epfd: i32, events: *mut EpollEvent, maxevents: i32, timeout: i32, _sigmask: *const u8) -> i32 {
+    let inode_number = get_or_init_proc().epoll_inode(epfd);
+    let inode_number = match inode_number {
+        Ok(ino) if maxevents > 0 => ino,
+        Ok(_) => return wait_result(Err(EINVAL)),
+        Err(e) => return wait_result(Err(e)),
+    };
+    let mut ready = vec![EpollEvent::default(); maxevents as usize];
+    let result = wait_ready(clock::from_millis(timeout), |proc| Ok(proc.epoll_collect(inode_number, &mut ready)));
+    if let Ok(n) = result {
+        for (i, event) in ready.iter().take(n).enumerate() {
+            unsafe { core::ptr::write_unaligned(events.add(i), *event) };
+        }
+    }
+    wait_result(result)
 }
 
+// POSIX shared memory: `name` is a file in /dev/shm, opened close-on-exec.
+// Size it with ftruncate and map it MAP_SHARED.
 #[no_mangle]
-pub extern "C" fn wasm_vfs_mprotect(_addr: *mut u8, _length: usize, _prot: i32) -> i32 {
-    // no-op
-    0
+pub extern "C" fn wasm_vfs_shm_open(name: *const i8, oflag: i32, mode: u32) -> i32 {
+    let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+    // One lock over the check and the open, so O_CREAT | O_EXCL can't
+    // race another opener
+    let mut proc = get_or_init_proc();
+    let path = match proc.shm_open_path(&name, oflag) {
+        Ok(path) => path,
+        Err(e) => {
+            proc.set_errno(e);
+            return -1;
+        }
+    };
+    let mut c_path = path.to_string_lossy().into_bytes();
+    c_path.push(0);
+    open_fd(&mut proc, c_path.as_ptr() as *const i8, oflag | O_CLOEXEC, mode)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_mlock(_addr: *const u8, _len: usize) -> i32 {
-    0
+pub extern "C" fn wasm_vfs_shm_unlink(name: *const i8) -> i32 {
+    let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+    let mut proc = get_or_init_proc();
+    match proc.shm_unlink(&name) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
+// Q_GETQUOTA and Q_SETQUOTA for user, group and project quotas, with
+// Q_SYNC as a no-op. There is one set of quotas per FileSystem, so
+// `special` only has to name something on it; it may be null.
 #[no_mangle]
-pub extern "C" fn wasm_vfs_munlock(_addr: *const u8, _len: usize) -> i32 {
-    0
+pub extern "C" fn wasm_vfs_quotactl(cmd: i32, special: *const i8, id: i32, addr: *mut u8) -> i32 {
+    let mut proc = get_or_init_proc();
+    let result = if special.is_null() {
+        Ok(0)
+    } else {
+        proc.path_inode(special, true)
+    };
+    match result.and_then(|_| proc.quotactl(cmd, id as u32, addr as *mut Dqblk)) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
+// An unnamed in-memory file. MFD_ALLOW_SEALING lets F_ADD_SEALS lock
+// its size or contents; without it the file is sealed against seals.
 #[no_mangle]
-pub extern "C" fn wasm_vfs_msync(_addr: *mut u8, _length: usize, _flags: i32) -> i32 {
-    0
+pub extern "C" fn wasm_vfs_memfd_create(name: *const i8, flags: u32) -> i32 {
+    let name = unsafe { CStr::from_ptr(name).to_string_lossy() };
+    let mut proc = get_or_init_proc();
+    match proc.memfd_create(&name, flags) {
+        Ok(fd) => fd,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
+// libc's eventfd() takes flags and is eventfd2 underneath.
+// EFD_NONBLOCK and EFD_CLOEXEC are O_NONBLOCK and O_CLOEXEC. Reads and
+// writes never block either way: they fail with EAGAIN instead.
 #[no_mangle]
-pub extern "C" fn wasm_vfs_sync() {
-    // no-op
+pub extern "C" fn wasm_vfs_eventfd(initval: u32, flags: i32) -> i32 {
+    wasm_vfs_eventfd2(initval, flags)
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fsync(_fd: i32) -> i32 {
-    // no-op
-    0
+pub extern "C" fn wasm_vfs_eventfd2(initval: u32, flags: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.eventfd_create(initval, flags) {
+        Ok(fd) => fd,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
+// TFD_NONBLOCK and TFD_CLOEXEC are O_NONBLOCK and O_CLOEXEC
 #[no_mangle]
-pub extern "C" fn wasm_vfs_fdatasync(_fd: i32) -> i32 {
-    // no-op
-    0
+pub extern "C" fn wasm_vfs_timerfd_create(clockid: i32, flags: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.timerfd_create(clockid, flags) {
+        Ok(fd) => fd,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_syncfs(_fd: i32) -> i32 {
-    0
+pub extern "C" fn wasm_vfs_timerfd_settime(fd: i32, flags: i32, new_value: *const Itimerspec, old_value: *mut Itimerspec) -> i32 {
+    let mut proc = get_or_init_proc();
+    if new_value.is_null() {
+        proc.set_errno(EFAULT);
+        return -1;
+    }
+    let new_value = unsafe { *new_value };
+    match proc.timer(fd).and_then(|timer| timer.settime(flags, &new_value)) {
+        Ok(old) => {
+            if !old_value.is_null() {
+                unsafe { *old_value = old };
+            }
+            0
+        }
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_inotify_init() -> i32 {
-    -1
+pub extern "C" fn wasm_vfs_timerfd_gettime(fd: i32, curr_value: *mut Itimerspec) -> i32 {
+    let mut proc = get_or_init_proc();
+    if curr_value.is_null() {
+        proc.set_errno(EFAULT);
+        return -1;
+    }
+    match proc.timer(fd) {
+        Ok(timer) => {
+            unsafe { *curr_value = timer.gettime() };
+            0
+        }
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
+/// Reports changes at or below `root` to the `box_host_fs_changes`
+/// import, tagged with `tag`. With `batch` 0 each change is sent as it
+/// happens; otherwise up to `batch` are held back until that many are
+/// pending or `wasm_vfs_flush_changes` (or `sync`) is called. Returns
+/// the subscription id.
+#[cfg(feature = "host-changes")]
 #[no_mangle]
-pub extern "C" fn wasm_vfs_inotify_init1(_flags: i32) -> i32 {
-    -1
+pub extern "C" fn wasm_vfs_subscribe_changes(root: *const i8, batch: u32, tag: u32) -> i32 {
+    let root = unsafe { CStr::from_ptr(root).to_string_lossy() };
+    let batching = if batch == 0 { Batching::Immediate } else { Batching::Batch(batch as usize) };
+    let mut proc = get_or_init_proc();
+    proc.subscribe_changes(&root, batching, Box::new(crate::changes::HostObserver::new(tag))) as i32
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_inotify_add_watch(_fd: i32, _pathname: *const i8, _mask: u32) -> i32 {
-    -1
+pub extern "C" fn wasm_vfs_unsubscribe_changes(id: i32) -> i32 {
+    let mut proc = get_or_init_proc();
+    match proc.unsubscribe_changes(id as u32) {
+        Ok(()) => 0,
+        Err(e) => {
+            proc.set_errno(e);
+            -1
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn wasm_vfs_inotify_rm_watch(_fd: i32, _wd: i32) -> i32 {
-    -1
+pub extern "C" fn wasm_vfs_flush_changes() {
+    get_or_init_proc().flush_changes();
 }
 
 // -----------------------------------------------------------
@@ -1595,9 +6267,9 @@ pub extern "C" fn wasm_vfs_inotify_rm_watch(_fd: i32, _wd: i32) -> i32 {
         //let meta = host.wasm_vfs_symlink_metadata()?;
         //if meta.is_dir() {
             //if vfs_path != path::new("/") {
-                //let inode_number = proc.fs.next_inode_number;
-                //proc.fs.next_inode_number += 1;
-                //proc.fs.path_map.insert(vfs_path.to_path_buf(), inode_number);
+                //let inode_number = proc.fs.borrow().next_inode_number;
+                //proc.fs.borrow_mut().next_inode_number += 1;
+                //proc.fs.borrow_mut().path_map.insert(vfs_path.to_path_buf(), inode_number);
                 //let inode = Inode::new(
                     //inode_number,
                     //0,
@@ -1609,11 +6281,11 @@ pub extern "C" fn wasm_vfs_inotify_rm_watch(_fd: i32, _wd: i32) -> i32 {
                     //0,
                     //InodeKind::Directory
                 //);
-                //while proc.fs.inodes.len() <= inode_number as usize {
-                    //proc.fs.inodes.push(Inode::default());
+                //while proc.fs.borrow().inodes.len() <= inode_number as usize {
+                    //proc.fs.borrow_mut().inodes.push(Inode::default());
                 //}
-                //proc.fs.inodes[inode_number as usize] = inode;
-                //proc.fs.files.insert(inode_number, vec![]);
+                //proc.fs.borrow_mut().inodes[inode_number as usize] = inode;
+                //proc.fs.borrow_mut().files.insert(inode_number, vec![]);
             //}
             //for entry in fs::read_dir(host)? {
                 //let entry = entry?;
@@ -1622,9 +6294,9 @@ pub extern "C" fn wasm_vfs_inotify_rm_watch(_fd: i32, _wd: i32) -> i32 {
                 //recurse(proc, &entry.path(), &new_vfs_path)?;
             //}
         //} else if meta.is_file() {
-            //let inode_number = proc.fs.next_inode_number;
-            //proc.fs.next_inode_number += 1;
-            //proc.fs.path_map.insert(vfs_path.to_path_buf(), inode_number);
+            //let inode_number = proc.fs.borrow().next_inode_number;
+            //proc.fs.borrow_mut().next_inode_number += 1;
+            //proc.fs.borrow_mut().path_map.insert(vfs_path.to_path_buf(), inode_number);
             //let data = fs::read(host)?;
             //let inode = Inode::new(
                 //inode_number,
@@ -1637,16 +6309,16 @@ pub extern "C" fn wasm_vfs_inotify_rm_watch(_fd: i32, _wd: i32) -> i32 {
                 //0,
                 //InodeKind::file
             //);
-            //while proc.fs.inodes.len() <= inode_number as usize {
-                //proc.fs.inodes.push(Inode::default());
+            //while proc.fs.borrow().inodes.len() <= inode_number as usize {
+                //proc.fs.borrow_mut().inodes.push(Inode::default());
             //}
-            //proc.fs.inodes[inode_number as usize] = inode;
-            //proc.fs.files.insert(inode_number, data);
+            //proc.fs.borrow_mut().inodes[inode_number as usize] = inode;
+            //proc.fs.borrow_mut().files.insert(inode_number, data);
         //} else if meta.file_type().is_symlink() {
             //let target = fs::read_link(host)?;
-            //let inode_number = proc.fs.next_inode_number;
-            //proc.fs.next_inode_number += 1;
-            //proc.fs.path_map.insert(vfs_path.to_path_buf(), inode_number);
+            //let inode_number = proc.fs.borrow().next_inode_number;
+            //proc.fs.borrow_mut().next_inode_number += 1;
+            //proc.fs.borrow_mut().path_map.insert(vfs_path.to_path_buf(), inode_number);
             //let inode = Inode::new(
                 //inode_number,
                 //0,
@@ -1658,10 +6330,10 @@ pub extern "C" fn wasm_vfs_inotify_rm_watch(_fd: i32, _wd: i32) -> i32 {
                 //0,
                 //InodeKind::SymbolicLink(target),
             //);
-            //while proc.fs.inodes.len() <= inode_number as usize {
-                //proc.fs.inodes.push(Inode::default());
+            //while proc.fs.borrow().inodes.len() <= inode_number as usize {
+                //proc.fs.borrow_mut().inodes.push(Inode::default());
             //}
-            //proc.fs.inodes[inode_number as usize] = inode;
+            //proc.fs.borrow_mut().inodes[inode_number as usize] = inode;
         //}
 
         //Ok(())
@@ -1731,3 +6403,6 @@ pub extern "C" fn wasm_vfs_mount_in_memory(count: u32, files: *const FileDef) ->
     0
 }
 
+
+#[cfg(test)]
+mod tests;
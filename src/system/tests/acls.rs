@@ -0,0 +1,94 @@
+use super::*;
+
+#[test]
+fn acls_decide_access_and_only_owners_change_them() {
+    use crate::acl::{ACL_GROUP_OBJ, ACL_MASK, ACL_OTHER, ACL_USER, ACL_USER_OBJ};
+    let _serial = fresh();
+    let entry = |tag: u16, perm: u16, id: u32| [&tag.to_le_bytes()[..], &perm.to_le_bytes(), &id.to_le_bytes()].concat();
+    let none = u32::MAX;
+    let acl = [
+        &2u32.to_le_bytes()[..],
+        &entry(ACL_USER_OBJ, 6, none),
+        &entry(ACL_USER, 4, 2000),
+        &entry(ACL_GROUP_OBJ, 0, none),
+        &entry(ACL_MASK, 4, none),
+        &entry(ACL_OTHER, 0, none),
+    ]
+    .concat();
+    let fd = wasm_vfs_open(c(b"/f\0"), O_CREAT | O_RDWR, 0o600);
+    assert_eq!(wasm_vfs_chown(c(b"/f\0"), 1000, 100), 0);
+    assert_eq!(wasm_vfs_setxattr(c(b"/f\0"), c(b"system.posix_acl_access\0"), acl.as_ptr(), acl.len(), 0), 0);
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_fstat(fd, &mut st), 0);
+    assert_eq!(st.st_mode & 0o777, 0o640);
+    // a default ACL only goes on a directory
+    assert_eq!(wasm_vfs_setxattr(c(b"/f\0"), c(b"system.posix_acl_default\0"), acl.as_ptr(), acl.len(), 0), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+
+    get_or_init_proc().uid = 2000;
+    assert_eq!(wasm_vfs_access(c(b"/f\0"), R_OK), 0);
+    assert_eq!(wasm_vfs_access(c(b"/f\0"), W_OK), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_chmod(c(b"/f\0"), 0o666), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_removexattr(c(b"/f\0"), c(b"system.posix_acl_access\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_chown(c(b"/f\0"), 2000, 2000), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+
+    // The owner may chmod, which rewrites the ACL's mask, but still
+    // not give the file away
+    get_or_init_proc().uid = 1000;
+    assert_eq!(wasm_vfs_chmod(c(b"/f\0"), 0o600), 0);
+    assert_eq!(wasm_vfs_fchown(fd, 2000, 100), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    get_or_init_proc().uid = 2000;
+    assert_eq!(wasm_vfs_access(c(b"/f\0"), R_OK), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+
+    assert_eq!(wasm_vfs_chmod(c(b"/nope\0"), 0o600), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    assert_eq!(wasm_vfs_access(c(b"/nope\0"), R_OK), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    assert_eq!(wasm_vfs_fchmod(99, 0o600), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+}
+
+#[test]
+fn opens_and_directory_changes_check_permissions() {
+    use crate::acl::{ACL_GROUP_OBJ, ACL_MASK, ACL_OTHER, ACL_USER, ACL_USER_OBJ};
+    let _serial = fresh();
+    let entry = |tag: u16, perm: u16, id: u32| [&tag.to_le_bytes()[..], &perm.to_le_bytes(), &id.to_le_bytes()].concat();
+    let none = u32::MAX;
+    // Everyone may read and write except user 2000, who may only read
+    let acl = [
+        &2u32.to_le_bytes()[..],
+        &entry(ACL_USER_OBJ, 6, none),
+        &entry(ACL_USER, 4, 2000),
+        &entry(ACL_GROUP_OBJ, 6, none),
+        &entry(ACL_MASK, 6, none),
+        &entry(ACL_OTHER, 6, none),
+    ]
+    .concat();
+    assert_eq!(wasm_vfs_mkdir(c(b"/d\0"), 0o755), 0);
+    let fd = wasm_vfs_open(c(b"/d/f\0"), O_CREAT | O_RDWR, 0o666);
+    assert_eq!(wasm_vfs_fsetxattr(fd, c(b"system.posix_acl_access\0"), acl.as_ptr(), acl.len(), 0), 0);
+
+    get_or_init_proc().set_credentials(2000, 2000, &[]);
+    assert_eq!(wasm_vfs_open(c(b"/d/f\0"), O_RDWR, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_open(c(b"/d/f\0"), O_RDONLY | O_TRUNC, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert!(wasm_vfs_open(c(b"/d/f\0"), O_RDONLY, 0) >= 0);
+
+    // /d is root's 0755, so its entries are fixed for anyone else
+    assert_eq!(wasm_vfs_open(c(b"/d/g\0"), O_CREAT | O_RDWR, 0o644), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_mkdir(c(b"/d/sub\0"), 0o755), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_unlink(c(b"/d/f\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_rename(c(b"/d/f\0"), c(b"/d/h\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_access(c(b"/d/f\0"), R_OK), 0);
+}
@@ -0,0 +1,54 @@
+use super::*;
+
+#[test]
+fn the_change_feed_follows_the_tree() {
+    use crate::changes::{Batching, ChangeLog, ChangeOp};
+    let _serial = fresh();
+    let ops = |log: &ChangeLog| -> Vec<(ChangeOp, String)> {
+        log.take().into_iter().map(|c| (c.op, c.path.to_string_lossy())).collect()
+    };
+    let at = |op: ChangeOp, path: &str| (op, path.to_string());
+    let write = |offset: u64, len: u64| ChangeOp::Write { offset, len };
+    wasm_vfs_mkdir(c(b"/m\0"), 0o755);
+    wasm_vfs_mkdir(c(b"/other\0"), 0o755);
+    let (all, sub, batched) = (ChangeLog::new(), ChangeLog::new(), ChangeLog::new());
+    get_or_init_proc().subscribe_changes("/", Batching::Immediate, Box::new(all.clone()));
+    get_or_init_proc().subscribe_changes("/m", Batching::Immediate, Box::new(sub.clone()));
+    let batch = get_or_init_proc().subscribe_changes("/m", Batching::Batch(10), Box::new(batched.clone()));
+
+    let fd = wasm_vfs_open(c(b"/m/f\0"), O_CREAT | O_RDWR | O_TRUNC, 0o644);
+    write_all(fd, b"abc");
+    write_all(fd, b"def");
+    wasm_vfs_ftruncate(fd, 2);
+    wasm_vfs_close(fd);
+    wasm_vfs_chmod(c(b"/m/f\0"), 0o600);
+    wasm_vfs_rename(c(b"/m/f\0"), c(b"/other/g\0"));
+    let fd = wasm_vfs_open(c(b"/other/x\0"), O_CREAT | O_RDWR, 0o644);
+    wasm_vfs_close(fd);
+    wasm_vfs_unlink(c(b"/other/x\0"));
+
+    assert_eq!(
+        ops(&sub),
+        [
+            at(ChangeOp::Create, "/m/f"),
+            at(ChangeOp::Truncate { size: 0 }, "/m/f"),
+            at(write(0, 3), "/m/f"),
+            at(write(3, 3), "/m/f"),
+            at(ChangeOp::Truncate { size: 2 }, "/m/f"),
+            at(ChangeOp::Attrib, "/m/f"),
+            at(ChangeOp::Rename { from: PathBuf::from("/m/f") }, "/other/g"),
+        ]
+    );
+    assert!(batched.take().is_empty());
+    wasm_vfs_flush_changes();
+    let held = ops(&batched);
+    assert_eq!(held.len(), 6);
+    assert_eq!(held[2], at(write(0, 6), "/m/f"));
+    let everything = ops(&all);
+    assert_eq!(everything.len(), 9);
+    assert_eq!(everything[8], at(ChangeOp::Unlink, "/other/x"));
+
+    assert_eq!(wasm_vfs_unsubscribe_changes(batch as i32), 0);
+    assert_eq!(wasm_vfs_unsubscribe_changes(batch as i32), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+}
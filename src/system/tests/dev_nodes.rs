@@ -0,0 +1,25 @@
+use super::*;
+
+#[test]
+fn dev_holds_the_standard_nodes() {
+    let _serial = fresh();
+    let mut buf = [7u8; 16];
+    let zero = wasm_vfs_open(c(b"/dev/zero\0"), O_RDONLY, 0);
+    assert_eq!(wasm_vfs_read(zero, buf.as_mut_ptr(), 16), 16);
+    assert_eq!(buf, [0u8; 16]);
+    let null = wasm_vfs_open(c(b"/dev/null\0"), O_RDWR, 0);
+    assert_eq!(wasm_vfs_read(null, buf.as_mut_ptr(), 16), 0);
+    assert_eq!(wasm_vfs_write(null, buf.as_ptr(), 16), 16);
+    assert_eq!(wasm_vfs_lseek(null, 10, SEEK_SET), 0);
+    let full = wasm_vfs_open(c(b"/dev/full\0"), O_RDWR, 0);
+    assert_eq!(wasm_vfs_write(full, buf.as_ptr(), 16), -1);
+    assert_eq!(wasm_vfs_errno(), ENOSPC);
+
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_stat(c(b"/dev/urandom\0"), &mut st), 0);
+    assert_eq!(st.st_mode, S_IFCHR | 0o666);
+    assert_eq!(st.st_rdev, device::makedev(1, 9));
+
+    // /dev/tty needs a terminal attached
+    assert_eq!(wasm_vfs_open(c(b"/dev/tty\0"), O_RDWR, 0), -1);
+}
@@ -0,0 +1,98 @@
+use super::*;
+
+// A block device over a fixed buffer that counts its closes
+struct Disk {
+    data: Vec<u8>,
+    closes: std::rc::Rc<std::cell::Cell<u32>>,
+}
+
+impl device::Device for Disk {
+    fn read(&mut self, _minor: u32, buf: &mut [u8], offset: u64) -> Result<usize, i32> {
+        let offset = min(offset as usize, self.data.len());
+        let n = min(buf.len(), self.data.len() - offset);
+        buf[..n].copy_from_slice(&self.data[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn write(&mut self, _minor: u32, data: &[u8], offset: u64) -> Result<usize, i32> {
+        let offset = min(offset as usize, self.data.len());
+        let n = min(data.len(), self.data.len() - offset);
+        self.data[offset..offset + n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn ioctl(&mut self, minor: u32, request: u32, _arg: *mut u8) -> Result<i32, i32> {
+        if request == 0x1234 { Ok(minor as i32) } else { Err(ENOTTY) }
+    }
+
+    fn close(&mut self, _minor: u32) {
+        self.closes.set(self.closes.get() + 1);
+    }
+
+    fn size(&self, _minor: u32) -> u64 {
+        self.data.len() as u64
+    }
+}
+
+#[test]
+fn registered_drivers_back_device_nodes() {
+    let _serial = fresh();
+    let closes = std::rc::Rc::new(std::cell::Cell::new(0));
+    assert_eq!(wasm_vfs_mknod(c(b"/sda\0"), S_IFBLK | 0o660, device::makedev(8, 1)), 0);
+    assert_eq!(wasm_vfs_open(c(b"/sda\0"), O_RDWR, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENXIO);
+
+    let disk = Disk { data: vec![0; 100], closes: closes.clone() };
+    get_or_init_proc().fs.borrow_mut().register_block_device(8, None, Box::new(disk));
+    let fd = wasm_vfs_open(c(b"/sda\0"), O_RDWR, 0);
+    assert!(fd >= 0);
+    write_all(fd, b"hello");
+    assert_eq!(wasm_vfs_lseek(fd, 0, SEEK_CUR), 5);
+    assert_eq!(wasm_vfs_lseek(fd, -10, SEEK_END), 90);
+    let mut buf = [0u8; 5];
+    assert_eq!(wasm_vfs_pread64(fd, buf.as_mut_ptr(), 5, 0), 5);
+    assert_eq!(&buf, b"hello");
+    assert_eq!(wasm_vfs_ioctl(fd, 0x1234, core::ptr::null_mut()), 1);
+
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_stat(c(b"/sda\0"), &mut st), 0);
+    assert_eq!(st.st_mode, S_IFBLK | 0o640);
+    assert_eq!(st.st_rdev, device::makedev(8, 1));
+
+    // The driver hears about the close once the last handle goes
+    let second = wasm_vfs_open(c(b"/sda\0"), O_RDONLY, 0);
+    let dup = wasm_vfs_dup(fd);
+    assert_eq!(wasm_vfs_close(fd), 0);
+    assert_eq!(wasm_vfs_close(second), 0);
+    assert_eq!(closes.get(), 0);
+    assert_eq!(wasm_vfs_close(dup), 0);
+    assert_eq!(closes.get(), 1);
+}
+
+#[test]
+fn mknod_reaches_the_mem_driver() {
+    let _serial = fresh();
+    assert_eq!(wasm_vfs_mknod(c(b"/null\0"), S_IFCHR | 0o666, device::makedev(1, 3)), 0);
+    let fd = wasm_vfs_open(c(b"/null\0"), O_RDWR, 0);
+    write_all(fd, b"gone");
+    assert_eq!(read_some(fd, 4), b"");
+    assert_eq!(wasm_vfs_ioctl(fd, 0x1234, core::ptr::null_mut()), -1);
+}
+
+#[test]
+fn mknod_of_devices_needs_root_and_a_writable_directory() {
+    let _serial = fresh();
+    assert_eq!(wasm_vfs_mkdir(c(b"/shut\0"), 0o555), 0);
+    assert_eq!(wasm_vfs_mknod(c(b"/shut/p\0"), S_IFIFO | 0o600, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+
+    assert_eq!(wasm_vfs_mkdir(c(b"/pub\0"), 0o777), 0);
+    assert_eq!(wasm_vfs_chmod(c(b"/pub\0"), 0o777), 0);
+    get_or_init_proc().set_credentials(1000, 1000, &[]);
+    assert_eq!(wasm_vfs_mknod(c(b"/pub/null\0"), S_IFCHR | 0o666, device::makedev(1, 3)), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_mknod(c(b"/pub/sda\0"), S_IFBLK | 0o660, device::makedev(8, 1)), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    // A FIFO needs no privilege
+    assert_eq!(wasm_vfs_mknod(c(b"/pub/p\0"), S_IFIFO | 0o600, 0), 0);
+}
@@ -0,0 +1,33 @@
+use super::*;
+
+#[test]
+fn eventfds_count_and_poll() {
+    use crate::poll::{PollFd, POLLIN, POLLOUT};
+    let _serial = fresh();
+    let efd = wasm_vfs_eventfd(3, O_NONBLOCK);
+    assert!(efd >= 0);
+    assert_eq!(read_u64(efd), Ok(3));
+    assert_eq!(read_u64(efd), Err(EAGAIN));
+    assert_eq!(write_u64(efd, 2), Ok(()));
+    assert_eq!(write_u64(efd, 5), Ok(()));
+    let mut small = [0u8; 4];
+    assert_eq!(wasm_vfs_read(efd, small.as_mut_ptr(), 4), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(write_u64(efd, u64::MAX), Err(EINVAL));
+    assert_eq!(write_u64(efd, u64::MAX - 1), Err(EAGAIN));
+    let mut fds = [PollFd { fd: efd, events: POLLIN | POLLOUT, revents: 0 }];
+    assert_eq!(wasm_vfs_poll(fds.as_mut_ptr(), 1, 0), 1);
+    assert_eq!(fds[0].revents, POLLIN | POLLOUT);
+    assert_eq!(read_u64(efd), Ok(7));
+    assert_eq!(wasm_vfs_fcntl(efd, F_GETFL, 0) & O_ACCMODE, O_RDWR);
+
+    let semaphore = wasm_vfs_eventfd2(2, crate::eventfd::EFD_SEMAPHORE);
+    assert_eq!(read_u64(semaphore), Ok(1));
+    assert_eq!(read_u64(semaphore), Ok(1));
+    assert_eq!(read_u64(semaphore), Err(EAGAIN));
+    assert_eq!(wasm_vfs_eventfd(0, 0x40000), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_close(efd), 0);
+    assert_eq!(wasm_vfs_close(semaphore), 0);
+    assert!(get_or_init_proc().eventfds.iter().next().is_none());
+}
@@ -0,0 +1,94 @@
+use super::*;
+
+fn size_and_blocks(fd: i32) -> (i64, i64) {
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_fstat(fd, &mut st), 0);
+    (st.st_size, st.st_blocks)
+}
+
+#[test]
+fn fallocate_reserves_and_punches() {
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/fa\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_fallocate(fd, 0, 0, 1 << 20), 0);
+    assert_eq!(size_and_blocks(fd), (1 << 20, (1 << 20) / 512));
+    assert_eq!(wasm_vfs_ftruncate(fd, 0), 0);
+    // FALLOC_FL_KEEP_SIZE
+    assert_eq!(wasm_vfs_fallocate(fd, 1, 0, 8192), 0);
+    assert_eq!(size_and_blocks(fd), (0, 16));
+
+    let data: Vec<u8> = (0..16384u32).map(|i| (i % 251 + 1) as u8).collect();
+    assert_eq!(wasm_vfs_pwrite64(fd, data.as_ptr(), data.len(), 0), 16384);
+    // PUNCH_HOLE needs KEEP_SIZE; unknown combinations are refused
+    assert_eq!(wasm_vfs_fallocate(fd, 2, 0, 10), -1);
+    assert_eq!(wasm_vfs_errno(), EOPNOTSUPP);
+    assert_eq!(wasm_vfs_fallocate(fd, 0x40, 0, 10), -1);
+    assert_eq!(wasm_vfs_errno(), EOPNOTSUPP);
+    assert_eq!(wasm_vfs_fallocate(fd, 3, 100, 8900), 0);
+    assert_eq!(size_and_blocks(fd), (16384, 24));
+    assert!(pread_vec(fd, 100, 8900).iter().all(|&b| b == 0));
+    assert_eq!(wasm_vfs_lseek(fd, 100, SEEK_HOLE), 4096);
+
+    // ZERO_RANGE may grow the file
+    assert_eq!(wasm_vfs_fallocate(fd, 0x10, 16000, 5000), 0);
+    assert_eq!(size_and_blocks(fd).0, 21000);
+    assert_eq!(pread_vec(fd, 15999, 1), [data[15999]]);
+}
+
+#[test]
+fn fallocate_collapses_and_inserts_pages() {
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/fa\0"), O_CREAT | O_RDWR, 0o644);
+    let data: Vec<u8> = (0..16384u32).map(|i| (i % 251 + 1) as u8).collect();
+    assert_eq!(wasm_vfs_pwrite64(fd, data.as_ptr(), data.len(), 0), 16384);
+    // COLLAPSE_RANGE
+    assert_eq!(wasm_vfs_fallocate(fd, 8, 4096, 4096), 0);
+    assert_eq!(size_and_blocks(fd).0, 12288);
+    assert_eq!(pread_vec(fd, 4096, 8192), &data[8192..]);
+    assert_eq!(wasm_vfs_fallocate(fd, 8, 8192, 4096), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_fallocate(fd, 8, 100, 4096), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    // INSERT_RANGE
+    assert_eq!(wasm_vfs_fallocate(fd, 0x20, 4096, 8192), 0);
+    assert_eq!(size_and_blocks(fd).0, 20480);
+    assert!(pread_vec(fd, 4096, 8192).iter().all(|&b| b == 0));
+    assert_eq!(pread_vec(fd, 12288, 8192), &data[8192..]);
+    assert_eq!(wasm_vfs_fallocate(fd, 0x20, 20480, 4096), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+}
+
+#[test]
+fn fallocate_beyond_the_mount_is_enospc() {
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/big\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_fallocate(fd, 0, 0, 1 << 40), -1);
+    assert_eq!(wasm_vfs_errno(), ENOSPC);
+    // Sparse growth costs nothing until written
+    assert_eq!(wasm_vfs_fallocate(fd, 0, 1 << 40, 8192), 0);
+    assert_eq!(wasm_vfs_pwrite64(fd, b"x".as_ptr(), 1, 1 << 50), 1);
+
+    assert_eq!(wasm_vfs_posix_fallocate(fd, -1, 4), EINVAL);
+    let [r, w] = pipe(0);
+    assert_eq!(wasm_vfs_posix_fallocate(w, 0, 1), ESPIPE);
+    assert_eq!(wasm_vfs_posix_fallocate(r, 0, 1), EBADF);
+}
+
+#[test]
+fn fallocate_needs_a_writable_descriptor() {
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/fa\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_ftruncate(fd, 16384), 0);
+    let ro = wasm_vfs_open(c(b"/fa\0"), O_RDONLY, 0);
+    assert_eq!(wasm_vfs_fallocate(ro, 0, 0, 4096), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+
+    // An appender may grow the file but not shift its contents
+    let app = wasm_vfs_open(c(b"/fa\0"), O_WRONLY | O_APPEND, 0);
+    assert_eq!(wasm_vfs_fallocate(app, 0, 16384, 4096), 0);
+    assert_eq!(wasm_vfs_fallocate(app, 8, 0, 4096), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_fallocate(app, 0x20, 0, 4096), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(size_and_blocks(fd).0, 20480);
+}
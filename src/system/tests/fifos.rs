@@ -0,0 +1,76 @@
+use super::*;
+
+#[test]
+fn fifo_needs_both_ends() {
+    let _serial = fresh();
+    assert_eq!(wasm_vfs_mkfifo(c(b"/ff\0"), 0o644), 0);
+    assert_eq!(wasm_vfs_mkfifo(c(b"/ff\0"), 0o644), -1);
+    assert_eq!(wasm_vfs_errno(), EEXIST);
+
+    // A non-blocking writer with no reader is ENXIO
+    assert_eq!(wasm_vfs_open(c(b"/ff\0"), O_WRONLY | O_NONBLOCK, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENXIO);
+    let r = wasm_vfs_open(c(b"/ff\0"), O_RDONLY, 0);
+    assert!(r >= 0);
+    let mut buf = [0u8; 8];
+    assert_eq!(wasm_vfs_read(r, buf.as_mut_ptr(), 8), -1);
+    assert_eq!(wasm_vfs_errno(), EAGAIN);
+
+    let w = wasm_vfs_open(c(b"/ff\0"), O_WRONLY | O_NONBLOCK, 0);
+    assert!(w >= 0);
+    write_all(w, b"abc");
+    assert_eq!(read_some(r, 8), b"abc");
+    assert_eq!(wasm_vfs_close(w), 0);
+    assert_eq!(read_some(r, 8), b"");
+
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_stat(c(b"/ff\0"), &mut st), 0);
+    assert_eq!(st.st_mode & S_IFMT, S_IFIFO);
+}
+
+#[test]
+fn mknod_makes_fifos() {
+    let _serial = fresh();
+    assert_eq!(wasm_vfs_mknod(c(b"/nod\0"), S_IFIFO | 0o600, 0), 0);
+    let r = wasm_vfs_open(c(b"/nod\0"), O_RDWR, 0);
+    assert!(r >= 0);
+    write_all(r, b"loop");
+    assert_eq!(read_some(r, 8), b"loop");
+}
+
+#[test]
+fn fifo_open_fails_when_the_pipe_table_is_full() {
+    let _serial = fresh();
+    assert_eq!(wasm_vfs_mkfifo(c(b"/ff\0"), 0o644), 0);
+    let mut fds = [0; 2];
+    while wasm_vfs_pipe2(fds.as_mut_ptr(), 0) == 0 {}
+    assert_eq!(wasm_vfs_open(c(b"/ff\0"), O_RDONLY, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENFILE);
+}
+
+#[test]
+fn fifo_connects_two_procs() {
+    let _serial = fresh();
+    assert_eq!(wasm_vfs_mkfifo(c(b"/chan\0"), 0o666), 0);
+    let r = wasm_vfs_open(c(b"/chan\0"), O_RDONLY | O_NONBLOCK, 0);
+    assert!(r >= 0);
+
+    // The reader here is the peer a writer in the other process needs
+    let reader = switch_to(sibling());
+    let w = wasm_vfs_open(c(b"/chan\0"), O_WRONLY | O_NONBLOCK, 0);
+    assert!(w >= 0);
+    write_all(w, b"hello");
+    let writer = switch_to(reader);
+    assert_eq!(read_some(r, 16), b"hello");
+    let mut buf = [0u8; 8];
+    assert_eq!(wasm_vfs_read(r, buf.as_mut_ptr(), 8), -1);
+    assert_eq!(wasm_vfs_errno(), EAGAIN);
+
+    // The writer's process going away closes its end
+    drop(writer);
+    assert_eq!(read_some(r, 16), b"");
+    assert_eq!(wasm_vfs_close(r), 0);
+    let _reader = switch_to(sibling());
+    assert_eq!(wasm_vfs_open(c(b"/chan\0"), O_WRONLY | O_NONBLOCK, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENXIO);
+}
@@ -0,0 +1,44 @@
+use super::*;
+
+#[test]
+fn statfs_reports_each_mount() {
+    use crate::statfs::{Statfs, Statvfs, PROC_SUPER_MAGIC, ST_NODEV, ST_NOSUID, ST_VALID, TMPFS_MAGIC};
+    let _serial = fresh();
+    get_or_init_proc().mount_procfs();
+    let fd = wasm_vfs_open(c(b"/sf.dat\0"), O_CREAT | O_RDWR, 0o644);
+    let mut before = Statfs::default();
+    assert_eq!(wasm_vfs_fstatfs(fd, &mut before), 0);
+    assert_eq!(before.f_type as u64, TMPFS_MAGIC);
+    assert_eq!(before.f_bsize, 4096);
+    assert!(before.f_flags as u64 & ST_VALID != 0);
+    assert_eq!(wasm_vfs_pwrite64(fd, b"x".as_ptr(), 1, 3 * 4096), 1);
+    let mut after = Statfs::default();
+    assert_eq!(wasm_vfs_statfs(c(b"/sf.dat\0"), &mut after), 0);
+    assert_eq!(before.f_bfree - after.f_bfree, 1);
+
+    let mut shm = Statvfs::default();
+    assert_eq!(wasm_vfs_statvfs(c(b"/dev/shm\0"), &mut shm), 0);
+    assert_eq!(shm.f_flag as u64, ST_NOSUID | ST_NODEV);
+    assert_ne!(shm.f_fsid, 0);
+    let mut proc_fs = Statfs::default();
+    assert_eq!(wasm_vfs_statfs(c(b"/proc/self\0"), &mut proc_fs), 0);
+    assert_eq!(proc_fs.f_type as u64, PROC_SUPER_MAGIC);
+    assert_eq!(proc_fs.f_blocks, 0);
+
+    assert_eq!(wasm_vfs_statfs(c(b"/nope\0"), &mut proc_fs), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    assert_eq!(wasm_vfs_fstatvfs(999, &mut shm), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+
+    // Limits on a mount show up as its size
+    {
+        let mut proc = get_or_init_proc();
+        assert!(proc.fs.borrow_mut().set_mount_limits(&PathBuf::from("/"), Some(1 << 20), Some(100)));
+        let usage = proc.fs.borrow().usage_of(&PathBuf::from("/sf.dat"));
+        assert_eq!((usage.bytes_total, usage.inodes_total), (1 << 20, 100));
+        assert!(usage.bytes_used >= 4096);
+    }
+    let mut limited = Statvfs::default();
+    assert_eq!(wasm_vfs_fstatvfs(fd, &mut limited), 0);
+    assert_eq!((limited.f_blocks, limited.f_files), (256, 100));
+}
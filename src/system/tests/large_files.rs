@@ -0,0 +1,26 @@
+use super::*;
+
+#[test]
+fn files_grow_past_4gib() {
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/big\0"), O_CREAT | O_RDWR, 0o644);
+    let off: i64 = 6 << 30;
+    assert_eq!(wasm_vfs_pwrite64(fd, b"tail".as_ptr(), 4, off), 4);
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_fstat(fd, &mut st), 0);
+    assert_eq!(st.st_size, off + 4);
+    let mut buf = [9u8; 8];
+    assert_eq!(wasm_vfs_pread64(fd, buf.as_mut_ptr(), 8, off - 4), 8);
+    assert_eq!(&buf, b"\0\0\0\0tail");
+
+    assert_eq!(wasm_vfs_lseek(fd, 0, SEEK_END), off + 4);
+    write_all(fd, b"more");
+    assert_eq!(wasm_vfs_lseek(fd, 0, SEEK_CUR), off + 8);
+    assert_eq!(wasm_vfs_ftruncate(fd, off + 2), 0);
+    assert_eq!(wasm_vfs_ftruncate(fd, off + 8), 0);
+    assert_eq!(wasm_vfs_pread64(fd, buf.as_mut_ptr(), 8, off), 8);
+    assert_eq!(&buf, b"ta\0\0\0\0\0\0");
+
+    assert_eq!(wasm_vfs_pwrite64(fd, b"x".as_ptr(), 1, i64::MAX), -1);
+    assert_eq!(wasm_vfs_errno(), EFBIG);
+}
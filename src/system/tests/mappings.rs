@@ -0,0 +1,122 @@
+use super::*;
+
+#[test]
+fn shared_maps_write_back_on_msync_munmap_and_close() {
+    use crate::mmap::{MAP_PRIVATE, MAP_SHARED, MS_ASYNC, MS_SYNC, PROT_READ, PROT_WRITE};
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/m\0"), O_CREAT | O_RDWR, 0o644);
+    let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+    write_all(fd, &data);
+    let p = wasm_vfs_mmap(core::ptr::null_mut(), 10000, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    let shared = mapped(p, 12288);
+    assert_eq!(p as usize % 4096, 0);
+    assert_eq!(&shared[..10000], &data[..]);
+    assert!(shared[10000..].iter().all(|&b| b == 0));
+    let q = wasm_vfs_mmap(core::ptr::null_mut(), 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, 4096);
+    let private = mapped(q, 4096);
+    let other = mapped(wasm_vfs_mmap(core::ptr::null_mut(), 4096, PROT_READ, MAP_SHARED, fd, 4096), 4096);
+
+    // Stores reach the file (and other shared maps) at msync; the
+    // tail past EOF never does
+    shared[4096] = 0xEE;
+    shared[11000] = 1;
+    assert_eq!(pread_vec(fd, 4096, 1), [data[4096]]);
+    assert_eq!(wasm_vfs_msync(p, 8192, MS_SYNC), 0);
+    assert_eq!(pread_vec(fd, 4096, 1), [0xEE]);
+    assert_eq!(other[0], 0xEE);
+    assert_eq!(private[0], data[4096]);
+    assert_eq!(pread_vec(fd, 0, 20000).len(), 10000);
+
+    private[1] = 0x11;
+    assert_eq!(wasm_vfs_munmap(q, 4096), 0);
+    assert_eq!(pread_vec(fd, 4097, 1), [data[4097]]);
+    // write() shows up in shared maps
+    assert_eq!(wasm_vfs_pwrite64(fd, b"XY".as_ptr(), 2, 4100), 2);
+    assert_eq!(&shared[4100..4102], b"XY");
+
+    shared[0] = 0x42;
+    assert_eq!(wasm_vfs_munmap(p, 4096), 0);
+    assert_eq!(pread_vec(fd, 0, 1), [0x42]);
+    assert_eq!(wasm_vfs_msync(p, 4096, MS_SYNC), -1);
+    assert_eq!(wasm_vfs_errno(), ENOMEM);
+    assert_eq!(wasm_vfs_msync(unsafe { p.add(4096) }, 4096, MS_SYNC | MS_ASYNC), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+
+    shared[8192] = 0x55;
+    assert_eq!(wasm_vfs_close(fd), 0);
+    let fd = wasm_vfs_open(c(b"/m\0"), O_RDONLY, 0);
+    assert_eq!(pread_vec(fd, 8192, 1), [0x55]);
+}
+
+#[test]
+fn mremap_and_mprotect_work_on_whole_pages() {
+    use crate::mmap::{MAP_SHARED, MREMAP_MAYMOVE, PROT_READ, PROT_WRITE};
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/m\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_ftruncate(fd, 20000), 0);
+    assert_eq!(wasm_vfs_pwrite64(fd, b"Z".as_ptr(), 1, 16384), 1);
+    let p = wasm_vfs_mmap(core::ptr::null_mut(), 8192, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    mapped(p, 8192)[0] = 0xEE;
+
+    // Growing in place is not possible without MREMAP_MAYMOVE
+    assert_eq!(wasm_vfs_mremap(p, 8192, 20480, 0, core::ptr::null_mut()), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), ENOMEM);
+    let r = wasm_vfs_mremap(p, 8192, 20480, MREMAP_MAYMOVE, core::ptr::null_mut());
+    let grown = mapped(r, 20480);
+    assert_eq!((grown[0], grown[16384]), (0xEE, b'Z'));
+
+    assert_eq!(wasm_vfs_mremap(r, 20480, 4096, 0, core::ptr::null_mut()), r);
+    assert_eq!(wasm_vfs_mprotect(r, 4096, PROT_READ), 0);
+    assert_eq!(wasm_vfs_mprotect(unsafe { r.add(4096) }, 4096, PROT_READ), -1);
+    assert_eq!(wasm_vfs_munmap(r, 4096), 0);
+    assert!(get_or_init_proc().mappings.is_empty());
+}
+
+#[test]
+fn mmap_checks_its_arguments() {
+    use crate::mmap::{MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MAP_SHARED, MAP_SHARED_VALIDATE, PROT_READ, PROT_WRITE};
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/m\0"), O_CREAT | O_RDWR, 0o644);
+    write_all(fd, b"file");
+    let anon = wasm_vfs_mmap(core::ptr::null_mut(), 100, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    let bytes = mapped(anon, 4096);
+    assert!(bytes.iter().all(|&b| b == 0));
+    // MAP_FIXED replaces part of an existing mapping
+    assert_eq!(wasm_vfs_mmap(anon, 4096, PROT_READ, MAP_SHARED | MAP_FIXED, fd, 0), anon);
+    assert_eq!(&bytes[..4], b"file");
+
+    let none = core::ptr::null_mut();
+    assert_eq!(wasm_vfs_mmap(none, 0, PROT_READ, MAP_SHARED, fd, 0), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_mmap(none, 10, PROT_READ, MAP_SHARED, fd, 100), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_mmap(none, 10, PROT_READ, MAP_SHARED, 999, 0), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_mmap(none, 10, PROT_READ, MAP_SHARED_VALIDATE | 0x100000, fd, 0), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), EOPNOTSUPP);
+    let [r, _w] = pipe(0);
+    assert_eq!(wasm_vfs_mmap(none, 10, PROT_READ, MAP_SHARED, r, 0), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), ENODEV);
+}
+
+#[test]
+fn mmap_needs_matching_access() {
+    use crate::mmap::{MAP_PRIVATE, MAP_SHARED, PROT_READ, PROT_WRITE};
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/m\0"), O_CREAT | O_RDWR, 0o644);
+    write_all(fd, b"file");
+    let ro = wasm_vfs_open(c(b"/m\0"), O_RDONLY, 0);
+    let wo = wasm_vfs_open(c(b"/m\0"), O_WRONLY, 0);
+    let none = core::ptr::null_mut();
+    assert_eq!(wasm_vfs_mmap(none, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, ro, 0), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_mmap(none, 4096, PROT_READ, MAP_SHARED, wo, 0), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_mmap(none, 4096, PROT_READ, MAP_PRIVATE, wo, 0), mmap::MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    // A private copy may be written whatever the descriptor allows
+    let private = wasm_vfs_mmap(none, 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE, ro, 0);
+    assert_eq!(&mapped(private, 4)[..], b"file");
+    let view = wasm_vfs_mmap(none, 4096, PROT_READ, MAP_SHARED, ro, 0);
+    assert_eq!(&mapped(view, 4)[..], b"file");
+}
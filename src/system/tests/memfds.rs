@@ -0,0 +1,142 @@
+use super::*;
+
+fn fstat_of(fd: i32) -> Stat {
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_fstat(fd, &mut st), 0);
+    st
+}
+
+#[test]
+fn memfd_seals_hold_against_writes_resizes_and_maps() {
+    use crate::memfd::{F_SEAL_GROW, F_SEAL_SEAL, F_SEAL_SHRINK, F_SEAL_WRITE, MFD_ALLOW_SEALING, MFD_CLOEXEC};
+    use crate::mmap::{MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+    let _serial = fresh();
+    let none = core::ptr::null_mut();
+    let m = wasm_vfs_memfd_create(c(b"buf\0"), MFD_ALLOW_SEALING | MFD_CLOEXEC);
+    assert!(m >= 0);
+    assert_eq!(wasm_vfs_fcntl(m, F_GETFD, 0), FD_CLOEXEC);
+    write_all(m, b"hello");
+    let st = fstat_of(m);
+    assert_eq!((st.st_nlink, st.st_size, st.st_mode), (0, 5, 0o100777));
+    assert_eq!(get_or_init_proc().fd_link_target(m).to_string_lossy(), "/memfd:buf (deleted)");
+
+    assert_eq!(wasm_vfs_fcntl(m, F_GET_SEALS, 0), 0);
+    assert_eq!(wasm_vfs_fcntl(m, F_ADD_SEALS, (F_SEAL_GROW | F_SEAL_SHRINK) as i32), 0);
+    assert_eq!(wasm_vfs_write(m, b"!".as_ptr(), 1), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_pwrite64(m, b"HE".as_ptr(), 2, 0), 2);
+    assert_eq!(wasm_vfs_pwrite64(m, b"LOL".as_ptr(), 3, 3), -1);
+    assert_eq!(wasm_vfs_ftruncate(m, 2), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_ftruncate(m, 20), -1);
+    assert_eq!(wasm_vfs_ftruncate(m, 5), 0);
+    assert_eq!(wasm_vfs_fallocate(m, 0, 0, 100), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+
+    // F_SEAL_WRITE waits for writable shared maps to go
+    let addr = wasm_vfs_mmap(none, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, m, 0);
+    assert_ne!(addr, MAP_FAILED);
+    assert_eq!(wasm_vfs_fcntl(m, F_ADD_SEALS, F_SEAL_WRITE as i32), -1);
+    assert_eq!(wasm_vfs_errno(), EBUSY);
+    assert_eq!(wasm_vfs_munmap(addr, 4096), 0);
+    assert_eq!(wasm_vfs_fcntl(m, F_ADD_SEALS, F_SEAL_WRITE as i32), 0);
+    assert_eq!(wasm_vfs_pwrite64(m, b"x".as_ptr(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_mmap(none, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, m, 0), MAP_FAILED);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    let ro = wasm_vfs_mmap(none, 4096, PROT_READ, MAP_SHARED, m, 0);
+    assert_ne!(ro, MAP_FAILED);
+    assert_eq!(&mapped(ro, 5)[..], b"HEllo");
+    assert_eq!(wasm_vfs_mprotect(ro, 4096, PROT_READ | PROT_WRITE), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_munmap(ro, 4096), 0);
+
+    assert_eq!(wasm_vfs_fcntl(m, F_ADD_SEALS, F_SEAL_SEAL as i32), 0);
+    assert_eq!(wasm_vfs_fcntl(m, F_ADD_SEALS, F_SEAL_GROW as i32), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_fcntl(m, F_GET_SEALS, 0) as u32, F_SEAL_SEAL | F_SEAL_GROW | F_SEAL_SHRINK | F_SEAL_WRITE);
+    let ino = fstat_of(m).st_ino;
+    assert_eq!(wasm_vfs_close(m), 0);
+    assert!(!get_or_init_proc().fs.borrow().files.contains_key(&ino));
+
+    let unsealable = wasm_vfs_memfd_create(c(b"buf\0"), 0);
+    assert_eq!(wasm_vfs_fcntl(unsealable, F_GET_SEALS, 0) as u32, F_SEAL_SEAL);
+    assert_eq!(wasm_vfs_fcntl(unsealable, F_ADD_SEALS, F_SEAL_GROW as i32), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_memfd_create(c(b"buf\0"), 0x100), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    let plain = wasm_vfs_open(c(b"/plain\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_fcntl(plain, F_ADD_SEALS, F_SEAL_SEAL as i32), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+}
+
+#[test]
+fn copies_respect_seals_on_the_target() {
+    let _serial = fresh();
+    let src = wasm_vfs_open(c(b"/src\0"), O_CREAT | O_RDWR, 0o644);
+    write_all(src, &[1u8; 100]);
+    let sealed = wasm_vfs_memfd_create(c(b"m\0"), memfd::MFD_ALLOW_SEALING);
+    assert_eq!(wasm_vfs_fcntl(sealed, F_ADD_SEALS, memfd::F_SEAL_WRITE as i32), 0);
+    let mut off = 0i64;
+    assert_eq!(wasm_vfs_sendfile(sealed, src, &mut off, 100), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    off = 0;
+    assert_eq!(wasm_vfs_copy_file_range(src, &mut off, sealed, core::ptr::null_mut(), 100, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+}
+
+#[test]
+fn tmpfiles_stay_unnamed_until_linked() {
+    let _serial = fresh();
+    let empty = c(b"\0");
+    assert_eq!(wasm_vfs_mkdir(c(b"/tmpd\0"), 0o755), 0);
+    let t = wasm_vfs_open(c(b"/tmpd\0"), O_TMPFILE | O_RDWR, 0o666);
+    assert!(t >= 0);
+    write_all(t, b"data");
+    let st = fstat_of(t);
+    assert_eq!((st.st_nlink, st.st_mode), (0, 0o100644));
+    assert!(get_or_init_proc().fd_link_target(t).to_string_lossy().starts_with("/tmpd/#"));
+    assert_eq!(wasm_vfs_linkat(t, empty, AT_FDCWD, c(b"/tmpd/named\0"), AT_EMPTY_PATH), 0);
+    assert_eq!(fstat_of(t).st_nlink, 1);
+    assert_eq!(wasm_vfs_linkat(t, empty, AT_FDCWD, c(b"/tmpd/named\0"), AT_EMPTY_PATH), -1);
+    assert_eq!(wasm_vfs_errno(), EEXIST);
+    assert_eq!(wasm_vfs_linkat(t, empty, AT_FDCWD, c(b"/tmpd/again\0"), AT_EMPTY_PATH), 0);
+    assert_eq!(fstat_of(t).st_nlink, 2);
+    let named = wasm_vfs_open(c(b"/tmpd/named\0"), O_RDONLY, 0);
+    assert_eq!(read_some(named, 4), b"data");
+
+    assert_eq!(wasm_vfs_open(c(b"/tmpd\0"), O_TMPFILE | O_RDONLY, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    let plain = wasm_vfs_open(c(b"/plain\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_open(c(b"/plain\0"), O_TMPFILE | O_RDWR, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENOTDIR);
+    let excl = wasm_vfs_open(c(b"/tmpd\0"), O_TMPFILE | O_RDWR | O_EXCL, 0o600);
+    assert_eq!(wasm_vfs_linkat(excl, empty, AT_FDCWD, c(b"/tmpd/nx\0"), AT_EMPTY_PATH), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    // An unlinked file can't be brought back
+    assert_eq!(wasm_vfs_unlink(c(b"/plain\0")), 0);
+    assert_eq!(fstat_of(plain).st_nlink, 0);
+    assert_eq!(wasm_vfs_linkat(plain, empty, AT_FDCWD, c(b"/tmpd/nx\0"), AT_EMPTY_PATH), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+
+    assert_eq!(wasm_vfs_close(t), 0);
+    assert!(get_or_init_proc().fs.borrow().lookup_inode_by_path(&PathBuf::from("/tmpd/named")).is_some());
+    let ino = fstat_of(named).st_ino;
+    assert!(get_or_init_proc().fs.borrow().files.contains_key(&ino));
+}
+
+#[test]
+fn memfd_and_tmpfile_tables_run_out_with_enfile() {
+    let _serial = fresh();
+    for _ in 0..MEMFD_CAP {
+        assert!(wasm_vfs_memfd_create(c(b"m\0"), 0) >= 0);
+    }
+    assert_eq!(wasm_vfs_memfd_create(c(b"m\0"), 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENFILE);
+    wasm_vfs_mkdir(c(b"/tmpd\0"), 0o755);
+    for _ in 0..TMPFILE_CAP {
+        assert!(wasm_vfs_open(c(b"/tmpd\0"), O_TMPFILE | O_RDWR, 0o600) >= 0);
+    }
+    assert_eq!(wasm_vfs_open(c(b"/tmpd\0"), O_TMPFILE | O_RDWR, 0o600), -1);
+    assert_eq!(wasm_vfs_errno(), ENFILE);
+}
@@ -0,0 +1,109 @@
+use super::*;
+use std::sync::{Mutex as TestLock, MutexGuard as TestGuard};
+
+mod pipes;
+mod fifos;
+mod streams;
+mod terminal;
+mod dev_nodes;
+mod drivers;
+mod proc_files;
+mod providers;
+mod vectored_io;
+mod reflinks;
+mod large_files;
+mod sparse_files;
+mod fallocate;
+mod mappings;
+mod xattrs;
+mod acls;
+mod watches;
+mod change_feed;
+mod polling;
+mod eventfds;
+mod timerfds;
+mod objects;
+mod memfds;
+mod shm;
+mod fs_stats;
+mod quotas;
+
+// The exports all share the global process: take turns, and start
+// every test from a fresh one.
+static SERIAL: TestLock<()> = TestLock::new(());
+
+fn fresh() -> TestGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    *get_or_init_proc() = Proc::new();
+    guard
+}
+
+// Another process on the current one's filesystem
+fn sibling() -> Proc {
+    Proc::with_filesystem(Rc::clone(&get_or_init_proc().fs))
+}
+
+// Makes `proc` the one the exports act on, handing back the previous one
+fn switch_to(proc: Proc) -> Proc {
+    core::mem::replace(&mut *get_or_init_proc(), proc)
+}
+
+fn c(path: &[u8]) -> *const i8 {
+    path.as_ptr() as *const i8
+}
+
+fn pipe(flags: i32) -> [i32; 2] {
+    let mut fds = [0; 2];
+    assert_eq!(wasm_vfs_pipe2(fds.as_mut_ptr(), flags), 0);
+    fds
+}
+
+fn write_all(fd: i32, data: &[u8]) {
+    assert_eq!(wasm_vfs_write(fd, data.as_ptr(), data.len()), data.len() as isize);
+}
+
+fn read_some(fd: i32, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    let n = wasm_vfs_read(fd, buf.as_mut_ptr(), len);
+    assert!(n >= 0, "read failed with errno {}", wasm_vfs_errno());
+    buf.truncate(n as usize);
+    buf
+}
+
+fn inode_of(fd: i32) -> u64 {
+    get_or_init_proc().open_files.get(&fd).unwrap().inode_number
+}
+
+fn shared(a: i32, b: i32) -> bool {
+    let (a, b) = (inode_of(a), inode_of(b));
+    let proc = get_or_init_proc();
+    let fs = proc.fs.borrow();
+    fs.files.get(&a).unwrap().shares_with(fs.files.get(&b).unwrap())
+}
+
+fn pread_vec(fd: i32, offset: i64, len: usize) -> Vec<u8> {
+    let mut buf = vec![0u8; len];
+    let n = wasm_vfs_pread64(fd, buf.as_mut_ptr(), len, offset);
+    buf.truncate(n as usize);
+    buf
+}
+
+fn mapped<'a>(addr: *mut u8, len: usize) -> &'a mut [u8] {
+    assert_ne!(addr, mmap::MAP_FAILED);
+    unsafe { core::slice::from_raw_parts_mut(addr, len) }
+}
+
+fn read_u64(fd: i32) -> Result<u64, i32> {
+    let mut buf = [0u8; 8];
+    match wasm_vfs_read(fd, buf.as_mut_ptr(), 8) {
+        8 => Ok(u64::from_ne_bytes(buf)),
+        _ => Err(wasm_vfs_errno()),
+    }
+}
+
+fn write_u64(fd: i32, value: u64) -> Result<(), i32> {
+    match wasm_vfs_write(fd, value.to_ne_bytes().as_ptr(), 8) {
+        8 => Ok(()),
+        _ => Err(wasm_vfs_errno()),
+    }
+}
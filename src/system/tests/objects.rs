@@ -0,0 +1,114 @@
+use super::*;
+
+// A socket-like object: what is written comes back on read
+struct Loopback {
+    buf: Vec<u8>,
+    closed: std::rc::Rc<core::cell::Cell<u32>>,
+}
+
+impl FdObject for Loopback {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize, i32> {
+        if self.buf.is_empty() {
+            return Err(EAGAIN);
+        }
+        let n = out.len().min(self.buf.len());
+        out[..n].copy_from_slice(&self.buf[..n]);
+        self.buf.drain(..n);
+        Ok(n)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn poll(&mut self) -> i16 {
+        use crate::poll::{POLLIN, POLLOUT};
+        if self.buf.is_empty() { POLLOUT } else { POLLIN | POLLOUT }
+    }
+
+    fn ioctl(&mut self, request: u32, arg: *mut u8) -> Result<i32, i32> {
+        if request != FIONREAD {
+            return Err(ENOTTY);
+        }
+        unsafe { *(arg as *mut i32) = self.buf.len() as i32 };
+        Ok(0)
+    }
+
+    fn close(&mut self) {
+        self.closed.set(self.closed.get() + 1);
+    }
+
+    fn stat(&self, stat: &mut Stat) {
+        stat.st_mode = 0o140000 | 0o777;
+    }
+}
+
+struct Null;
+
+impl FdObject for Null {
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, i32> {
+        Ok(0)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        Ok(data.len())
+    }
+}
+
+#[test]
+fn objects_behave_like_descriptors() {
+    use crate::poll::{EpollEvent, PollFd, EPOLLIN, EPOLL_CTL_ADD, POLLIN};
+    let _serial = fresh();
+    let closed = std::rc::Rc::new(core::cell::Cell::new(0));
+    let loopback = Loopback { buf: Vec::new(), closed: closed.clone() };
+    let fd = get_or_init_proc().install_object(Box::new(loopback), O_NONBLOCK).unwrap();
+    assert_eq!(get_or_init_proc().install_object(Box::new(Null), O_APPEND).err(), Some(EINVAL));
+    let mut buf = [0u8; 8];
+    assert_eq!(wasm_vfs_read(fd, buf.as_mut_ptr(), 8), -1);
+    assert_eq!(wasm_vfs_errno(), EAGAIN);
+    let mut fds = [PollFd { fd, events: POLLIN, revents: 0 }];
+    assert_eq!(wasm_vfs_poll(fds.as_mut_ptr(), 1, 0), 0);
+    write_all(fd, b"hello");
+    assert_eq!(wasm_vfs_poll(fds.as_mut_ptr(), 1, 0), 1);
+    let mut pending = 0i32;
+    assert_eq!(wasm_vfs_ioctl(fd, FIONREAD, &mut pending as *mut i32 as *mut u8), 0);
+    assert_eq!(pending, 5);
+    assert_eq!(wasm_vfs_ioctl(fd, TIOCGWINSZ, buf.as_mut_ptr()), -1);
+    assert_eq!(wasm_vfs_errno(), ENOTTY);
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_fstat(fd, &mut st), 0);
+    assert_eq!(st.st_mode, 0o140777);
+    assert_eq!(wasm_vfs_fstat(fd, core::ptr::null_mut()), -1);
+    assert_eq!(wasm_vfs_errno(), EFAULT);
+    assert_eq!(wasm_vfs_fcntl(fd, F_GETFL, 0), O_RDWR | O_NONBLOCK);
+
+    assert_eq!(wasm_vfs_dup2(fd, 40), 40);
+    assert_eq!(read_some(40, 3), b"hel");
+    assert_eq!(wasm_vfs_pread64(40, buf.as_mut_ptr(), 3, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ESPIPE);
+    let ep = wasm_vfs_epoll_create1(0);
+    let mut event = EpollEvent { events: EPOLLIN, data: 1 };
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_ADD, 40, &mut event), 0);
+    let mut out = [EpollEvent::default(); 1];
+    assert_eq!(wasm_vfs_epoll_wait(ep, out.as_mut_ptr(), 1, 0), 1);
+
+    assert_eq!(get_or_init_proc().object_as::<Loopback>(fd).unwrap().buf, b"lo");
+    assert_eq!(get_or_init_proc().object_as::<Null>(fd).err(), Some(EINVAL));
+    assert_eq!(get_or_init_proc().object_mut(ep).err(), Some(EINVAL));
+    // Closed with the last descriptor, and only then
+    assert_eq!(wasm_vfs_close(fd), 0);
+    assert_eq!(closed.get(), 0);
+    assert_eq!(wasm_vfs_close(40), 0);
+    assert_eq!(closed.get(), 1);
+    assert_eq!(get_or_init_proc().object_mut(40).err(), Some(EBADF));
+}
+
+#[test]
+fn install_object_runs_out_with_enfile() {
+    let _serial = fresh();
+    for _ in 0..OBJECTS_CAP {
+        assert!(get_or_init_proc().install_object(Box::new(Null), 0).is_ok());
+    }
+    assert_eq!(get_or_init_proc().install_object(Box::new(Null), 0).err(), Some(ENFILE));
+}
@@ -0,0 +1,124 @@
+use super::*;
+
+#[test]
+fn pipe_carries_data_until_the_writer_closes() {
+    let _serial = fresh();
+    let [r, w] = pipe(0);
+    write_all(w, b"ping");
+    assert_eq!(read_some(r, 16), b"ping");
+    let mut buf = [0u8; 4];
+    assert_eq!(wasm_vfs_read(r, buf.as_mut_ptr(), 4), -1);
+    assert_eq!(wasm_vfs_errno(), EAGAIN);
+    assert_eq!(wasm_vfs_read(w, buf.as_mut_ptr(), 4), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_close(w), 0);
+    assert_eq!(read_some(r, 4), b"");
+}
+
+#[test]
+fn pipe_without_a_reader_is_epipe() {
+    let _serial = fresh();
+    let [r, w] = pipe(0);
+    assert_eq!(wasm_vfs_close(r), 0);
+    assert_eq!(wasm_vfs_write(w, b"x".as_ptr(), 1), -1);
+    assert_eq!(wasm_vfs_errno(), EPIPE);
+}
+
+#[test]
+fn pipe2_rejects_unknown_flags_and_a_full_table() {
+    let _serial = fresh();
+    let mut fds = [0; 2];
+    assert_eq!(wasm_vfs_pipe2(fds.as_mut_ptr(), O_APPEND), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    let mut made = 0;
+    while wasm_vfs_pipe2(fds.as_mut_ptr(), 0) == 0 {
+        made += 1;
+    }
+    assert_eq!(wasm_vfs_errno(), ENFILE);
+    assert!(made > 0);
+}
+
+#[test]
+fn splice_moves_file_data_through_a_pipe() {
+    let _serial = fresh();
+    let src = wasm_vfs_open(c(b"/src\0"), O_CREAT | O_RDWR, 0o644);
+    let dst = wasm_vfs_open(c(b"/dst\0"), O_CREAT | O_RDWR, 0o644);
+    write_all(src, b"0123456789");
+    let [r, w] = pipe(0);
+
+    let mut off: i64 = 2;
+    assert_eq!(wasm_vfs_splice(src, &mut off, w, core::ptr::null_mut(), 5, 0), 5);
+    assert_eq!(off, 7);
+    assert_eq!(wasm_vfs_splice(r, core::ptr::null_mut(), dst, core::ptr::null_mut(), 64, 0), 5);
+    let mut buf = [0u8; 5];
+    assert_eq!(wasm_vfs_pread64(dst, buf.as_mut_ptr(), 5, 0), 5);
+    assert_eq!(&buf, b"23456");
+
+    // Offsets only make sense on the file side
+    assert_eq!(wasm_vfs_splice(r, &mut off, dst, core::ptr::null_mut(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ESPIPE);
+    assert_eq!(wasm_vfs_splice(99, core::ptr::null_mut(), dst, core::ptr::null_mut(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+}
+
+#[test]
+fn tee_copies_without_consuming() {
+    let _serial = fresh();
+    let [r1, w1] = pipe(0);
+    let [r2, w2] = pipe(0);
+    write_all(w1, b"dup me");
+    assert_eq!(wasm_vfs_tee(r1, w2, 64, 0), 6);
+    assert_eq!(read_some(r2, 16), b"dup me");
+    assert_eq!(read_some(r1, 16), b"dup me");
+
+    let file = wasm_vfs_open(c(b"/f\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_tee(r1, file, 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+}
+
+#[test]
+fn fcntl_getfl_reports_the_access_mode() {
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/g\0"), O_CREAT | O_WRONLY | O_APPEND, 0o644);
+    assert_eq!(wasm_vfs_fcntl(fd, F_GETFL, 0), O_WRONLY | O_APPEND);
+    let fd = wasm_vfs_open(c(b"/g\0"), O_RDONLY, 0);
+    assert_eq!(wasm_vfs_fcntl(fd, F_GETFL, 0), O_RDONLY);
+    let [r, w] = pipe(O_NONBLOCK);
+    assert_eq!(wasm_vfs_fcntl(r, F_GETFL, 0), O_RDONLY | O_NONBLOCK);
+    assert_eq!(wasm_vfs_fcntl(w, F_GETFL, 0), O_WRONLY | O_NONBLOCK);
+}
+
+#[test]
+fn failures_set_errno() {
+    let _serial = fresh();
+    let mut buf = [0u8; 4];
+    assert_eq!(wasm_vfs_read(77, buf.as_mut_ptr(), 4), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_pwrite64(77, buf.as_ptr(), 4, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_open(c(b"/nope\0"), O_RDONLY, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    assert_eq!(wasm_vfs_rename(c(b"/nope\0"), c(b"/x\0")), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    assert_eq!(wasm_vfs_unlink(c(b"/nope\0")), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+}
+
+#[test]
+fn vmsplice_and_sendfile_check_their_arguments() {
+    let _serial = fresh();
+    let [_r, w] = pipe(0);
+    assert_eq!(wasm_vfs_vmsplice(w, core::ptr::null(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EFAULT);
+    let data = *b"abc";
+    let iov = Iovec { iov_base: data.as_ptr() as *mut u8, iov_len: data.len() };
+    assert_eq!(wasm_vfs_vmsplice(w, &iov, IOV_MAX as usize + 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_vmsplice(w, &iov, 1, 0), 3);
+
+    let file = wasm_vfs_open(c(b"/s\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_sendfile(file, 77, core::ptr::null_mut(), 4), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_sendfile(77, file, core::ptr::null_mut(), 4), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+}
@@ -0,0 +1,108 @@
+use super::*;
+
+#[test]
+fn poll_and_select_report_readiness() {
+    use crate::poll::*;
+    let _serial = fresh();
+    let [r, w] = pipe(0);
+    let file = wasm_vfs_open(c(b"/pf\0"), O_CREAT | O_RDWR, 0o644);
+    let watch = |fd: i32, events: i16| PollFd { fd, events, revents: 0 };
+    let mut fds = [watch(r, POLLIN), watch(w, POLLOUT), watch(file, POLLIN | POLLOUT), watch(999, POLLIN), watch(-1, POLLIN)];
+    assert_eq!(wasm_vfs_poll(fds.as_mut_ptr(), 5, 0), 3);
+    let revents: Vec<i16> = fds.iter().map(|f| f.revents).collect();
+    assert_eq!(revents, [0, POLLOUT, POLLIN | POLLOUT, POLLNVAL, 0]);
+
+    // The virtual clock jumps to the deadline instead of sleeping
+    let start = clock::now(CLOCK_MONOTONIC);
+    let mut one = [watch(r, POLLIN)];
+    assert_eq!(wasm_vfs_poll(one.as_mut_ptr(), 1, 50), 0);
+    assert_eq!(clock::now(CLOCK_MONOTONIC) - start, 50_000_000);
+    assert_eq!(wasm_vfs_poll(one.as_mut_ptr(), 1, -1), 0);
+    write_all(w, b"x");
+    assert_eq!(wasm_vfs_poll(one.as_mut_ptr(), 1, -1), 1);
+    assert_eq!(one[0].revents, POLLIN);
+
+    let (mut readable, mut writable) = ([0u8; FD_SETSIZE / 8], [0u8; FD_SETSIZE / 8]);
+    fd_set(&mut readable, r as usize);
+    fd_set(&mut readable, file as usize);
+    fd_set(&mut writable, w as usize);
+    let mut tv = Timeval { tv_sec: 0, tv_usec: 0 };
+    let none = core::ptr::null_mut();
+    assert_eq!(wasm_vfs_select(FD_SETSIZE as i32, readable.as_mut_ptr(), writable.as_mut_ptr(), none, &mut tv), 3);
+    assert!(fd_isset(&readable, r as usize) && fd_isset(&writable, w as usize));
+    let mut bad = [0u8; FD_SETSIZE / 8];
+    fd_set(&mut bad, 500);
+    assert_eq!(wasm_vfs_select(FD_SETSIZE as i32, bad.as_mut_ptr(), none, none, &mut tv), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+
+    wasm_vfs_close(w);
+    let mut one = [watch(r, POLLIN)];
+    assert_eq!(wasm_vfs_poll(one.as_mut_ptr(), 1, 0), 1);
+    assert_eq!(one[0].revents & POLLHUP, POLLHUP);
+}
+
+#[test]
+fn epoll_levels_edges_and_nesting() {
+    use crate::poll::*;
+    let _serial = fresh();
+    let [r, w] = pipe(0);
+    let file = wasm_vfs_open(c(b"/pf\0"), O_CREAT | O_RDWR, 0o644);
+    let ep = wasm_vfs_epoll_create1(O_CLOEXEC);
+    assert!(ep >= 0);
+    let mut level = EpollEvent { events: EPOLLIN, data: 7 };
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_ADD, file, &mut level), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_ADD, r, &mut level), 0);
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_ADD, r, &mut level), -1);
+    assert_eq!(wasm_vfs_errno(), EEXIST);
+    let mut edge = EpollEvent { events: EPOLLOUT | EPOLLET, data: 8 };
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_ADD, w, &mut edge), 0);
+
+    let mut out = [EpollEvent::default(); 4];
+    write_all(w, b"x");
+    assert_eq!(wasm_vfs_epoll_wait(ep, out.as_mut_ptr(), 4, 0), 2);
+    assert_eq!(wasm_vfs_epoll_wait(ep, out.as_mut_ptr(), 4, 0), 1);
+    let data = out[0].data;
+    assert_eq!(data, 7);
+    read_some(r, 4);
+    assert_eq!(wasm_vfs_epoll_wait(ep, out.as_mut_ptr(), 4, 10), 0);
+
+    let mut oneshot = EpollEvent { events: EPOLLIN | EPOLLONESHOT, data: 9 };
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_MOD, r, &mut oneshot), 0);
+    write_all(w, b"y");
+    assert_eq!(wasm_vfs_epoll_wait(ep, out.as_mut_ptr(), 4, 0), 1);
+    assert_eq!(wasm_vfs_epoll_wait(ep, out.as_mut_ptr(), 4, 0), 0);
+
+    // An epoll fd is readable when its own set is, and may not
+    // end up watching itself
+    let outer = wasm_vfs_epoll_create1(0);
+    let mut nested = EpollEvent { events: EPOLLIN, data: 1 };
+    assert_eq!(wasm_vfs_epoll_ctl(outer, EPOLL_CTL_ADD, ep, &mut nested), 0);
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_ADD, outer, &mut nested), -1);
+    assert_eq!(wasm_vfs_errno(), ELOOP);
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_MOD, r, &mut level), 0);
+    assert_eq!(wasm_vfs_epoll_wait(outer, out.as_mut_ptr(), 4, 0), 1);
+
+    wasm_vfs_close(w);
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_DEL, w, core::ptr::null_mut()), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_epoll_wait(file, out.as_mut_ptr(), 4, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    wasm_vfs_close(ep);
+    wasm_vfs_close(outer);
+    assert!(get_or_init_proc().epolls.iter().next().is_none());
+}
+
+#[test]
+fn epoll_create_checks_its_arguments_and_the_table() {
+    let _serial = fresh();
+    assert_eq!(wasm_vfs_epoll_create(0), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_epoll_create1(O_NONBLOCK), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    for _ in 0..EPOLL_CAP {
+        assert!(wasm_vfs_epoll_create(1) >= 0);
+    }
+    assert_eq!(wasm_vfs_epoll_create1(0), -1);
+    assert_eq!(wasm_vfs_errno(), ENFILE);
+}
@@ -0,0 +1,90 @@
+use super::*;
+
+fn readlink(path: &str) -> Result<String, i32> {
+    let path = format!("{}\0", path);
+    let mut buf = [0i8; 256];
+    let n = wasm_vfs_readlink(c(path.as_bytes()), buf.as_mut_ptr(), buf.len());
+    if n < 0 {
+        return Err(wasm_vfs_errno());
+    }
+    Ok(buf[..n as usize].iter().map(|&b| b as u8 as char).collect())
+}
+
+fn cat(path: &[u8]) -> String {
+    let fd = wasm_vfs_open(c(path), O_RDONLY, 0);
+    assert!(fd >= 0);
+    let text = read_some(fd, 1024);
+    wasm_vfs_close(fd);
+    String::from_utf8(text).unwrap()
+}
+
+#[test]
+fn procfs_reflects_the_process() {
+    let _serial = fresh();
+    // A new Proc has no /proc until the host mounts it
+    assert_eq!(readlink("/proc/self/cwd"), Err(ENOENT));
+    get_or_init_proc().mount_procfs();
+    let f = wasm_vfs_open(c(b"/hello.txt\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(readlink(&format!("/proc/self/fd/{}", f)), Ok("/hello.txt".into()));
+    assert!(readlink("/proc/self/fd/1").unwrap().starts_with("pipe:["));
+    assert_eq!(readlink("/hello.txt"), Err(EINVAL));
+    assert_eq!(wasm_vfs_mkdir(c(b"/work\0"), 0o755), 0);
+    assert_eq!(wasm_vfs_chdir(c(b"/work\0")), 0);
+    assert_eq!(readlink("/proc/self/cwd"), Ok("/work".into()));
+
+    let mounts = cat(b"/proc/mounts\0");
+    assert!(mounts.contains("wasmvfs / wasmvfs rw 0 0\n"), "{}", mounts);
+    assert!(mounts.contains("proc /proc proc"), "{}", mounts);
+    wasm_vfs_umask(0o027);
+    assert!(cat(b"/proc/self/status\0").contains("Umask:\t0027\n"));
+
+    assert_eq!(wasm_vfs_open(c(b"/proc/mounts\0"), O_WRONLY, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+}
+
+#[test]
+fn procfs_drops_closed_fds() {
+    let _serial = fresh();
+    get_or_init_proc().mount_procfs();
+    let f = wasm_vfs_open(c(b"/a\0"), O_CREAT | O_RDWR, 0o644);
+    let d = wasm_vfs_open(c(b"/proc/self/fd\0"), O_RDONLY, 0);
+    let mut buf = vec![0u8; 64 * core::mem::size_of::<Dirent>()];
+    let n = wasm_vfs_getdents(d, buf.as_mut_ptr() as *mut Dirent, buf.len());
+    // 0, 1, 2, the file and the directory itself
+    assert_eq!(n as usize / core::mem::size_of::<Dirent>(), 5);
+
+    assert_eq!(wasm_vfs_close(f), 0);
+    let link = format!("/proc/self/fd/{}", f);
+    assert!(get_or_init_proc().fs.borrow().lookup_inode_by_path(&PathBuf::from(link.as_str())).is_none());
+    assert_eq!(readlink(&link), Err(ENOENT));
+
+    // A closed descriptor's link hands its inode to the next one
+    let next = get_or_init_proc().fs.borrow().next_inode_number;
+    for _ in 0..10 {
+        let f = wasm_vfs_open(c(b"/a\0"), O_RDWR, 0);
+        assert_eq!(readlink(&format!("/proc/self/fd/{}", f)), Ok("/a".into()));
+        assert_eq!(wasm_vfs_close(f), 0);
+    }
+    assert_eq!(get_or_init_proc().fs.borrow().next_inode_number, next);
+
+    get_or_init_proc().unmount_procfs();
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_stat(c(b"/proc/mounts\0"), &mut st), -1);
+    assert_eq!(wasm_vfs_stat(c(b"/proc\0"), &mut st), 0);
+}
+
+#[test]
+fn procfs_fails_loudly_when_the_path_map_is_full() {
+    let _serial = fresh();
+    get_or_init_proc().mount_procfs();
+    let mut i = 0;
+    while !get_or_init_proc().fs.borrow().path_map.is_full() {
+        let dir = format!("/d{}\0", i);
+        assert_eq!(wasm_vfs_mkdir(c(dir.as_bytes()), 0o755), 0);
+        i += 1;
+    }
+    // No room for the /proc/self/fd entry of a new descriptor
+    let fd = wasm_vfs_open(c(b"/d0\0"), O_RDONLY, 0);
+    assert!(fd >= 0);
+    assert_eq!(readlink(&format!("/proc/self/fd/{}", fd)), Err(ENOMEM));
+}
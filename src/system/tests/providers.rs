@@ -0,0 +1,63 @@
+use super::*;
+
+// A writable provider over an in-memory buffer
+struct Config(Vec<u8>);
+
+impl crate::provider::FileProvider for Config {
+    fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize, i32> {
+        let offset = min(offset as usize, self.0.len());
+        let n = min(buf.len(), self.0.len() - offset);
+        buf[..n].copy_from_slice(&self.0[offset..offset + n]);
+        Ok(n)
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> Result<usize, i32> {
+        let end = offset as usize + data.len();
+        if self.0.len() < end {
+            self.0.resize(end, 0);
+        }
+        self.0[offset as usize..end].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn size(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn truncate(&mut self, len: u64) -> Result<(), i32> {
+        self.0.resize(len as usize, 0);
+        Ok(())
+    }
+}
+
+#[test]
+fn providers_serve_file_content() {
+    let _serial = fresh();
+    {
+        let mut proc = get_or_init_proc();
+        let fixed = Box::new(crate::provider::StaticProvider::new(b"abc\n".to_vec()));
+        proc.fs.borrow_mut().register_provider(&PathBuf::from("/sys/ro"), 0o444, fixed).unwrap();
+        proc.fs.borrow_mut().register_provider(&PathBuf::from("/cfg"), 0o644, Box::new(Config(b"x=1".to_vec()))).unwrap();
+    }
+    let ro = wasm_vfs_open(c(b"/sys/ro\0"), O_RDONLY, 0);
+    assert_eq!(read_some(ro, 2), b"ab");
+    assert_eq!(read_some(ro, 16), b"c\n");
+    assert_eq!(wasm_vfs_write(ro, b"x".as_ptr(), 1), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_fstat(ro, &mut st), 0);
+    assert_eq!(st.st_size, 4);
+    assert_eq!(wasm_vfs_lseek(ro, 0, SEEK_END), 4);
+    assert_eq!(wasm_vfs_truncate(c(b"/sys/ro\0"), 0), -1);
+
+    let cfg = wasm_vfs_open(c(b"/cfg\0"), O_RDWR | O_APPEND, 0);
+    write_all(cfg, b"\ny=2");
+    let mut buf = [0u8; 16];
+    assert_eq!(wasm_vfs_pread64(cfg, buf.as_mut_ptr(), 16, 0), 7);
+    assert_eq!(&buf[..7], b"x=1\ny=2");
+    assert_eq!(wasm_vfs_ftruncate(cfg, 3), 0);
+    assert_eq!(wasm_vfs_stat(c(b"/cfg\0"), &mut st), 0);
+    assert_eq!(st.st_size, 3);
+    // Provider content never lands in the file table
+    assert!(get_or_init_proc().fs.borrow().files.get(&st.st_ino).is_none());
+}
@@ -0,0 +1,175 @@
+use super::*;
+
+#[test]
+fn quotas_refuse_growth_with_edquot_and_enospc() {
+    use crate::quota::{qcmd, Dqblk, Limits, QuotaId, QIF_BLIMITS, Q_GETQUOTA, Q_SETQUOTA, USRQUOTA};
+    let _serial = fresh();
+    let page = [7u8; 4096];
+    let dqblk = |q: &mut Dqblk| q as *mut Dqblk as *mut u8;
+    let fd = wasm_vfs_open(c(b"/q.dat\0"), O_CREAT | O_RDWR, 0o644);
+    let base = get_or_init_proc().fs.borrow().quota_usage(QuotaId::User(0));
+    get_or_init_proc().fs.borrow_mut().set_quota(QuotaId::User(0), Limits { bytes: Some(base.bytes + 2 * 4096), inodes: None });
+    assert_eq!(wasm_vfs_pwrite64(fd, page.as_ptr(), 4096, 0), 4096);
+    assert_eq!(wasm_vfs_pwrite64(fd, page.as_ptr(), 4096, 8192), 4096);
+    assert_eq!(wasm_vfs_pwrite64(fd, page.as_ptr(), 4096, 4096), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+    assert_eq!(wasm_vfs_lseek(fd, 4096, SEEK_SET), 4096);
+    assert_eq!(wasm_vfs_write(fd, page.as_ptr(), 10), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+    // Pages already allocated can be rewritten, and holes are free
+    assert_eq!(wasm_vfs_pwrite64(fd, page.as_ptr(), 4096, 0), 4096);
+    assert_eq!(wasm_vfs_fallocate(fd, 0, 4096, 4096), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+    assert_eq!(wasm_vfs_ftruncate(fd, 1 << 20), 0);
+
+    let mut q = Dqblk::default();
+    assert_eq!(wasm_vfs_quotactl(qcmd(Q_GETQUOTA, USRQUOTA), core::ptr::null(), 0, dqblk(&mut q)), 0);
+    assert_eq!(q.dqb_curspace, base.bytes + 8192);
+    assert_eq!(q.dqb_bhardlimit, (base.bytes + 8192) / 1024);
+    q.dqb_bhardlimit = 0;
+    q.dqb_valid = QIF_BLIMITS;
+    assert_eq!(wasm_vfs_quotactl(qcmd(Q_SETQUOTA, USRQUOTA), c(b"/q.dat\0"), 0, dqblk(&mut q)), 0);
+    assert_eq!(wasm_vfs_pwrite64(fd, page.as_ptr(), 4096, 4096), 4096);
+    assert_eq!(wasm_vfs_quotactl(qcmd(Q_GETQUOTA, USRQUOTA), c(b"/nope\0"), 0, dqblk(&mut q)), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    assert_eq!(wasm_vfs_quotactl(qcmd(Q_GETQUOTA, 9), core::ptr::null(), 0, dqblk(&mut q)), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_quotactl(qcmd(Q_GETQUOTA, USRQUOTA), core::ptr::null(), 0, core::ptr::null_mut()), -1);
+    assert_eq!(wasm_vfs_errno(), EFAULT);
+    // Others' quotas are root's business
+    get_or_init_proc().uid = 1000;
+    assert_eq!(wasm_vfs_quotactl(qcmd(Q_GETQUOTA, USRQUOTA), core::ptr::null(), 0, dqblk(&mut q)), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_quotactl(qcmd(Q_GETQUOTA, USRQUOTA), core::ptr::null(), 1000, dqblk(&mut q)), 0);
+    assert_eq!(wasm_vfs_quotactl(qcmd(Q_SETQUOTA, USRQUOTA), core::ptr::null(), 1000, dqblk(&mut q)), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    get_or_init_proc().uid = 0;
+
+    // A project's inode limit covers every kind of new name
+    assert_eq!(wasm_vfs_mkdir(c(b"/proj\0"), 0o755), 0);
+    {
+        let mut proc = get_or_init_proc();
+        proc.fs.borrow_mut().set_project(7, &PathBuf::from("/proj"));
+        proc.fs.borrow_mut().set_quota(QuotaId::Project(7), Limits { bytes: None, inodes: Some(3) });
+    }
+    assert!(wasm_vfs_open(c(b"/proj/a\0"), O_CREAT | O_RDWR, 0o644) >= 0);
+    assert_eq!(wasm_vfs_mkdir(c(b"/proj/b\0"), 0o755), 0);
+    assert_eq!(wasm_vfs_mkdir(c(b"/proj/d\0"), 0o755), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+    assert_eq!(wasm_vfs_symlink(c(b"/proj/a\0"), c(b"/proj/l\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+    assert_eq!(wasm_vfs_open(c(b"/proj/d\0"), O_CREAT | O_RDWR, 0o644), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+    assert_eq!(wasm_vfs_mkdir(c(b"/outside\0"), 0o755), 0);
+
+    // The filesystem-wide limit is ENOSPC, for unnamed files too
+    {
+        let mut proc = get_or_init_proc();
+        let used = proc.fs.borrow().total_usage();
+        proc.fs.borrow_mut().quotas.total = Limits { bytes: Some(used.bytes), inodes: None };
+    }
+    assert_eq!(wasm_vfs_pwrite64(fd, page.as_ptr(), 4096, 65536), -1);
+    assert_eq!(wasm_vfs_errno(), ENOSPC);
+    let m = wasm_vfs_memfd_create(c(b"m\0"), 0);
+    assert!(m >= 0);
+    assert_eq!(wasm_vfs_write(m, page.as_ptr(), 1), -1);
+    assert_eq!(wasm_vfs_errno(), ENOSPC);
+    get_or_init_proc().fs.borrow_mut().quotas.total = Limits::default();
+    assert_eq!(wasm_vfs_write(m, page.as_ptr(), 1), 1);
+    // and so is a full mount
+    {
+        let mut proc = get_or_init_proc();
+        let used = proc.fs.borrow().usage_of(&PathBuf::from("/dev/shm"));
+        assert!(proc.fs.borrow_mut().set_mount_limits(&PathBuf::from("/dev/shm"), None, Some(used.inodes_used)));
+    }
+    assert_eq!(wasm_vfs_shm_open(c(b"/x\0"), O_CREAT | O_RDWR, 0o600), -1);
+    assert_eq!(wasm_vfs_errno(), ENOSPC);
+}
+
+#[test]
+fn chown_needs_room_and_names_stay_in_their_project() {
+    use crate::quota::{Limits, QuotaId};
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/big\0"), O_CREAT | O_RDWR, 0o644);
+    write_all(fd, &[1u8; 8192]);
+    get_or_init_proc().fs.borrow_mut().set_quota(QuotaId::User(5), Limits { bytes: Some(4096), inodes: None });
+    get_or_init_proc().fs.borrow_mut().set_quota(QuotaId::Group(6), Limits { bytes: None, inodes: Some(0) });
+    assert_eq!(wasm_vfs_fchown(fd, 5, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+    assert_eq!(wasm_vfs_chown(c(b"/big\0"), 0, 6), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+    assert_eq!(wasm_vfs_chown(c(b"/big\0"), 0, 0), 0);
+    assert_eq!(wasm_vfs_ftruncate(fd, 100), 0);
+    assert_eq!(wasm_vfs_fchown(fd, 5, 0), 0);
+    assert_eq!(get_or_init_proc().fs.borrow().quota_usage(QuotaId::User(5)).inodes, 1);
+    assert_eq!(wasm_vfs_chown(c(b"/nope\0"), 5, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+
+    wasm_vfs_mkdir(c(b"/p1\0"), 0o755);
+    wasm_vfs_mkdir(c(b"/p2\0"), 0o755);
+    get_or_init_proc().fs.borrow_mut().set_project(1, &PathBuf::from("/p1"));
+    let f = wasm_vfs_open(c(b"/p1/f\0"), O_CREAT | O_RDWR, 0o644);
+    wasm_vfs_close(f);
+    assert_eq!(wasm_vfs_rename(c(b"/p1/f\0"), c(b"/p2/f\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EXDEV);
+    assert_eq!(wasm_vfs_link(c(b"/p1/f\0"), c(b"/f\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EXDEV);
+    assert_eq!(wasm_vfs_rename(c(b"/big\0"), c(b"/p1/big\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EXDEV);
+    assert_eq!(wasm_vfs_rename(c(b"/p1/f\0"), c(b"/p1/g\0")), 0);
+}
+
+fn usage_snapshot() -> Vec<(u64, u64)> {
+    use crate::quota::QuotaId;
+    let proc = get_or_init_proc();
+    let mut all = vec![proc.fs.borrow().total_usage(), proc.fs.borrow().quota_usage(QuotaId::User(5)), proc.fs.borrow().quota_usage(QuotaId::Project(3))];
+    all.extend(proc.fs.borrow().usage().iter().map(|m| crate::quota::Usage { bytes: m.bytes_used, inodes: m.inodes_used }));
+    all.iter().map(|u| (u.bytes, u.inodes)).collect()
+}
+
+// The running totals match a recount from scratch
+fn assert_ledger_consistent() {
+    let kept = usage_snapshot();
+    get_or_init_proc().fs.borrow_mut().recount();
+    assert_eq!(kept, usage_snapshot());
+}
+
+#[test]
+fn the_ledger_matches_a_recount() {
+    use crate::quota::{Limits, QuotaId};
+    let _serial = fresh();
+    let page = [7u8; 8192];
+    get_or_init_proc().fs.borrow_mut().set_quota(QuotaId::User(5), Limits { bytes: Some(1 << 20), inodes: None });
+    assert_eq!(wasm_vfs_mkdir(c(b"/proj\0"), 0o777), 0);
+    get_or_init_proc().fs.borrow_mut().set_project(3, &PathBuf::from("/proj"));
+    get_or_init_proc().fs.borrow_mut().set_quota(QuotaId::Project(3), Limits { bytes: Some(1 << 20), inodes: None });
+    let fd = wasm_vfs_open(c(b"/proj/a\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_pwrite64(fd, page.as_ptr(), 8192, 0), 8192);
+    assert_ledger_consistent();
+    assert_eq!(get_or_init_proc().fs.borrow().quota_usage(QuotaId::Project(3)).bytes, 8192);
+    assert_eq!(wasm_vfs_fchown(fd, 5, 5), 0);
+    assert_ledger_consistent();
+    assert_eq!(get_or_init_proc().fs.borrow().quota_usage(QuotaId::User(5)).bytes, 8192);
+    assert_eq!(wasm_vfs_link(c(b"/proj/a\0"), c(b"/proj/b\0")), 0);
+    assert_ledger_consistent();
+    assert_eq!(wasm_vfs_unlink(c(b"/proj/a\0")), 0);
+    assert_ledger_consistent();
+    assert_eq!(get_or_init_proc().fs.borrow().quota_usage(QuotaId::Project(3)).bytes, 8192);
+    assert_eq!(wasm_vfs_ftruncate(fd, 100), 0);
+    assert_ledger_consistent();
+    assert_eq!(wasm_vfs_fallocate(fd, 0, 0, 65536), 0);
+    assert_ledger_consistent();
+    assert_eq!(wasm_vfs_rename(c(b"/proj/b\0"), c(b"/proj/x\0")), 0);
+    assert_ledger_consistent();
+    let m = wasm_vfs_memfd_create(c(b"m\0"), 0);
+    assert_eq!(wasm_vfs_write(m, page.as_ptr(), 5000), 5000);
+    assert_ledger_consistent();
+    assert_eq!(wasm_vfs_close(m), 0);
+    assert_ledger_consistent();
+    assert_eq!(wasm_vfs_close(fd), 0);
+    assert_eq!(wasm_vfs_unlink(c(b"/proj/x\0")), 0);
+    assert_ledger_consistent();
+    let fd = wasm_vfs_open(c(b"/proj/big\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_fallocate(fd, 0, 0, 2 << 20), -1);
+    assert_eq!(wasm_vfs_errno(), EDQUOT);
+}
@@ -0,0 +1,73 @@
+use super::*;
+
+#[test]
+fn ficlone_shares_until_written() {
+    let _serial = fresh();
+    let a = wasm_vfs_open(c(b"/a\0"), O_CREAT | O_RDWR, 0o644);
+    write_all(a, &[7u8; 10000]);
+    let b = wasm_vfs_open(c(b"/b\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_ioctl(b, FICLONE, a as usize as *mut u8), 0);
+    assert!(shared(a, b));
+    assert_eq!(wasm_vfs_pwrite64(b, b"x".as_ptr(), 1, 0), 1);
+    assert!(!shared(a, b));
+    let mut buf = [0u8; 1];
+    assert_eq!(wasm_vfs_pread64(a, buf.as_mut_ptr(), 1, 0), 1);
+    assert_eq!(buf, [7]);
+
+    // FICLONERANGE wants page-aligned ranges
+    let range = FileCloneRange { src_fd: a as i64, src_offset: 1, src_length: 4096, dest_offset: 0 };
+    assert_eq!(wasm_vfs_ioctl(b, FICLONERANGE, &range as *const _ as *mut u8), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    let range = FileCloneRange { src_fd: a as i64, src_offset: 4096, src_length: 0, dest_offset: 0 };
+    assert_eq!(wasm_vfs_ioctl(b, FICLONERANGE, &range as *const _ as *mut u8), 0);
+    assert_eq!(wasm_vfs_pread64(b, buf.as_mut_ptr(), 1, 0), 1);
+    assert_eq!(buf, [7]);
+    assert_eq!(wasm_vfs_ioctl(b, FICLONERANGE, core::ptr::null_mut()), -1);
+    assert_eq!(wasm_vfs_errno(), EFAULT);
+}
+
+#[test]
+fn copy_file_range_moves_the_offsets_it_uses() {
+    let _serial = fresh();
+    let a = wasm_vfs_open(c(b"/a\0"), O_CREAT | O_RDWR, 0o644);
+    write_all(a, &[7u8; 10000]);
+    let d = wasm_vfs_open(c(b"/d\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_lseek(a, 0, SEEK_SET), 0);
+    assert_eq!(wasm_vfs_copy_file_range(a, core::ptr::null_mut(), d, core::ptr::null_mut(), 1 << 20, 0), 10000);
+    assert_eq!(wasm_vfs_lseek(a, 0, SEEK_CUR), 10000);
+    assert_eq!(wasm_vfs_lseek(d, 0, SEEK_CUR), 10000);
+    assert!(shared(a, d));
+
+    let (mut off_in, mut off_out) = (9998i64, 2i64);
+    assert_eq!(wasm_vfs_copy_file_range(a, &mut off_in, d, &mut off_out, 100, 0), 2);
+    assert_eq!((off_in, off_out), (10000, 4));
+    assert_eq!(wasm_vfs_copy_file_range(a, core::ptr::null_mut(), d, core::ptr::null_mut(), 1, 1), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+}
+
+#[test]
+fn copies_and_clones_need_the_right_access() {
+    let _serial = fresh();
+    let a = wasm_vfs_open(c(b"/a\0"), O_CREAT | O_RDWR, 0o644);
+    write_all(a, &[7u8; 4096]);
+    let w = wasm_vfs_open(c(b"/a\0"), O_WRONLY, 0);
+    let r = wasm_vfs_open(c(b"/r\0"), O_CREAT | O_RDONLY, 0o644);
+    let d = wasm_vfs_open(c(b"/d\0"), O_CREAT | O_RDWR, 0o644);
+    let null = core::ptr::null_mut();
+    assert_eq!(wasm_vfs_copy_file_range(w, null, d, null, 10, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_copy_file_range(a, null, r, null, 10, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_ioctl(r, FICLONE, a as usize as *mut u8), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_ioctl(d, FICLONE, w as usize as *mut u8), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+
+    // Ranges whose end doesn't fit
+    let (mut off_in, mut off_out) = (0i64, i64::MAX);
+    assert_eq!(wasm_vfs_copy_file_range(a, &mut off_in, d, &mut off_out, usize::MAX, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EOVERFLOW);
+    let range = FileCloneRange { src_fd: a as i64, src_offset: 0, src_length: 4096, dest_offset: u64::MAX - 4095 };
+    assert_eq!(wasm_vfs_ioctl(d, FICLONERANGE, &range as *const _ as *mut u8), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+}
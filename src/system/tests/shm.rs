@@ -0,0 +1,102 @@
+use super::*;
+
+#[test]
+fn shm_objects_are_shared_files_under_dev_shm() {
+    use crate::mmap::{MAP_FAILED, MAP_SHARED, MS_SYNC, PROT_READ, PROT_WRITE};
+    let _serial = fresh();
+    let none = core::ptr::null_mut();
+    let a = wasm_vfs_shm_open(c(b"/obj\0"), O_CREAT | O_RDWR, 0o600);
+    assert!(a >= 0);
+    assert_eq!(wasm_vfs_fcntl(a, F_GETFD, 0), FD_CLOEXEC);
+    assert_eq!(wasm_vfs_ftruncate(a, 8192), 0);
+    let b = wasm_vfs_shm_open(c(b"obj\0"), O_RDWR, 0);
+    assert!(b >= 0);
+    assert_eq!(wasm_vfs_shm_open(c(b"/obj\0"), O_CREAT | O_EXCL | O_RDWR, 0o600), -1);
+    assert_eq!(wasm_vfs_errno(), EEXIST);
+    assert_eq!(wasm_vfs_shm_open(c(b"/nope\0"), O_RDWR, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    for bad in [&b"/\0"[..], b"a/b\0", b"//\0", b"..\0"] {
+        assert_eq!(wasm_vfs_shm_open(c(bad), O_CREAT | O_RDWR, 0o600), -1);
+        assert_eq!(wasm_vfs_errno(), EINVAL);
+    }
+    let long = [&[b'x'; 300][..], b"\0"].concat();
+    assert_eq!(wasm_vfs_shm_open(c(&long), O_CREAT | O_RDWR, 0o600), -1);
+    assert_eq!(wasm_vfs_errno(), ENAMETOOLONG);
+
+    let ma = wasm_vfs_mmap(none, 8192, PROT_READ | PROT_WRITE, MAP_SHARED, a, 0);
+    let mb = wasm_vfs_mmap(none, 8192, PROT_READ | PROT_WRITE, MAP_SHARED, b, 0);
+    assert!(ma != MAP_FAILED && mb != MAP_FAILED);
+    mapped(ma, 8192)[4096..4100].copy_from_slice(b"ping");
+    assert_eq!(wasm_vfs_msync(ma, 8192, MS_SYNC), 0);
+    assert_eq!(&mapped(mb, 8192)[4096..4100], b"ping");
+    assert_eq!(wasm_vfs_pwrite64(b, b"pong".as_ptr(), 4, 0), 4);
+    assert_eq!(&mapped(ma, 4)[..], b"pong");
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_stat(c(b"/dev/shm/obj\0"), &mut st), 0);
+    assert_eq!(st.st_size, 8192);
+
+    // Unlinked, the object lives on for its maps and descriptors
+    // and is freed with the last of them
+    assert_eq!(wasm_vfs_shm_unlink(c(b"/obj\0")), 0);
+    assert_eq!(wasm_vfs_shm_unlink(c(b"/obj\0")), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    assert_eq!(wasm_vfs_shm_open(c(b"/obj\0"), O_RDWR, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    mapped(mb, 4).copy_from_slice(b"late");
+    assert_eq!(wasm_vfs_msync(mb, 4096, MS_SYNC), 0);
+    assert_eq!(&mapped(ma, 4)[..], b"late");
+    let ino = st.st_ino;
+    assert_eq!(wasm_vfs_close(a), 0);
+    assert_eq!(wasm_vfs_close(b), 0);
+    assert_eq!(wasm_vfs_munmap(ma, 8192), 0);
+    assert!(get_or_init_proc().fs.borrow().files.contains_key(&ino));
+    assert_eq!(wasm_vfs_munmap(mb, 8192), 0);
+    assert!(!get_or_init_proc().fs.borrow().files.contains_key(&ino));
+
+    assert_eq!(wasm_vfs_shm_unlink(c(b"a/b\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    // Without /dev/shm there is nowhere to keep them
+    assert_eq!(wasm_vfs_rmdir(c(b"/dev/shm\0")), 0);
+    assert_eq!(wasm_vfs_shm_open(c(b"/obj\0"), O_CREAT | O_RDWR, 0o600), -1);
+    assert_eq!(wasm_vfs_errno(), ENOSYS);
+}
+
+#[test]
+fn shm_mappings_in_two_procs_see_each_others_writes() {
+    use crate::mmap::{MAP_SHARED, MS_SYNC, PROT_READ, PROT_WRITE};
+    let _serial = fresh();
+    let none = core::ptr::null_mut();
+    let a = wasm_vfs_shm_open(c(b"/ring\0"), O_CREAT | O_EXCL | O_RDWR, 0o600);
+    assert!(a >= 0);
+    assert_eq!(wasm_vfs_ftruncate(a, 4096), 0);
+    let ma = wasm_vfs_mmap(none, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, a, 0);
+    mapped(ma, 4).copy_from_slice(b"ping");
+    assert_eq!(wasm_vfs_msync(ma, 4096, MS_SYNC), 0);
+
+    let first = switch_to(sibling());
+    assert_eq!(wasm_vfs_shm_open(c(b"/ring\0"), O_CREAT | O_EXCL | O_RDWR, 0o600), -1);
+    assert_eq!(wasm_vfs_errno(), EEXIST);
+    let b = wasm_vfs_shm_open(c(b"/ring\0"), O_RDWR, 0);
+    assert!(b >= 0);
+    let mb = wasm_vfs_mmap(none, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, b, 0);
+    assert_eq!(&mapped(mb, 4)[..], b"ping");
+    mapped(mb, 12)[8..].copy_from_slice(b"pong");
+    assert_eq!(wasm_vfs_msync(mb, 4096, MS_SYNC), 0);
+    assert_eq!(&mapped(ma, 12)[8..], b"pong");
+    assert_eq!(wasm_vfs_pwrite64(b, b"post".as_ptr(), 4, 100), 4);
+    assert_eq!(&mapped(ma, 104)[100..], b"post");
+
+    let second = switch_to(first);
+    mapped(ma, 204)[200..].copy_from_slice(b"back");
+    assert_eq!(wasm_vfs_munmap(ma, 4096), 0);
+    assert_eq!(&mapped(mb, 204)[200..], b"back");
+
+    // Unlinked and closed here, the object lives on for the other
+    // process's mapping, and goes when that process does
+    let ino = inode_of(a);
+    assert_eq!(wasm_vfs_shm_unlink(c(b"/ring\0")), 0);
+    assert_eq!(wasm_vfs_close(a), 0);
+    assert!(get_or_init_proc().fs.borrow().files.contains_key(&ino));
+    drop(second);
+    assert!(!get_or_init_proc().fs.borrow().files.contains_key(&ino));
+}
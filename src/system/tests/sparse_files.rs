@@ -0,0 +1,26 @@
+use super::*;
+
+#[test]
+fn seek_data_and_hole_follow_allocation() {
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/sparse\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_pwrite64(fd, b"abc".as_ptr(), 3, 10000), 3);
+    assert_eq!(wasm_vfs_pwrite64(fd, b"def".as_ptr(), 3, 20000), 3);
+    assert_eq!(wasm_vfs_ftruncate(fd, 40000), 0);
+    let mut st: Stat = unsafe { core::mem::zeroed() };
+    assert_eq!(wasm_vfs_fstat(fd, &mut st), 0);
+    assert_eq!(st.st_size, 40000);
+    // Two 4 KiB pages, in 512-byte blocks
+    assert_eq!(st.st_blocks, 16);
+
+    assert_eq!(wasm_vfs_lseek(fd, 0, SEEK_DATA), 8192);
+    assert_eq!(wasm_vfs_lseek(fd, 0, SEEK_HOLE), 0);
+    assert_eq!(wasm_vfs_lseek(fd, 9000, SEEK_HOLE), 12288);
+    assert_eq!(wasm_vfs_lseek(fd, 12288, SEEK_DATA), 16384);
+    assert_eq!(wasm_vfs_lseek(fd, 20480, SEEK_DATA), -1);
+    assert_eq!(wasm_vfs_errno(), ENXIO);
+    assert_eq!(wasm_vfs_lseek(fd, 40000, SEEK_HOLE), -1);
+    assert_eq!(wasm_vfs_errno(), ENXIO);
+    assert_eq!(wasm_vfs_lseek(fd, 0, 9), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+}
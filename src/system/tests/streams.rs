@@ -0,0 +1,65 @@
+use super::*;
+
+#[test]
+fn stdio_is_redirectable() {
+    let _serial = fresh();
+    let out = stdio::CaptureStream::new();
+    get_or_init_proc().set_stdio(1, Box::new(out.clone()), BufferMode::Line).unwrap();
+    assert_eq!(wasm_vfs_write(1, b"abc\nd".as_ptr(), 5), 5);
+    assert_eq!(out.output(), b"abc\n");
+
+    let input = stdio::CaptureStream::new();
+    input.push_input(b"hi");
+    get_or_init_proc().set_stdio(0, Box::new(input), BufferMode::Unbuffered).unwrap();
+    assert_eq!(read_some(0, 4), b"hi");
+
+    // dup2 over stdout flushes what it held and points it at the file
+    let f = wasm_vfs_open(c(b"/out\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_dup2(f, 1), 1);
+    assert_eq!(out.output(), b"abc\nd");
+    write_all(1, b"zz");
+    let mut buf = [0u8; 4];
+    assert_eq!(wasm_vfs_pread64(f, buf.as_mut_ptr(), 4, 0), 2);
+    assert_eq!(wasm_vfs_lseek(2, 0, SEEK_SET), -1);
+    assert_eq!(wasm_vfs_errno(), ESPIPE);
+}
+
+#[test]
+fn set_stdio_reports_a_full_stream_table() {
+    let _serial = fresh();
+    let mut proc = get_or_init_proc();
+    assert_eq!(proc.set_stdio(-1, Box::new(stdio::DiscardStream), BufferMode::Line), Err(EBADF));
+    let result = (3..64)
+        .map(|fd| proc.set_stdio(fd, Box::new(stdio::DiscardStream), BufferMode::Line))
+        .find(|r| r.is_err());
+    assert_eq!(result, Some(Err(ENFILE)));
+}
+
+#[test]
+fn dup_shares_the_open_file_description() {
+    let _serial = fresh();
+    let f = wasm_vfs_open(c(b"/log\0"), O_CREAT | O_WRONLY | O_TRUNC, 0o644);
+    assert_eq!(wasm_vfs_dup2(f, 1), 1);
+    assert_eq!(wasm_vfs_dup2(1, 2), 2);
+    // `2>&1`: both streams write through one file position
+    write_all(1, b"out 1\n");
+    write_all(2, b"err 1\n");
+    write_all(1, b"out 2\n");
+    write_all(2, b"err 2\n");
+    assert_eq!(pread_vec(wasm_vfs_open(c(b"/log\0"), O_RDONLY, 0), 0, 64), b"out 1\nerr 1\nout 2\nerr 2\n");
+    assert_eq!(wasm_vfs_lseek(f, 0, SEEK_CUR), 24);
+
+    // Seeks and F_SETFL on one are seen by the other; FD_CLOEXEC is not
+    let d = wasm_vfs_dup(f);
+    assert_eq!(wasm_vfs_lseek(d, 3, SEEK_SET), 3);
+    assert_eq!(wasm_vfs_lseek(f, 0, SEEK_CUR), 3);
+    assert_eq!(wasm_vfs_fcntl(d, F_SETFL, O_NONBLOCK | O_APPEND), 0);
+    assert_eq!(wasm_vfs_fcntl(f, F_GETFL, 0) & (O_NONBLOCK | O_APPEND), O_NONBLOCK | O_APPEND);
+    assert_eq!(wasm_vfs_fcntl(d, F_SETFD, FD_CLOEXEC), 0);
+    assert_eq!(wasm_vfs_fcntl(f, F_GETFD, 0), 0);
+
+    // A separate open has its own
+    let other = wasm_vfs_open(c(b"/log\0"), O_RDONLY, 0);
+    assert_eq!(wasm_vfs_lseek(other, 0, SEEK_CUR), 0);
+    assert_eq!(wasm_vfs_fcntl(other, F_GETFL, 0) & O_NONBLOCK, 0);
+}
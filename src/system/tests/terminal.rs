@@ -0,0 +1,45 @@
+use super::*;
+
+#[test]
+fn tty_ioctls_go_through_the_terminal() {
+    let _serial = fresh();
+    let out = stdio::CaptureStream::new();
+    get_or_init_proc().attach_tty(Box::new(out));
+    assert_eq!(wasm_vfs_isatty(0), 1);
+
+    let mut termios = Termios::default();
+    assert_eq!(wasm_vfs_ioctl(0, TCGETS, &mut termios as *mut _ as *mut u8), 0);
+    assert!(termios.c_lflag & crate::tty::ICANON != 0);
+    termios.c_lflag &= !crate::tty::ICANON;
+    assert_eq!(wasm_vfs_ioctl(0, TCSETS, &mut termios as *mut _ as *mut u8), 0);
+    assert!(get_or_init_proc().tty_input(b"k"));
+    let mut pending = 0i32;
+    assert_eq!(wasm_vfs_ioctl(0, FIONREAD, &mut pending as *mut _ as *mut u8), 0);
+    assert_eq!(pending, 1);
+
+    assert_eq!(wasm_vfs_tty_set_winsize(50, 132), 0);
+    let mut winsize = Winsize::default();
+    assert_eq!(wasm_vfs_ioctl(1, TIOCGWINSZ, &mut winsize as *mut _ as *mut u8), 0);
+    assert_eq!((winsize.ws_row, winsize.ws_col), (50, 132));
+    assert_eq!(wasm_vfs_tty_take_signal(), crate::tty::SIGWINCH);
+}
+
+#[test]
+fn tty_ioctls_check_their_arguments() {
+    let _serial = fresh();
+    get_or_init_proc().attach_tty(Box::new(stdio::DiscardStream));
+    assert_eq!(wasm_vfs_ioctl(0, TCGETS, core::ptr::null_mut()), -1);
+    assert_eq!(wasm_vfs_errno(), EFAULT);
+
+    // Unaligned pointers are fine
+    let mut raw = [0u8; 1 + core::mem::size_of::<Winsize>()];
+    assert_eq!(wasm_vfs_ioctl(0, TIOCGWINSZ, raw[1..].as_mut_ptr()), 0);
+    let winsize: Winsize = unsafe { core::ptr::read_unaligned(raw[1..].as_ptr() as *const Winsize) };
+    assert_eq!(winsize.ws_col, 80);
+
+    let f = wasm_vfs_open(c(b"/plain\0"), O_CREAT | O_RDWR, 0o644);
+    let mut termios = Termios::default();
+    assert_eq!(wasm_vfs_ioctl(f, TCGETS, &mut termios as *mut _ as *mut u8), -1);
+    assert_eq!(wasm_vfs_errno(), ENOTTY);
+    assert_eq!(wasm_vfs_isatty(f), 0);
+}
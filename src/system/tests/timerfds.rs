@@ -0,0 +1,75 @@
+use super::*;
+
+#[test]
+fn timerfds_fire_on_the_virtual_clock() {
+    use crate::poll::{EpollEvent, PollFd, EPOLLIN, EPOLL_CTL_ADD, POLLIN};
+    use crate::timerfd::{Itimerspec, TFD_TIMER_ABSTIME};
+    let _serial = fresh();
+    let ms = |n: u64| clock::to_timespec(n * 1_000_000);
+    assert_eq!(wasm_vfs_timerfd_create(5, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    let tfd = wasm_vfs_timerfd_create(CLOCK_MONOTONIC, O_NONBLOCK);
+    assert!(tfd >= 0);
+    assert_eq!(read_u64(tfd), Err(EAGAIN));
+    let spec = Itimerspec { it_interval: ms(10), it_value: ms(50) };
+    let mut old = Itimerspec::default();
+    assert_eq!(wasm_vfs_timerfd_settime(tfd, 0, &spec, &mut old), 0);
+    assert_eq!(old, Itimerspec::default());
+    let mut current = Itimerspec::default();
+    assert_eq!(wasm_vfs_timerfd_gettime(tfd, &mut current), 0);
+    assert_eq!(current, spec);
+
+    // Waiting forever jumps the clock to the first expiry
+    let efd = wasm_vfs_eventfd(0, 0);
+    let start = clock::now(CLOCK_MONOTONIC);
+    let mut fds = [PollFd { fd: tfd, events: POLLIN, revents: 0 }, PollFd { fd: efd, events: POLLIN, revents: 0 }];
+    assert_eq!(wasm_vfs_poll(fds.as_mut_ptr(), 2, -1), 1);
+    assert_eq!(clock::now(CLOCK_MONOTONIC) - start, 50_000_000);
+    assert_eq!(fds[0].revents, POLLIN);
+    assert_eq!(read_u64(tfd), Ok(1));
+    clock::sleep(35_000_000);
+    assert_eq!(read_u64(tfd), Ok(3));
+    assert_eq!(wasm_vfs_timerfd_gettime(tfd, &mut current), 0);
+    assert_eq!(current.it_value, ms(5));
+
+    let ep = wasm_vfs_epoll_create1(0);
+    let mut event = EpollEvent { events: EPOLLIN, data: 9 };
+    assert_eq!(wasm_vfs_epoll_ctl(ep, EPOLL_CTL_ADD, tfd, &mut event), 0);
+    let mut out = [EpollEvent::default(); 2];
+    let start = clock::now(CLOCK_MONOTONIC);
+    assert_eq!(wasm_vfs_epoll_wait(ep, out.as_mut_ptr(), 2, -1), 1);
+    assert_eq!(clock::now(CLOCK_MONOTONIC) - start, 5_000_000);
+
+    // Disarmed, nothing can wake a wait; TFD_TIMER_ABSTIME takes a
+    // deadline on the clock
+    assert_eq!(wasm_vfs_timerfd_settime(tfd, 0, &Itimerspec::default(), &mut old), 0);
+    assert_eq!(old.it_interval, ms(10));
+    assert_eq!(wasm_vfs_poll(fds.as_mut_ptr(), 1, -1), 0);
+    let deadline = Itimerspec { it_interval: Default::default(), it_value: clock::to_timespec(clock::now(CLOCK_MONOTONIC) + 7) };
+    assert_eq!(wasm_vfs_timerfd_settime(tfd, TFD_TIMER_ABSTIME, &deadline, core::ptr::null_mut()), 0);
+    assert_eq!(wasm_vfs_poll(fds.as_mut_ptr(), 1, -1), 1);
+    assert_eq!(read_u64(tfd), Ok(1));
+
+    assert_eq!(wasm_vfs_timerfd_gettime(efd, &mut current), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_timerfd_settime(tfd, 0, core::ptr::null(), &mut old), -1);
+    assert_eq!(wasm_vfs_errno(), EFAULT);
+    assert_eq!(write_u64(tfd, 1), Err(EINVAL));
+    assert_eq!(wasm_vfs_close(tfd), 0);
+    assert!(get_or_init_proc().timers.iter().next().is_none());
+}
+
+#[test]
+fn eventfd_and_timerfd_tables_run_out_with_enfile() {
+    let _serial = fresh();
+    for _ in 0..EVENTFD_CAP {
+        assert!(wasm_vfs_eventfd(0, 0) >= 0);
+    }
+    assert_eq!(wasm_vfs_eventfd(0, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENFILE);
+    for _ in 0..TIMERFD_CAP {
+        assert!(wasm_vfs_timerfd_create(CLOCK_MONOTONIC, 0) >= 0);
+    }
+    assert_eq!(wasm_vfs_timerfd_create(CLOCK_MONOTONIC, 0), -1);
+    assert_eq!(wasm_vfs_errno(), ENFILE);
+}
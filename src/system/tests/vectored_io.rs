@@ -0,0 +1,92 @@
+use super::*;
+
+fn iov(buf: &mut [u8]) -> Iovec {
+    Iovec { iov_base: buf.as_mut_ptr(), iov_len: buf.len() }
+}
+
+#[test]
+fn vectored_io_on_files() {
+    let _serial = fresh();
+    let fd = wasm_vfs_open(c(b"/v\0"), O_CREAT | O_RDWR, 0o644);
+    let (mut a, mut b) = (*b"hello ", *b"world");
+    let out = [iov(&mut a), iov(&mut b)];
+    assert_eq!(wasm_vfs_writev(fd, out.as_ptr(), 2), 11);
+    assert_eq!(wasm_vfs_lseek(fd, 0, SEEK_CUR), 11);
+
+    let (mut x, mut y) = ([0u8; 3], [0u8; 20]);
+    let into = [iov(&mut x), iov(&mut y)];
+    assert_eq!(wasm_vfs_preadv(fd, into.as_ptr(), 2, 1), 10);
+    assert_eq!(&x, b"ell");
+    assert_eq!(&y[..7], b"o world");
+
+    // RWF_APPEND writes at the end whatever the offset says
+    assert_eq!(wasm_vfs_pwritev2(fd, out.as_ptr(), 1, 0, RWF_APPEND), 6);
+    assert_eq!(wasm_vfs_pwritev2(fd, out[1..].as_ptr(), 1, -1, RWF_APPEND), 5);
+    assert_eq!(wasm_vfs_lseek(fd, 0, SEEK_CUR), 22);
+    let mut all = [0u8; 30];
+    let whole = [iov(&mut all)];
+    assert_eq!(wasm_vfs_preadv2(fd, whole.as_ptr(), 1, 0, RWF_NOWAIT), 22);
+    assert_eq!(&all[..22], b"hello worldhello world");
+
+    assert_eq!(wasm_vfs_pwritev2(fd, out.as_ptr(), 1, 0, 0x1000), -1);
+    assert_eq!(wasm_vfs_errno(), EOPNOTSUPP);
+    assert_eq!(wasm_vfs_readv(fd, out.as_ptr(), -1), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_readv(99, whole.as_ptr(), 1), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+}
+
+#[test]
+fn vectored_io_skips_gaps_and_stops_short() {
+    let _serial = fresh();
+    // Empty and null segments in the middle are skipped
+    let fd = wasm_vfs_open(c(b"/v\0"), O_CREAT | O_RDWR, 0o644);
+    let (mut a, mut b) = (*b"hello ", *b"world");
+    let gap = Iovec { iov_base: core::ptr::null_mut(), iov_len: 0 };
+    let out = [iov(&mut a), gap, iov(&mut b)];
+    assert_eq!(wasm_vfs_pwritev(fd, out.as_ptr(), 3, 100), 11);
+    let (mut x, mut y) = ([0u8; 4], [0u8; 20]);
+    let into = [iov(&mut x), iov(&mut y)];
+    assert_eq!(wasm_vfs_preadv(fd, into.as_ptr(), 2, 102), 9);
+    assert_eq!(&x, b"llo ");
+    // All-empty vectors still check the descriptor
+    assert_eq!(wasm_vfs_writev(99, [gap].as_ptr(), 1), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+
+    // A pipe that fills part way through reports what went in
+    let [r, w] = pipe(O_NONBLOCK);
+    let mut big = vec![1u8; 1 << 20];
+    let mut small = [2u8; 1000];
+    let out = [iov(&mut small), iov(&mut big)];
+    let n = wasm_vfs_writev(w, out.as_ptr(), 2);
+    assert!(n > 1000 && n < (1 << 20) + 1000, "{}", n);
+    assert_eq!(wasm_vfs_writev(w, out.as_ptr(), 2), -1);
+    assert_eq!(wasm_vfs_errno(), EAGAIN);
+
+    let (mut s1, mut s2) = ([0u8; 999], [0u8; 4]);
+    let into = [iov(&mut s1), iov(&mut s2)];
+    assert_eq!(wasm_vfs_readv(r, into.as_ptr(), 2), 1003);
+    assert_eq!(&s2, &[2, 1, 1, 1]);
+}
+
+#[test]
+fn small_writev_to_a_pipe_is_atomic() {
+    let _serial = fresh();
+    let [r, w] = pipe(O_NONBLOCK);
+    let capacity = wasm_vfs_fcntl(w, F_GETPIPE_SZ, 0) as usize;
+    let fill = vec![1u8; capacity - 100];
+    assert_eq!(wasm_vfs_write(w, fill.as_ptr(), fill.len()), fill.len() as isize);
+
+    // 150 bytes don't fit in the 100 left, and no part of them goes in
+    let (mut a, mut b) = ([2u8; 50], [3u8; 100]);
+    let out = [iov(&mut a), iov(&mut b)];
+    assert_eq!(wasm_vfs_writev(w, out.as_ptr(), 2), -1);
+    assert_eq!(wasm_vfs_errno(), EAGAIN);
+    assert_eq!(read_some(r, capacity).len(), capacity - 100);
+
+    // Once there is room the whole vector lands in order
+    assert_eq!(wasm_vfs_writev(w, out.as_ptr(), 2), 150);
+    let got = read_some(r, 200);
+    assert_eq!(&got[..50], &[2u8; 50][..]);
+    assert_eq!(&got[50..], &[3u8; 100][..]);
+}
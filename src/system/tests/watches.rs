@@ -0,0 +1,105 @@
+use super::*;
+
+fn inotify_events(fd: i32) -> Vec<(i32, u32, u32, String)> {
+    let mut buf = [0u8; 4096];
+    let n = wasm_vfs_read(fd, buf.as_mut_ptr(), buf.len());
+    let mut events = Vec::new();
+    let mut at = 0;
+    while at < n.max(0) as usize {
+        let field = |i: usize| u32::from_ne_bytes(buf[at + i..at + i + 4].try_into().unwrap());
+        let len = field(12) as usize;
+        let name = String::from_utf8_lossy(&buf[at + 16..at + 16 + len]).trim_end_matches('\0').to_string();
+        events.push((field(0) as i32, field(4), field(8), name));
+        at += 16 + len;
+    }
+    events
+}
+
+#[test]
+fn inotify_reports_directory_and_file_events() {
+    use crate::inotify::*;
+    let _serial = fresh();
+    let named = |wd: i32, mask: u32, name: &str| (wd, mask, 0, name.to_string());
+    wasm_vfs_mkdir(c(b"/wd\0"), 0o755);
+    let fd = wasm_vfs_inotify_init1(O_NONBLOCK);
+    assert!(fd >= 0);
+    assert_eq!(wasm_vfs_inotify_init1(0x1), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_inotify_add_watch(fd, c(b"/wd\0"), IN_ALL_EVENTS), 1);
+    let mut buf = [0u8; 64];
+    assert_eq!(wasm_vfs_read(fd, buf.as_mut_ptr(), 64), -1);
+    assert_eq!(wasm_vfs_errno(), EAGAIN);
+
+    let a = wasm_vfs_open(c(b"/wd/a\0"), O_CREAT | O_WRONLY, 0o644);
+    write_all(a, b"hi");
+    write_all(a, b"hi");
+    wasm_vfs_close(a);
+    let mut pending = 0i32;
+    assert_eq!(wasm_vfs_ioctl(fd, FIONREAD, &mut pending as *mut i32 as *mut u8), 0);
+    assert_eq!(pending, 4 * 32);
+    assert_eq!(
+        inotify_events(fd),
+        [named(1, IN_CREATE, "a"), named(1, IN_OPEN, "a"), named(1, IN_MODIFY, "a"), named(1, IN_CLOSE_WRITE, "a")]
+    );
+
+    assert_eq!(wasm_vfs_inotify_add_watch(fd, c(b"/wd/a\0"), IN_ATTRIB | IN_DELETE_SELF | IN_MOVE_SELF), 2);
+    wasm_vfs_chmod(c(b"/wd/a\0"), 0o600);
+    assert_eq!(wasm_vfs_read(fd, buf.as_mut_ptr(), 10), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(inotify_events(fd), [named(2, IN_ATTRIB, ""), named(1, IN_ATTRIB, "a")]);
+
+    // A rename pairs its two halves with a cookie
+    assert_eq!(wasm_vfs_rename(c(b"/wd/a\0"), c(b"/wd/b\0")), 0);
+    let events = inotify_events(fd);
+    assert_eq!(events.len(), 3);
+    assert_eq!((events[0].1, events[0].3.as_str()), (IN_MOVED_FROM, "a"));
+    assert_eq!((events[1].1, events[1].3.as_str()), (IN_MOVED_TO, "b"));
+    assert!(events[0].2 != 0 && events[0].2 == events[1].2);
+    assert_eq!((events[2].0, events[2].1), (2, IN_MOVE_SELF));
+    wasm_vfs_unlink(c(b"/wd/b\0"));
+    assert_eq!(
+        inotify_events(fd),
+        [named(2, IN_ATTRIB, ""), named(1, IN_DELETE, "b"), named(2, IN_DELETE_SELF, ""), named(2, IN_IGNORED, "")]
+    );
+    wasm_vfs_mkdir(c(b"/wd/s\0"), 0o755);
+    assert_eq!(inotify_events(fd), [named(1, IN_CREATE | IN_ISDIR, "s")]);
+
+    assert_eq!(wasm_vfs_inotify_add_watch(fd, c(b"/wd/a\0"), IN_ALL_EVENTS), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    let h = wasm_vfs_open(c(b"/wd/h\0"), O_CREAT | O_RDONLY, 0o644);
+    wasm_vfs_close(h);
+    assert_eq!(wasm_vfs_inotify_add_watch(fd, c(b"/wd/h\0"), IN_ALL_EVENTS | IN_ONLYDIR), -1);
+    assert_eq!(wasm_vfs_errno(), ENOTDIR);
+    inotify_events(fd);
+
+    let oneshot = wasm_vfs_inotify_add_watch(fd, c(b"/wd/h\0"), IN_OPEN | IN_ONESHOT);
+    let h = wasm_vfs_open(c(b"/wd/h\0"), O_RDONLY, 0);
+    wasm_vfs_close(h);
+    let events = inotify_events(fd);
+    assert!(events.contains(&named(oneshot, IN_OPEN, "")));
+    assert!(events.contains(&named(oneshot, IN_IGNORED, "")));
+    assert!(events.contains(&named(1, IN_CLOSE_NOWRITE, "h")));
+    assert_eq!(wasm_vfs_inotify_rm_watch(fd, oneshot), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_inotify_rm_watch(fd, 1), 0);
+    assert_eq!(inotify_events(fd), [named(1, IN_IGNORED, "")]);
+
+    let [r, _w] = pipe(0);
+    assert_eq!(wasm_vfs_inotify_add_watch(r, c(b"/wd/h\0"), IN_ALL_EVENTS), -1);
+    assert_eq!(wasm_vfs_errno(), EINVAL);
+    assert_eq!(wasm_vfs_inotify_rm_watch(99, 1), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    assert_eq!(wasm_vfs_fcntl(fd, F_GETFL, 0) & O_ACCMODE, O_RDONLY);
+    assert_eq!(wasm_vfs_close(fd), 0);
+    assert!(get_or_init_proc().inotify.iter().next().is_none());
+}
+
+#[test]
+fn inotify_instances_run_out_with_emfile() {
+    let _serial = fresh();
+    for _ in 0..INOTIFY_CAP {
+        assert!(wasm_vfs_inotify_init() >= 0);
+    }
+    assert_eq!(wasm_vfs_inotify_init(), -1);
+    assert_eq!(wasm_vfs_errno(), EMFILE);
+}
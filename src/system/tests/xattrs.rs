@@ -0,0 +1,94 @@
+use super::*;
+
+#[test]
+fn xattrs_through_paths_fds_and_symlinks() {
+    use crate::xattr::{XATTR_CREATE, XATTR_REPLACE};
+    let _serial = fresh();
+    let user = c(b"user.hash\0");
+    let trusted = c(b"trusted.cap\0");
+    let fd = wasm_vfs_open(c(b"/x\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_setxattr(c(b"/x\0"), user, b"abc".as_ptr(), 3, 0), 0);
+    assert_eq!(wasm_vfs_setxattr(c(b"/x\0"), user, b"abc".as_ptr(), 3, XATTR_CREATE), -1);
+    assert_eq!(wasm_vfs_errno(), EEXIST);
+    assert_eq!(wasm_vfs_fsetxattr(fd, trusted, b"zz".as_ptr(), 2, XATTR_REPLACE), -1);
+    assert_eq!(wasm_vfs_errno(), ENODATA);
+    assert_eq!(wasm_vfs_fsetxattr(fd, trusted, b"".as_ptr(), 0, XATTR_CREATE), 0);
+
+    let mut buf = [0u8; 8];
+    assert_eq!(wasm_vfs_getxattr(c(b"/x\0"), user, core::ptr::null_mut(), 0), 3);
+    assert_eq!(wasm_vfs_getxattr(c(b"/x\0"), user, buf.as_mut_ptr(), 2), -1);
+    assert_eq!(wasm_vfs_errno(), ERANGE);
+    assert_eq!(wasm_vfs_fgetxattr(fd, user, buf.as_mut_ptr(), 8), 3);
+    assert_eq!(&buf[..3], b"abc");
+    assert_eq!(wasm_vfs_getxattr(c(b"/x\0"), c(b"user.none\0"), buf.as_mut_ptr(), 8), -1);
+    assert_eq!(wasm_vfs_errno(), ENODATA);
+    assert_eq!(wasm_vfs_setxattr(c(b"/x\0"), c(b"bogus.x\0"), buf.as_ptr(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EOPNOTSUPP);
+    let big = vec![0u8; 70000];
+    assert_eq!(wasm_vfs_setxattr(c(b"/x\0"), user, big.as_ptr(), big.len(), 0), -1);
+    assert_eq!(wasm_vfs_errno(), E2BIG);
+
+    let mut list = [0u8; 64];
+    assert_eq!(wasm_vfs_listxattr(c(b"/x\0"), core::ptr::null_mut(), 0), 22);
+    assert_eq!(wasm_vfs_listxattr(c(b"/x\0"), list.as_mut_ptr(), 64), 22);
+    assert_eq!(&list[..22], b"trusted.cap\0user.hash\0");
+
+    // The l* calls stop at the link, where only trusted.* and
+    // security.* may be set
+    wasm_vfs_symlink(c(b"x\0"), c(b"/xl\0"));
+    assert_eq!(wasm_vfs_getxattr(c(b"/xl\0"), user, buf.as_mut_ptr(), 8), 3);
+    assert_eq!(wasm_vfs_lgetxattr(c(b"/xl\0"), user, buf.as_mut_ptr(), 8), -1);
+    assert_eq!(wasm_vfs_errno(), ENODATA);
+    assert_eq!(wasm_vfs_lsetxattr(c(b"/xl\0"), user, buf.as_ptr(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_lsetxattr(c(b"/xl\0"), c(b"security.selinux\0"), buf.as_ptr(), 1, 0), 0);
+    assert_eq!(wasm_vfs_llistxattr(c(b"/xl\0"), list.as_mut_ptr(), 64), 17);
+    assert_eq!(wasm_vfs_removexattr(c(b"/xl\0"), user), 0);
+    assert_eq!(wasm_vfs_fremovexattr(fd, user), -1);
+    assert_eq!(wasm_vfs_errno(), ENODATA);
+
+    assert_eq!(wasm_vfs_getxattr(c(b"/nope\0"), user, buf.as_mut_ptr(), 8), -1);
+    assert_eq!(wasm_vfs_errno(), ENOENT);
+    assert_eq!(wasm_vfs_fgetxattr(99, user, buf.as_mut_ptr(), 8), -1);
+    assert_eq!(wasm_vfs_errno(), EBADF);
+    wasm_vfs_symlink(c(b"/loop\0"), c(b"/loop\0"));
+    assert_eq!(wasm_vfs_getxattr(c(b"/loop\0"), user, buf.as_mut_ptr(), 8), -1);
+    assert_eq!(wasm_vfs_errno(), ELOOP);
+}
+
+#[test]
+fn xattr_namespaces_have_their_own_permissions() {
+    let _serial = fresh();
+    let user = c(b"user.tag\0");
+    let fd = wasm_vfs_open(c(b"/x\0"), O_CREAT | O_RDWR, 0o644);
+    assert_eq!(wasm_vfs_setxattr(c(b"/x\0"), user, b"a".as_ptr(), 1, 0), 0);
+    assert_eq!(wasm_vfs_setxattr(c(b"/x\0"), c(b"trusted.t\0"), b"a".as_ptr(), 1, 0), 0);
+    assert_eq!(wasm_vfs_mkdir(c(b"/pub\0"), 0o777), 0);
+    assert_eq!(wasm_vfs_chmod(c(b"/pub\0"), 0o777), 0);
+
+    get_or_init_proc().set_credentials(1000, 1000, &[]);
+    // user.* needs write permission on the file
+    assert_eq!(wasm_vfs_setxattr(c(b"/x\0"), user, b"b".as_ptr(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    assert_eq!(wasm_vfs_fremovexattr(fd, user), -1);
+    assert_eq!(wasm_vfs_errno(), EACCES);
+    // trusted.* and security.* are root's
+    assert_eq!(wasm_vfs_fsetxattr(fd, c(b"trusted.u\0"), b"b".as_ptr(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_lsetxattr(c(b"/x\0"), c(b"security.s\0"), b"b".as_ptr(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_removexattr(c(b"/x\0"), c(b"trusted.t\0")), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    // ACLs are the owner's
+    let acl = Acl::from_mode(0o600).to_xattr();
+    assert_eq!(wasm_vfs_setxattr(c(b"/x\0"), c(b"system.posix_acl_access\0"), acl.as_ptr(), acl.len(), 0), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+
+    // On its own file the user may set user.* and ACLs, but not trusted.*
+    let mine = wasm_vfs_open(c(b"/pub/mine\0"), O_CREAT | O_RDWR, 0o600);
+    assert!(mine >= 0);
+    assert_eq!(wasm_vfs_fsetxattr(mine, user, b"b".as_ptr(), 1, 0), 0);
+    assert_eq!(wasm_vfs_fsetxattr(mine, c(b"trusted.u\0"), b"b".as_ptr(), 1, 0), -1);
+    assert_eq!(wasm_vfs_errno(), EPERM);
+    assert_eq!(wasm_vfs_fsetxattr(mine, c(b"system.posix_acl_access\0"), acl.as_ptr(), acl.len(), 0), 0);
+}
@@ -0,0 +1,143 @@
+// timerfd.rs
+#![allow(dead_code)]
+
+// timerfd timers. Nothing fires on its own: expirations are counted
+// from the clock whenever the timer is read, polled or queried.
+
+use crate::clock::{self, Timespec};
+use crate::errno::{EAGAIN, EINVAL};
+
+pub const TFD_TIMER_ABSTIME: i32 = 0x1;
+pub const TFD_TIMER_CANCEL_ON_SET: i32 = 0x2;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Itimerspec {
+    pub it_interval: Timespec,
+    pub it_value: Timespec,
+}
+
+#[derive(Debug, Clone)]
+pub struct Timer {
+    clock: i32,
+    // Period in ns; 0 for a one-shot timer
+    interval: u64,
+    // Next expiry on `clock`, or None when disarmed
+    next: Option<u64>,
+    // Expirations not read yet
+    expirations: u64,
+}
+
+impl Timer {
+    pub fn new(clock: i32) -> Self {
+        Self { clock, interval: 0, next: None, expirations: 0 }
+    }
+
+    // Counts the expirations that have passed since the last update
+    fn update(&mut self) {
+        let next = match self.next {
+            Some(next) => next,
+            None => return,
+        };
+        let now = clock::now(self.clock);
+        if now < next {
+            return;
+        }
+        if self.interval == 0 {
+            self.expirations += 1;
+            self.next = None;
+        } else {
+            let n = (now - next) / self.interval + 1;
+            self.expirations = self.expirations.saturating_add(n);
+            self.next = Some(next + n * self.interval);
+        }
+    }
+
+    pub fn gettime(&mut self) -> Itimerspec {
+        self.update();
+        let remaining = self.next.map(|next| next - clock::now(self.clock)).unwrap_or(0);
+        Itimerspec { it_interval: clock::to_timespec(self.interval), it_value: clock::to_timespec(remaining) }
+    }
+
+    // Arms (or with a zero value, disarms) the timer and returns the
+    // previous setting. Pending expirations are dropped.
+    pub fn settime(&mut self, flags: i32, new: &Itimerspec) -> Result<Itimerspec, i32> {
+        if flags & !(TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET) != 0 {
+            return Err(EINVAL);
+        }
+        let value = clock::from_timespec(&new.it_value)?;
+        let interval = clock::from_timespec(&new.it_interval)?;
+        let old = self.gettime();
+        self.expirations = 0;
+        self.interval = interval;
+        self.next = if value == 0 {
+            None
+        } else if flags & TFD_TIMER_ABSTIME != 0 {
+            Some(value)
+        } else {
+            Some(clock::now(self.clock).saturating_add(value))
+        };
+        Ok(old)
+    }
+
+    // The expiration count as a u64; none yet would block, so EAGAIN
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, i32> {
+        if buf.len() < 8 {
+            return Err(EINVAL);
+        }
+        self.update();
+        if self.expirations == 0 {
+            return Err(EAGAIN);
+        }
+        buf[..8].copy_from_slice(&self.expirations.to_ne_bytes());
+        self.expirations = 0;
+        Ok(8)
+    }
+
+    pub fn readable(&mut self) -> bool {
+        self.update();
+        self.expirations > 0
+    }
+
+    // Nanoseconds until the next expiry, if armed
+    pub fn time_to_expiry(&self) -> Option<u64> {
+        self.next.map(|next| next.saturating_sub(clock::now(self.clock)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The virtual clock is shared with the system tests, which move it,
+    // so these stay away from anything that depends on the time
+    fn spec(interval: u64, value: u64) -> Itimerspec {
+        Itimerspec { it_interval: clock::to_timespec(interval), it_value: clock::to_timespec(value) }
+    }
+
+    #[test]
+    fn settime_checks_its_arguments() {
+        let mut timer = Timer::new(clock::CLOCK_MONOTONIC);
+        assert_eq!(timer.settime(0x4, &spec(0, 1)), Err(EINVAL));
+        let bad = Itimerspec { it_interval: Timespec::default(), it_value: Timespec { tv_sec: 0, tv_nsec: 1_000_000_000 } };
+        assert_eq!(timer.settime(0, &bad), Err(EINVAL));
+        let negative = Itimerspec { it_interval: Timespec { tv_sec: -1, tv_nsec: 0 }, it_value: Timespec::default() };
+        assert_eq!(timer.settime(0, &negative), Err(EINVAL));
+        assert_eq!(timer.time_to_expiry(), None);
+    }
+
+    #[test]
+    fn a_disarmed_timer_never_fires() {
+        let mut timer = Timer::new(clock::CLOCK_MONOTONIC);
+        assert_eq!(timer.gettime(), Itimerspec::default());
+        assert_eq!(timer.read(&mut [0u8; 8]), Err(EAGAIN));
+        assert_eq!(timer.read(&mut [0u8; 4]), Err(EINVAL));
+        assert!(!timer.readable());
+        // arming far off and disarming hands back the old setting
+        timer.settime(0, &spec(5, u64::MAX / 4)).unwrap();
+        let old = timer.settime(0, &spec(0, 0)).unwrap();
+        assert_eq!(old.it_interval, clock::to_timespec(5));
+        assert_eq!(timer.time_to_expiry(), None);
+        assert!(!timer.readable());
+    }
+}
@@ -0,0 +1,480 @@
+// tty.rs
+#![allow(dead_code)]
+
+use crate::collections::ByteQueue;
+use crate::errno::{EAGAIN, EINTR};
+use crate::stdio::BufferedStream;
+
+// c_iflag
+pub const IGNBRK: u32 = 0o1;
+pub const BRKINT: u32 = 0o2;
+pub const ISTRIP: u32 = 0o40;
+pub const INLCR: u32 = 0o100;
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+pub const IXON: u32 = 0o2000;
+pub const IUTF8: u32 = 0o40000;
+
+// c_oflag
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+
+// c_cflag
+pub const B38400: u32 = 0o17;
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+
+// c_lflag
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+pub const NOFLSH: u32 = 0o200;
+pub const ECHOCTL: u32 = 0o1000;
+pub const ECHOKE: u32 = 0o4000;
+pub const IEXTEN: u32 = 0o100000;
+
+// c_cc indices
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VWERASE: usize = 14;
+pub const VLNEXT: usize = 15;
+pub const VEOL2: usize = 16;
+
+pub const NCCS: usize = 32;
+
+// Signals the line discipline can raise
+pub const SIGINT: i32 = 2;
+pub const SIGQUIT: i32 = 3;
+pub const SIGTSTP: i32 = 20;
+pub const SIGWINCH: i32 = 28;
+
+// musl's struct termios, which is what tcgetattr/tcsetattr hand to TCGETS/TCSETS
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; NCCS],
+    pub c_ispeed: u32,
+    pub c_ospeed: u32,
+}
+
+impl Default for Termios {
+    // What a fresh Linux pty starts with (`stty sane`)
+    fn default() -> Self {
+        let mut c_cc = [0u8; NCCS];
+        c_cc[VINTR] = 0x03;   // ^C
+        c_cc[VQUIT] = 0x1c;   // ^\
+        c_cc[VERASE] = 0x7f;  // DEL
+        c_cc[VKILL] = 0x15;   // ^U
+        c_cc[VEOF] = 0x04;    // ^D
+        c_cc[VTIME] = 0;
+        c_cc[VMIN] = 1;
+        c_cc[VSTART] = 0x11;  // ^Q
+        c_cc[VSTOP] = 0x13;   // ^S
+        c_cc[VSUSP] = 0x1a;   // ^Z
+        c_cc[VWERASE] = 0x17; // ^W
+        c_cc[VLNEXT] = 0x16;  // ^V
+        Self {
+            c_iflag: ICRNL | IXON | IUTF8,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: B38400 | CS8 | CREAD,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc,
+            c_ispeed: B38400,
+            c_ospeed: B38400,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Winsize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+// A terminal: termios state plus the line discipline between the
+// keystrokes the host feeds in and what the guest reads back.
+// Output (including echo) goes to the wrapped stream.
+pub struct Tty {
+    pub termios: Termios,
+    pub winsize: Winsize,
+    output: BufferedStream,
+    // Canonical mode: finished lines, oldest first. An empty line is
+    // an end-of-file mark left by ^D at the start of a line.
+    lines: Vec<Vec<u8>>,
+    // Canonical mode: the line still being edited
+    line: Vec<u8>,
+    // Non-canonical mode: bytes available to read
+    raw: ByteQueue,
+    literal_next: bool,
+    // The next read fails with EINTR after ^C/^\/^Z
+    interrupted: bool,
+    // Signal raised by the line discipline, for the host to collect
+    pending_signal: i32,
+}
+
+impl Tty {
+    pub fn new(output: BufferedStream) -> Self {
+        Self {
+            termios: Termios::default(),
+            winsize: Winsize { ws_row: 24, ws_col: 80, ws_xpixel: 0, ws_ypixel: 0 },
+            output,
+            lines: Vec::new(),
+            line: Vec::new(),
+            raw: ByteQueue::new(),
+            literal_next: false,
+            interrupted: false,
+            pending_signal: 0,
+        }
+    }
+
+    fn canonical(&self) -> bool {
+        self.termios.c_lflag & ICANON != 0
+    }
+
+    fn lflag(&self, flag: u32) -> bool {
+        self.termios.c_lflag & flag != 0
+    }
+
+    // Returns and clears the last signal raised (0 if none)
+    pub fn take_signal(&mut self) -> i32 {
+        core::mem::replace(&mut self.pending_signal, 0)
+    }
+
+    pub fn set_winsize(&mut self, winsize: Winsize) {
+        if winsize != self.winsize {
+            self.winsize = winsize;
+            self.pending_signal = SIGWINCH;
+        }
+    }
+
+    // TCSETS*. Leaving canonical mode makes the partial line readable;
+    // entering it turns unread raw bytes back into an editable line.
+    pub fn set_termios(&mut self, termios: Termios, flush_input: bool) {
+        let was_canonical = self.canonical();
+        self.termios = termios;
+        if flush_input {
+            self.flush_input();
+        }
+        let now_canonical = self.canonical();
+        if was_canonical && !now_canonical {
+            for line in self.lines.drain(..) {
+                self.raw.push_slice(&line);
+            }
+            self.raw.push_slice(&self.line);
+            self.line.clear();
+        } else if !was_canonical && now_canonical {
+            self.line.extend_from_slice(self.raw.as_slice());
+            self.raw.clear();
+        }
+    }
+
+    pub fn flush_input(&mut self) {
+        self.lines.clear();
+        self.line.clear();
+        self.raw.clear();
+    }
+
+    pub fn flush_output(&mut self) -> Result<(), i32> {
+        self.output.flush()
+    }
+
+    // FIONREAD: bytes a read could return right now
+    pub fn available(&self) -> usize {
+        if self.canonical() {
+            self.lines.iter().map(|l| l.len()).sum()
+        } else {
+            self.raw.len()
+        }
+    }
+
+    // Whether a read would return right away, for poll: a finished
+    // line (or end-of-file mark), raw input, a VMIN of 0 or a pending
+    // EINTR
+    pub fn readable(&self) -> bool {
+        if self.interrupted {
+            return true;
+        }
+        if self.canonical() {
+            !self.lines.is_empty()
+        } else {
+            !self.raw.is_empty() || self.termios.c_cc[VMIN] == 0
+        }
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, i32> {
+        if self.interrupted {
+            self.interrupted = false;
+            return Err(EINTR);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.canonical() {
+            if self.lines.is_empty() {
+                return Err(EAGAIN);
+            }
+            // One line per read, the rest stays for the next call
+            let line = &mut self.lines[0];
+            let n = if buf.len() < line.len() { buf.len() } else { line.len() };
+            buf[..n].copy_from_slice(&line[..n]);
+            if n == line.len() {
+                self.lines.remove(0);
+            } else {
+                line.drain(..n);
+            }
+            return Ok(n);
+        }
+        if self.raw.is_empty() {
+            return if self.termios.c_cc[VMIN] == 0 { Ok(0) } else { Err(EAGAIN) };
+        }
+        Ok(self.raw.pop_into(buf))
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<usize, i32> {
+        self.emit(data)?;
+        Ok(data.len())
+    }
+
+    // Output processing (OPOST/ONLCR) on the way to the stream
+    fn emit(&mut self, data: &[u8]) -> Result<(), i32> {
+        let oflag = self.termios.c_oflag;
+        if oflag & OPOST != 0 && oflag & ONLCR != 0 && data.contains(&b'\n') {
+            let mut translated = Vec::with_capacity(data.len() + 8);
+            for &byte in data {
+                if byte == b'\n' {
+                    translated.push(b'\r');
+                }
+                translated.push(byte);
+            }
+            self.output.write(&translated)?;
+        } else {
+            self.output.write(data)?;
+        }
+        Ok(())
+    }
+
+    fn echo_byte(&mut self, byte: u8) {
+        let control = byte < 0x20 && byte != b'\n' && byte != b'\t';
+        if (control || byte == 0x7f) && self.lflag(ECHOCTL) {
+            let shown = if byte == 0x7f { b'?' } else { byte + 0x40 };
+            let _ = self.emit(&[b'^', shown]);
+        } else {
+            let _ = self.emit(&[byte]);
+        }
+    }
+
+    // Rubs out the last character of the line being edited. With
+    // IUTF8 a whole multi-byte character goes at once.
+    fn erase_char(&mut self) {
+        let mut last = match self.line.pop() {
+            Some(b) => b,
+            None => return,
+        };
+        if self.termios.c_iflag & IUTF8 != 0 {
+            while last & 0xc0 == 0x80 {
+                match self.line.pop() {
+                    Some(b) => last = b,
+                    None => break,
+                }
+            }
+        }
+        if self.lflag(ECHO) && self.lflag(ECHOE) {
+            let _ = self.emit(b"\x08 \x08");
+        }
+    }
+
+    fn raise(&mut self, signal: i32, byte: u8) {
+        if !self.lflag(NOFLSH) {
+            self.flush_input();
+        }
+        if self.lflag(ECHO) {
+            self.echo_byte(byte);
+        }
+        self.interrupted = true;
+        self.pending_signal = signal;
+    }
+
+    fn finish_line(&mut self) {
+        let line = core::mem::take(&mut self.line);
+        self.lines.push(line);
+    }
+
+    // Keystrokes from the host, run through the line discipline
+    pub fn input(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            let mut byte = b;
+            let cc = self.termios.c_cc;
+            let iflag = self.termios.c_iflag;
+
+            if self.literal_next {
+                self.literal_next = false;
+                self.line.push(byte);
+                if self.lflag(ECHO) {
+                    self.echo_byte(byte);
+                }
+                continue;
+            }
+
+            if byte == b'\r' {
+                if iflag & IGNCR != 0 {
+                    continue;
+                }
+                if iflag & ICRNL != 0 {
+                    byte = b'\n';
+                }
+            } else if byte == b'\n' && iflag & INLCR != 0 {
+                byte = b'\r';
+            }
+            if iflag & ISTRIP != 0 {
+                byte &= 0x7f;
+            }
+
+            if self.lflag(ISIG) {
+                let signal = if byte == cc[VINTR] {
+                    SIGINT
+                } else if byte == cc[VQUIT] {
+                    SIGQUIT
+                } else if byte == cc[VSUSP] {
+                    SIGTSTP
+                } else {
+                    0
+                };
+                if signal != 0 && byte != 0 {
+                    self.raise(signal, byte);
+                    continue;
+                }
+            }
+
+            if !self.canonical() {
+                self.raw.push_slice(&[byte]);
+                if self.lflag(ECHO) {
+                    self.echo_byte(byte);
+                }
+                continue;
+            }
+
+            if self.lflag(IEXTEN) && byte == cc[VLNEXT] {
+                self.literal_next = true;
+            } else if byte == cc[VERASE] {
+                self.erase_char();
+            } else if self.lflag(IEXTEN) && byte == cc[VWERASE] {
+                while self.line.last() == Some(&b' ') {
+                    self.erase_char();
+                }
+                while matches!(self.line.last(), Some(c) if *c != b' ') {
+                    self.erase_char();
+                }
+            } else if byte == cc[VKILL] {
+                while !self.line.is_empty() {
+                    self.erase_char();
+                }
+            } else if byte == cc[VEOF] {
+                // ^D hands over the partial line as-is; on an empty
+                // line that is a zero-length read, i.e. end-of-file
+                self.finish_line();
+            } else if byte == b'\n' || (byte != 0 && (byte == cc[VEOL] || byte == cc[VEOL2])) {
+                self.line.push(byte);
+                if self.lflag(ECHO) || (byte == b'\n' && self.lflag(ECHONL)) {
+                    let _ = self.emit(&[byte]);
+                }
+                self.finish_line();
+            } else {
+                self.line.push(byte);
+                if self.lflag(ECHO) {
+                    self.echo_byte(byte);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdio::{BufferMode, CaptureStream};
+
+    fn tty() -> (CaptureStream, Tty) {
+        let capture = CaptureStream::new();
+        (capture.clone(), Tty::new(BufferedStream::new(Box::new(capture), BufferMode::Unbuffered)))
+    }
+
+    #[test]
+    fn canonical_mode_edits_and_echoes_the_line() {
+        let (capture, mut tty) = tty();
+        let mut buf = [0u8; 32];
+        tty.input(b"lx\x7fs -l");
+        assert_eq!(tty.read(&mut buf), Err(EAGAIN));
+        tty.input(b"\r");
+        assert_eq!(tty.available(), 6);
+        assert_eq!(tty.read(&mut buf), Ok(6));
+        assert_eq!(&buf[..6], b"ls -l\n");
+        assert_eq!(capture.take_output(), b"lx\x08 \x08s -l\r\n");
+    }
+
+    #[test]
+    fn eof_at_line_start_reads_as_zero() {
+        let (_capture, mut tty) = tty();
+        let mut buf = [0u8; 8];
+        tty.input(b"\x04");
+        assert_eq!(tty.read(&mut buf), Ok(0));
+        assert_eq!(tty.read(&mut buf), Err(EAGAIN));
+    }
+
+    #[test]
+    fn interrupt_raises_sigint_and_eintr() {
+        let (_capture, mut tty) = tty();
+        let mut buf = [0u8; 8];
+        tty.input(b"abc\x03");
+        assert!(tty.readable());
+        assert_eq!(tty.read(&mut buf), Err(EINTR));
+        assert_eq!(tty.take_signal(), SIGINT);
+        assert_eq!(tty.take_signal(), 0);
+    }
+
+    #[test]
+    fn raw_mode_hands_over_bytes_as_they_come() {
+        let (capture, mut tty) = tty();
+        let mut termios = tty.termios;
+        termios.c_lflag &= !(ICANON | ECHO);
+        tty.input(b"par");
+        tty.set_termios(termios, false);
+        tty.input(b"q");
+        let mut buf = [0u8; 8];
+        assert_eq!(tty.read(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"parq");
+        capture.take_output();
+        assert_eq!(tty.write(b"a\n"), Ok(2));
+        assert_eq!(capture.take_output(), b"a\r\n");
+    }
+
+    #[test]
+    fn resizing_raises_sigwinch_once() {
+        let (_capture, mut tty) = tty();
+        let winsize = Winsize { ws_row: 50, ws_col: 132, ws_xpixel: 0, ws_ypixel: 0 };
+        tty.set_winsize(winsize);
+        tty.set_winsize(winsize);
+        assert_eq!(tty.take_signal(), SIGWINCH);
+        assert_eq!(tty.take_signal(), 0);
+    }
+}
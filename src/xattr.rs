@@ -0,0 +1,175 @@
+// xattr.rs
+#![allow(dead_code)]
+
+// Extended attributes: named byte strings kept on each inode, with
+// the limits and error numbers of Linux's setxattr(2) family.
+
+use crate::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::errno::{E2BIG, EEXIST, EINVAL, ENODATA, EOPNOTSUPP, ERANGE};
+
+// setxattr flags
+pub const XATTR_CREATE: i32 = 0x1;
+pub const XATTR_REPLACE: i32 = 0x2;
+
+// Same limits as Linux
+pub const XATTR_NAME_MAX: usize = 255;
+pub const XATTR_SIZE_MAX: usize = 65536;
+pub const XATTR_LIST_MAX: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    User,
+    Trusted,
+    Security,
+    System,
+}
+
+impl Namespace {
+    // The namespace of a full attribute name such as "user.mime_type".
+    // Names outside the four namespaces (or with nothing after the
+    // prefix) are EOPNOTSUPP, and over-long ones ERANGE.
+    pub fn of(name: &str) -> Result<Namespace, i32> {
+        if name.is_empty() || name.len() > XATTR_NAME_MAX {
+            return Err(ERANGE);
+        }
+        let namespaces = [
+            ("user.", Namespace::User),
+            ("trusted.", Namespace::Trusted),
+            ("security.", Namespace::Security),
+            ("system.", Namespace::System),
+        ];
+        for (prefix, namespace) in namespaces {
+            if let Some(rest) = name.strip_prefix(prefix) {
+                return if rest.is_empty() { Err(EOPNOTSUPP) } else { Ok(namespace) };
+            }
+        }
+        Err(EOPNOTSUPP)
+    }
+}
+
+// The attributes of one inode, kept sorted so listxattr is stable
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct Xattrs {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl Xattrs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Result<&[u8], i32> {
+        self.entries.get(name).map(|v| v.as_slice()).ok_or(ENODATA)
+    }
+
+    pub fn set(&mut self, name: &str, value: &[u8], flags: i32) -> Result<(), i32> {
+        if flags & !(XATTR_CREATE | XATTR_REPLACE) != 0 {
+            return Err(EINVAL);
+        }
+        if value.len() > XATTR_SIZE_MAX {
+            return Err(E2BIG);
+        }
+        let exists = self.entries.contains_key(name);
+        if flags & XATTR_CREATE != 0 && exists {
+            return Err(EEXIST);
+        }
+        if flags & XATTR_REPLACE != 0 && !exists {
+            return Err(ENODATA);
+        }
+        self.entries.insert(name.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), i32> {
+        self.entries.remove(name).map(|_| ()).ok_or(ENODATA)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|k| k.as_str())
+    }
+
+    // listxattr's buffer: every name followed by a NUL
+    pub fn list(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for name in self.names() {
+            out.extend_from_slice(name.as_bytes());
+            out.push(0);
+        }
+        out
+    }
+}
+
+// Copies `value` out the way getxattr/listxattr do: a zero-sized buffer
+// asks for the size, a buffer that is too small is ERANGE.
+pub(crate) fn copy_out(value: &[u8], buf: *mut u8, size: usize) -> Result<usize, i32> {
+    if size == 0 {
+        return Ok(value.len());
+    }
+    if size < value.len() {
+        return Err(ERANGE);
+    }
+    unsafe {
+        core::ptr::copy_nonoverlapping(value.as_ptr(), buf, value.len());
+    }
+    Ok(value.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_come_from_the_prefix() {
+        assert_eq!(Namespace::of("user.mime_type"), Ok(Namespace::User));
+        assert_eq!(Namespace::of("trusted.cap"), Ok(Namespace::Trusted));
+        assert_eq!(Namespace::of("security.selinux"), Ok(Namespace::Security));
+        assert_eq!(Namespace::of("system.posix_acl_access"), Ok(Namespace::System));
+        assert_eq!(Namespace::of("user."), Err(EOPNOTSUPP));
+        assert_eq!(Namespace::of("bogus.x"), Err(EOPNOTSUPP));
+        assert_eq!(Namespace::of(""), Err(ERANGE));
+        let long = format!("user.{}", "n".repeat(XATTR_NAME_MAX));
+        assert_eq!(Namespace::of(&long), Err(ERANGE));
+    }
+
+    #[test]
+    fn set_honours_create_and_replace() {
+        let mut xattrs = Xattrs::new();
+        assert!(xattrs.is_empty());
+        assert_eq!(xattrs.set("user.a", b"1", XATTR_REPLACE), Err(ENODATA));
+        assert_eq!(xattrs.set("user.a", b"1", XATTR_CREATE), Ok(()));
+        assert_eq!(xattrs.set("user.a", b"2", XATTR_CREATE), Err(EEXIST));
+        assert_eq!(xattrs.set("user.a", b"2", XATTR_REPLACE), Ok(()));
+        assert_eq!(xattrs.get("user.a"), Ok(&b"2"[..]));
+        assert_eq!(xattrs.set("user.a", b"3", 0x4), Err(EINVAL));
+        assert_eq!(xattrs.set("user.a", &[0; XATTR_SIZE_MAX + 1], 0), Err(E2BIG));
+        assert_eq!(xattrs.set("user.a", &[0; XATTR_SIZE_MAX], 0), Ok(()));
+    }
+
+    #[test]
+    fn remove_and_list() {
+        let mut xattrs = Xattrs::new();
+        xattrs.set("user.b", b"", 0).unwrap();
+        xattrs.set("trusted.a", b"x", 0).unwrap();
+        assert_eq!(xattrs.list(), b"trusted.a\0user.b\0");
+        assert_eq!(xattrs.remove("user.b"), Ok(()));
+        assert_eq!(xattrs.remove("user.b"), Err(ENODATA));
+        assert_eq!(xattrs.get("user.b"), Err(ENODATA));
+        assert_eq!(xattrs.names().collect::<Vec<_>>(), ["trusted.a"]);
+    }
+
+    #[test]
+    fn copy_out_sizes_and_ranges() {
+        let mut buf = [0u8; 4];
+        assert_eq!(copy_out(b"abc", buf.as_mut_ptr(), 0), Ok(3));
+        assert_eq!(copy_out(b"abc", buf.as_mut_ptr(), 2), Err(ERANGE));
+        assert_eq!(copy_out(b"abc", buf.as_mut_ptr(), 4), Ok(3));
+        assert_eq!(&buf[..3], b"abc");
+    }
+}
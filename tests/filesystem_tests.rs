@@ -1,187 +1,239 @@
-#[cfg(test)]
+use std::ffi::CString;
+use std::sync::{Mutex, MutexGuard};
+
+use wasm_vfs::{get_or_init_proc, Proc};
+
+const O_RDWR: i32 = 2;
+const O_CREAT: i32 = 64;
+const O_DIRECTORY: i32 = 65536;
+const F_GETFD: i32 = 1;
+const EBADF: i32 = 9;
+const ENOENT: i32 = 2;
+
+extern "C" {
+    fn wasm_vfs_open(path: *const i8, flags: i32, mode: u32) -> i32;
+    fn wasm_vfs_openat(dirfd: i32, path: *const i8, flags: i32, mode: u32) -> i32;
+    fn wasm_vfs_creat(path: *const i8, mode: u32) -> i32;
+    fn wasm_vfs_close(fd: i32) -> i32;
+    fn wasm_vfs_dup(oldfd: i32) -> i32;
+    fn wasm_vfs_dup2(oldfd: i32, newfd: i32) -> i32;
+    fn wasm_vfs_fcntl(fd: i32, cmd: i32, arg: i32) -> i32;
+    fn wasm_vfs_write(fd: i32, buf: *const u8, count: usize) -> isize;
+    fn wasm_vfs_pread64(fd: i32, buf: *mut u8, count: usize, offset: i64) -> isize;
+    fn wasm_vfs_errno() -> i32;
+}
+
+// The library's default stdio writes through this host import
+#[no_mangle]
+extern "C" fn box_host_write_stdout_line(_ptr: *const u8, _len: usize) {}
+
+// Every test works on the one global process; take turns and start
+// each from a fresh one.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn fresh() -> MutexGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    *get_or_init_proc() = Proc::new();
+    guard
+}
+
+// An fd is open when F_GETFD succeeds on it
+fn is_open(fd: i32) -> bool {
+    unsafe { wasm_vfs_fcntl(fd, F_GETFD, 0) >= 0 }
+}
+
+// Both descriptors reach the same file: what goes in through one
+// comes out of the other.
+fn same_file(a: i32, b: i32) -> bool {
+    let mut buf = [0u8; 4];
+    unsafe {
+        wasm_vfs_write(a, b"same".as_ptr(), 4) == 4
+            && wasm_vfs_pread64(b, buf.as_mut_ptr(), 4, 0) == 4
+            && &buf == b"same"
+    }
+}
+
 mod tests {
     use super::*;
-    use std::path::PathBuf;
-    use std::ffi::CString;
 
     #[test]
     fn test_open_happy_path() {
+        let _serial = fresh();
         let path = CString::new("/testfile").unwrap();
         let flags = O_CREAT | O_RDWR;
         let mode = 0o644;
 
         // Create a file using open
-        let fd = unsafe { open(path.as_ptr(), flags, mode) };
+        let fd = unsafe { wasm_vfs_open(path.as_ptr(), flags, mode) };
         assert!(fd >= 0, "Failed to create and open file");
 
         // Ensure the file descriptor table contains the entry
-        let proc = get_or_init_proc();
-        assert!(proc.as_ref().unwrap().fd_table[fd as usize].is_some());
+        assert!(is_open(fd));
     }
 
     #[test]
     fn test_open_sad_path() {
+        let _serial = fresh();
         let path = CString::new("/nonexistentfile").unwrap();
         let flags = O_RDWR;
         let mode = 0o644;
 
         // Try to open a non-existent file without O_CREAT
-        let fd = unsafe { open(path.as_ptr(), flags, mode) };
+        let fd = unsafe { wasm_vfs_open(path.as_ptr(), flags, mode) };
         assert_eq!(fd, -1, "Opened a non-existent file without O_CREAT");
+        assert_eq!(unsafe { wasm_vfs_errno() }, ENOENT);
     }
 
     #[test]
     fn test_close_happy_path() {
+        let _serial = fresh();
         let path = CString::new("/testfile").unwrap();
         let flags = O_CREAT | O_RDWR;
         let mode = 0o644;
 
         // Create and open a file
-        let fd = unsafe { open(path.as_ptr(), flags, mode) };
+        let fd = unsafe { wasm_vfs_open(path.as_ptr(), flags, mode) };
         assert!(fd >= 0, "Failed to create and open file");
 
         // Close the file
-        let result = unsafe { close(fd) };
+        let result = unsafe { wasm_vfs_close(fd) };
         assert_eq!(result, 0, "Failed to close the file");
 
         // Ensure the file descriptor table no longer contains the entry
-        let proc = get_or_init_proc();
-        assert!(proc.as_ref().unwrap().fd_table[fd as usize].is_none());
+        assert!(!is_open(fd));
     }
 
     #[test]
     fn test_close_sad_path() {
+        let _serial = fresh();
         let invalid_fd = 999;
 
         // Try to close an invalid file descriptor
-        let result = unsafe { close(invalid_fd) };
+        let result = unsafe { wasm_vfs_close(invalid_fd) };
         assert_eq!(result, -1, "Closed an invalid file descriptor");
+        assert_eq!(unsafe { wasm_vfs_errno() }, EBADF);
     }
 
     #[test]
     fn test_creat_happy_path() {
+        let _serial = fresh();
         let path = CString::new("/testfile").unwrap();
         let mode = 0o644;
 
         // Create a file using creat
-        let fd = unsafe { creat(path.as_ptr(), mode) };
+        let fd = unsafe { wasm_vfs_creat(path.as_ptr(), mode) };
         assert!(fd >= 0, "Failed to create file");
 
         // Ensure the file descriptor table contains the entry
-        let proc = get_or_init_proc();
-        assert!(proc.as_ref().unwrap().fd_table[fd as usize].is_some());
+        assert!(is_open(fd));
     }
 
     #[test]
     fn test_creat_sad_path() {
+        let _serial = fresh();
         let path = CString::new("").unwrap();
         let mode = 0o644;
 
         // Try to create a file with an empty path
-        let fd = unsafe { creat(path.as_ptr(), mode) };
+        let fd = unsafe { wasm_vfs_creat(path.as_ptr(), mode) };
         assert_eq!(fd, -1, "Created a file with an invalid path");
+        assert_eq!(unsafe { wasm_vfs_errno() }, ENOENT);
     }
 
     #[test]
     fn test_openat_happy_path() {
+        let _serial = fresh();
         let dir_path = CString::new("/testdir").unwrap();
         let file_path = CString::new("testfile").unwrap();
         let flags = O_CREAT | O_RDWR;
         let mode = 0o644;
 
         // Create and open a directory
-        let dir_fd = unsafe { open(dir_path.as_ptr(), O_CREAT | O_DIRECTORY, 0o755) };
+        let dir_fd = unsafe { wasm_vfs_open(dir_path.as_ptr(), O_CREAT | O_DIRECTORY, 0o755) };
         assert!(dir_fd >= 0, "Failed to create and open directory");
 
         // Create and open a file within the directory using openat
-        let file_fd = unsafe { openat(dir_fd, file_path.as_ptr(), flags, mode) };
+        let file_fd = unsafe { wasm_vfs_openat(dir_fd, file_path.as_ptr(), flags, mode) };
         assert!(file_fd >= 0, "Failed to create and open file in directory");
 
         // Ensure the file descriptor table contains the entry
-        let proc = get_or_init_proc();
-        assert!(proc.as_ref().unwrap().fd_table[file_fd as usize].is_some());
+        assert!(is_open(file_fd));
     }
 
     #[test]
     fn test_openat_sad_path() {
+        let _serial = fresh();
         let dir_fd = 999; // Invalid directory file descriptor
         let file_path = CString::new("testfile").unwrap();
         let flags = O_RDWR;
         let mode = 0o644;
 
         // Try to open a file within an invalid directory
-        let file_fd = unsafe { openat(dir_fd, file_path.as_ptr(), flags, mode) };
+        let file_fd = unsafe { wasm_vfs_openat(dir_fd, file_path.as_ptr(), flags, mode) };
         assert_eq!(file_fd, -1, "Opened a file in an invalid directory");
+        assert_eq!(unsafe { wasm_vfs_errno() }, EBADF);
     }
 }
 
 #[test]
 fn test_dup_happy_path() {
+    let _serial = fresh();
     let path = CString::new("/testfile").unwrap();
     let flags = O_CREAT | O_RDWR;
     let mode = 0o644;
 
     // Open a file
-    let fd = unsafe { open(path.as_ptr(), flags, mode) };
+    let fd = unsafe { wasm_vfs_open(path.as_ptr(), flags, mode) };
     assert!(fd >= 0, "Failed to create and open file");
 
     // Duplicate the file descriptor
-    let new_fd = unsafe { dup(fd) };
+    let new_fd = unsafe { wasm_vfs_dup(fd) };
     assert!(new_fd >= 0, "Failed to duplicate file descriptor");
 
     // Ensure both file descriptors point to the same inode
-    let proc = get_or_init_proc();
-    let proc = proc.as_ref().unwrap();
-    assert_eq!(
-        proc.fd_table[fd as usize],
-        proc.fd_table[new_fd as usize],
-        "Duplicated file descriptor does not point to the same inode"
-    );
+    assert!(same_file(fd, new_fd), "Duplicated file descriptor does not point to the same inode");
 }
 
 #[test]
 fn test_dup_sad_path() {
+    let _serial = fresh();
     let invalid_fd = 999; // Invalid file descriptor
 
     // Try to duplicate an invalid file descriptor
-    let new_fd = unsafe { dup(invalid_fd) };
+    let new_fd = unsafe { wasm_vfs_dup(invalid_fd) };
     assert_eq!(new_fd, -1, "Duplicated an invalid file descriptor");
 }
 
 #[test]
 fn test_dup2_happy_path() {
+    let _serial = fresh();
     let path = CString::new("/testfile").unwrap();
     let flags = O_CREAT | O_RDWR;
     let mode = 0o644;
 
     // Open a file
-    let fd = unsafe { open(path.as_ptr(), flags, mode) };
+    let fd = unsafe { wasm_vfs_open(path.as_ptr(), flags, mode) };
     assert!(fd >= 0, "Failed to create and open file");
 
     // Allocate a new file descriptor
     let new_fd = 10;
 
     // Duplicate fd to a specific new_fd
-    let result_fd = unsafe { dup2(fd, new_fd) };
+    let result_fd = unsafe { wasm_vfs_dup2(fd, new_fd) };
     assert_eq!(result_fd, new_fd, "Failed to duplicate to a specific file descriptor");
 
     // Ensure both file descriptors point to the same inode
-    let proc = get_or_init_proc();
-    let proc = proc.as_ref().unwrap();
-    assert_eq!(
-        proc.fd_table[fd as usize],
-        proc.fd_table[new_fd as usize],
-        "Duplicated file descriptor does not point to the same inode"
-    );
+    assert!(same_file(fd, new_fd), "Duplicated file descriptor does not point to the same inode");
 }
 
 #[test]
 fn test_dup2_sad_path() {
+    let _serial = fresh();
     let invalid_fd = 999; // Invalid file descriptor
     let new_fd = 10; // Target file descriptor
 
     // Try to duplicate an invalid file descriptor
-    let result_fd = unsafe { dup2(invalid_fd, new_fd) };
+    let result_fd = unsafe { wasm_vfs_dup2(invalid_fd, new_fd) };
     assert_eq!(result_fd, -1, "Duplicated an invalid file descriptor");
 
     // Try to duplicate to an out-of-range new_fd
@@ -190,10 +242,9 @@ fn test_dup2_sad_path() {
     let flags = O_CREAT | O_RDWR;
     let mode = 0o644;
 
-    let fd = unsafe { open(path.as_ptr(), flags, mode) };
+    let fd = unsafe { wasm_vfs_open(path.as_ptr(), flags, mode) };
     assert!(fd >= 0, "Failed to create and open file");
 
-    let result_fd = unsafe { dup2(fd, out_of_range_fd) };
+    let result_fd = unsafe { wasm_vfs_dup2(fd, out_of_range_fd) };
     assert_eq!(result_fd, -1, "Duplicated to an out-of-range file descriptor");
 }
-